@@ -22,9 +22,13 @@ mod collections;
 mod floats;
 mod integers;
 mod lazy;
+#[cfg(feature = "component-model")]
+mod option;
 mod strings;
 
 pub use blob::Blob;
 pub(crate) use collections::WasmbinCountable;
 pub use floats::FloatConst;
 pub use lazy::{Lazy, UnparsedBytes};
+#[cfg(feature = "component-model")]
+pub(crate) use option::WasmbinOptional;
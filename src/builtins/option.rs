@@ -0,0 +1,44 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::io::{Decode, DecodeError, Encode};
+
+/// A trait for types that should be prefixed with a presence flag when encoded as an [`Option`].
+///
+/// Note that `Option<u8>` is deliberately not covered, as it's used to detect end of input.
+pub(crate) trait WasmbinOptional {}
+
+impl WasmbinOptional for u32 {}
+
+impl<T: WasmbinOptional + Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        match self {
+            None => 0_u8.encode(w),
+            Some(value) => {
+                1_u8.encode(w)?;
+                value.encode(w)
+            }
+        }
+    }
+}
+
+impl<T: WasmbinOptional + Decode> Decode for Option<T> {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        match u8::decode(r)? {
+            0 => Ok(None),
+            1 => T::decode(r).map(Some),
+            discriminant => Err(DecodeError::unsupported_discriminant::<Self>(discriminant)),
+        }
+    }
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::builtins::WasmbinCountable;
 use crate::io::{Decode, DecodeError, Encode};
 use crate::visit::Visit;
 
@@ -33,4 +34,6 @@ impl Decode for String {
     }
 }
 
+impl WasmbinCountable for String {}
+
 impl Visit for String {}
@@ -0,0 +1,117 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Component Model](https://github.com/WebAssembly/component-model) binaries.
+
+pub mod sections;
+pub mod types;
+
+use crate::io::{
+    encode_decode_as, Decode, DecodeError, DecodeErrorKind, DecodeWithDiscriminant, Encode,
+    PathItem,
+};
+use crate::visit::Visit;
+use sections::ComponentSection;
+
+const MAGIC_AND_VERSION: [u8; 8] = [b'\0', b'a', b's', b'm', 0x0D, 0x00, 0x01, 0x00];
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+struct MagicAndVersion;
+
+encode_decode_as!(MagicAndVersion, {
+    MagicAndVersion <=> MAGIC_AND_VERSION,
+}, |actual| {
+    Err(DecodeErrorKind::InvalidMagic { actual }.into())
+});
+
+/// [WebAssembly Component](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md).
+///
+/// This is the counterpart of [`Module`](crate::Module) for binaries using the component
+/// preamble. Nested core modules are represented as regular [`Module`](crate::Module)s.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct Component {
+    /// Component [sections](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#component-definitions)
+    /// in their original order.
+    pub sections: Vec<ComponentSection>,
+}
+
+impl Encode for Component {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        MagicAndVersion.encode(w)?;
+        for section in &self.sections {
+            section.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode for Component {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        MagicAndVersion::decode(r)?;
+        let mut sections = Vec::new();
+        while let Some(disc) = Option::decode(r)? {
+            let i = sections.len();
+            sections.push(
+                ComponentSection::decode_with_discriminant(disc, r)
+                    .map_err(move |err| err.in_path(PathItem::Index(i)))?,
+            );
+        }
+        Ok(Component { sections })
+    }
+}
+
+impl Component {
+    /// Decode a component from an arbitrary input.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::BufReader;
+    /// use wasmbin::component::Component;
+    ///
+    /// # fn main() -> Result<(), wasmbin::io::DecodeError> {
+    /// let file = File::open("component.wasm")?;
+    /// let mut reader = BufReader::new(file);
+    /// let component = Component::decode_from(reader)?;
+    /// println!("{component:#?}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn decode_from(mut r: impl std::io::Read) -> Result<Component, DecodeError> {
+        Self::decode(&mut r)
+    }
+
+    /// Encode the component into an arbitrary output.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::BufWriter;
+    /// use wasmbin::component::Component;
+    ///
+    /// # fn main() -> std::io::Result<()> {
+    /// let file = File::create("component.wasm")?;
+    /// let mut writer = BufWriter::new(file);
+    /// # let component = Component::default();
+    /// component.encode_into(writer)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn encode_into<W: std::io::Write>(&self, mut w: W) -> std::io::Result<W> {
+        self.encode(&mut w)?;
+        Ok(w)
+    }
+}
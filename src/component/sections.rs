@@ -0,0 +1,305 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Component sections](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#component-definitions).
+
+use super::types::{ComponentType, CoreType, ValType};
+use super::Component;
+use crate::builtins::{Blob, WasmbinCountable, WasmbinOptional};
+#[cfg(feature = "exception-handling")]
+use crate::indices::ExceptionId;
+use crate::indices::{
+    ComponentFuncId, ComponentId, ComponentInstanceId, ComponentTypeId, CoreInstanceId,
+    CoreModuleId, FuncId, GlobalId, MemId, TableId, TypeId, ValueId,
+};
+use crate::io::Wasmbin;
+use crate::sections::CustomSection;
+use crate::visit::Visit;
+use crate::Module;
+
+/// [Core sort](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#alias-definitions)
+/// of an item in one of the core index spaces.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum CoreSort {
+    Func = 0x00,
+    Table = 0x01,
+    Memory = 0x02,
+    Global = 0x03,
+    #[cfg(feature = "exception-handling")]
+    Tag = 0x04,
+    Type = 0x10,
+    Module = 0x11,
+    Instance = 0x12,
+}
+
+/// Reference to an item in one of the core index spaces.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum CoreSortIdx {
+    Func(FuncId) = 0x00,
+    Table(TableId) = 0x01,
+    Memory(MemId) = 0x02,
+    Global(GlobalId) = 0x03,
+    #[cfg(feature = "exception-handling")]
+    Tag(ExceptionId) = 0x04,
+    Type(TypeId) = 0x10,
+    Module(CoreModuleId) = 0x11,
+    Instance(CoreInstanceId) = 0x12,
+}
+
+/// Named argument of a [core module instantiation](CoreInstance::Instantiate).
+///
+/// The spec only permits [instances](CoreSortIdx::Instance) here.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct CoreInstantiateArg {
+    pub name: String,
+    pub item: CoreSortIdx,
+}
+
+/// Named export of a [core instance built from exports](CoreInstance::FromExports).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct CoreInlineExport {
+    pub name: String,
+    pub item: CoreSortIdx,
+}
+
+/// [Core instance definition](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#instance-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum CoreInstance {
+    Instantiate {
+        module: CoreModuleId,
+        args: Vec<CoreInstantiateArg>,
+    } = 0x00,
+    FromExports(Vec<CoreInlineExport>) = 0x01,
+}
+
+/// [Sort](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#alias-definitions)
+/// of an item in one of the component index spaces.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum Sort {
+    Core(CoreSort) = 0x00,
+    Func = 0x01,
+    Value = 0x02,
+    Type = 0x03,
+    Component = 0x04,
+    Instance = 0x05,
+}
+
+/// Reference to an item in one of the component index spaces.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum SortIdx {
+    Core(CoreSortIdx) = 0x00,
+    Func(ComponentFuncId) = 0x01,
+    Value(ValueId) = 0x02,
+    Type(ComponentTypeId) = 0x03,
+    Component(ComponentId) = 0x04,
+    Instance(ComponentInstanceId) = 0x05,
+}
+
+/// Import or export name.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum ExternName {
+    Name(String) = 0x00,
+    /// Legacy encoding of interface names such as `wasi:cli/run@0.2.0`.
+    ///
+    /// Interface names are now recognised by their syntax alone, but older
+    /// toolchains still emit this discriminant.
+    Interface(String) = 0x01,
+}
+
+impl ExternName {
+    /// The name itself regardless of the encoding.
+    pub fn as_str(&self) -> &str {
+        match self {
+            ExternName::Name(name) | ExternName::Interface(name) => name,
+        }
+    }
+}
+
+/// Named argument of a [component instantiation](ComponentInstance::Instantiate).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct InstantiateArg {
+    pub name: String,
+    pub item: SortIdx,
+}
+
+/// Named export of an [instance built from exports](ComponentInstance::FromExports).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct InlineExport {
+    pub name: ExternName,
+    pub item: SortIdx,
+}
+
+/// [Component instance definition](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#instance-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum ComponentInstance {
+    Instantiate {
+        component: ComponentId,
+        args: Vec<InstantiateArg>,
+    } = 0x00,
+    FromExports(Vec<InlineExport>) = 0x01,
+}
+
+/// [Alias target](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#alias-definitions).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum AliasTarget {
+    Export {
+        instance: ComponentInstanceId,
+        name: String,
+    } = 0x00,
+    CoreExport {
+        instance: CoreInstanceId,
+        name: String,
+    } = 0x01,
+    Outer {
+        count: u32,
+        index: u32,
+    } = 0x02,
+}
+
+/// [Alias definition](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#alias-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct Alias {
+    pub sort: Sort,
+    pub target: AliasTarget,
+}
+
+/// Reference to a core module type in an [`ExternDesc`].
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[wasmbin(discriminant = 0x11)]
+pub struct CoreModuleDesc {
+    pub ty: TypeId,
+}
+
+/// Bound of an imported or exported value.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum ValueBound {
+    Eq(ValueId) = 0x00,
+    Type(ValType) = 0x01,
+}
+
+/// Bound of an imported or exported type.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum TypeBound {
+    Eq(ComponentTypeId) = 0x00,
+    SubResource = 0x01,
+}
+
+/// [Extern descriptor](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#import-and-export-definitions)
+/// describing the type of an import or export.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum ExternDesc {
+    CoreModule(CoreModuleDesc) = 0x00,
+    Func(ComponentTypeId) = 0x01,
+    Value(ValueBound) = 0x02,
+    Type(TypeBound) = 0x03,
+    Component(ComponentTypeId) = 0x04,
+    Instance(ComponentTypeId) = 0x05,
+}
+
+impl WasmbinOptional for ExternDesc {}
+
+/// [Canonical ABI option](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#canonical-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum CanonOpt {
+    Utf8 = 0x00,
+    Utf16 = 0x01,
+    CompactUtf16 = 0x02,
+    Memory(MemId) = 0x03,
+    Realloc(FuncId) = 0x04,
+    PostReturn(FuncId) = 0x05,
+}
+
+/// Lifting of a core function into a component function.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[wasmbin(discriminant = 0x00)]
+pub struct CanonLift {
+    pub func: FuncId,
+    pub opts: Vec<CanonOpt>,
+    pub ty: ComponentTypeId,
+}
+
+/// Lowering of a component function into a core function.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[wasmbin(discriminant = 0x00)]
+pub struct CanonLower {
+    pub func: ComponentFuncId,
+    pub opts: Vec<CanonOpt>,
+}
+
+/// [Canonical definition](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#canonical-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum Canon {
+    Lift(CanonLift) = 0x00,
+    Lower(CanonLower) = 0x01,
+    ResourceNew(ComponentTypeId) = 0x02,
+    ResourceDrop(ComponentTypeId) = 0x03,
+    ResourceRep(ComponentTypeId) = 0x04,
+}
+
+/// [Component start function](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#start-definitions).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ComponentStart {
+    pub func: ComponentFuncId,
+    pub args: Vec<ValueId>,
+    pub results: u32,
+}
+
+/// [Component import](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#import-and-export-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ComponentImport {
+    pub name: ExternName,
+    pub desc: ExternDesc,
+}
+
+/// [Component export](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#import-and-export-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ComponentExport {
+    pub name: ExternName,
+    pub item: SortIdx,
+    /// Optional type ascription for the exported item.
+    pub desc: Option<ExternDesc>,
+}
+
+/// [Component section](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#component-definitions).
+///
+/// Unlike module sections, these can appear in any order and any number of times.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum ComponentSection {
+    Custom(Blob<CustomSection>) = 0,
+    CoreModule(Blob<Module>) = 1,
+    CoreInstance(Blob<Vec<CoreInstance>>) = 2,
+    CoreType(Blob<Vec<CoreType>>) = 3,
+    Component(Blob<Component>) = 4,
+    Instance(Blob<Vec<ComponentInstance>>) = 5,
+    Alias(Blob<Vec<Alias>>) = 6,
+    Type(Blob<Vec<ComponentType>>) = 7,
+    Canon(Blob<Vec<Canon>>) = 8,
+    Start(Blob<ComponentStart>) = 9,
+    Import(Blob<Vec<ComponentImport>>) = 10,
+    Export(Blob<Vec<ComponentExport>>) = 11,
+}
@@ -0,0 +1,229 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Component types](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions).
+
+use super::sections::{Alias, ComponentImport, CoreSort, ExternDesc, ExternName};
+use crate::builtins::{WasmbinCountable, WasmbinOptional};
+use crate::indices::{ComponentTypeId, FuncId};
+use crate::io::{Decode, DecodeError, DecodeWithDiscriminant, Encode, PathItem, Wasmbin};
+use crate::sections::{Import, ImportDesc};
+use crate::types::{FuncType, ValueType};
+use crate::visit::Visit;
+use std::convert::TryFrom;
+
+/// Outer alias target inside a [core module type](CoreType::Module).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum CoreAliasTarget {
+    Outer { count: u32, index: u32 } = 0x01,
+}
+
+/// [Core alias](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions)
+/// inside a [core module type](CoreType::Module).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct CoreAlias {
+    pub sort: CoreSort,
+    pub target: CoreAliasTarget,
+}
+
+/// Export declaration inside a [core module type](CoreType::Module).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct CoreExportDecl {
+    pub name: String,
+    pub desc: ImportDesc,
+}
+
+/// Declaration inside a [core module type](CoreType::Module).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum CoreModuleDecl {
+    Import(Import) = 0x00,
+    Type(FuncType) = 0x01,
+    Alias(CoreAlias) = 0x02,
+    Export(CoreExportDecl) = 0x03,
+}
+
+/// [Core type definition](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum CoreType {
+    /// Core function type.
+    Func(FuncType),
+    /// Core module type described by its imports and exports.
+    Module(Vec<CoreModuleDecl>) = 0x50,
+}
+
+/// [Primitive value type](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum PrimitiveValType {
+    Bool = 0x7F,
+    S8 = 0x7E,
+    U8 = 0x7D,
+    S16 = 0x7C,
+    U16 = 0x7B,
+    S32 = 0x7A,
+    U32 = 0x79,
+    S64 = 0x78,
+    U64 = 0x77,
+    F32 = 0x76,
+    F64 = 0x75,
+    Char = 0x74,
+    String = 0x73,
+    ErrorContext = 0x64,
+}
+
+/// [Value type](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions)
+/// used by component-level functions and values.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+pub enum ValType {
+    /// One of the built-in primitive types.
+    Primitive(PrimitiveValType),
+    /// Reference to a defined type in the component type index space.
+    Type(ComponentTypeId),
+}
+
+impl Encode for ValType {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        match self {
+            ValType::Primitive(ty) => ty.encode(w),
+            ValType::Type(id) => i64::from(id.index).encode(w),
+        }
+    }
+}
+
+impl Decode for ValType {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let discriminant = u8::decode(r)?;
+        if let Some(ty) = PrimitiveValType::maybe_decode_with_discriminant(discriminant, r)
+            .map_err(|err| err.in_path(PathItem::Variant("ValType::Primitive")))?
+        {
+            return Ok(ValType::Primitive(ty));
+        }
+        let index = (move || -> Result<_, DecodeError> {
+            // Same as with `BlockType`, type indices are encoded as
+            // non-negative s33 and share the first byte with primitives.
+            let buf = [discriminant];
+            let mut r = std::io::Read::chain(&buf[..], r);
+            let as_i64 = i64::decode(&mut r)?;
+            let index = u32::try_from(as_i64)?;
+            Ok(index)
+        })()
+        .map_err(|err| err.in_path(PathItem::Variant("ValType::Type")))?;
+        Ok(ValType::Type(ComponentTypeId { index }))
+    }
+}
+
+impl WasmbinCountable for ValType {}
+
+impl WasmbinOptional for ValType {}
+
+/// Named value type used in records and function parameters.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct LabelValType {
+    pub name: String,
+    pub ty: ValType,
+}
+
+/// A single case of a [variant](DefinedType::Variant) type.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct VariantCase {
+    pub name: String,
+    pub ty: Option<ValType>,
+    /// Index of another case this one refines (deprecated, but still present in the encoding).
+    pub refines: Option<u32>,
+}
+
+/// [Defined value type](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum DefinedType {
+    Primitive(PrimitiveValType),
+    Record(Vec<LabelValType>) = 0x72,
+    Variant(Vec<VariantCase>) = 0x71,
+    List(ValType) = 0x70,
+    Tuple(Vec<ValType>) = 0x6F,
+    Flags(Vec<String>) = 0x6E,
+    Enum(Vec<String>) = 0x6D,
+    Option(ValType) = 0x6B,
+    Result {
+        ok: Option<ValType>,
+        err: Option<ValType>,
+    } = 0x6A,
+    Own(ComponentTypeId) = 0x69,
+    Borrow(ComponentTypeId) = 0x68,
+    Stream(Option<ValType>) = 0x66,
+    Future(Option<ValType>) = 0x65,
+}
+
+/// Results of a [component function type](ComponentFuncType).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum ResultList {
+    /// A single unnamed result.
+    Unnamed(ValType) = 0x00,
+    /// Named results; current toolchains only emit an empty list here.
+    Named(Vec<LabelValType>) = 0x01,
+}
+
+/// [Component function type](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ComponentFuncType {
+    pub params: Vec<LabelValType>,
+    pub results: ResultList,
+}
+
+/// Export declaration inside a [component](ComponentType::Component) or [instance](ComponentType::Instance) type.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ExportDecl {
+    pub name: ExternName,
+    pub desc: ExternDesc,
+}
+
+/// Declaration inside an [instance type](ComponentType::Instance).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum InstanceDecl {
+    CoreType(CoreType) = 0x00,
+    Type(ComponentType) = 0x01,
+    Alias(Alias) = 0x02,
+    Export(ExportDecl) = 0x04,
+}
+
+/// Declaration inside a [component type](ComponentType::Component).
+///
+/// Component types can contain everything instance types can, plus imports.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum ComponentDecl {
+    Import(ComponentImport) = 0x03,
+    Instance(InstanceDecl),
+}
+
+/// [Component type definition](https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md#type-definitions).
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum ComponentType {
+    Defined(DefinedType),
+    Func(ComponentFuncType) = 0x40,
+    Component(Vec<ComponentDecl>) = 0x41,
+    Instance(Vec<InstanceDecl>) = 0x42,
+    Resource {
+        /// Core representation type; currently always `i32`.
+        rep: ValueType,
+        /// Optional core destructor function.
+        dtor: Option<FuncId>,
+    } = 0x3F,
+}
@@ -0,0 +1,165 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::map_address;
+use crate::transforms::address_map::AddressMap;
+use gimli::write::{
+    Address, AttributeValue, ConvertResult, ConvertUnit, ConvertUnitEntry, Location, LocationList,
+    Range, RangeList, UnitEntryId,
+};
+use gimli::{constants, read, LocationListsOffset, RangeListsOffset, Reader};
+
+/// Convert all entries of a unit along with its line program.
+pub(super) fn convert_unit<'a, R: Reader<Offset = usize>>(
+    unit: &mut ConvertUnit<'a, R>,
+    root_entry: ConvertUnitEntry<'a, R>,
+    map: &AddressMap,
+) -> ConvertResult<()> {
+    if let Some(program) = unit.read_line_program(None, None)? {
+        let (program, files) = super::line::convert_program(program, map)?;
+        unit.set_line_program(program, files);
+    }
+    let root = unit.unit.root();
+    convert_attributes(unit, root, &root_entry, map)?;
+    let mut entry = root_entry;
+    while let Some(id) = unit.read_entry(&mut entry)? {
+        let id = unit.add_entry(id, &entry);
+        convert_attributes(unit, id, &entry, map)?;
+    }
+    Ok(())
+}
+
+/// Address conversion which replaces references to removed code with the tombstone value.
+fn address_converter(map: &AddressMap, address_size: u8) -> impl Fn(u64) -> Option<Address> + '_ {
+    let tombstone = u64::MAX >> (64 - 8 * u32::from(address_size));
+    move |addr| {
+        Some(Address::Constant(
+            map_address(map, addr).unwrap_or(tombstone),
+        ))
+    }
+}
+
+fn convert_attributes<R: Reader<Offset = usize>>(
+    unit: &mut ConvertUnit<'_, R>,
+    id: UnitEntryId,
+    entry: &ConvertUnitEntry<'_, R>,
+    map: &AddressMap,
+) -> ConvertResult<()> {
+    let read_unit = entry.read_unit;
+    let convert_address = address_converter(map, read_unit.encoding().address_size);
+    for attr in &entry.attrs {
+        let value = match (attr.name(), attr.udata_value(), attr.value()) {
+            // GNU extension that gimli can't convert, and which is safe to drop.
+            (constants::DW_AT_GNU_locviews, ..) => continue,
+            // Length relative to `DW_AT_low_pc` rather than an address.
+            (constants::DW_AT_high_pc, Some(length), _) => {
+                AttributeValue::Udata(high_pc_length(read_unit, entry, length, map)?)
+            }
+            (_, _, read::AttributeValue::RangeListsRef(offset)) => {
+                let offset = read_unit.ranges_offset_from_raw(offset);
+                let ranges = convert_ranges(read_unit, offset, map)?;
+                AttributeValue::RangeListRef(unit.unit.ranges.add(ranges))
+            }
+            (_, _, read::AttributeValue::DebugRngListsIndex(index)) => {
+                let offset = read_unit.ranges_offset(index)?;
+                let ranges = convert_ranges(read_unit, offset, map)?;
+                AttributeValue::RangeListRef(unit.unit.ranges.add(ranges))
+            }
+            (_, _, read::AttributeValue::LocationListsRef(offset)) => {
+                let locations = convert_locations(unit, read_unit, offset, map)?;
+                AttributeValue::LocationListRef(unit.unit.locations.add(locations))
+            }
+            (_, _, read::AttributeValue::DebugLocListsIndex(index)) => {
+                let offset = read_unit.locations_offset(index)?;
+                let locations = convert_locations(unit, read_unit, offset, map)?;
+                AttributeValue::LocationListRef(unit.unit.locations.add(locations))
+            }
+            _ => unit.convert_attribute_value(read_unit, attr, &convert_address)?,
+        };
+        unit.unit.get_mut(id).set(attr.name(), value);
+    }
+    Ok(())
+}
+
+/// Translate a `DW_AT_high_pc` length relative to the `DW_AT_low_pc` of the same entry.
+///
+/// Lengths of removed code are kept as is.
+fn high_pc_length<R: Reader<Offset = usize>>(
+    read_unit: read::UnitRef<'_, R>,
+    entry: &ConvertUnitEntry<'_, R>,
+    length: u64,
+    map: &AddressMap,
+) -> ConvertResult<u64> {
+    let Some(low_pc) = entry.attr_value(constants::DW_AT_low_pc) else {
+        return Ok(length);
+    };
+    let Some(low_pc) = read_unit.attr_address(low_pc)? else {
+        return Ok(length);
+    };
+    Ok(map_range(map, low_pc, low_pc.wrapping_add(length))
+        .map_or(length, |(begin, end)| end - begin))
+}
+
+/// Translate an address range, returning `None` if it covers removed code or becomes empty.
+fn map_range(map: &AddressMap, begin: u64, end: u64) -> Option<(u64, u64)> {
+    let begin = map_address(map, begin)?;
+    let end = map_address(map, end)?;
+    (begin < end).then_some((begin, end))
+}
+
+/// Convert a range list, resolving its entries to absolute addresses.
+fn convert_ranges<R: Reader<Offset = usize>>(
+    read_unit: read::UnitRef<'_, R>,
+    offset: RangeListsOffset,
+    map: &AddressMap,
+) -> ConvertResult<RangeList> {
+    // DWARF 4 ranges are relative to the unit base address unless it's reset.
+    let mut ranges = vec![Range::BaseAddress {
+        address: Address::Constant(0),
+    }];
+    let mut iter = read_unit.ranges(offset)?;
+    while let Some(range) = iter.next()? {
+        if let Some((begin, end)) = map_range(map, range.begin, range.end) {
+            ranges.push(Range::StartEnd {
+                begin: Address::Constant(begin),
+                end: Address::Constant(end),
+            });
+        }
+    }
+    Ok(RangeList(ranges))
+}
+
+/// Convert a location list, resolving its entries to absolute addresses.
+fn convert_locations<R: Reader<Offset = usize>>(
+    unit: &ConvertUnit<'_, R>,
+    read_unit: read::UnitRef<'_, R>,
+    offset: LocationListsOffset,
+    map: &AddressMap,
+) -> ConvertResult<LocationList> {
+    let convert_address = address_converter(map, read_unit.encoding().address_size);
+    let mut locations = vec![Location::BaseAddress {
+        address: Address::Constant(0),
+    }];
+    let mut iter = read_unit.locations(offset)?;
+    while let Some(location) = iter.next()? {
+        if let Some((begin, end)) = map_range(map, location.range.begin, location.range.end) {
+            locations.push(Location::StartEnd {
+                begin: Address::Constant(begin),
+                end: Address::Constant(end),
+                data: unit.convert_expression(read_unit, location.data, &convert_address)?,
+            });
+        }
+    }
+    Ok(LocationList(locations))
+}
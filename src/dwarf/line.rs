@@ -0,0 +1,71 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::map_address;
+use crate::transforms::address_map::AddressMap;
+use gimli::write::{
+    Address, ConvertLineProgram, ConvertLineSequenceEnd, ConvertResult, FileId, LineProgram,
+};
+use gimli::Reader;
+
+/// Convert a line program, translating the address of every row.
+///
+/// Rows whose addresses are no longer increasing, e.g. because functions were reordered,
+/// start a new sequence. Sequences covering removed code are dropped.
+pub(super) fn convert_program<R: Reader<Offset = usize>>(
+    mut program: ConvertLineProgram<'_, R>,
+    map: &AddressMap,
+) -> ConvertResult<(LineProgram, Vec<FileId>)> {
+    while let Some(sequence) = program.read_sequence()? {
+        let start = sequence.start.unwrap_or_default();
+        let end = match sequence.end {
+            ConvertLineSequenceEnd::Length(length) => start.wrapping_add(length),
+            ConvertLineSequenceEnd::Address(address) => address,
+        };
+        let rows = sequence
+            .rows
+            .into_iter()
+            .map(|row| {
+                Some((
+                    map_address(map, start.wrapping_add(row.address_offset))?,
+                    row,
+                ))
+            })
+            .collect::<Option<Vec<_>>>();
+        let (Some(rows), Some(end)) = (rows, map_address(map, end)) else {
+            continue;
+        };
+        // Start address of the current output sequence and address of its last row.
+        let mut current: Option<(u64, u64)> = None;
+        for (address, mut row) in rows {
+            let base = match current {
+                Some((base, last)) if address >= last => base,
+                _ => {
+                    if let Some((base, last)) = current {
+                        program.end_sequence(last - base);
+                    }
+                    program.begin_sequence(Some(Address::Constant(address)));
+                    address
+                }
+            };
+            row.address_offset = address - base;
+            program.generate_row(row);
+            current = Some((base, address));
+        }
+        if let Some((base, last)) = current {
+            program.end_sequence(end.max(last) - base);
+        }
+    }
+    Ok(program.program())
+}
@@ -0,0 +1,208 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access to [DWARF](https://yurydelendik.github.io/webassembly-dwarf/) debug sections and
+//! rewriting of the code addresses they contain after function bodies move.
+//!
+//! Sections are exposed as raw bytes or loaded into a [`gimli`] reader. Code addresses in
+//! DWARF versions 2 to 5 can be translated with an [`AddressMap`] built from
+//! [`CodeLayout`](crate::transforms::address_map::CodeLayout) snapshots taken before and
+//! after a transformation.
+//!
+//! ```no_run
+//! use wasmbin::dwarf::rewrite_addresses;
+//! use wasmbin::transforms::address_map::{AddressMap, CodeLayout};
+//! # fn transform(_: &mut wasmbin::Module) {}
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = wasmbin::Module::decode_from(std::fs::File::open("input.wasm")?)?;
+//! let before = CodeLayout::of(&module)?;
+//! transform(&mut module);
+//! let after = CodeLayout::of(&module)?;
+//! rewrite_addresses(&mut module, &AddressMap::new(before, after))?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod info;
+mod line;
+
+use crate::builtins::{Blob, UnparsedBytes};
+use crate::io::DecodeError;
+use crate::sections::{CustomSection, RawCustomSection, Section};
+use crate::transforms::address_map::AddressMap;
+use crate::Module;
+use gimli::write::{ConvertError, EndianVec, Sections};
+use gimli::LittleEndian;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use thiserror::Error;
+
+/// Error returned by [`rewrite_addresses`].
+#[derive(Debug, Error)]
+pub enum DwarfError {
+    /// Decoding error occured while reading a custom section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Debug information couldn't be read or converted.
+    #[error(transparent)]
+    Convert(#[from] ConvertError),
+
+    /// Converted debug information couldn't be written.
+    #[error(transparent)]
+    Write(#[from] gimli::write::Error),
+
+    /// Module contains a debug section that can't be rewritten.
+    #[error("Unsupported {0} section")]
+    UnsupportedSection(&'static str),
+}
+
+/// Raw contents of the `.debug_*` custom sections of a module, keyed by section name.
+#[derive(Debug, Default, Clone)]
+pub struct DwarfSections<'a> {
+    sections: HashMap<&'a str, &'a [u8]>,
+}
+
+impl<'a> DwarfSections<'a> {
+    /// Collect the debug sections of a module.
+    pub fn from_module(module: &'a Module) -> Result<Self, DecodeError> {
+        let mut sections = HashMap::new();
+        for section in &module.sections {
+            if let Section::Custom(custom) = section {
+                if let CustomSection::Other(RawCustomSection { name, data }) =
+                    custom.try_contents()?
+                {
+                    if name.starts_with(".debug_") {
+                        sections.insert(name.as_str(), data.bytes.as_slice());
+                    }
+                }
+            }
+        }
+        Ok(DwarfSections { sections })
+    }
+
+    /// Contents of the section with the given name (e.g. `.debug_info`), or an empty slice
+    /// if it's not present.
+    pub fn get(&self, name: &str) -> &'a [u8] {
+        self.sections.get(name).copied().unwrap_or_default()
+    }
+
+    /// Load the sections into a [`gimli::Dwarf`] reader.
+    pub fn to_gimli(&self) -> gimli::Dwarf<gimli::EndianSlice<'a, LittleEndian>> {
+        gimli::Dwarf::load(|id| {
+            Ok::<_, Infallible>(gimli::EndianSlice::new(self.get(id.name()), LittleEndian))
+        })
+        .unwrap_or_else(|never| match never {})
+    }
+
+    /// Iterate over all the collected sections.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a [u8])> + '_ {
+        self.sections.iter().map(|(&name, &data)| (name, data))
+    }
+}
+
+/// Sections replaced by the output of [`rewrite_addresses`].
+///
+/// Besides the sections written by [`gimli`], this includes `.debug_addr` and
+/// `.debug_str_offsets`, whose entries are inlined into the rewritten `.debug_info`, and the
+/// lookup tables which refer to offsets in the original `.debug_info`.
+const REWRITTEN_SECTIONS: &[&str] = &[
+    ".debug_abbrev",
+    ".debug_addr",
+    ".debug_aranges",
+    ".debug_info",
+    ".debug_line",
+    ".debug_line_str",
+    ".debug_loc",
+    ".debug_loclists",
+    ".debug_names",
+    ".debug_pubnames",
+    ".debug_pubtypes",
+    ".debug_ranges",
+    ".debug_rnglists",
+    ".debug_str",
+    ".debug_str_offsets",
+];
+
+fn is_rewritten(section: &Section) -> bool {
+    let Section::Custom(custom) = section else {
+        return false;
+    };
+    matches!(
+        custom.try_contents(),
+        Ok(CustomSection::Other(RawCustomSection { name, .. }))
+            if REWRITTEN_SECTIONS.contains(&name.as_str())
+    )
+}
+
+/// Translate code addresses in the DWARF sections of a module according to `map`.
+///
+/// The debug information is converted with [`gimli::write`], translating addresses in
+/// attributes, location expressions, range and location lists, and line programs. Ranges
+/// and line sequences covering removed functions are dropped, while other references to them
+/// are replaced with the tombstone value used by linkers.
+///
+/// The rewritten sections replace the original ones. Lookup tables such as
+/// `.debug_aranges` and `.debug_names` are removed rather than rebuilt, as consumers can
+/// fall back to scanning `.debug_info`.
+pub fn rewrite_addresses(module: &mut Module, map: &AddressMap) -> Result<(), DwarfError> {
+    let sections = DwarfSections::from_module(module)?;
+    if sections.get(".debug_info").is_empty() {
+        return Ok(());
+    }
+    // Type units would refer to the original abbreviations.
+    if !sections.get(".debug_types").is_empty() {
+        return Err(DwarfError::UnsupportedSection(".debug_types"));
+    }
+    let read = sections.to_gimli();
+    let mut dwarf = gimli::write::Dwarf::new();
+    let mut convert = dwarf.convert(&read)?;
+    while let Some((mut unit, root_entry)) = convert.read_unit()? {
+        info::convert_unit(&mut unit, root_entry, map)?;
+    }
+    let mut out = Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut out)?;
+    let mut rewritten = Vec::new();
+    out.for_each(|id, data| {
+        if !data.slice().is_empty() {
+            rewritten.push(Section::Custom(Blob::from(CustomSection::Other(
+                RawCustomSection {
+                    name: id.name().to_owned(),
+                    data: UnparsedBytes {
+                        bytes: data.slice().to_vec(),
+                    },
+                },
+            ))));
+        }
+        Ok::<_, Infallible>(())
+    })
+    .unwrap_or_else(|never| match never {});
+    let position = module
+        .sections
+        .iter()
+        .position(is_rewritten)
+        .unwrap_or(module.sections.len());
+    module.sections.retain(|section| !is_rewritten(section));
+    module.sections.splice(position..position, rewritten);
+    Ok(())
+}
+
+/// Translate an address read from a section, keeping values that aren't code offsets.
+fn map_address(map: &AddressMap, addr: u64) -> Option<u64> {
+    match u32::try_from(addr) {
+        Ok(addr) => map.map(addr).map(u64::from),
+        Err(_) => Some(addr),
+    }
+}
@@ -0,0 +1,407 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of [WebAssembly features](https://webassembly.org/features/) used by a module.
+
+#[cfg(feature = "fp16")]
+use crate::instructions::SIMD;
+use crate::instructions::{Instruction, Misc};
+use crate::io::DecodeError;
+use crate::sections::{DataInit, Element, ImportDesc, Section};
+use crate::types::{BlockType, FuncType, MemType, RefType, TableType, ValueType};
+use crate::Module;
+use std::collections::BTreeSet;
+
+macro_rules! define_features {
+    ($($(#[doc = $doc:literal])* $name:ident = $str:literal,)*) => {
+        /// A WebAssembly feature, as named in the
+        /// [`target_features`](crate::sections::CustomSection::TargetFeatures) section.
+        #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+        #[non_exhaustive]
+        pub enum Feature {
+            $($(#[doc = $doc])* $name,)*
+        }
+
+        impl Feature {
+            /// Name of the feature as used in the `target_features` section.
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Self::$name => $str,)*
+                }
+            }
+
+            /// Look up a feature by its `target_features` name.
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($str => Some(Self::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+define_features! {
+    /// [Threads](https://github.com/WebAssembly/threads) atomic instructions and shared memories.
+    Atomics = "atomics",
+    /// [Bulk memory operations](https://github.com/WebAssembly/bulk-memory-operations).
+    BulkMemory = "bulk-memory",
+    /// [Exception handling](https://github.com/WebAssembly/exception-handling).
+    ExceptionHandling = "exception-handling",
+    /// [Half-precision floating point](https://github.com/WebAssembly/half-precision).
+    Fp16 = "fp16",
+    /// [Multiple memories](https://github.com/WebAssembly/multi-memory).
+    MultiMemory = "multimemory",
+    /// [Multi-value](https://github.com/WebAssembly/multi-value) results.
+    MultiValue = "multivalue",
+    /// [Non-trapping float-to-int conversions](https://github.com/WebAssembly/nontrapping-float-to-int-conversions).
+    NontrappingFptoint = "nontrapping-fptoint",
+    /// [Reference types](https://github.com/WebAssembly/reference-types).
+    ReferenceTypes = "reference-types",
+    /// [Sign-extension operators](https://github.com/WebAssembly/sign-extension-ops).
+    SignExt = "sign-ext",
+    /// [Fixed-width SIMD](https://github.com/WebAssembly/simd).
+    Simd128 = "simd128",
+    /// [Tail calls](https://github.com/WebAssembly/tail-call).
+    TailCall = "tail-call",
+    /// [Wide arithmetic](https://github.com/WebAssembly/wide-arithmetic).
+    WideArithmetic = "wide-arithmetic",
+}
+
+fn misc_feature(misc: &Misc) -> Feature {
+    match misc {
+        Misc::I32TruncSatF32S
+        | Misc::I32TruncSatF32U
+        | Misc::I32TruncSatF64S
+        | Misc::I32TruncSatF64U
+        | Misc::I64TruncSatF32S
+        | Misc::I64TruncSatF32U
+        | Misc::I64TruncSatF64S
+        | Misc::I64TruncSatF64U => Feature::NontrappingFptoint,
+        Misc::MemoryInit { .. }
+        | Misc::DataDrop(_)
+        | Misc::MemoryCopy { .. }
+        | Misc::MemoryFill(_)
+        | Misc::TableInit { .. }
+        | Misc::ElemDrop(_)
+        | Misc::TableCopy { .. } => Feature::BulkMemory,
+        Misc::TableGrow(_) | Misc::TableSize(_) | Misc::TableFill(_) => Feature::ReferenceTypes,
+        #[cfg(feature = "wide-arithmetic")]
+        Misc::I64Add128 | Misc::I64Sub128 | Misc::I64MulWideS | Misc::I64MulWideU => {
+            Feature::WideArithmetic
+        }
+        #[cfg(feature = "fp16")]
+        Misc::F32LoadF16(_) | Misc::F32StoreF16(_) => Feature::Fp16,
+    }
+}
+
+fn instruction_feature(instr: &Instruction) -> Option<Feature> {
+    Some(match instr {
+        Instruction::ReturnCall(_) | Instruction::ReturnCallIndirect(_) => Feature::TailCall,
+        Instruction::I32Extend8S
+        | Instruction::I32Extend16S
+        | Instruction::I64Extend8S
+        | Instruction::I64Extend16S
+        | Instruction::I64Extend32S => Feature::SignExt,
+        Instruction::RefNull(_)
+        | Instruction::RefIsNull
+        | Instruction::RefFunc(_)
+        | Instruction::TableGet(_)
+        | Instruction::TableSet(_)
+        | Instruction::SelectWithTypes(_) => Feature::ReferenceTypes,
+        #[cfg(feature = "exception-handling")]
+        Instruction::Throw(_) | Instruction::ThrowRef | Instruction::TryTable(_) => {
+            Feature::ExceptionHandling
+        }
+        #[cfg(feature = "legacy-exceptions")]
+        Instruction::TryStart(_)
+        | Instruction::TryCatch(_)
+        | Instruction::TryCatchAll
+        | Instruction::TryDelegate(_)
+        | Instruction::Rethrow(_) => Feature::ExceptionHandling,
+        Instruction::Misc(misc) => misc_feature(misc),
+        #[cfg(feature = "fp16")]
+        Instruction::SIMD(
+            SIMD::F16x8Splat
+            | SIMD::F16x8ExtractLane(_)
+            | SIMD::F16x8ReplaceLane(_)
+            | SIMD::F16x8Abs
+            | SIMD::F16x8Neg
+            | SIMD::F16x8Sqrt
+            | SIMD::F16x8Ceil
+            | SIMD::F16x8Floor
+            | SIMD::F16x8Trunc
+            | SIMD::F16x8Nearest
+            | SIMD::F16x8Eq
+            | SIMD::F16x8Ne
+            | SIMD::F16x8Lt
+            | SIMD::F16x8Gt
+            | SIMD::F16x8Le
+            | SIMD::F16x8Ge
+            | SIMD::F16x8Add
+            | SIMD::F16x8Sub
+            | SIMD::F16x8Mul
+            | SIMD::F16x8Div
+            | SIMD::F16x8Min
+            | SIMD::F16x8Max
+            | SIMD::F16x8Pmin
+            | SIMD::F16x8Pmax
+            | SIMD::I16x8TruncSatF16x8S
+            | SIMD::I16x8TruncSatF16x8U
+            | SIMD::F16x8ConvertI16x8S
+            | SIMD::F16x8ConvertI16x8U
+            | SIMD::F16x8DemoteF32x4Zero
+            | SIMD::F32x4PromoteLowF16x8
+            | SIMD::F16x8RelaxedMadd
+            | SIMD::F16x8RelaxedNmadd,
+        ) => Feature::Fp16,
+        Instruction::SIMD(_) => Feature::Simd128,
+        #[cfg(feature = "threads")]
+        Instruction::Atomic(_) => Feature::Atomics,
+        _ => return None,
+    })
+}
+
+/// Features found so far, along with the number of tables and memories.
+#[derive(Default)]
+struct Detector {
+    features: BTreeSet<Feature>,
+    tables: usize,
+    memories: usize,
+}
+
+impl Detector {
+    fn insert(&mut self, feature: Feature) {
+        self.features.insert(feature);
+    }
+
+    fn ref_type(&mut self, ty: &RefType) {
+        if *ty != RefType::Func {
+            self.insert(Feature::ReferenceTypes);
+        }
+        #[cfg(feature = "exception-handling")]
+        if *ty == RefType::Exception {
+            self.insert(Feature::ExceptionHandling);
+        }
+    }
+
+    fn value_type(&mut self, ty: &ValueType) {
+        match ty {
+            ValueType::V128 => self.insert(Feature::Simd128),
+            ValueType::Ref(ty) => {
+                self.insert(Feature::ReferenceTypes);
+                self.ref_type(ty);
+            }
+            _ => {}
+        }
+    }
+
+    fn block_type(&mut self, ty: &BlockType) {
+        match ty {
+            BlockType::Empty => {}
+            BlockType::Value(ty) => self.value_type(ty),
+            BlockType::MultiValue(_) => self.insert(Feature::MultiValue),
+        }
+    }
+
+    fn func_type(&mut self, ty: &FuncType) {
+        for ty in ty.params.iter().chain(&ty.results) {
+            self.value_type(ty);
+        }
+        if ty.results.len() > 1 {
+            self.insert(Feature::MultiValue);
+        }
+    }
+
+    fn table_type(&mut self, ty: &TableType) {
+        self.tables += 1;
+        self.ref_type(&ty.elem_type);
+    }
+
+    #[allow(unused_variables)]
+    fn mem_type(&mut self, ty: &MemType) {
+        self.memories += 1;
+        #[cfg(feature = "threads")]
+        if ty.is_shared {
+            self.insert(Feature::Atomics);
+        }
+    }
+
+    fn expression(&mut self, expr: &[Instruction]) {
+        for instr in expr {
+            self.features.extend(instruction_feature(instr));
+            match instr {
+                Instruction::BlockStart(ty)
+                | Instruction::LoopStart(ty)
+                | Instruction::IfStart(ty) => {
+                    self.block_type(ty);
+                }
+                #[cfg(feature = "legacy-exceptions")]
+                Instruction::TryStart(ty) => self.block_type(ty),
+                #[cfg(feature = "exception-handling")]
+                Instruction::TryTable(try_table) => {
+                    self.block_type(&try_table.block_type);
+                    self.expression(&try_table.instructions);
+                }
+                Instruction::SelectWithTypes(types) => {
+                    for ty in types {
+                        self.value_type(ty);
+                    }
+                }
+                Instruction::RefNull(ty) => self.ref_type(ty),
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, elem: &Element) {
+        let (offset, exprs) = match elem {
+            Element::ActiveWithFuncs { offset, .. } => (Some(offset), &[][..]),
+            Element::PassiveWithFuncs { .. } | Element::DeclarativeWithFuncs { .. } => {
+                self.insert(Feature::BulkMemory);
+                (None, &[][..])
+            }
+            Element::ActiveWithTableAndFuncs { offset, .. } => {
+                self.insert(Feature::ReferenceTypes);
+                (Some(offset), &[][..])
+            }
+            Element::ActiveWithExprs { offset, exprs }
+            | Element::ActiveWithTableAndExprs { offset, exprs, .. } => {
+                self.insert(Feature::ReferenceTypes);
+                (Some(offset), exprs.as_slice())
+            }
+            Element::PassiveWithExprs { exprs, .. }
+            | Element::DeclarativeWithExprs { exprs, .. } => {
+                self.insert(Feature::ReferenceTypes);
+                (None, exprs.as_slice())
+            }
+        };
+        for expr in offset.into_iter().chain(exprs) {
+            self.expression(expr);
+        }
+    }
+
+    fn section(&mut self, section: &Section) -> Result<(), DecodeError> {
+        match section {
+            Section::Type(types) => {
+                for ty in types.try_contents()? {
+                    if let Some(ty) = ty.as_func() {
+                        self.func_type(ty);
+                    }
+                }
+            }
+            Section::Import(imports) => {
+                for import in imports.try_contents()? {
+                    match &import.desc {
+                        ImportDesc::Func(_) => {}
+                        ImportDesc::Table(ty) => self.table_type(ty),
+                        ImportDesc::Mem(ty) => self.mem_type(ty),
+                        ImportDesc::Global(ty) => self.value_type(&ty.value_type),
+                        #[cfg(feature = "exception-handling")]
+                        ImportDesc::Exception(_) => self.insert(Feature::ExceptionHandling),
+                    }
+                }
+            }
+            Section::Table(tables) => {
+                for ty in tables.try_contents()? {
+                    self.table_type(ty);
+                }
+            }
+            Section::Memory(memories) => {
+                for ty in memories.try_contents()? {
+                    self.mem_type(ty);
+                }
+            }
+            #[cfg(feature = "exception-handling")]
+            Section::Exception(tags) => {
+                if !tags.try_contents()?.is_empty() {
+                    self.insert(Feature::ExceptionHandling);
+                }
+            }
+            Section::Global(globals) => {
+                for global in globals.try_contents()? {
+                    self.value_type(&global.ty.value_type);
+                    self.expression(&global.init);
+                }
+            }
+            Section::Element(elems) => {
+                for elem in elems.try_contents()? {
+                    self.element(elem);
+                }
+            }
+            Section::DataCount(_) => self.insert(Feature::BulkMemory),
+            Section::Code(code) => {
+                for body in code.try_contents()? {
+                    let body = body.try_contents()?;
+                    for locals in &body.locals {
+                        self.value_type(&locals.ty);
+                    }
+                    self.expression(&body.expr);
+                }
+            }
+            Section::Data(data) => {
+                for data in data.try_contents()? {
+                    match &data.init {
+                        DataInit::Passive => self.insert(Feature::BulkMemory),
+                        DataInit::Active { offset } | DataInit::ActiveWithMemory { offset, .. } => {
+                            self.expression(offset);
+                        }
+                    }
+                }
+            }
+            Section::Custom(_) | Section::Function(_) | Section::Export(_) | Section::Start(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Compute the set of features required by the instructions and types used in the module.
+///
+/// Features that only relax validation rules, such as mutable global imports or
+/// extended constant expressions, are not detected.
+///
+/// Table and memory indices are not inspected: referring to any but the first table or
+/// memory requires defining several of them, which is detected instead.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use wasmbin::features::{used_features, Feature};
+/// use wasmbin::Module;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let module = Module::decode_from(File::open("module.wasm")?)?;
+/// let supported = [Feature::BulkMemory, Feature::SignExt, Feature::MultiValue];
+/// for feature in used_features(&module)? {
+///     if !supported.contains(&feature) {
+///         eprintln!("Module uses unsupported feature {}", feature.name());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn used_features(module: &Module) -> Result<BTreeSet<Feature>, DecodeError> {
+    let mut detector = Detector::default();
+    for section in &module.sections {
+        detector.section(section)?;
+    }
+    if detector.tables > 1 {
+        detector.insert(Feature::ReferenceTypes);
+    }
+    if detector.memories > 1 {
+        detector.insert(Feature::MultiMemory);
+    }
+    Ok(detector.features)
+}
@@ -20,7 +20,9 @@ use crate::visit::Visit;
 
 macro_rules! newtype_id {
     ($name:ident) => {
-        #[derive(PartialEq, Eq, Clone, Copy, Wasmbin, WasmbinCountable, Hash, Visit)]
+        #[derive(
+            PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Wasmbin, WasmbinCountable, Hash, Visit,
+        )]
         #[repr(transparent)]
         pub struct $name {
             pub index: u32,
@@ -63,3 +65,21 @@ newtype_id!(TypeId);
 
 #[cfg(feature = "exception-handling")]
 newtype_id!(ExceptionId);
+
+#[cfg(feature = "component-model")]
+newtype_id!(ComponentId);
+#[cfg(feature = "component-model")]
+newtype_id!(ComponentFuncId);
+#[cfg(feature = "component-model")]
+newtype_id!(ComponentInstanceId);
+#[cfg(feature = "component-model")]
+newtype_id!(ComponentTypeId);
+#[cfg(feature = "component-model")]
+newtype_id!(CoreInstanceId);
+#[cfg(feature = "component-model")]
+newtype_id!(CoreModuleId);
+#[cfg(feature = "component-model")]
+newtype_id!(ValueId);
+
+#[cfg(feature = "component-model")]
+impl crate::builtins::WasmbinOptional for FuncId {}
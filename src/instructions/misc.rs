@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "fp16")]
+use super::MemArg;
 use crate::indices::{DataId, ElemId, MemId, TableId};
 use crate::io::Wasmbin;
 use crate::visit::Visit;
@@ -38,4 +40,16 @@ pub enum Misc {
     TableGrow(TableId) = 0x0F,
     TableSize(TableId) = 0x10,
     TableFill(TableId) = 0x11,
+    #[cfg(feature = "wide-arithmetic")]
+    I64Add128 = 0x13,
+    #[cfg(feature = "wide-arithmetic")]
+    I64Sub128 = 0x14,
+    #[cfg(feature = "wide-arithmetic")]
+    I64MulWideS = 0x15,
+    #[cfg(feature = "wide-arithmetic")]
+    I64MulWideU = 0x16,
+    #[cfg(feature = "fp16")]
+    F32LoadF16(MemArg) = 0x30,
+    #[cfg(feature = "fp16")]
+    F32StoreF16(MemArg) = 0x31,
 }
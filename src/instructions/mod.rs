@@ -25,6 +25,10 @@ const OP_CODE_BLOCK_START: u8 = 0x02;
 const OP_CODE_LOOP_START: u8 = 0x03;
 const OP_CODE_IF_START: u8 = 0x04;
 const OP_CODE_END: u8 = 0x0B;
+#[cfg(feature = "legacy-exceptions")]
+const OP_CODE_TRY_START: u8 = 0x06;
+#[cfg(feature = "legacy-exceptions")]
+const OP_CODE_TRY_DELEGATE: u8 = 0x18;
 
 #[derive(Debug, Error)]
 #[error("Mismatched block depth")]
@@ -70,9 +74,17 @@ impl Encode for [Instruction] {
                 | Instruction::IfStart(_) => {
                     depth_tracker.inc();
                 }
+                #[cfg(feature = "legacy-exceptions")]
+                Instruction::TryStart(_) => {
+                    depth_tracker.inc();
+                }
                 Instruction::End => {
                     depth_tracker.try_dec()?;
                 }
+                #[cfg(feature = "legacy-exceptions")]
+                Instruction::TryDelegate(_) => {
+                    depth_tracker.try_dec()?;
+                }
                 _ => {}
             }
             instr.encode(w)?;
@@ -92,10 +104,17 @@ impl Decode for Vec<Instruction> {
                 OP_CODE_BLOCK_START | OP_CODE_LOOP_START | OP_CODE_IF_START => {
                     depth_tracker.inc();
                 }
-                OP_CODE_END => {
-                    if depth_tracker.try_dec().is_err() {
-                        break;
-                    }
+                #[cfg(feature = "legacy-exceptions")]
+                OP_CODE_TRY_START => {
+                    depth_tracker.inc();
+                }
+                // The final `end` terminates the expression itself.
+                OP_CODE_END if depth_tracker.depth == 0 => break,
+                OP_CODE_END => depth_tracker.try_dec().map_err(std::io::Error::from)?,
+                #[cfg(feature = "legacy-exceptions")]
+                OP_CODE_TRY_DELEGATE => {
+                    // Unlike `end`, `delegate` can never terminate the expression itself.
+                    depth_tracker.try_dec().map_err(std::io::Error::from)?;
                 }
                 _ => {}
             }
@@ -163,11 +182,12 @@ pub struct CallIndirect {
 /// WebAssembly [instruction set](https://webassembly.github.io/spec/core/binary/instructions.html).
 ///
 /// In most cases, these will map 1:1 to the instructions in the spec, but an exception is made for
-/// structured control flow instructions (`block`, `loop` and `if`). Representing those as nested
-/// blocks would be ideal semantically, but is very expensive and tends to blow up the stack for even
-/// moderately-sized modules. Instead, we follow the other WebAssembly parsers and represent them as
-/// a start (`BlockStart`, `LoopStart` or `IfStart`) instruction followed by the contents of the block,
-/// and an `End` instruction - all in the same flat instruction list.
+/// structured control flow instructions (`block`, `loop`, `if` and legacy `try`). Representing those
+/// as nested blocks would be ideal semantically, but is very expensive and tends to blow up the stack
+/// for even moderately-sized modules. Instead, we follow the other WebAssembly parsers and represent
+/// them as a start (`BlockStart`, `LoopStart`, `IfStart` or `TryStart`) instruction followed by the
+/// contents of the block, and an `End` (or `TryDelegate`) instruction - all in the same flat
+/// instruction list.
 #[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
 #[repr(u8)]
 pub enum Instruction {
@@ -177,8 +197,17 @@ pub enum Instruction {
     LoopStart(BlockType) = OP_CODE_LOOP_START,
     IfStart(BlockType) = OP_CODE_IF_START,
     IfElse = 0x05,
+    /// Legacy `try` block start.
+    #[cfg(feature = "legacy-exceptions")]
+    TryStart(BlockType) = OP_CODE_TRY_START,
+    /// Legacy `catch` clause of the enclosing `try` block.
+    #[cfg(feature = "legacy-exceptions")]
+    TryCatch(crate::indices::ExceptionId) = 0x07,
     #[cfg(feature = "exception-handling")]
     Throw(crate::indices::ExceptionId) = 0x08,
+    /// Legacy `rethrow` of the exception caught by the referenced `try` block.
+    #[cfg(feature = "legacy-exceptions")]
+    Rethrow(LabelId) = 0x09,
     #[cfg(feature = "exception-handling")]
     ThrowRef = 0x0A,
     End = OP_CODE_END,
@@ -193,6 +222,12 @@ pub enum Instruction {
     CallIndirect(CallIndirect) = 0x11,
     ReturnCall(FuncId) = 0x12,
     ReturnCallIndirect(CallIndirect) = 0x13,
+    /// Legacy `delegate` that ends the enclosing `try` block.
+    #[cfg(feature = "legacy-exceptions")]
+    TryDelegate(LabelId) = OP_CODE_TRY_DELEGATE,
+    /// Legacy `catch_all` clause of the enclosing `try` block.
+    #[cfg(feature = "legacy-exceptions")]
+    TryCatchAll = 0x19,
     Drop = 0x1A,
     Select = 0x1B,
     SelectWithTypes(Vec<ValueType>) = 0x1C,
@@ -365,6 +400,24 @@ pub enum Instruction {
     RefNull(RefType) = 0xD0,
     RefIsNull = 0xD1,
     RefFunc(FuncId) = 0xD2,
+    #[cfg(feature = "stack-switching")]
+    ContNew(TypeId) = 0xE0,
+    #[cfg(feature = "stack-switching")]
+    ContBind {
+        from: TypeId,
+        to: TypeId,
+    } = 0xE1,
+    #[cfg(feature = "stack-switching")]
+    Suspend(crate::indices::ExceptionId) = 0xE2,
+    #[cfg(feature = "stack-switching")]
+    Resume(Resume) = 0xE3,
+    #[cfg(feature = "stack-switching")]
+    ResumeThrow(ResumeThrow) = 0xE4,
+    #[cfg(feature = "stack-switching")]
+    Switch {
+        cont_type: TypeId,
+        tag: crate::indices::ExceptionId,
+    } = 0xE5,
     Misc(Misc) = 0xFC,
     SIMD(SIMD) = 0xFD,
     #[cfg(feature = "threads")]
@@ -386,3 +439,8 @@ pub use threads::Atomic;
 pub mod exceptions;
 #[cfg(feature = "exception-handling")]
 pub use exceptions::{Catch, TryTable};
+
+#[cfg(feature = "stack-switching")]
+pub mod stack_switching;
+#[cfg(feature = "stack-switching")]
+pub use stack_switching::{Handler, Resume, ResumeThrow};
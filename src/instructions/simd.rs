@@ -332,4 +332,68 @@ pub enum SIMD {
     I16x8ExtaddPairwiseI8x16U = 0x7D,
     I32x4ExtaddPairwiseI16x8S = 0x7E,
     I32x4ExtaddPairwiseI16x8U = 0x7F,
+    #[cfg(feature = "fp16")]
+    F16x8Splat = 0x120,
+    #[cfg(feature = "fp16")]
+    F16x8ExtractLane(LaneId8) = 0x121,
+    #[cfg(feature = "fp16")]
+    F16x8ReplaceLane(LaneId8) = 0x122,
+    #[cfg(feature = "fp16")]
+    F16x8Abs = 0x130,
+    #[cfg(feature = "fp16")]
+    F16x8Neg = 0x131,
+    #[cfg(feature = "fp16")]
+    F16x8Sqrt = 0x132,
+    #[cfg(feature = "fp16")]
+    F16x8Ceil = 0x133,
+    #[cfg(feature = "fp16")]
+    F16x8Floor = 0x134,
+    #[cfg(feature = "fp16")]
+    F16x8Trunc = 0x135,
+    #[cfg(feature = "fp16")]
+    F16x8Nearest = 0x136,
+    #[cfg(feature = "fp16")]
+    F16x8Eq = 0x137,
+    #[cfg(feature = "fp16")]
+    F16x8Ne = 0x138,
+    #[cfg(feature = "fp16")]
+    F16x8Lt = 0x139,
+    #[cfg(feature = "fp16")]
+    F16x8Gt = 0x13A,
+    #[cfg(feature = "fp16")]
+    F16x8Le = 0x13B,
+    #[cfg(feature = "fp16")]
+    F16x8Ge = 0x13C,
+    #[cfg(feature = "fp16")]
+    F16x8Add = 0x13D,
+    #[cfg(feature = "fp16")]
+    F16x8Sub = 0x13E,
+    #[cfg(feature = "fp16")]
+    F16x8Mul = 0x13F,
+    #[cfg(feature = "fp16")]
+    F16x8Div = 0x140,
+    #[cfg(feature = "fp16")]
+    F16x8Min = 0x141,
+    #[cfg(feature = "fp16")]
+    F16x8Max = 0x142,
+    #[cfg(feature = "fp16")]
+    F16x8Pmin = 0x143,
+    #[cfg(feature = "fp16")]
+    F16x8Pmax = 0x144,
+    #[cfg(feature = "fp16")]
+    I16x8TruncSatF16x8S = 0x145,
+    #[cfg(feature = "fp16")]
+    I16x8TruncSatF16x8U = 0x146,
+    #[cfg(feature = "fp16")]
+    F16x8ConvertI16x8S = 0x147,
+    #[cfg(feature = "fp16")]
+    F16x8ConvertI16x8U = 0x148,
+    #[cfg(feature = "fp16")]
+    F16x8DemoteF32x4Zero = 0x149,
+    #[cfg(feature = "fp16")]
+    F32x4PromoteLowF16x8 = 0x14A,
+    #[cfg(feature = "fp16")]
+    F16x8RelaxedMadd = 0x14E,
+    #[cfg(feature = "fp16")]
+    F16x8RelaxedNmadd = 0x14F,
 }
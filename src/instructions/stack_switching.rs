@@ -0,0 +1,55 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::builtins::WasmbinCountable;
+use crate::indices::{ExceptionId, LabelId, TypeId};
+use crate::io::{encode_decode_as, Wasmbin};
+use crate::visit::Visit;
+
+#[derive(Wasmbin)]
+#[repr(u8)]
+enum HandlerRepr {
+    OnLabel { tag: ExceptionId, target: LabelId } = 0x00,
+    OnSwitch { tag: ExceptionId } = 0x01,
+}
+
+/// A [handler](https://github.com/WebAssembly/stack-switching/blob/main/proposals/stack-switching/Explainer.md#binary-format)
+/// of a `resume` or `resume_throw` instruction.
+#[derive(WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct Handler {
+    /// Control tag to handle.
+    pub tag: ExceptionId,
+    /// Target label to branch to on suspension or `None` to handle the tag via `switch`.
+    pub target: Option<LabelId>,
+}
+
+encode_decode_as!(Handler, {
+    (Handler { tag, target: Some(target) }) <=> (HandlerRepr::OnLabel { tag, target }),
+    (Handler { tag, target: None }) <=> (HandlerRepr::OnSwitch { tag }),
+});
+
+/// Immediates of the `resume` instruction.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct Resume {
+    pub cont_type: TypeId,
+    pub handlers: Vec<Handler>,
+}
+
+/// Immediates of the `resume_throw` instruction.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ResumeThrow {
+    pub cont_type: TypeId,
+    pub exception: ExceptionId,
+    pub handlers: Vec<Handler>,
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::MemArg;
+use crate::indices::{GlobalId, TableId};
 use crate::instructions::MemId;
 use crate::io::{Decode, DecodeError, Encode, Wasmbin};
 use crate::visit::Visit;
@@ -58,6 +59,20 @@ pub type MemArg16 = AlignedMemArg<1>;
 pub type MemArg32 = AlignedMemArg<2>;
 pub type MemArg64 = AlignedMemArg<3>;
 
+/// Reserved zero byte following [`Atomic::Fence`].
+#[derive(Wasmbin, Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[wasmbin(discriminant = 0x00)]
+pub struct FenceReserved;
+
+/// [Memory ordering](https://github.com/WebAssembly/shared-everything-threads/blob/main/proposals/shared-everything-threads/Overview.md#memory-model)
+/// of atomic global and table accesses.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum AtomicOrdering {
+    SeqCst = 0x00,
+    AcqRel = 0x01,
+}
+
 /// [Atomic memory instructions](https://webassembly.github.io/threads/core/binary/instructions.html#atomic-memory-instructions).
 #[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
 #[repr(u8)]
@@ -65,6 +80,7 @@ pub enum Atomic {
     Wake(MemArg32) = 0x00,
     I32Wait(MemArg32) = 0x01,
     I64Wait(MemArg64) = 0x02,
+    Fence(FenceReserved) = 0x03,
     I32Load(MemArg32) = 0x10,
     I64Load(MemArg64) = 0x11,
     I32Load8U(MemArg8) = 0x12,
@@ -128,4 +144,56 @@ pub enum Atomic {
     I64Rmw8CmpXchgU(MemArg8) = 0x4C,
     I64Rmw16CmpXchgU(MemArg16) = 0x4D,
     I64Rmw32CmpXchgU(MemArg32) = 0x4E,
+    GlobalGet {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x4F,
+    GlobalSet {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x50,
+    GlobalRmwAdd {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x51,
+    GlobalRmwSub {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x52,
+    GlobalRmwAnd {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x53,
+    GlobalRmwOr {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x54,
+    GlobalRmwXor {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x55,
+    GlobalRmwXchg {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x56,
+    GlobalRmwCmpXchg {
+        ordering: AtomicOrdering,
+        global: GlobalId,
+    } = 0x57,
+    TableGet {
+        ordering: AtomicOrdering,
+        table: TableId,
+    } = 0x58,
+    TableSet {
+        ordering: AtomicOrdering,
+        table: TableId,
+    } = 0x59,
+    TableRmwXchg {
+        ordering: AtomicOrdering,
+        table: TableId,
+    } = 0x5A,
+    TableRmwCmpXchg {
+        ordering: AtomicOrdering,
+        table: TableId,
+    } = 0x5B,
 }
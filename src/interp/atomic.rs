@@ -0,0 +1,332 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Narrow accesses zero-extend and truncate their operands, which is what `as` casts do.
+#![allow(clippy::cast_possible_truncation)]
+
+use super::exec::Machine;
+use super::numeric::{pop, pop_value};
+use super::{Host, Trap, Value};
+use crate::instructions::threads::AlignedMemArg;
+use crate::instructions::{Atomic, Instruction, MemArg};
+
+/// Convert an integer operand into its unsigned bits.
+fn to_bits(value: &Value) -> Result<u64, Trap> {
+    match *value {
+        Value::I32(value) => Ok(value.cast_unsigned().into()),
+        Value::I64(value) => Ok(value.cast_unsigned()),
+        _ => Err(Trap::Invalid("operand type mismatch")),
+    }
+}
+
+/// Convert bits back into an integer of the given width.
+fn from_bits(bits: u64, wide: bool) -> Value {
+    if wide {
+        Value::I64(bits.cast_signed())
+    } else {
+        Value::I32((bits as u32).cast_signed())
+    }
+}
+
+impl<H: Host> Machine<'_, H> {
+    /// Pop the address operand and check that `SIZE` bytes at the effective address are in
+    /// bounds and naturally aligned.
+    fn atomic_address<const ALIGN_LOG2: u32>(
+        &mut self,
+        arg: &AlignedMemArg<ALIGN_LOG2>,
+    ) -> Result<(MemArg, u64), Trap> {
+        let arg = MemArg::from(arg.clone());
+        let addr = u64::from(pop::<i32>(&mut self.stack)?.cast_unsigned()) + u64::from(arg.offset);
+        let size = 1 << ALIGN_LOG2;
+        self.memory(arg.memory)?.read(addr, size)?;
+        if addr % size as u64 != 0 {
+            return Err(Trap::UnalignedAtomic);
+        }
+        Ok((arg, addr))
+    }
+
+    fn atomic_read(&mut self, arg: &MemArg, addr: u64, size: usize) -> Result<u64, Trap> {
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(self.memory(arg.memory)?.read(addr, size)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn atomic_write(
+        &mut self,
+        arg: &MemArg,
+        addr: u64,
+        size: usize,
+        bits: u64,
+    ) -> Result<(), Trap> {
+        self.memory(arg.memory)?
+            .write(addr, &bits.to_le_bytes()[..size])
+    }
+
+    fn atomic_load<const ALIGN_LOG2: u32>(
+        &mut self,
+        arg: &AlignedMemArg<ALIGN_LOG2>,
+        wide: bool,
+    ) -> Result<(), Trap> {
+        let (arg, addr) = self.atomic_address(arg)?;
+        let bits = self.atomic_read(&arg, addr, 1 << ALIGN_LOG2)?;
+        self.stack.push(from_bits(bits, wide));
+        Ok(())
+    }
+
+    fn atomic_store<const ALIGN_LOG2: u32>(
+        &mut self,
+        arg: &AlignedMemArg<ALIGN_LOG2>,
+    ) -> Result<(), Trap> {
+        let bits = to_bits(&pop_value(&mut self.stack)?)?;
+        let (arg, addr) = self.atomic_address(arg)?;
+        self.atomic_write(&arg, addr, 1 << ALIGN_LOG2, bits)
+    }
+
+    /// Read-modify-write returning the old value.
+    fn atomic_rmw<const ALIGN_LOG2: u32>(
+        &mut self,
+        arg: &AlignedMemArg<ALIGN_LOG2>,
+        wide: bool,
+        f: impl FnOnce(u64, u64) -> u64,
+    ) -> Result<(), Trap> {
+        let operand = to_bits(&pop_value(&mut self.stack)?)?;
+        let (arg, addr) = self.atomic_address(arg)?;
+        let size = 1 << ALIGN_LOG2;
+        let old = self.atomic_read(&arg, addr, size)?;
+        self.atomic_write(&arg, addr, size, f(old, operand))?;
+        self.stack.push(from_bits(old, wide));
+        Ok(())
+    }
+
+    fn atomic_cmpxchg<const ALIGN_LOG2: u32>(
+        &mut self,
+        arg: &AlignedMemArg<ALIGN_LOG2>,
+        wide: bool,
+    ) -> Result<(), Trap> {
+        let replacement = to_bits(&pop_value(&mut self.stack)?)?;
+        let expected = to_bits(&pop_value(&mut self.stack)?)?;
+        let (arg, addr) = self.atomic_address(arg)?;
+        let size = 1 << ALIGN_LOG2;
+        let old = self.atomic_read(&arg, addr, size)?;
+        // Compare only the bits that fit into the accessed memory.
+        if old == expected & (u64::MAX >> (64 - 8 * size)) {
+            self.atomic_write(&arg, addr, size, replacement)?;
+        }
+        self.stack.push(from_bits(old, wide));
+        Ok(())
+    }
+
+    /// Wait for a notification. As no other thread can notify a single-threaded interpreter,
+    /// waits either return immediately or time out.
+    fn atomic_wait<const ALIGN_LOG2: u32>(
+        &mut self,
+        arg: &AlignedMemArg<ALIGN_LOG2>,
+        op: &Atomic,
+    ) -> Result<(), Trap> {
+        let timeout = pop::<i64>(&mut self.stack)?;
+        let expected = to_bits(&pop_value(&mut self.stack)?)?;
+        let (arg, addr) = self.atomic_address(arg)?;
+        let loaded = self.atomic_read(&arg, addr, 1 << ALIGN_LOG2)?;
+        let result = match (loaded == expected, timeout >= 0) {
+            // "not-equal"
+            (false, _) => 1,
+            // "timed-out"
+            (true, true) => 2,
+            (true, false) => {
+                return Err(Trap::Unsupported(Box::new(Instruction::Atomic(op.clone()))))
+            }
+        };
+        self.stack.push(Value::I32(result));
+        Ok(())
+    }
+
+    pub(super) fn atomic(&mut self, op: &Atomic) -> Result<(), Trap> {
+        match op {
+            Atomic::Wake(arg) => {
+                pop::<i32>(&mut self.stack)?;
+                self.atomic_address(arg)?;
+                // There are no waiters to wake up.
+                self.stack.push(Value::I32(0));
+                Ok(())
+            }
+            Atomic::I32Wait(arg) => self.atomic_wait(arg, op),
+            Atomic::I64Wait(arg) => self.atomic_wait(arg, op),
+            Atomic::Fence(_) => Ok(()),
+            Atomic::I32Load(arg) => self.atomic_load(arg, false),
+            Atomic::I64Load(arg) => self.atomic_load(arg, true),
+            Atomic::I32Load8U(arg) => self.atomic_load(arg, false),
+            Atomic::I32Load16U(arg) => self.atomic_load(arg, false),
+            Atomic::I64Load8U(arg) => self.atomic_load(arg, true),
+            Atomic::I64Load16U(arg) => self.atomic_load(arg, true),
+            Atomic::I64Load32U(arg) => self.atomic_load(arg, true),
+            Atomic::I32Store(arg) | Atomic::I64Store32(arg) => self.atomic_store(arg),
+            Atomic::I64Store(arg) => self.atomic_store(arg),
+            Atomic::I32Store8(arg) | Atomic::I64Store8(arg) => self.atomic_store(arg),
+            Atomic::I32Store16(arg) | Atomic::I64Store16(arg) => self.atomic_store(arg),
+            Atomic::I32RmwCmpXchg(arg) => self.atomic_cmpxchg(arg, false),
+            Atomic::I64RmwCmpXchg(arg) => self.atomic_cmpxchg(arg, true),
+            Atomic::I32Rmw8CmpXchgU(arg) => self.atomic_cmpxchg(arg, false),
+            Atomic::I32Rmw16CmpXchgU(arg) => self.atomic_cmpxchg(arg, false),
+            Atomic::I64Rmw8CmpXchgU(arg) => self.atomic_cmpxchg(arg, true),
+            Atomic::I64Rmw16CmpXchgU(arg) => self.atomic_cmpxchg(arg, true),
+            Atomic::I64Rmw32CmpXchgU(arg) => self.atomic_cmpxchg(arg, true),
+            _ => {
+                if !self.atomic_rmw_op(op)? {
+                    self.atomic_global_or_table_op(op)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn atomic_rmw_op(&mut self, op: &Atomic) -> Result<bool, Trap> {
+        let add = u64::wrapping_add;
+        let sub = u64::wrapping_sub;
+        let and = |a, b| a & b;
+        let or = |a, b| a | b;
+        let xor = |a, b| a ^ b;
+        let xchg = |_, b| b;
+        match op {
+            Atomic::I32RmwAdd(arg) => self.atomic_rmw(arg, false, add),
+            Atomic::I64RmwAdd(arg) => self.atomic_rmw(arg, true, add),
+            Atomic::I32Rmw8AddU(arg) => self.atomic_rmw(arg, false, add),
+            Atomic::I32Rmw16AddU(arg) => self.atomic_rmw(arg, false, add),
+            Atomic::I64Rmw8AddU(arg) => self.atomic_rmw(arg, true, add),
+            Atomic::I64Rmw16AddU(arg) => self.atomic_rmw(arg, true, add),
+            Atomic::I64Rmw32AddU(arg) => self.atomic_rmw(arg, true, add),
+            Atomic::I32RmwSub(arg) => self.atomic_rmw(arg, false, sub),
+            Atomic::I64RmwSub(arg) => self.atomic_rmw(arg, true, sub),
+            Atomic::I32Rmw8SubU(arg) => self.atomic_rmw(arg, false, sub),
+            Atomic::I32Rmw16SubU(arg) => self.atomic_rmw(arg, false, sub),
+            Atomic::I64Rmw8SubU(arg) => self.atomic_rmw(arg, true, sub),
+            Atomic::I64Rmw16SubU(arg) => self.atomic_rmw(arg, true, sub),
+            Atomic::I64Rmw32SubU(arg) => self.atomic_rmw(arg, true, sub),
+            Atomic::I32RmwAnd(arg) => self.atomic_rmw(arg, false, and),
+            Atomic::I64RmwAnd(arg) => self.atomic_rmw(arg, true, and),
+            Atomic::I32Rmw8AndU(arg) => self.atomic_rmw(arg, false, and),
+            Atomic::I32Rmw16AndU(arg) => self.atomic_rmw(arg, false, and),
+            Atomic::I64Rmw8AndU(arg) => self.atomic_rmw(arg, true, and),
+            Atomic::I64Rmw16AndU(arg) => self.atomic_rmw(arg, true, and),
+            Atomic::I64Rmw32AndU(arg) => self.atomic_rmw(arg, true, and),
+            Atomic::I32RmwOr(arg) => self.atomic_rmw(arg, false, or),
+            Atomic::I64RmwOr(arg) => self.atomic_rmw(arg, true, or),
+            Atomic::I32Rmw8OrU(arg) => self.atomic_rmw(arg, false, or),
+            Atomic::I32Rmw16OrU(arg) => self.atomic_rmw(arg, false, or),
+            Atomic::I64Rmw8OrU(arg) => self.atomic_rmw(arg, true, or),
+            Atomic::I64Rmw16OrU(arg) => self.atomic_rmw(arg, true, or),
+            Atomic::I64Rmw32OrU(arg) => self.atomic_rmw(arg, true, or),
+            Atomic::I32RmwXor(arg) => self.atomic_rmw(arg, false, xor),
+            Atomic::I64RmwXor(arg) => self.atomic_rmw(arg, true, xor),
+            Atomic::I32Rmw8XorU(arg) => self.atomic_rmw(arg, false, xor),
+            Atomic::I32Rmw16XorU(arg) => self.atomic_rmw(arg, false, xor),
+            Atomic::I64Rmw8XorU(arg) => self.atomic_rmw(arg, true, xor),
+            Atomic::I64Rmw16XorU(arg) => self.atomic_rmw(arg, true, xor),
+            Atomic::I64Rmw32XorU(arg) => self.atomic_rmw(arg, true, xor),
+            Atomic::I32RmwXchg(arg) => self.atomic_rmw(arg, false, xchg),
+            Atomic::I64RmwXchg(arg) => self.atomic_rmw(arg, true, xchg),
+            Atomic::I32Rmw8XchgU(arg) => self.atomic_rmw(arg, false, xchg),
+            Atomic::I32Rmw16XchgU(arg) => self.atomic_rmw(arg, false, xchg),
+            Atomic::I64Rmw8XchgU(arg) => self.atomic_rmw(arg, true, xchg),
+            Atomic::I64Rmw16XchgU(arg) => self.atomic_rmw(arg, true, xchg),
+            Atomic::I64Rmw32XchgU(arg) => self.atomic_rmw(arg, true, xchg),
+            _ => return Ok(false),
+        }?;
+        Ok(true)
+    }
+
+    /// Read-modify-write of an integer global returning the old value.
+    fn global_rmw(&mut self, global: u32, f: impl FnOnce(u64, u64) -> u64) -> Result<(), Trap> {
+        let operand = to_bits(&pop_value(&mut self.stack)?)?;
+        let slot = self.global(global)?;
+        let old = slot.clone();
+        *slot = from_bits(f(to_bits(&old)?, operand), matches!(old, Value::I64(_)));
+        self.stack.push(old);
+        Ok(())
+    }
+
+    fn atomic_global_or_table_op(&mut self, op: &Atomic) -> Result<(), Trap> {
+        match op {
+            Atomic::GlobalGet { global, .. } => {
+                let value = self.global(global.index)?.clone();
+                self.stack.push(value);
+            }
+            Atomic::GlobalSet { global, .. } => {
+                let value = pop_value(&mut self.stack)?;
+                *self.global(global.index)? = value;
+            }
+            Atomic::GlobalRmwAdd { global, .. } => {
+                self.global_rmw(global.index, u64::wrapping_add)?;
+            }
+            Atomic::GlobalRmwSub { global, .. } => {
+                self.global_rmw(global.index, u64::wrapping_sub)?;
+            }
+            Atomic::GlobalRmwAnd { global, .. } => self.global_rmw(global.index, |a, b| a & b)?,
+            Atomic::GlobalRmwOr { global, .. } => self.global_rmw(global.index, |a, b| a | b)?,
+            Atomic::GlobalRmwXor { global, .. } => self.global_rmw(global.index, |a, b| a ^ b)?,
+            Atomic::GlobalRmwXchg { global, .. } => {
+                let value = pop_value(&mut self.stack)?;
+                let old = std::mem::replace(self.global(global.index)?, value);
+                self.stack.push(old);
+            }
+            Atomic::GlobalRmwCmpXchg { global, .. } => {
+                let replacement = pop_value(&mut self.stack)?;
+                let expected = pop_value(&mut self.stack)?;
+                let slot = self.global(global.index)?;
+                let old = slot.clone();
+                if old == expected {
+                    *slot = replacement;
+                }
+                self.stack.push(old);
+            }
+            Atomic::TableGet { table, .. } => {
+                let index = pop::<i32>(&mut self.stack)?.cast_unsigned();
+                let value = self.table(*table)?.read(index.into(), 1)?[0].clone();
+                self.stack.push(value);
+            }
+            Atomic::TableSet { table, .. } => {
+                let value = pop_value(&mut self.stack)?;
+                let index = pop::<i32>(&mut self.stack)?.cast_unsigned();
+                self.table(*table)?.write(index.into(), &[value])?;
+            }
+            Atomic::TableRmwXchg { table, .. } => {
+                let value = pop_value(&mut self.stack)?;
+                let index = pop::<i32>(&mut self.stack)?.cast_unsigned();
+                let slot = self
+                    .table(*table)?
+                    .elements
+                    .get_mut(index as usize)
+                    .ok_or(Trap::TableOutOfBounds)?;
+                let old = std::mem::replace(slot, value);
+                self.stack.push(old);
+            }
+            Atomic::TableRmwCmpXchg { table, .. } => {
+                let replacement = pop_value(&mut self.stack)?;
+                let expected = pop_value(&mut self.stack)?;
+                let index = pop::<i32>(&mut self.stack)?.cast_unsigned();
+                let slot = self
+                    .table(*table)?
+                    .elements
+                    .get_mut(index as usize)
+                    .ok_or(Trap::TableOutOfBounds)?;
+                let old = slot.clone();
+                if old == expected {
+                    *slot = replacement;
+                }
+                self.stack.push(old);
+            }
+            _ => return Err(Trap::Unsupported(Box::new(Instruction::Atomic(op.clone())))),
+        }
+        Ok(())
+    }
+}
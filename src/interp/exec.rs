@@ -0,0 +1,660 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::numeric::{self, pop, pop_value, Operand};
+use super::store::Memory;
+use super::{simd, Body, Code, Func, Host, Store, Table, Trap, Value};
+use crate::indices::{FuncId, LocalId, MemId, TableId};
+use crate::instructions::{CallIndirect, Instruction, MemArg, Misc};
+use crate::types::{BlockType, ValueType};
+
+/// Target of a branch.
+#[derive(Clone, Copy)]
+struct Label {
+    /// Instruction to continue with.
+    target: usize,
+    /// Number of values passed to the target.
+    arity: usize,
+    /// Operand stack height at the block entry, excluding block parameters.
+    height: usize,
+    is_loop: bool,
+}
+
+struct Frame<'a> {
+    body: &'a Body,
+    pc: usize,
+    locals: Vec<Value>,
+    labels: Vec<Label>,
+    /// Operand stack height at the function entry.
+    base: usize,
+    /// Number of results.
+    arity: usize,
+}
+
+pub(super) struct Machine<'a, H> {
+    code: &'a Code,
+    store: &'a mut Store,
+    host: &'a mut H,
+    fuel: &'a mut Option<u64>,
+    max_call_depth: usize,
+    /// Operand stack shared by all frames.
+    pub(super) stack: Vec<Value>,
+    frames: Vec<Frame<'a>>,
+}
+
+fn zero(ty: &ValueType) -> Value {
+    match ty {
+        ValueType::I32 => Value::I32(0),
+        ValueType::I64 => Value::I64(0),
+        ValueType::F32 => Value::F32(0.0),
+        ValueType::F64 => Value::F64(0.0),
+        ValueType::V128 => Value::V128([0; 16]),
+        ValueType::Ref(ty) => Value::Null(ty.clone()),
+    }
+}
+
+/// Offset of a memory access, which is computed with infinite precision.
+fn effective_address(addr: i32, arg: &MemArg) -> u64 {
+    u64::from(addr.cast_unsigned()) + u64::from(arg.offset)
+}
+
+impl<'a, H: Host> Machine<'a, H> {
+    pub(super) fn new(
+        code: &'a Code,
+        store: &'a mut Store,
+        host: &'a mut H,
+        fuel: &'a mut Option<u64>,
+        max_call_depth: usize,
+    ) -> Self {
+        Self {
+            code,
+            store,
+            host,
+            fuel,
+            max_call_depth,
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Call a function with the given arguments and run it to completion.
+    pub(super) fn run(mut self, func: FuncId, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        self.stack.extend_from_slice(args);
+        self.call(func)?;
+        while let Some(frame) = self.frames.last_mut() {
+            let body = frame.body;
+            let Some(instr) = body.expr.get(frame.pc) else {
+                self.return_()?;
+                continue;
+            };
+            frame.pc += 1;
+            if let Some(fuel) = self.fuel.as_mut() {
+                *fuel = fuel.checked_sub(1).ok_or(Trap::OutOfFuel)?;
+            }
+            self.step(instr)?;
+        }
+        Ok(self.stack)
+    }
+
+    fn frame(&mut self) -> Result<&mut Frame<'a>, Trap> {
+        self.frames
+            .last_mut()
+            .ok_or(Trap::Invalid("no active frame"))
+    }
+
+    fn pop<T: Operand>(&mut self) -> Result<T, Trap> {
+        pop(&mut self.stack)
+    }
+
+    fn push(&mut self, value: impl Into<Value>) {
+        self.stack.push(value.into());
+    }
+
+    fn pop_n(&mut self, n: usize) -> Result<Vec<Value>, Trap> {
+        let start = self
+            .stack
+            .len()
+            .checked_sub(n)
+            .ok_or(Trap::Invalid("operand stack underflow"))?;
+        Ok(self.stack.split_off(start))
+    }
+
+    fn step(&mut self, instr: &'a Instruction) -> Result<(), Trap> {
+        match instr {
+            Instruction::Unreachable => return Err(Trap::Unreachable),
+            Instruction::Nop => {}
+            Instruction::BlockStart(ty) => self.enter_block(ty, false)?,
+            Instruction::LoopStart(ty) => self.enter_block(ty, true)?,
+            Instruction::IfStart(ty) => self.enter_if(ty)?,
+            Instruction::IfElse => {
+                // End of the `then` branch; skip to the `end` that closes the block.
+                let frame = self.frame()?;
+                frame.pc = frame.body.ends[frame.pc - 1];
+            }
+            Instruction::End => {
+                self.frame()?.labels.pop();
+            }
+            Instruction::Br(label) => self.branch(label.index)?,
+            Instruction::BrIf(label) => {
+                if self.pop::<i32>()? != 0 {
+                    self.branch(label.index)?;
+                }
+            }
+            Instruction::BrTable {
+                branches,
+                otherwise,
+            } => {
+                let index = self.pop::<i32>()?.cast_unsigned() as usize;
+                self.branch(branches.get(index).unwrap_or(otherwise).index)?;
+            }
+            Instruction::Return => self.return_()?,
+            Instruction::Call(func) => self.call(*func)?,
+            Instruction::CallIndirect(call) => {
+                let func = self.indirect_target(call)?;
+                self.call(func)?;
+            }
+            Instruction::ReturnCall(func) => self.tail_call(*func)?,
+            Instruction::ReturnCallIndirect(call) => {
+                let func = self.indirect_target(call)?;
+                self.tail_call(func)?;
+            }
+            Instruction::Drop => {
+                pop_value(&mut self.stack)?;
+            }
+            Instruction::Select | Instruction::SelectWithTypes(_) => {
+                let cond = self.pop::<i32>()?;
+                let b = pop_value(&mut self.stack)?;
+                let a = pop_value(&mut self.stack)?;
+                self.push(if cond != 0 { a } else { b });
+            }
+            Instruction::LocalGet(local) => {
+                let value = self.local(*local)?.clone();
+                self.push(value);
+            }
+            Instruction::LocalSet(local) => {
+                let value = pop_value(&mut self.stack)?;
+                *self.local(*local)? = value;
+            }
+            Instruction::LocalTee(local) => {
+                let value = self
+                    .stack
+                    .last()
+                    .cloned()
+                    .ok_or(Trap::Invalid("operand stack underflow"))?;
+                *self.local(*local)? = value;
+            }
+            Instruction::GlobalGet(global) => {
+                let value = self.global(global.index)?.clone();
+                self.push(value);
+            }
+            Instruction::GlobalSet(global) => {
+                let value = pop_value(&mut self.stack)?;
+                *self.global(global.index)? = value;
+            }
+            Instruction::I32Const(value) => self.push(*value),
+            Instruction::I64Const(value) => self.push(*value),
+            Instruction::F32Const(value) => self.push(value.value),
+            Instruction::F64Const(value) => self.push(value.value),
+            Instruction::RefNull(ty) => self.push(Value::Null(ty.clone())),
+            Instruction::RefIsNull => {
+                let value = pop_value(&mut self.stack)?;
+                self.push(i32::from(matches!(value, Value::Null(_))));
+            }
+            Instruction::RefFunc(func) => self.push(Value::Func(*func)),
+            Instruction::SIMD(op) => simd::eval(self, op)?,
+            #[cfg(feature = "threads")]
+            Instruction::Atomic(op) => self.atomic(op)?,
+            _ => {
+                if !(self.memory_op(instr)?
+                    || self.table_op(instr)?
+                    || numeric::eval(instr, &mut self.stack)?)
+                {
+                    return Err(Trap::Unsupported(Box::new(instr.clone())));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn local(&mut self, local: LocalId) -> Result<&mut Value, Trap> {
+        self.frame()?
+            .locals
+            .get_mut(local.index as usize)
+            .ok_or(Trap::Invalid("unknown local"))
+    }
+
+    pub(super) fn global(&mut self, index: u32) -> Result<&mut Value, Trap> {
+        self.store
+            .globals
+            .get_mut(index as usize)
+            .ok_or(Trap::Invalid("unknown global"))
+    }
+
+    pub(super) fn memory(&mut self, memory: MemId) -> Result<&mut Memory, Trap> {
+        self.store
+            .memories
+            .get_mut(memory.index as usize)
+            .ok_or(Trap::Invalid("unknown memory"))
+    }
+
+    pub(super) fn table(&mut self, table: TableId) -> Result<&mut Table, Trap> {
+        self.store
+            .tables
+            .get_mut(table.index as usize)
+            .ok_or(Trap::Invalid("unknown table"))
+    }
+
+    /// Number of parameters and results of a block.
+    fn block_arity(&self, ty: &BlockType) -> Result<(usize, usize), Trap> {
+        Ok(match ty {
+            BlockType::Empty => (0, 0),
+            BlockType::Value(_) => (0, 1),
+            BlockType::MultiValue(ty) => {
+                let ty = self.code.func_type(*ty)?;
+                (ty.params.len(), ty.results.len())
+            }
+        })
+    }
+
+    fn enter_block(&mut self, ty: &BlockType, is_loop: bool) -> Result<(), Trap> {
+        let (params, results) = self.block_arity(ty)?;
+        let height = self
+            .stack
+            .len()
+            .checked_sub(params)
+            .ok_or(Trap::Invalid("operand stack underflow"))?;
+        let frame = self.frame()?;
+        let start = frame.pc - 1;
+        frame.labels.push(Label {
+            target: if is_loop {
+                start
+            } else {
+                frame.body.ends[start]
+            },
+            arity: if is_loop { params } else { results },
+            height,
+            is_loop,
+        });
+        Ok(())
+    }
+
+    fn enter_if(&mut self, ty: &BlockType) -> Result<(), Trap> {
+        let cond = self.pop::<i32>()?;
+        if cond != 0 {
+            return self.enter_block(ty, false);
+        }
+        let frame = self.frame()?;
+        let start = frame.pc - 1;
+        if let Some(&else_) = frame.body.elses.get(&start) {
+            self.enter_block(ty, false)?;
+            self.frame()?.pc = else_ + 1;
+        } else {
+            // Without an `else` the block must leave its parameters intact, so there is
+            // nothing to execute.
+            frame.pc = frame.body.ends[start] + 1;
+        }
+        Ok(())
+    }
+
+    fn branch(&mut self, depth: u32) -> Result<(), Trap> {
+        let frame = self
+            .frames
+            .last_mut()
+            .ok_or(Trap::Invalid("no active frame"))?;
+        let depth = depth as usize;
+        let Some(index) = frame.labels.len().checked_sub(depth + 1) else {
+            // The outermost label is the function body itself.
+            return if depth == frame.labels.len() {
+                self.return_()
+            } else {
+                Err(Trap::Invalid("unknown label"))
+            };
+        };
+        let label = frame.labels[index];
+        let results_start = self
+            .stack
+            .len()
+            .checked_sub(label.arity)
+            .filter(|&start| start >= label.height)
+            .ok_or(Trap::Invalid("operand stack underflow"))?;
+        self.stack.drain(label.height..results_start);
+        frame
+            .labels
+            .truncate(if label.is_loop { index + 1 } else { index });
+        // Loops continue after the `loop` instruction and blocks after their `end`.
+        frame.pc = label.target + 1;
+        Ok(())
+    }
+
+    fn return_(&mut self) -> Result<(), Trap> {
+        let frame = self.frames.pop().ok_or(Trap::Invalid("no active frame"))?;
+        let results_start = self
+            .stack
+            .len()
+            .checked_sub(frame.arity)
+            .filter(|&start| start >= frame.base)
+            .ok_or(Trap::Invalid("operand stack underflow"))?;
+        self.stack.drain(frame.base..results_start);
+        Ok(())
+    }
+
+    fn call(&mut self, func: FuncId) -> Result<(), Trap> {
+        let code = self.code;
+        match code.func(func)? {
+            Func::Imported { path, ty } => {
+                let ty = code.func_type(*ty)?;
+                let args = self.pop_n(ty.params.len())?;
+                let results = self.host.call(path, ty, &args, self.store)?;
+                if results.len() != ty.results.len() {
+                    return Err(Trap::Invalid("host returned wrong number of results"));
+                }
+                self.stack.extend(results);
+            }
+            Func::Defined { ty, body } => {
+                if self.frames.len() >= self.max_call_depth {
+                    return Err(Trap::StackOverflow);
+                }
+                let ty = code.func_type(*ty)?;
+                let mut locals = self.pop_n(ty.params.len())?;
+                locals.extend(body.locals.iter().map(zero));
+                self.frames.push(Frame {
+                    body,
+                    pc: 0,
+                    locals,
+                    labels: Vec::new(),
+                    base: self.stack.len(),
+                    arity: ty.results.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn tail_call(&mut self, func: FuncId) -> Result<(), Trap> {
+        let params = self
+            .code
+            .func_type(self.code.func(func)?.ty())?
+            .params
+            .len();
+        let args = self.pop_n(params)?;
+        let frame = self.frames.pop().ok_or(Trap::Invalid("no active frame"))?;
+        self.stack.truncate(frame.base);
+        self.stack.extend(args);
+        self.call(func)
+    }
+
+    fn indirect_target(&mut self, call: &CallIndirect) -> Result<FuncId, Trap> {
+        let index = self.pop::<i32>()?.cast_unsigned();
+        let func = match self
+            .table(call.table)?
+            .elements
+            .get(index as usize)
+            .ok_or(Trap::UndefinedElement)?
+        {
+            Value::Func(func) => *func,
+            Value::Null(_) => return Err(Trap::UninitializedElement),
+            _ => return Err(Trap::Invalid("non-function reference in call_indirect")),
+        };
+        if self.code.func_type(call.ty)? != self.code.func_type(self.code.func(func)?.ty())? {
+            return Err(Trap::IndirectCallTypeMismatch);
+        }
+        Ok(func)
+    }
+
+    /// Pop the address operand and load `N` bytes from memory.
+    pub(super) fn load<const N: usize>(&mut self, arg: &MemArg) -> Result<[u8; N], Trap> {
+        let addr = effective_address(self.pop()?, arg);
+        let bytes = self.memory(arg.memory)?.read(addr, N)?;
+        Ok(bytes.try_into().expect("read returns the requested length"))
+    }
+
+    /// Pop the address operand and store bytes into memory.
+    pub(super) fn store(&mut self, arg: &MemArg, bytes: &[u8]) -> Result<(), Trap> {
+        let addr = effective_address(self.pop()?, arg);
+        self.memory(arg.memory)?.write(addr, bytes)
+    }
+
+    fn memory_op(&mut self, instr: &Instruction) -> Result<bool, Trap> {
+        match instr {
+            Instruction::I32Load(arg) => {
+                let value = i32::from_le_bytes(self.load(arg)?);
+                self.push(value);
+            }
+            Instruction::I64Load(arg) => {
+                let value = i64::from_le_bytes(self.load(arg)?);
+                self.push(value);
+            }
+            Instruction::F32Load(arg) => {
+                let value = f32::from_le_bytes(self.load(arg)?);
+                self.push(value);
+            }
+            Instruction::F64Load(arg) => {
+                let value = f64::from_le_bytes(self.load(arg)?);
+                self.push(value);
+            }
+            Instruction::I32Load8S(arg) => {
+                let value = i8::from_le_bytes(self.load(arg)?);
+                self.push(i32::from(value));
+            }
+            Instruction::I32Load8U(arg) => {
+                let value = u8::from_le_bytes(self.load(arg)?);
+                self.push(i32::from(value));
+            }
+            Instruction::I32Load16S(arg) => {
+                let value = i16::from_le_bytes(self.load(arg)?);
+                self.push(i32::from(value));
+            }
+            Instruction::I32Load16U(arg) => {
+                let value = u16::from_le_bytes(self.load(arg)?);
+                self.push(i32::from(value));
+            }
+            Instruction::I64Load8S(arg) => {
+                let value = i8::from_le_bytes(self.load(arg)?);
+                self.push(i64::from(value));
+            }
+            Instruction::I64Load8U(arg) => {
+                let value = u8::from_le_bytes(self.load(arg)?);
+                self.push(i64::from(value));
+            }
+            Instruction::I64Load16S(arg) => {
+                let value = i16::from_le_bytes(self.load(arg)?);
+                self.push(i64::from(value));
+            }
+            Instruction::I64Load16U(arg) => {
+                let value = u16::from_le_bytes(self.load(arg)?);
+                self.push(i64::from(value));
+            }
+            Instruction::I64Load32S(arg) => {
+                let value = i32::from_le_bytes(self.load(arg)?);
+                self.push(i64::from(value));
+            }
+            Instruction::I64Load32U(arg) => {
+                let value = u32::from_le_bytes(self.load(arg)?);
+                self.push(i64::from(value));
+            }
+            _ => return self.store_op(instr),
+        }
+        Ok(true)
+    }
+
+    fn store_op(&mut self, instr: &Instruction) -> Result<bool, Trap> {
+        match instr {
+            Instruction::I32Store(arg) => {
+                let value = self.pop::<i32>()?;
+                self.store(arg, &value.to_le_bytes())?;
+            }
+            Instruction::I64Store(arg) => {
+                let value = self.pop::<i64>()?;
+                self.store(arg, &value.to_le_bytes())?;
+            }
+            Instruction::F32Store(arg) => {
+                let value = self.pop::<f32>()?;
+                self.store(arg, &value.to_le_bytes())?;
+            }
+            Instruction::F64Store(arg) => {
+                let value = self.pop::<f64>()?;
+                self.store(arg, &value.to_le_bytes())?;
+            }
+            Instruction::I32Store8(arg) => {
+                let value = self.pop::<i32>()?;
+                self.store(arg, &value.to_le_bytes()[..1])?;
+            }
+            Instruction::I32Store16(arg) => {
+                let value = self.pop::<i32>()?;
+                self.store(arg, &value.to_le_bytes()[..2])?;
+            }
+            Instruction::I64Store8(arg) => {
+                let value = self.pop::<i64>()?;
+                self.store(arg, &value.to_le_bytes()[..1])?;
+            }
+            Instruction::I64Store16(arg) => {
+                let value = self.pop::<i64>()?;
+                self.store(arg, &value.to_le_bytes()[..2])?;
+            }
+            Instruction::I64Store32(arg) => {
+                let value = self.pop::<i64>()?;
+                self.store(arg, &value.to_le_bytes()[..4])?;
+            }
+            Instruction::MemorySize(memory) => {
+                let pages = self.memory(*memory)?.pages();
+                // Sizes are limited to 32 bits, so the cast only reinterprets the sign.
+                self.push(u32::try_from(pages).unwrap_or(u32::MAX).cast_signed());
+            }
+            Instruction::MemoryGrow(memory) => {
+                let delta = self.pop::<i32>()?.cast_unsigned();
+                let old = self.memory(*memory)?.grow(u64::from(delta));
+                self.push(old.map_or(-1, |old| {
+                    u32::try_from(old).unwrap_or(u32::MAX).cast_signed()
+                }));
+            }
+            Instruction::Misc(misc) => return self.bulk_memory_op(misc),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn bulk_memory_op(&mut self, misc: &Misc) -> Result<bool, Trap> {
+        match misc {
+            Misc::MemoryInit { data, mem } => {
+                let len = self.pop::<i32>()?.cast_unsigned() as usize;
+                let src = self.pop::<i32>()?.cast_unsigned() as usize;
+                let dest = self.pop::<i32>()?.cast_unsigned();
+                let segment = self
+                    .store
+                    .data
+                    .get(data.index as usize)
+                    .ok_or(Trap::Invalid("unknown data segment"))?;
+                let bytes = src
+                    .checked_add(len)
+                    .and_then(|end| segment.get(src..end))
+                    .ok_or(Trap::MemoryOutOfBounds)?
+                    .to_vec();
+                self.memory(*mem)?.write(dest.into(), &bytes)?;
+            }
+            Misc::DataDrop(data) => {
+                self.store
+                    .data
+                    .get_mut(data.index as usize)
+                    .ok_or(Trap::Invalid("unknown data segment"))?
+                    .clear();
+            }
+            Misc::MemoryCopy { dest, src } => {
+                let len = self.pop::<i32>()?.cast_unsigned() as usize;
+                let src_addr = self.pop::<i32>()?.cast_unsigned();
+                let dest_addr = self.pop::<i32>()?.cast_unsigned();
+                let bytes = self.memory(*src)?.read(src_addr.into(), len)?.to_vec();
+                self.memory(*dest)?.write(dest_addr.into(), &bytes)?;
+            }
+            Misc::MemoryFill(memory) => {
+                let len = self.pop::<i32>()?.cast_unsigned() as usize;
+                let value = self.pop::<i32>()?.to_le_bytes()[0];
+                let dest = self.pop::<i32>()?.cast_unsigned();
+                self.memory(*memory)?.fill(dest.into(), len, value)?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn table_op(&mut self, instr: &Instruction) -> Result<bool, Trap> {
+        match instr {
+            Instruction::TableGet(table) => {
+                let index = self.pop::<i32>()?.cast_unsigned();
+                let value = self.table(*table)?.read(index.into(), 1)?[0].clone();
+                self.push(value);
+            }
+            Instruction::TableSet(table) => {
+                let value = pop_value(&mut self.stack)?;
+                let index = self.pop::<i32>()?.cast_unsigned();
+                self.table(*table)?.write(index.into(), &[value])?;
+            }
+            Instruction::Misc(misc) => return self.bulk_table_op(misc),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn bulk_table_op(&mut self, misc: &Misc) -> Result<bool, Trap> {
+        match misc {
+            Misc::TableInit { elem, table } => {
+                let len = self.pop::<i32>()?.cast_unsigned() as usize;
+                let src = self.pop::<i32>()?.cast_unsigned() as usize;
+                let dest = self.pop::<i32>()?.cast_unsigned();
+                let segment = self
+                    .store
+                    .elems
+                    .get(elem.index as usize)
+                    .ok_or(Trap::Invalid("unknown element segment"))?;
+                let values = src
+                    .checked_add(len)
+                    .and_then(|end| segment.get(src..end))
+                    .ok_or(Trap::TableOutOfBounds)?
+                    .to_vec();
+                self.table(*table)?.write(dest.into(), &values)?;
+            }
+            Misc::ElemDrop(elem) => {
+                self.store
+                    .elems
+                    .get_mut(elem.index as usize)
+                    .ok_or(Trap::Invalid("unknown element segment"))?
+                    .clear();
+            }
+            Misc::TableCopy { dest, src } => {
+                let len = self.pop::<i32>()?.cast_unsigned() as usize;
+                let src_index = self.pop::<i32>()?.cast_unsigned();
+                let dest_index = self.pop::<i32>()?.cast_unsigned();
+                let values = self.table(*src)?.read(src_index.into(), len)?.to_vec();
+                self.table(*dest)?.write(dest_index.into(), &values)?;
+            }
+            Misc::TableGrow(table) => {
+                let delta = self.pop::<i32>()?.cast_unsigned();
+                let init = pop_value(&mut self.stack)?;
+                let old = self.table(*table)?.grow(delta, init);
+                self.push(old.map_or(-1, u32::cast_signed));
+            }
+            Misc::TableSize(table) => {
+                let size = self.table(*table)?.size();
+                self.push(size.cast_signed());
+            }
+            Misc::TableFill(table) => {
+                let len = self.pop::<i32>()?.cast_unsigned() as usize;
+                let value = pop_value(&mut self.stack)?;
+                let dest = self.pop::<i32>()?.cast_unsigned();
+                self.table(*table)?.fill(dest.into(), len, &value)?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
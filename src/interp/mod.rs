@@ -0,0 +1,568 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reference interpreter executing modules directly from their [`Instruction`]
+//! representation.
+//!
+//! The interpreter favours simplicity over speed and is intended for checking that a module
+//! behaves the same before and after a transformation rather than for running production code.
+//! It doesn't validate modules upfront; ill-formed code traps with [`Trap::Invalid`] once
+//! it's reached.
+//!
+//! Exception handling, stack switching, half-precision and relaxed SIMD instructions are not
+//! supported and trap with [`Trap::Unsupported`].
+//!
+//! ```no_run
+//! use wasmbin::interp::{Host, Instance, Store, Trap, Value};
+//! use wasmbin::sections::ImportPath;
+//! use wasmbin::types::FuncType;
+//! use wasmbin::Module;
+//!
+//! struct NoImports;
+//!
+//! impl Host for NoImports {
+//!     fn call(
+//!         &mut self,
+//!         import: &ImportPath,
+//!         _ty: &FuncType,
+//!         _args: &[Value],
+//!         _store: &mut Store,
+//!     ) -> Result<Vec<Value>, Trap> {
+//!         Err(Trap::Host(format!("unexpected call to {}.{}", import.module, import.name)))
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let module = Module::decode_from(std::fs::File::open("add.wasm")?)?;
+//! let mut instance = Instance::instantiate(&module, NoImports)?;
+//! instance.set_fuel(Some(1_000_000));
+//! let results = instance.invoke("add", &[Value::I32(1), Value::I32(2)])?;
+//! assert_eq!(results, [Value::I32(3)]);
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "threads")]
+mod atomic;
+mod exec;
+mod numeric;
+mod simd;
+mod store;
+
+pub use store::{Memory, Store, Table};
+
+use crate::indices::{FuncId, TypeId};
+use crate::instructions::{Expression, Instruction};
+use crate::io::DecodeError;
+use crate::sections::{
+    payload, DataInit, Element, Export, ExportDesc, FuncBody, ImportDesc, ImportPath,
+};
+use crate::transforms::const_eval::{
+    active_data, active_elements, eval_const_expr, eval_globals, ConstEvalError, ConstValue,
+};
+use crate::types::{FuncType, GlobalType, MemType, RefType, TableType, ValueType};
+use crate::Module;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Runtime value.
+///
+/// Like [`ConstValue`], floats compare by their bit pattern, so that NaNs with the same
+/// payload are equal and `0.0` differs from `-0.0`.
+#[derive(Debug, Clone)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    V128([u8; 16]),
+    /// Null reference of the given type.
+    Null(RefType),
+    /// Reference to a function.
+    Func(FuncId),
+    /// Opaque host reference.
+    Extern(u32),
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::I32(a), Value::I32(b)) => a == b,
+            (Value::I64(a), Value::I64(b)) => a == b,
+            (Value::F32(a), Value::F32(b)) => a.to_bits() == b.to_bits(),
+            (Value::F64(a), Value::F64(b)) => a.to_bits() == b.to_bits(),
+            (Value::V128(a), Value::V128(b)) => a == b,
+            (Value::Null(a), Value::Null(b)) => a == b,
+            (Value::Func(a), Value::Func(b)) => a == b,
+            (Value::Extern(a), Value::Extern(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl From<ConstValue> for Value {
+    fn from(value: ConstValue) -> Self {
+        match value {
+            ConstValue::I32(value) => Value::I32(value),
+            ConstValue::I64(value) => Value::I64(value),
+            ConstValue::F32(value) => Value::F32(value),
+            ConstValue::F64(value) => Value::F64(value),
+            ConstValue::V128(value) => Value::V128(value),
+            ConstValue::Null(ty) => Value::Null(ty),
+            ConstValue::Func(func) => Value::Func(func),
+            ConstValue::Extern(value) => Value::Extern(value),
+        }
+    }
+}
+
+impl From<Value> for ConstValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::I32(value) => ConstValue::I32(value),
+            Value::I64(value) => ConstValue::I64(value),
+            Value::F32(value) => ConstValue::F32(value),
+            Value::F64(value) => ConstValue::F64(value),
+            Value::V128(value) => ConstValue::V128(value),
+            Value::Null(ty) => ConstValue::Null(ty),
+            Value::Func(func) => ConstValue::Func(func),
+            Value::Extern(value) => ConstValue::Extern(value),
+        }
+    }
+}
+
+/// Default limit of nested calls before [`Trap::StackOverflow`].
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+/// Runtime error aborting the execution.
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum Trap {
+    #[error("Unreachable executed")]
+    Unreachable,
+
+    #[error("Out of bounds memory access")]
+    MemoryOutOfBounds,
+
+    #[error("Out of bounds table access")]
+    TableOutOfBounds,
+
+    /// Indirect call index is outside of the table.
+    #[error("Undefined element")]
+    UndefinedElement,
+
+    /// Indirect call to a null table element.
+    #[error("Uninitialized element")]
+    UninitializedElement,
+
+    #[error("Indirect call type mismatch")]
+    IndirectCallTypeMismatch,
+
+    #[error("Integer divide by zero")]
+    DivisionByZero,
+
+    #[error("Integer overflow")]
+    IntegerOverflow,
+
+    /// Truncation of a NaN to an integer.
+    #[error("Invalid conversion to integer")]
+    InvalidConversion,
+
+    /// Atomic memory access is not aligned to its size.
+    #[cfg(feature = "threads")]
+    #[error("Unaligned atomic")]
+    UnalignedAtomic,
+
+    /// Call depth exceeded the [limit](Instance::set_max_call_depth).
+    #[error("Call stack exhausted")]
+    StackOverflow,
+
+    /// Execution used up all the [fuel](Instance::set_fuel).
+    #[error("All fuel consumed")]
+    OutOfFuel,
+
+    /// Instruction is not supported by the interpreter.
+    #[error("Unsupported instruction {0:?}")]
+    Unsupported(Box<Instruction>),
+
+    /// Module is malformed or failed validation.
+    #[error("Invalid module: {0}")]
+    Invalid(&'static str),
+
+    /// Trap raised by a [`Host`] function.
+    #[error("{0}")]
+    Host(String),
+}
+
+/// Error returned when a module can't be instantiated or a function can't be invoked.
+#[derive(Debug, Error)]
+pub enum InterpError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Global initializer or segment offset couldn't be evaluated.
+    #[error(transparent)]
+    ConstEval(#[from] ConstEvalError),
+
+    /// Execution trapped.
+    #[error(transparent)]
+    Trap(#[from] Trap),
+
+    /// Module doesn't export a function with the given name.
+    #[error("No function is exported as {0:?}")]
+    UnknownExport(String),
+
+    /// Number of arguments doesn't match the function signature.
+    #[error("Function expects {expected} arguments, got {actual}")]
+    ArgumentCount { expected: usize, actual: usize },
+}
+
+/// Provider of module imports.
+pub trait Host {
+    /// Call an imported function.
+    ///
+    /// The `store` gives access to the memories, tables and globals of the calling instance.
+    fn call(
+        &mut self,
+        import: &ImportPath,
+        ty: &FuncType,
+        args: &[Value],
+        store: &mut Store,
+    ) -> Result<Vec<Value>, Trap>;
+
+    /// Provide the value of an imported global.
+    ///
+    /// Returning `None` fails the instantiation.
+    fn global(&mut self, import: &ImportPath, ty: &GlobalType) -> Option<Value> {
+        let _ = (import, ty);
+        None
+    }
+
+    /// Provide an imported memory. Creates an empty memory of the imported type by default.
+    fn memory(&mut self, import: &ImportPath, ty: &MemType) -> Memory {
+        let _ = import;
+        Memory::new(ty)
+    }
+
+    /// Provide an imported table. Creates an empty table of the imported type by default.
+    fn table(&mut self, import: &ImportPath, ty: &TableType) -> Table {
+        let _ = import;
+        Table::new(ty)
+    }
+}
+
+/// Function body with precomputed block boundaries.
+struct Body {
+    locals: Vec<ValueType>,
+    expr: Expression,
+    /// Index of the matching `end` for each block start and `else`.
+    ends: Vec<usize>,
+    /// Index of the `else` for each `if` that has one.
+    elses: HashMap<usize, usize>,
+}
+
+impl Body {
+    fn new(body: &FuncBody) -> Result<Self, Trap> {
+        let unbalanced = || Trap::Invalid("unbalanced blocks");
+        let mut ends = vec![0; body.expr.len()];
+        let mut elses = HashMap::new();
+        let mut open = Vec::new();
+        for (i, instr) in body.expr.iter().enumerate() {
+            match instr {
+                Instruction::BlockStart(_)
+                | Instruction::LoopStart(_)
+                | Instruction::IfStart(_) => open.push(i),
+                #[cfg(feature = "legacy-exceptions")]
+                Instruction::TryStart(_) => open.push(i),
+                Instruction::IfElse => {
+                    elses.insert(*open.last().ok_or_else(unbalanced)?, i);
+                }
+                Instruction::End => {
+                    let start = open.pop().ok_or_else(unbalanced)?;
+                    ends[start] = i;
+                    if let Some(&else_) = elses.get(&start) {
+                        ends[else_] = i;
+                    }
+                }
+                #[cfg(feature = "legacy-exceptions")]
+                Instruction::TryDelegate(_) => {
+                    open.pop().ok_or_else(unbalanced)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            locals: body
+                .locals
+                .iter()
+                .flat_map(|locals| std::iter::repeat_n(&locals.ty, locals.repeat as usize))
+                .cloned()
+                .collect(),
+            expr: body.expr.clone(),
+            ends,
+            elses,
+        })
+    }
+}
+
+enum Func {
+    Imported { path: ImportPath, ty: TypeId },
+    Defined { ty: TypeId, body: Body },
+}
+
+impl Func {
+    fn ty(&self) -> TypeId {
+        match self {
+            Func::Imported { ty, .. } | Func::Defined { ty, .. } => *ty,
+        }
+    }
+}
+
+/// Immutable parts of the module needed for execution.
+struct Code {
+    /// Function types, or `None` for other kinds of types.
+    types: Vec<Option<FuncType>>,
+    funcs: Vec<Func>,
+    exports: Vec<Export>,
+    start: Option<FuncId>,
+}
+
+impl Code {
+    fn new(module: &Module) -> Result<Self, InterpError> {
+        let mut code = Code {
+            types: Vec::new(),
+            funcs: Vec::new(),
+            exports: Vec::new(),
+            start: None,
+        };
+        if let Some(types) = module.find_std_section::<payload::Type>() {
+            code.types = types
+                .try_contents()?
+                .iter()
+                .map(|ty| ty.as_func().cloned())
+                .collect();
+        }
+        if let Some(imports) = module.find_std_section::<payload::Import>() {
+            for import in imports.try_contents()? {
+                if let ImportDesc::Func(ty) = import.desc {
+                    code.funcs.push(Func::Imported {
+                        path: import.path.clone(),
+                        ty,
+                    });
+                }
+            }
+        }
+        if let Some(funcs) = module.find_std_section::<payload::Function>() {
+            let funcs = funcs.try_contents()?;
+            let bodies = match module.find_std_section::<payload::Code>() {
+                Some(bodies) => bodies.try_contents()?.as_slice(),
+                None => &[],
+            };
+            if funcs.len() != bodies.len() {
+                return Err(Trap::Invalid("function and code section sizes differ").into());
+            }
+            for (ty, body) in funcs.iter().zip(bodies) {
+                code.funcs.push(Func::Defined {
+                    ty: *ty,
+                    body: Body::new(body.try_contents()?)?,
+                });
+            }
+        }
+        if let Some(exports) = module.find_std_section::<payload::Export>() {
+            code.exports.clone_from(exports.try_contents()?);
+        }
+        if let Some(start) = module.find_std_section::<payload::Start>() {
+            code.start = Some(*start.try_contents()?);
+        }
+        Ok(code)
+    }
+
+    fn func(&self, func: FuncId) -> Result<&Func, Trap> {
+        self.funcs
+            .get(func.index as usize)
+            .ok_or(Trap::Invalid("unknown function"))
+    }
+
+    fn func_type(&self, ty: TypeId) -> Result<&FuncType, Trap> {
+        self.types
+            .get(ty.index as usize)
+            .and_then(Option::as_ref)
+            .ok_or(Trap::Invalid("unknown function type"))
+    }
+}
+
+/// Instantiated module.
+pub struct Instance<H> {
+    pub host: H,
+    pub store: Store,
+    code: Code,
+    fuel: Option<u64>,
+    max_call_depth: usize,
+}
+
+impl<H: Host> Instance<H> {
+    /// Instantiate a module without running its start function.
+    ///
+    /// Imports are resolved via `host`, and active element and data segments are copied into
+    /// their tables and memories.
+    pub fn new(module: &Module, mut host: H) -> Result<Self, InterpError> {
+        let code = Code::new(module)?;
+        let mut store = Store::default();
+        if let Some(imports) = module.find_std_section::<payload::Import>() {
+            for import in imports.try_contents()? {
+                match &import.desc {
+                    ImportDesc::Mem(ty) => store.memories.push(host.memory(&import.path, ty)),
+                    ImportDesc::Table(ty) => store.tables.push(host.table(&import.path, ty)),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(tables) = module.find_std_section::<payload::Table>() {
+            store
+                .tables
+                .extend(tables.try_contents()?.iter().map(Table::new));
+        }
+        if let Some(memories) = module.find_std_section::<payload::Memory>() {
+            store
+                .memories
+                .extend(memories.try_contents()?.iter().map(Memory::new));
+        }
+        let globals = eval_globals(module, |path, ty| host.global(path, ty).map(Into::into))?;
+        if let Some(elements) = module.find_std_section::<payload::Element>() {
+            for element in elements.try_contents()? {
+                store.elems.push(match element {
+                    Element::PassiveWithFuncs { funcs, .. } => {
+                        funcs.iter().copied().map(Value::Func).collect()
+                    }
+                    Element::PassiveWithExprs { exprs, .. } => exprs
+                        .iter()
+                        .map(|expr| eval_const_expr(expr, &globals).map(Value::from))
+                        .collect::<Result<_, _>>()?,
+                    // Active and declarative segments are dropped once instantiated.
+                    _ => Vec::new(),
+                });
+            }
+        }
+        if let Some(data) = module.find_std_section::<payload::Data>() {
+            for segment in data.try_contents()? {
+                store.data.push(match segment.init {
+                    DataInit::Passive => segment.blob.clone(),
+                    _ => Vec::new(),
+                });
+            }
+        }
+        for segment in active_elements(module, &globals)? {
+            let items: Vec<_> = segment.items.into_iter().map(Value::from).collect();
+            store
+                .tables
+                .get_mut(segment.table.index as usize)
+                .ok_or(Trap::Invalid("unknown table"))?
+                .write(segment.offset, &items)?;
+        }
+        if let Some(data) = module.find_std_section::<payload::Data>() {
+            let data = data.try_contents()?;
+            for segment in active_data(module, &globals)? {
+                store
+                    .memories
+                    .get_mut(segment.memory.index as usize)
+                    .ok_or(Trap::Invalid("unknown memory"))?
+                    .write(segment.offset, &data[segment.data.index as usize].blob)?;
+            }
+        }
+        store.globals = globals.into_iter().map(Value::from).collect();
+        Ok(Self {
+            host,
+            store,
+            code,
+            fuel: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        })
+    }
+
+    /// Instantiate a module and run its start function.
+    pub fn instantiate(module: &Module, host: H) -> Result<Self, InterpError> {
+        let mut instance = Self::new(module, host)?;
+        instance.run_start()?;
+        Ok(instance)
+    }
+
+    /// Run the start function of the module, if any.
+    pub fn run_start(&mut self) -> Result<(), InterpError> {
+        if let Some(start) = self.code.start {
+            self.call(start, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Remaining fuel, or `None` if execution is unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Limit the number of instructions that can be executed before trapping with
+    /// [`Trap::OutOfFuel`].
+    ///
+    /// Fuel is shared by all subsequent calls until it's reset.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Limit the number of nested calls before trapping with [`Trap::StackOverflow`].
+    ///
+    /// Defaults to [`DEFAULT_MAX_CALL_DEPTH`].
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Find an export by name.
+    pub fn export(&self, name: &str) -> Option<&ExportDesc> {
+        self.code
+            .exports
+            .iter()
+            .find(|export| export.name == name)
+            .map(|export| &export.desc)
+    }
+
+    /// Call an exported function by name.
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, InterpError> {
+        match self.export(name) {
+            Some(ExportDesc::Func(func)) => self.call(*func, args),
+            _ => Err(InterpError::UnknownExport(name.to_owned())),
+        }
+    }
+
+    /// Call a function by its index.
+    pub fn call(&mut self, func: FuncId, args: &[Value]) -> Result<Vec<Value>, InterpError> {
+        let expected = self
+            .code
+            .func_type(self.code.func(func)?.ty())?
+            .params
+            .len();
+        if args.len() != expected {
+            return Err(InterpError::ArgumentCount {
+                expected,
+                actual: args.len(),
+            });
+        }
+        let machine = exec::Machine::new(
+            &self.code,
+            &mut self.store,
+            &mut self.host,
+            &mut self.fuel,
+            self.max_call_depth,
+        );
+        Ok(machine.run(func, args)?)
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Numeric instructions are defined in terms of wrapping, truncating and saturating
+// conversions, which is exactly what `as` casts do.
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss,
+    clippy::float_cmp
+)]
+
+use super::{Trap, Value};
+use crate::instructions::{Instruction, Misc};
+
+/// Rust type that can be taken from and put onto the operand stack.
+pub(super) trait Operand: Sized + Into<Value> {
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+macro_rules! operands {
+    ($($ty:ty => $variant:ident,)*) => {$(
+        impl From<$ty> for Value {
+            fn from(value: $ty) -> Self {
+                Value::$variant(value)
+            }
+        }
+
+        impl Operand for $ty {
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+operands! {
+    i32 => I32,
+    i64 => I64,
+    f32 => F32,
+    f64 => F64,
+    [u8; 16] => V128,
+}
+
+pub(super) fn pop_value(stack: &mut Vec<Value>) -> Result<Value, Trap> {
+    stack.pop().ok_or(Trap::Invalid("operand stack underflow"))
+}
+
+pub(super) fn pop<T: Operand>(stack: &mut Vec<Value>) -> Result<T, Trap> {
+    T::from_value(pop_value(stack)?).ok_or(Trap::Invalid("operand type mismatch"))
+}
+
+pub(super) fn unary<A: Operand, R: Operand>(
+    stack: &mut Vec<Value>,
+    f: impl FnOnce(A) -> R,
+) -> Result<(), Trap> {
+    let a = pop(stack)?;
+    stack.push(f(a).into());
+    Ok(())
+}
+
+pub(super) fn binary<A: Operand, R: Operand>(
+    stack: &mut Vec<Value>,
+    f: impl FnOnce(A, A) -> R,
+) -> Result<(), Trap> {
+    let b = pop(stack)?;
+    let a = pop(stack)?;
+    stack.push(f(a, b).into());
+    Ok(())
+}
+
+fn try_unary<A: Operand, R: Operand>(
+    stack: &mut Vec<Value>,
+    f: impl FnOnce(A) -> Result<R, Trap>,
+) -> Result<(), Trap> {
+    let a = pop(stack)?;
+    stack.push(f(a)?.into());
+    Ok(())
+}
+
+fn try_binary<A: Operand, R: Operand>(
+    stack: &mut Vec<Value>,
+    f: impl FnOnce(A, A) -> Result<R, Trap>,
+) -> Result<(), Trap> {
+    let b = pop(stack)?;
+    let a = pop(stack)?;
+    stack.push(f(a, b)?.into());
+    Ok(())
+}
+
+macro_rules! float_min_max {
+    ($($ty:ident: $min:ident, $max:ident;)*) => {$(
+        /// `min` that propagates NaNs and orders -0 before +0.
+        pub(super) fn $min(a: $ty, b: $ty) -> $ty {
+            if a.is_nan() || b.is_nan() {
+                $ty::NAN
+            } else if a == b {
+                if a.is_sign_negative() { a } else { b }
+            } else {
+                a.min(b)
+            }
+        }
+
+        /// `max` that propagates NaNs and orders +0 after -0.
+        pub(super) fn $max(a: $ty, b: $ty) -> $ty {
+            if a.is_nan() || b.is_nan() {
+                $ty::NAN
+            } else if a == b {
+                if a.is_sign_positive() { a } else { b }
+            } else {
+                a.max(b)
+            }
+        }
+    )*};
+}
+
+float_min_max! {
+    f32: f32_min, f32_max;
+    f64: f64_min, f64_max;
+}
+
+/// Truncate a float, trapping if the result doesn't fit into an integer of the given width.
+fn trunc(value: f64, bits: i32, signed: bool) -> Result<f64, Trap> {
+    if value.is_nan() {
+        return Err(Trap::InvalidConversion);
+    }
+    let value = value.trunc();
+    let (min, max) = if signed {
+        (-(2f64.powi(bits - 1)), 2f64.powi(bits - 1))
+    } else {
+        (0.0, 2f64.powi(bits))
+    };
+    if value < min || value >= max {
+        return Err(Trap::IntegerOverflow);
+    }
+    Ok(value)
+}
+
+fn i32_op(instr: &Instruction, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match instr {
+        Instruction::I32Eqz => unary(stack, |a: i32| i32::from(a == 0)),
+        Instruction::I32Eq => binary(stack, |a: i32, b| i32::from(a == b)),
+        Instruction::I32Ne => binary(stack, |a: i32, b| i32::from(a != b)),
+        Instruction::I32LtS => binary(stack, |a: i32, b| i32::from(a < b)),
+        Instruction::I32LtU => binary(stack, |a: i32, b| i32::from((a as u32) < b as u32)),
+        Instruction::I32GtS => binary(stack, |a: i32, b| i32::from(a > b)),
+        Instruction::I32GtU => binary(stack, |a: i32, b| i32::from(a as u32 > b as u32)),
+        Instruction::I32LeS => binary(stack, |a: i32, b| i32::from(a <= b)),
+        Instruction::I32LeU => binary(stack, |a: i32, b| i32::from(a as u32 <= b as u32)),
+        Instruction::I32GeS => binary(stack, |a: i32, b| i32::from(a >= b)),
+        Instruction::I32GeU => binary(stack, |a: i32, b| i32::from(a as u32 >= b as u32)),
+        Instruction::I32Clz => unary(stack, |a: i32| a.leading_zeros() as i32),
+        Instruction::I32Ctz => unary(stack, |a: i32| a.trailing_zeros() as i32),
+        Instruction::I32PopCnt => unary(stack, |a: i32| a.count_ones() as i32),
+        Instruction::I32Add => binary(stack, i32::wrapping_add),
+        Instruction::I32Sub => binary(stack, i32::wrapping_sub),
+        Instruction::I32Mul => binary(stack, i32::wrapping_mul),
+        Instruction::I32DivS => try_binary(stack, |a: i32, b| match b {
+            0 => Err(Trap::DivisionByZero),
+            -1 if a == i32::MIN => Err(Trap::IntegerOverflow),
+            _ => Ok(a / b),
+        }),
+        Instruction::I32DivU => try_binary(stack, |a: i32, b| {
+            Ok((a as u32)
+                .checked_div(b as u32)
+                .ok_or(Trap::DivisionByZero)? as i32)
+        }),
+        Instruction::I32RemS => try_binary(stack, |a: i32, b| match b {
+            0 => Err(Trap::DivisionByZero),
+            _ => Ok(a.wrapping_rem(b)),
+        }),
+        Instruction::I32RemU => try_binary(stack, |a: i32, b| {
+            Ok((a as u32)
+                .checked_rem(b as u32)
+                .ok_or(Trap::DivisionByZero)? as i32)
+        }),
+        Instruction::I32And => binary(stack, |a: i32, b| a & b),
+        Instruction::I32Or => binary(stack, |a: i32, b| a | b),
+        Instruction::I32Xor => binary(stack, |a: i32, b| a ^ b),
+        Instruction::I32Shl => binary(stack, |a: i32, b| a.wrapping_shl(b as u32)),
+        Instruction::I32ShrS => binary(stack, |a: i32, b| a.wrapping_shr(b as u32)),
+        Instruction::I32ShrU => binary(stack, |a: i32, b| (a as u32).wrapping_shr(b as u32) as i32),
+        Instruction::I32RotL => binary(stack, |a: i32, b| a.rotate_left(b as u32 % 32)),
+        Instruction::I32RotR => binary(stack, |a: i32, b| a.rotate_right(b as u32 % 32)),
+        Instruction::I32Extend8S => unary(stack, |a: i32| i32::from(a as i8)),
+        Instruction::I32Extend16S => unary(stack, |a: i32| i32::from(a as i16)),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn i64_op(instr: &Instruction, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match instr {
+        Instruction::I64Eqz => unary(stack, |a: i64| i32::from(a == 0)),
+        Instruction::I64Eq => binary(stack, |a: i64, b| i32::from(a == b)),
+        Instruction::I64Ne => binary(stack, |a: i64, b| i32::from(a != b)),
+        Instruction::I64LtS => binary(stack, |a: i64, b| i32::from(a < b)),
+        Instruction::I64LtU => binary(stack, |a: i64, b| i32::from((a as u64) < b as u64)),
+        Instruction::I64GtS => binary(stack, |a: i64, b| i32::from(a > b)),
+        Instruction::I64GtU => binary(stack, |a: i64, b| i32::from(a as u64 > b as u64)),
+        Instruction::I64LeS => binary(stack, |a: i64, b| i32::from(a <= b)),
+        Instruction::I64LeU => binary(stack, |a: i64, b| i32::from(a as u64 <= b as u64)),
+        Instruction::I64GeS => binary(stack, |a: i64, b| i32::from(a >= b)),
+        Instruction::I64GeU => binary(stack, |a: i64, b| i32::from(a as u64 >= b as u64)),
+        Instruction::I64Clz => unary(stack, |a: i64| i64::from(a.leading_zeros())),
+        Instruction::I64Ctz => unary(stack, |a: i64| i64::from(a.trailing_zeros())),
+        Instruction::I64PopCnt => unary(stack, |a: i64| i64::from(a.count_ones())),
+        Instruction::I64Add => binary(stack, i64::wrapping_add),
+        Instruction::I64Sub => binary(stack, i64::wrapping_sub),
+        Instruction::I64Mul => binary(stack, i64::wrapping_mul),
+        Instruction::I64DivS => try_binary(stack, |a: i64, b| match b {
+            0 => Err(Trap::DivisionByZero),
+            -1 if a == i64::MIN => Err(Trap::IntegerOverflow),
+            _ => Ok(a / b),
+        }),
+        Instruction::I64DivU => try_binary(stack, |a: i64, b| {
+            Ok((a as u64)
+                .checked_div(b as u64)
+                .ok_or(Trap::DivisionByZero)? as i64)
+        }),
+        Instruction::I64RemS => try_binary(stack, |a: i64, b| match b {
+            0 => Err(Trap::DivisionByZero),
+            _ => Ok(a.wrapping_rem(b)),
+        }),
+        Instruction::I64RemU => try_binary(stack, |a: i64, b| {
+            Ok((a as u64)
+                .checked_rem(b as u64)
+                .ok_or(Trap::DivisionByZero)? as i64)
+        }),
+        Instruction::I64And => binary(stack, |a: i64, b| a & b),
+        Instruction::I64Or => binary(stack, |a: i64, b| a | b),
+        Instruction::I64Xor => binary(stack, |a: i64, b| a ^ b),
+        Instruction::I64Shl => binary(stack, |a: i64, b| a.wrapping_shl(b as u32)),
+        Instruction::I64ShrS => binary(stack, |a: i64, b| a.wrapping_shr(b as u32)),
+        Instruction::I64ShrU => binary(stack, |a: i64, b| (a as u64).wrapping_shr(b as u32) as i64),
+        Instruction::I64RotL => binary(stack, |a: i64, b| a.rotate_left((b % 64) as u32)),
+        Instruction::I64RotR => binary(stack, |a: i64, b| a.rotate_right((b % 64) as u32)),
+        Instruction::I64Extend8S => unary(stack, |a: i64| i64::from(a as i8)),
+        Instruction::I64Extend16S => unary(stack, |a: i64| i64::from(a as i16)),
+        Instruction::I64Extend32S => unary(stack, |a: i64| i64::from(a as i32)),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn f32_op(instr: &Instruction, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match instr {
+        Instruction::F32Eq => binary(stack, |a: f32, b| i32::from(a == b)),
+        Instruction::F32Ne => binary(stack, |a: f32, b| i32::from(a != b)),
+        Instruction::F32Lt => binary(stack, |a: f32, b| i32::from(a < b)),
+        Instruction::F32Gt => binary(stack, |a: f32, b| i32::from(a > b)),
+        Instruction::F32Le => binary(stack, |a: f32, b| i32::from(a <= b)),
+        Instruction::F32Ge => binary(stack, |a: f32, b| i32::from(a >= b)),
+        Instruction::F32Abs => unary(stack, f32::abs),
+        Instruction::F32Neg => unary(stack, |a: f32| -a),
+        Instruction::F32Ceil => unary(stack, f32::ceil),
+        Instruction::F32Floor => unary(stack, f32::floor),
+        Instruction::F32Trunc => unary(stack, f32::trunc),
+        Instruction::F32Nearest => unary(stack, f32::round_ties_even),
+        Instruction::F32Sqrt => unary(stack, f32::sqrt),
+        Instruction::F32Add => binary(stack, |a: f32, b| a + b),
+        Instruction::F32Sub => binary(stack, |a: f32, b| a - b),
+        Instruction::F32Mul => binary(stack, |a: f32, b| a * b),
+        Instruction::F32Div => binary(stack, |a: f32, b| a / b),
+        Instruction::F32Min => binary(stack, f32_min),
+        Instruction::F32Max => binary(stack, f32_max),
+        Instruction::F32CopySign => binary(stack, f32::copysign),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn f64_op(instr: &Instruction, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match instr {
+        Instruction::F64Eq => binary(stack, |a: f64, b| i32::from(a == b)),
+        Instruction::F64Ne => binary(stack, |a: f64, b| i32::from(a != b)),
+        Instruction::F64Lt => binary(stack, |a: f64, b| i32::from(a < b)),
+        Instruction::F64Gt => binary(stack, |a: f64, b| i32::from(a > b)),
+        Instruction::F64Le => binary(stack, |a: f64, b| i32::from(a <= b)),
+        Instruction::F64Ge => binary(stack, |a: f64, b| i32::from(a >= b)),
+        Instruction::F64Abs => unary(stack, f64::abs),
+        Instruction::F64Neg => unary(stack, |a: f64| -a),
+        Instruction::F64Ceil => unary(stack, f64::ceil),
+        Instruction::F64Floor => unary(stack, f64::floor),
+        Instruction::F64Trunc => unary(stack, f64::trunc),
+        Instruction::F64Nearest => unary(stack, f64::round_ties_even),
+        Instruction::F64Sqrt => unary(stack, f64::sqrt),
+        Instruction::F64Add => binary(stack, |a: f64, b| a + b),
+        Instruction::F64Sub => binary(stack, |a: f64, b| a - b),
+        Instruction::F64Mul => binary(stack, |a: f64, b| a * b),
+        Instruction::F64Div => binary(stack, |a: f64, b| a / b),
+        Instruction::F64Min => binary(stack, f64_min),
+        Instruction::F64Max => binary(stack, f64_max),
+        Instruction::F64CopySign => binary(stack, f64::copysign),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn conversion_op(instr: &Instruction, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match instr {
+        Instruction::I32WrapI64 => unary(stack, |a: i64| a as i32),
+        Instruction::I32TruncF32S => {
+            try_unary(stack, |a: f32| Ok(trunc(a.into(), 32, true)? as i32))
+        }
+        Instruction::I32TruncF332U => {
+            try_unary(
+                stack,
+                |a: f32| Ok(trunc(a.into(), 32, false)? as u32 as i32),
+            )
+        }
+        Instruction::I32TruncF64S => try_unary(stack, |a: f64| Ok(trunc(a, 32, true)? as i32)),
+        Instruction::I32TruncF64U => {
+            try_unary(stack, |a: f64| Ok(trunc(a, 32, false)? as u32 as i32))
+        }
+        Instruction::I64ExtendI32S => unary(stack, |a: i32| i64::from(a)),
+        Instruction::I64ExtendI32U => unary(stack, |a: i32| i64::from(a as u32)),
+        Instruction::I64TruncF32S => {
+            try_unary(stack, |a: f32| Ok(trunc(a.into(), 64, true)? as i64))
+        }
+        Instruction::I64TruncF32U => {
+            try_unary(
+                stack,
+                |a: f32| Ok(trunc(a.into(), 64, false)? as u64 as i64),
+            )
+        }
+        Instruction::I64TruncF64S => try_unary(stack, |a: f64| Ok(trunc(a, 64, true)? as i64)),
+        Instruction::I64TruncF64U => {
+            try_unary(stack, |a: f64| Ok(trunc(a, 64, false)? as u64 as i64))
+        }
+        Instruction::F32ConvertI32S => unary(stack, |a: i32| a as f32),
+        Instruction::F32ConvertI32U => unary(stack, |a: i32| a as u32 as f32),
+        Instruction::F32ConvertI64S => unary(stack, |a: i64| a as f32),
+        Instruction::F32ConvertI64U => unary(stack, |a: i64| a as u64 as f32),
+        Instruction::F32DemoteF64 => unary(stack, |a: f64| a as f32),
+        Instruction::F64ConvertI32S => unary(stack, |a: i32| f64::from(a)),
+        Instruction::F64ConvertI32U => unary(stack, |a: i32| f64::from(a as u32)),
+        Instruction::F64ConvertI64S => unary(stack, |a: i64| a as f64),
+        Instruction::F64ConvertI64U => unary(stack, |a: i64| a as u64 as f64),
+        Instruction::F64PromoteF32 => unary(stack, |a: f32| f64::from(a)),
+        Instruction::I32ReinterpretF32 => unary(stack, |a: f32| a.to_bits() as i32),
+        Instruction::I64ReinterpretF64 => unary(stack, |a: f64| a.to_bits() as i64),
+        Instruction::F32ReinterpretI32 => unary(stack, |a: i32| f32::from_bits(a as u32)),
+        Instruction::F64ReinterpretI64 => unary(stack, |a: i64| f64::from_bits(a as u64)),
+        Instruction::Misc(misc) => return misc_op(misc, stack),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+#[cfg(feature = "wide-arithmetic")]
+fn wide_op(stack: &mut Vec<Value>, f: impl FnOnce(i128, i128) -> i128) -> Result<(), Trap> {
+    let pop_i128 = |stack: &mut Vec<Value>| -> Result<i128, Trap> {
+        let hi = pop::<i64>(stack)?;
+        let lo = pop::<i64>(stack)?;
+        Ok(i128::from(hi) << 64 | i128::from(lo as u64))
+    };
+    let b = pop_i128(stack)?;
+    let a = pop_i128(stack)?;
+    let result = f(a, b);
+    stack.push(Value::I64(result as i64));
+    stack.push(Value::I64((result >> 64) as i64));
+    Ok(())
+}
+
+fn misc_op(misc: &Misc, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match misc {
+        // Float-to-int `as` casts saturate and turn NaNs into zeroes, just like these instructions.
+        Misc::I32TruncSatF32S => unary(stack, |a: f32| a as i32),
+        Misc::I32TruncSatF32U => unary(stack, |a: f32| a as u32 as i32),
+        Misc::I32TruncSatF64S => unary(stack, |a: f64| a as i32),
+        Misc::I32TruncSatF64U => unary(stack, |a: f64| a as u32 as i32),
+        Misc::I64TruncSatF32S => unary(stack, |a: f32| a as i64),
+        Misc::I64TruncSatF32U => unary(stack, |a: f32| a as u64 as i64),
+        Misc::I64TruncSatF64S => unary(stack, |a: f64| a as i64),
+        Misc::I64TruncSatF64U => unary(stack, |a: f64| a as u64 as i64),
+        #[cfg(feature = "wide-arithmetic")]
+        Misc::I64Add128 => wide_op(stack, i128::wrapping_add),
+        #[cfg(feature = "wide-arithmetic")]
+        Misc::I64Sub128 => wide_op(stack, i128::wrapping_sub),
+        #[cfg(feature = "wide-arithmetic")]
+        Misc::I64MulWideS => {
+            let b = pop::<i64>(stack)?;
+            let a = pop::<i64>(stack)?;
+            let result = i128::from(a) * i128::from(b);
+            stack.push(Value::I64(result as i64));
+            stack.push(Value::I64((result >> 64) as i64));
+            Ok(())
+        }
+        #[cfg(feature = "wide-arithmetic")]
+        Misc::I64MulWideU => {
+            let b = pop::<i64>(stack)?;
+            let a = pop::<i64>(stack)?;
+            let result = u128::from(a as u64) * u128::from(b as u64);
+            stack.push(Value::I64(result as i64));
+            stack.push(Value::I64((result >> 64) as i64));
+            Ok(())
+        }
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+/// Evaluate a numeric instruction, returning `false` if `instr` is not one.
+pub(super) fn eval(instr: &Instruction, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    Ok(i32_op(instr, stack)?
+        || i64_op(instr, stack)?
+        || f32_op(instr, stack)?
+        || f64_op(instr, stack)?
+        || conversion_op(instr, stack)?)
+}
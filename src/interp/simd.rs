@@ -0,0 +1,604 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Lane conversions follow the same wrapping and saturating `as` semantics as scalar ones.
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss,
+    clippy::float_cmp
+)]
+
+use super::exec::Machine;
+use super::numeric::{f32_max, f32_min, f64_max, f64_min, pop, Operand};
+use super::{Host, Trap, Value};
+use crate::instructions::simd::LaneId;
+use crate::instructions::{Instruction, MemArg, SIMD};
+
+type V128 = [u8; 16];
+
+/// Scalar type of a single vector lane.
+trait Lane: Copy {
+    const SIZE: usize;
+
+    fn read(bytes: &[u8]) -> Self;
+
+    fn write(self, bytes: &mut [u8]);
+}
+
+/// Integer lane type that represents comparison results.
+trait Mask {
+    fn mask(value: bool) -> Self;
+}
+
+macro_rules! lanes {
+    ($($ty:ident)*) => {$(
+        impl Lane for $ty {
+            const SIZE: usize = std::mem::size_of::<$ty>();
+
+            fn read(bytes: &[u8]) -> Self {
+                $ty::from_le_bytes(bytes.try_into().expect("lane size"))
+            }
+
+            fn write(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_le_bytes());
+            }
+        }
+    )*};
+}
+
+lanes!(i8 u8 i16 u16 i32 u32 i64 u64 f32 f64);
+
+macro_rules! masks {
+    ($($ty:ident)*) => {$(
+        impl Mask for $ty {
+            fn mask(value: bool) -> Self {
+                -$ty::from(value)
+            }
+        }
+    )*};
+}
+
+masks!(i8 i16 i32 i64);
+
+fn lanes<T: Lane>(v: V128) -> Vec<T> {
+    v.chunks_exact(T::SIZE).map(T::read).collect()
+}
+
+/// Build a vector from lanes, filling the remaining bytes with zeroes.
+fn from_lanes<T: Lane>(lanes: impl IntoIterator<Item = T>) -> V128 {
+    let mut v = [0; 16];
+    for (lane, bytes) in lanes.into_iter().zip(v.chunks_exact_mut(T::SIZE)) {
+        lane.write(bytes);
+    }
+    v
+}
+
+fn lane_index<const MAX: u8>(lane: LaneId<MAX>) -> usize {
+    usize::from(u8::from(lane))
+}
+
+// Returns a `Result` to be usable as a tail expression of the evaluation helpers.
+#[allow(clippy::unnecessary_wraps)]
+fn push(stack: &mut Vec<Value>, v: V128) -> Result<(), Trap> {
+    stack.push(Value::V128(v));
+    Ok(())
+}
+
+fn map<T: Lane, R: Lane>(stack: &mut Vec<Value>, f: impl Fn(T) -> R) -> Result<(), Trap> {
+    let a = pop::<V128>(stack)?;
+    push(stack, from_lanes(lanes(a).into_iter().map(f)))
+}
+
+fn zip<T: Lane, R: Lane>(stack: &mut Vec<Value>, f: impl Fn(T, T) -> R) -> Result<(), Trap> {
+    let b = pop::<V128>(stack)?;
+    let a = pop::<V128>(stack)?;
+    push(
+        stack,
+        from_lanes(lanes(a).into_iter().zip(lanes(b)).map(|(a, b)| f(a, b))),
+    )
+}
+
+fn compare<T: Lane, M: Lane + Mask>(
+    stack: &mut Vec<Value>,
+    f: impl Fn(T, T) -> bool,
+) -> Result<(), Trap> {
+    zip(stack, |a, b| M::mask(f(a, b)))
+}
+
+/// Apply `f` to either the low or the high half of the lanes, producing twice as wide lanes.
+fn extend<T: Lane, R: Lane>(
+    stack: &mut Vec<Value>,
+    high: bool,
+    f: impl Fn(T) -> R,
+) -> Result<(), Trap> {
+    let a = lanes::<T>(pop(stack)?);
+    let half = if high {
+        &a[a.len() / 2..]
+    } else {
+        &a[..a.len() / 2]
+    };
+    push(stack, from_lanes(half.iter().copied().map(f)))
+}
+
+/// Multiply either the low or the high half of the lanes, producing twice as wide lanes.
+fn extmul<T: Lane, R: Lane>(
+    stack: &mut Vec<Value>,
+    high: bool,
+    f: impl Fn(T, T) -> R,
+) -> Result<(), Trap> {
+    let b = lanes::<T>(pop(stack)?);
+    let a = lanes::<T>(pop(stack)?);
+    let range = if high {
+        a.len() / 2..a.len()
+    } else {
+        0..a.len() / 2
+    };
+    push(stack, from_lanes(range.map(|i| f(a[i], b[i]))))
+}
+
+/// Combine adjacent pairs of lanes, producing twice as wide lanes.
+fn pairwise<T: Lane, R: Lane>(stack: &mut Vec<Value>, f: impl Fn(T, T) -> R) -> Result<(), Trap> {
+    let a = lanes::<T>(pop(stack)?);
+    push(
+        stack,
+        from_lanes(a.chunks_exact(2).map(|pair| f(pair[0], pair[1]))),
+    )
+}
+
+/// Convert lanes of both operands into twice as narrow lanes.
+fn narrow<T: Lane, R: Lane>(stack: &mut Vec<Value>, f: impl Fn(T) -> R) -> Result<(), Trap> {
+    let b = pop::<V128>(stack)?;
+    let a = pop::<V128>(stack)?;
+    push(
+        stack,
+        from_lanes(lanes(a).into_iter().chain(lanes(b)).map(f)),
+    )
+}
+
+fn shift<T: Lane>(stack: &mut Vec<Value>, f: impl Fn(T, u32) -> T) -> Result<(), Trap> {
+    // Shift amounts are taken modulo the lane width by the `wrapping_*` methods.
+    let amount = pop::<i32>(stack)?.cast_unsigned();
+    map(stack, |a| f(a, amount))
+}
+
+fn splat<S: Operand, T: Lane>(stack: &mut Vec<Value>, f: impl Fn(S) -> T) -> Result<(), Trap> {
+    let lane = f(pop(stack)?);
+    push(stack, from_lanes(std::iter::repeat_n(lane, 16 / T::SIZE)))
+}
+
+fn extract<T: Lane, R: Operand>(
+    stack: &mut Vec<Value>,
+    lane: usize,
+    f: impl Fn(T) -> R,
+) -> Result<(), Trap> {
+    let a = lanes::<T>(pop(stack)?);
+    stack.push(f(a[lane]).into());
+    Ok(())
+}
+
+fn replace<S: Operand, T: Lane>(
+    stack: &mut Vec<Value>,
+    lane: usize,
+    f: impl Fn(S) -> T,
+) -> Result<(), Trap> {
+    let value = f(pop(stack)?);
+    let mut a = pop::<V128>(stack)?;
+    value.write(&mut a[lane * T::SIZE..][..T::SIZE]);
+    push(stack, a)
+}
+
+fn test<T: Lane>(stack: &mut Vec<Value>, f: impl Fn(&[T]) -> bool) -> Result<(), Trap> {
+    let a = lanes::<T>(pop(stack)?);
+    stack.push(Value::I32(f(&a).into()));
+    Ok(())
+}
+
+fn bitmask<T: Lane>(stack: &mut Vec<Value>, is_negative: impl Fn(T) -> bool) -> Result<(), Trap> {
+    let a = lanes::<T>(pop(stack)?);
+    let mask = a
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, lane)| is_negative(lane))
+        .fold(0, |mask, (i, _)| mask | (1 << i));
+    stack.push(Value::I32(mask));
+    Ok(())
+}
+
+fn memory_op<H: Host>(m: &mut Machine<'_, H>, op: &SIMD) -> Result<bool, Trap> {
+    match op {
+        SIMD::V128Load(arg) => {
+            let v = m.load::<16>(arg)?;
+            push(&mut m.stack, v)
+        }
+        SIMD::V128Load8x8S(arg) => load_extend(m, arg, |a: i8| i16::from(a)),
+        SIMD::V128Load8x8U(arg) => load_extend(m, arg, |a: u8| u16::from(a)),
+        SIMD::V128Load16x4S(arg) => load_extend(m, arg, |a: i16| i32::from(a)),
+        SIMD::V128Load16x4U(arg) => load_extend(m, arg, |a: u16| u32::from(a)),
+        SIMD::V128Load32x2S(arg) => load_extend(m, arg, |a: i32| i64::from(a)),
+        SIMD::V128Load32x2U(arg) => load_extend(m, arg, |a: u32| u64::from(a)),
+        SIMD::V128Load8Splat(arg) => load_splat::<u8, 1, _>(m, arg),
+        SIMD::V128Load16Splat(arg) => load_splat::<u16, 2, _>(m, arg),
+        SIMD::V128Load32Splat(arg) => load_splat::<u32, 4, _>(m, arg),
+        SIMD::V128Load64Splat(arg) => load_splat::<u64, 8, _>(m, arg),
+        SIMD::V128Load32Zero(arg) => {
+            let bytes = m.load::<4>(arg)?;
+            push(&mut m.stack, from_lanes([u32::read(&bytes)]))
+        }
+        SIMD::V128Load64Zero(arg) => {
+            let bytes = m.load::<8>(arg)?;
+            push(&mut m.stack, from_lanes([u64::read(&bytes)]))
+        }
+        SIMD::V128Store(arg) => {
+            let v = pop::<V128>(&mut m.stack)?;
+            m.store(arg, &v)
+        }
+        SIMD::V128Load8Lane(arg, lane) => load_lane::<1, _>(m, arg, lane_index(*lane)),
+        SIMD::V128Load16Lane(arg, lane) => load_lane::<2, _>(m, arg, lane_index(*lane)),
+        SIMD::V128Load32Lane(arg, lane) => load_lane::<4, _>(m, arg, lane_index(*lane)),
+        SIMD::V128Load64Lane(arg, lane) => load_lane::<8, _>(m, arg, lane_index(*lane)),
+        SIMD::V128Store8Lane(arg, lane) => store_lane(m, arg, lane_index(*lane), 1),
+        SIMD::V128Store16Lane(arg, lane) => store_lane(m, arg, lane_index(*lane), 2),
+        SIMD::V128Store32Lane(arg, lane) => store_lane(m, arg, lane_index(*lane), 4),
+        SIMD::V128Store64Lane(arg, lane) => store_lane(m, arg, lane_index(*lane), 8),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn load_extend<H: Host, T: Lane, R: Lane>(
+    m: &mut Machine<'_, H>,
+    arg: &MemArg,
+    f: impl Fn(T) -> R,
+) -> Result<(), Trap> {
+    let bytes = m.load::<8>(arg)?;
+    push(
+        &mut m.stack,
+        from_lanes(bytes.chunks_exact(T::SIZE).map(T::read).map(f)),
+    )
+}
+
+fn load_splat<T: Lane, const N: usize, H: Host>(
+    m: &mut Machine<'_, H>,
+    arg: &MemArg,
+) -> Result<(), Trap> {
+    let lane = T::read(&m.load::<N>(arg)?);
+    push(&mut m.stack, from_lanes(std::iter::repeat_n(lane, 16 / N)))
+}
+
+fn load_lane<const N: usize, H: Host>(
+    m: &mut Machine<'_, H>,
+    arg: &MemArg,
+    lane: usize,
+) -> Result<(), Trap> {
+    let mut v = pop::<V128>(&mut m.stack)?;
+    let bytes = m.load::<N>(arg)?;
+    v[lane * N..][..N].copy_from_slice(&bytes);
+    push(&mut m.stack, v)
+}
+
+fn store_lane<H: Host>(
+    m: &mut Machine<'_, H>,
+    arg: &MemArg,
+    lane: usize,
+    size: usize,
+) -> Result<(), Trap> {
+    let v = pop::<V128>(&mut m.stack)?;
+    m.store(arg, &v[lane * size..][..size])
+}
+
+fn lane_op(op: &SIMD, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match op {
+        SIMD::V128Const(v) => push(stack, *v),
+        SIMD::I8x16Shuffle(indices) => {
+            let b = pop::<V128>(stack)?;
+            let a = pop::<V128>(stack)?;
+            let both = [a, b].concat();
+            push(stack, indices.map(|i| both[lane_index(i)]))
+        }
+        SIMD::I8x16Swizzle => {
+            let s = pop::<V128>(stack)?;
+            let a = pop::<V128>(stack)?;
+            push(
+                stack,
+                s.map(|i| a.get(usize::from(i)).copied().unwrap_or(0)),
+            )
+        }
+        SIMD::I8x16Splat => splat(stack, |a: i32| a as i8),
+        SIMD::I16x8Splat => splat(stack, |a: i32| a as i16),
+        SIMD::I32x4Splat => splat(stack, |a: i32| a),
+        SIMD::I64x2Splat => splat(stack, |a: i64| a),
+        SIMD::F32x4Splat => splat(stack, |a: f32| a),
+        SIMD::F64x2Splat => splat(stack, |a: f64| a),
+        SIMD::I8x16ExtractLaneS(lane) => extract(stack, lane_index(*lane), |a: i8| i32::from(a)),
+        SIMD::I8x16ExtractLaneU(lane) => extract(stack, lane_index(*lane), |a: u8| i32::from(a)),
+        SIMD::I16x8ExtractLaneS(lane) => extract(stack, lane_index(*lane), |a: i16| i32::from(a)),
+        SIMD::I16x8ExtractLaneU(lane) => extract(stack, lane_index(*lane), |a: u16| i32::from(a)),
+        SIMD::I32x4ExtractLane(lane) => extract(stack, lane_index(*lane), |a: i32| a),
+        SIMD::I64x2ExtractLane(lane) => extract(stack, lane_index(*lane), |a: i64| a),
+        SIMD::F32x4ExtractLane(lane) => extract(stack, lane_index(*lane), |a: f32| a),
+        SIMD::F64x2ExtractLane(lane) => extract(stack, lane_index(*lane), |a: f64| a),
+        SIMD::I8x16ReplaceLane(lane) => replace(stack, lane_index(*lane), |a: i32| a as i8),
+        SIMD::I16x8ReplaceLane(lane) => replace(stack, lane_index(*lane), |a: i32| a as i16),
+        SIMD::I32x4ReplaceLane(lane) => replace(stack, lane_index(*lane), |a: i32| a),
+        SIMD::I64x2ReplaceLane(lane) => replace(stack, lane_index(*lane), |a: i64| a),
+        SIMD::F32x4ReplaceLane(lane) => replace(stack, lane_index(*lane), |a: f32| a),
+        SIMD::F64x2ReplaceLane(lane) => replace(stack, lane_index(*lane), |a: f64| a),
+        SIMD::V128Not => map(stack, |a: u64| !a),
+        SIMD::V128And => zip(stack, |a: u64, b| a & b),
+        SIMD::V128Andnot => zip(stack, |a: u64, b| a & !b),
+        SIMD::V128Or => zip(stack, |a: u64, b| a | b),
+        SIMD::V128Xor => zip(stack, |a: u64, b| a ^ b),
+        SIMD::V128Bitselect => {
+            let c = u128::from_le_bytes(pop(stack)?);
+            let b = u128::from_le_bytes(pop(stack)?);
+            let a = u128::from_le_bytes(pop(stack)?);
+            push(stack, ((a & c) | (b & !c)).to_le_bytes())
+        }
+        SIMD::V128AnyTrue => test(stack, |a: &[u64]| a.iter().any(|&a| a != 0)),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn compare_op(op: &SIMD, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match op {
+        SIMD::I8x16Eq => compare::<i8, i8>(stack, |a, b| a == b),
+        SIMD::I8x16Ne => compare::<i8, i8>(stack, |a, b| a != b),
+        SIMD::I8x16LtS => compare::<i8, i8>(stack, |a, b| a < b),
+        SIMD::I8x16LtU => compare::<u8, i8>(stack, |a, b| a < b),
+        SIMD::I8x16GtS => compare::<i8, i8>(stack, |a, b| a > b),
+        SIMD::I8x16GtU => compare::<u8, i8>(stack, |a, b| a > b),
+        SIMD::I8x16LeS => compare::<i8, i8>(stack, |a, b| a <= b),
+        SIMD::I8x16LeU => compare::<u8, i8>(stack, |a, b| a <= b),
+        SIMD::I8x16GeS => compare::<i8, i8>(stack, |a, b| a >= b),
+        SIMD::I8x16GeU => compare::<u8, i8>(stack, |a, b| a >= b),
+        SIMD::I16x8Eq => compare::<i16, i16>(stack, |a, b| a == b),
+        SIMD::I16x8Ne => compare::<i16, i16>(stack, |a, b| a != b),
+        SIMD::I16x8LtS => compare::<i16, i16>(stack, |a, b| a < b),
+        SIMD::I16x8LtU => compare::<u16, i16>(stack, |a, b| a < b),
+        SIMD::I16x8GtS => compare::<i16, i16>(stack, |a, b| a > b),
+        SIMD::I16x8GtU => compare::<u16, i16>(stack, |a, b| a > b),
+        SIMD::I16x8LeS => compare::<i16, i16>(stack, |a, b| a <= b),
+        SIMD::I16x8LeU => compare::<u16, i16>(stack, |a, b| a <= b),
+        SIMD::I16x8GeS => compare::<i16, i16>(stack, |a, b| a >= b),
+        SIMD::I16x8GeU => compare::<u16, i16>(stack, |a, b| a >= b),
+        SIMD::I32x4Eq => compare::<i32, i32>(stack, |a, b| a == b),
+        SIMD::I32x4Ne => compare::<i32, i32>(stack, |a, b| a != b),
+        SIMD::I32x4LtS => compare::<i32, i32>(stack, |a, b| a < b),
+        SIMD::I32x4LtU => compare::<u32, i32>(stack, |a, b| a < b),
+        SIMD::I32x4GtS => compare::<i32, i32>(stack, |a, b| a > b),
+        SIMD::I32x4GtU => compare::<u32, i32>(stack, |a, b| a > b),
+        SIMD::I32x4LeS => compare::<i32, i32>(stack, |a, b| a <= b),
+        SIMD::I32x4LeU => compare::<u32, i32>(stack, |a, b| a <= b),
+        SIMD::I32x4GeS => compare::<i32, i32>(stack, |a, b| a >= b),
+        SIMD::I32x4GeU => compare::<u32, i32>(stack, |a, b| a >= b),
+        SIMD::I64x2Eq => compare::<i64, i64>(stack, |a, b| a == b),
+        SIMD::I64x2Ne => compare::<i64, i64>(stack, |a, b| a != b),
+        SIMD::I64x2LtS => compare::<i64, i64>(stack, |a, b| a < b),
+        SIMD::I64x2GtS => compare::<i64, i64>(stack, |a, b| a > b),
+        SIMD::I64x2LeS => compare::<i64, i64>(stack, |a, b| a <= b),
+        SIMD::I64x2GeS => compare::<i64, i64>(stack, |a, b| a >= b),
+        SIMD::F32x4Eq => compare::<f32, i32>(stack, |a, b| a == b),
+        SIMD::F32x4Ne => compare::<f32, i32>(stack, |a, b| a != b),
+        SIMD::F32x4Lt => compare::<f32, i32>(stack, |a, b| a < b),
+        SIMD::F32x4Gt => compare::<f32, i32>(stack, |a, b| a > b),
+        SIMD::F32x4Le => compare::<f32, i32>(stack, |a, b| a <= b),
+        SIMD::F32x4Ge => compare::<f32, i32>(stack, |a, b| a >= b),
+        SIMD::F64x2Eq => compare::<f64, i64>(stack, |a, b| a == b),
+        SIMD::F64x2Ne => compare::<f64, i64>(stack, |a, b| a != b),
+        SIMD::F64x2Lt => compare::<f64, i64>(stack, |a, b| a < b),
+        SIMD::F64x2Gt => compare::<f64, i64>(stack, |a, b| a > b),
+        SIMD::F64x2Le => compare::<f64, i64>(stack, |a, b| a <= b),
+        SIMD::F64x2Ge => compare::<f64, i64>(stack, |a, b| a >= b),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn narrow_int_op(op: &SIMD, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match op {
+        SIMD::I8x16Abs => map(stack, i8::wrapping_abs),
+        SIMD::I8x16Neg => map(stack, i8::wrapping_neg),
+        SIMD::I8x16Popcnt => map(stack, |a: u8| a.count_ones() as u8),
+        SIMD::I8x16AllTrue => test(stack, |a: &[u8]| a.iter().all(|&a| a != 0)),
+        SIMD::I8x16Bitmask => bitmask(stack, |a: i8| a < 0),
+        SIMD::I8x16NarrowI16x8S => narrow(stack, |a: i16| {
+            a.clamp(i8::MIN.into(), i8::MAX.into()) as i8
+        }),
+        SIMD::I8x16NarrowI16x8U => narrow(stack, |a: i16| a.clamp(0, u8::MAX.into()) as u8),
+        SIMD::I8x16Shl => shift(stack, i8::wrapping_shl),
+        SIMD::I8x16ShrS => shift(stack, i8::wrapping_shr),
+        SIMD::I8x16ShrU => shift(stack, u8::wrapping_shr),
+        SIMD::I8x16Add => zip(stack, i8::wrapping_add),
+        SIMD::I8x16AddSatS => zip(stack, i8::saturating_add),
+        SIMD::I8x16AddSatU => zip(stack, u8::saturating_add),
+        SIMD::I8x16Sub => zip(stack, i8::wrapping_sub),
+        SIMD::I8x16SubSatS => zip(stack, i8::saturating_sub),
+        SIMD::I8x16SubSatU => zip(stack, u8::saturating_sub),
+        SIMD::I8x16MinS => zip(stack, i8::min),
+        SIMD::I8x16MinU => zip(stack, u8::min),
+        SIMD::I8x16MaxS => zip(stack, i8::max),
+        SIMD::I8x16MaxU => zip(stack, u8::max),
+        SIMD::I8x16AvgrU => zip(stack, |a: u8, b| {
+            (u16::from(a) + u16::from(b)).div_ceil(2) as u8
+        }),
+        SIMD::I16x8Abs => map(stack, i16::wrapping_abs),
+        SIMD::I16x8Neg => map(stack, i16::wrapping_neg),
+        SIMD::I16x8AllTrue => test(stack, |a: &[u16]| a.iter().all(|&a| a != 0)),
+        SIMD::I16x8Bitmask => bitmask(stack, |a: i16| a < 0),
+        SIMD::I16x8NarrowI32x4S => narrow(stack, |a: i32| {
+            a.clamp(i16::MIN.into(), i16::MAX.into()) as i16
+        }),
+        SIMD::I16x8NarrowI32x4U => narrow(stack, |a: i32| a.clamp(0, u16::MAX.into()) as u16),
+        SIMD::I16x8ExtendLowI8x16S => extend(stack, false, |a: i8| i16::from(a)),
+        SIMD::I16x8ExtendHighI8x16S => extend(stack, true, |a: i8| i16::from(a)),
+        SIMD::I16x8ExtendLowI8x16U => extend(stack, false, |a: u8| u16::from(a)),
+        SIMD::I16x8ExtendHighI8x16U => extend(stack, true, |a: u8| u16::from(a)),
+        SIMD::I16x8Shl => shift(stack, i16::wrapping_shl),
+        SIMD::I16x8ShrS => shift(stack, i16::wrapping_shr),
+        SIMD::I16x8ShrU => shift(stack, u16::wrapping_shr),
+        SIMD::I16x8Add => zip(stack, i16::wrapping_add),
+        SIMD::I16x8AddSatS => zip(stack, i16::saturating_add),
+        SIMD::I16x8AddSatU => zip(stack, u16::saturating_add),
+        SIMD::I16x8Sub => zip(stack, i16::wrapping_sub),
+        SIMD::I16x8SubSatS => zip(stack, i16::saturating_sub),
+        SIMD::I16x8SubSatU => zip(stack, u16::saturating_sub),
+        SIMD::I16x8Mul => zip(stack, i16::wrapping_mul),
+        SIMD::I16x8MinS => zip(stack, i16::min),
+        SIMD::I16x8MinU => zip(stack, u16::min),
+        SIMD::I16x8MaxS => zip(stack, i16::max),
+        SIMD::I16x8MaxU => zip(stack, u16::max),
+        SIMD::I16x8AvgrU => zip(stack, |a: u16, b| {
+            (u32::from(a) + u32::from(b)).div_ceil(2) as u16
+        }),
+        SIMD::I16x8Q15mulrSatS => zip(stack, |a: i16, b| {
+            ((i32::from(a) * i32::from(b) + 0x4000) >> 15).clamp(i16::MIN.into(), i16::MAX.into())
+                as i16
+        }),
+        SIMD::I16x8ExtaddPairwiseI8x16S => pairwise(stack, |a: i8, b| i16::from(a) + i16::from(b)),
+        SIMD::I16x8ExtaddPairwiseI8x16U => pairwise(stack, |a: u8, b| u16::from(a) + u16::from(b)),
+        SIMD::I16x8ExtmulLowI8x16S => extmul(stack, false, |a: i8, b| i16::from(a) * i16::from(b)),
+        SIMD::I16x8ExtmulHighI8x16S => extmul(stack, true, |a: i8, b| i16::from(a) * i16::from(b)),
+        SIMD::I16x8ExtmulLowI8x16U => extmul(stack, false, |a: u8, b| u16::from(a) * u16::from(b)),
+        SIMD::I16x8ExtmulHighI8x16U => extmul(stack, true, |a: u8, b| u16::from(a) * u16::from(b)),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn wide_int_op(op: &SIMD, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match op {
+        SIMD::I32x4Abs => map(stack, i32::wrapping_abs),
+        SIMD::I32x4Neg => map(stack, i32::wrapping_neg),
+        SIMD::I32x4AllTrue => test(stack, |a: &[u32]| a.iter().all(|&a| a != 0)),
+        SIMD::I32x4Bitmask => bitmask(stack, |a: i32| a < 0),
+        SIMD::I32x4ExtendLowI16x8S => extend(stack, false, |a: i16| i32::from(a)),
+        SIMD::I32x4ExtendHighI16x8S => extend(stack, true, |a: i16| i32::from(a)),
+        SIMD::I32x4ExtendLowI16x8U => extend(stack, false, |a: u16| u32::from(a)),
+        SIMD::I32x4ExtendHighI16x8U => extend(stack, true, |a: u16| u32::from(a)),
+        SIMD::I32x4Shl => shift(stack, i32::wrapping_shl),
+        SIMD::I32x4ShrS => shift(stack, i32::wrapping_shr),
+        SIMD::I32x4ShrU => shift(stack, u32::wrapping_shr),
+        SIMD::I32x4Add => zip(stack, i32::wrapping_add),
+        SIMD::I32x4Sub => zip(stack, i32::wrapping_sub),
+        SIMD::I32x4Mul => zip(stack, i32::wrapping_mul),
+        SIMD::I32x4MinS => zip(stack, i32::min),
+        SIMD::I32x4MinU => zip(stack, u32::min),
+        SIMD::I32x4MaxS => zip(stack, i32::max),
+        SIMD::I32x4MaxU => zip(stack, u32::max),
+        SIMD::I32x4DotI16x8S => {
+            let b = lanes::<i16>(pop(stack)?);
+            let a = lanes::<i16>(pop(stack)?);
+            let products = a.iter().zip(&b).map(|(&a, &b)| i32::from(a) * i32::from(b));
+            let products = products.collect::<Vec<_>>();
+            push(
+                stack,
+                from_lanes(
+                    products
+                        .chunks_exact(2)
+                        .map(|pair| pair[0].wrapping_add(pair[1])),
+                ),
+            )
+        }
+        SIMD::I32x4ExtaddPairwiseI16x8S => pairwise(stack, |a: i16, b| i32::from(a) + i32::from(b)),
+        SIMD::I32x4ExtaddPairwiseI16x8U => pairwise(stack, |a: u16, b| u32::from(a) + u32::from(b)),
+        SIMD::I32x4ExtmulLowI16x8S => extmul(stack, false, |a: i16, b| i32::from(a) * i32::from(b)),
+        SIMD::I32x4ExtmulHighI16x8S => extmul(stack, true, |a: i16, b| i32::from(a) * i32::from(b)),
+        SIMD::I32x4ExtmulLowI16x8U => extmul(stack, false, |a: u16, b| u32::from(a) * u32::from(b)),
+        SIMD::I32x4ExtmulHighI16x8U => extmul(stack, true, |a: u16, b| u32::from(a) * u32::from(b)),
+        SIMD::I64x2Abs => map(stack, i64::wrapping_abs),
+        SIMD::I64x2Neg => map(stack, i64::wrapping_neg),
+        SIMD::I64x2AllTrue => test(stack, |a: &[u64]| a.iter().all(|&a| a != 0)),
+        SIMD::I64x2Bitmask => bitmask(stack, |a: i64| a < 0),
+        SIMD::I64x2ExtendLowI32x4S => extend(stack, false, |a: i32| i64::from(a)),
+        SIMD::I64x2ExtendHighI32x4S => extend(stack, true, |a: i32| i64::from(a)),
+        SIMD::I64x2ExtendLowI32x4U => extend(stack, false, |a: u32| u64::from(a)),
+        SIMD::I64x2ExtendHighI32x4U => extend(stack, true, |a: u32| u64::from(a)),
+        SIMD::I64x2Shl => shift(stack, i64::wrapping_shl),
+        SIMD::I64x2ShrS => shift(stack, i64::wrapping_shr),
+        SIMD::I64x2ShrU => shift(stack, u64::wrapping_shr),
+        SIMD::I64x2Add => zip(stack, i64::wrapping_add),
+        SIMD::I64x2Sub => zip(stack, i64::wrapping_sub),
+        SIMD::I64x2Mul => zip(stack, i64::wrapping_mul),
+        SIMD::I64x2ExtmulLowI32x4S => extmul(stack, false, |a: i32, b| i64::from(a) * i64::from(b)),
+        SIMD::I64x2ExtmulHighI32x4S => extmul(stack, true, |a: i32, b| i64::from(a) * i64::from(b)),
+        SIMD::I64x2ExtmulLowI32x4U => extmul(stack, false, |a: u32, b| u64::from(a) * u64::from(b)),
+        SIMD::I64x2ExtmulHighI32x4U => extmul(stack, true, |a: u32, b| u64::from(a) * u64::from(b)),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+fn float_op(op: &SIMD, stack: &mut Vec<Value>) -> Result<bool, Trap> {
+    match op {
+        SIMD::F32x4Ceil => map(stack, f32::ceil),
+        SIMD::F32x4Floor => map(stack, f32::floor),
+        SIMD::F32x4Trunc => map(stack, f32::trunc),
+        SIMD::F32x4Nearest => map(stack, f32::round_ties_even),
+        SIMD::F32x4Abs => map(stack, f32::abs),
+        SIMD::F32x4Neg => map(stack, |a: f32| -a),
+        SIMD::F32x4Sqrt => map(stack, f32::sqrt),
+        SIMD::F32x4Add => zip(stack, |a: f32, b| a + b),
+        SIMD::F32x4Sub => zip(stack, |a: f32, b| a - b),
+        SIMD::F32x4Mul => zip(stack, |a: f32, b| a * b),
+        SIMD::F32x4Div => zip(stack, |a: f32, b| a / b),
+        SIMD::F32x4Min => zip(stack, f32_min),
+        SIMD::F32x4Max => zip(stack, f32_max),
+        SIMD::F32x4Pmin => zip(stack, |a: f32, b| if b < a { b } else { a }),
+        SIMD::F32x4Pmax => zip(stack, |a: f32, b| if a < b { b } else { a }),
+        SIMD::F64x2Ceil => map(stack, f64::ceil),
+        SIMD::F64x2Floor => map(stack, f64::floor),
+        SIMD::F64x2Trunc => map(stack, f64::trunc),
+        SIMD::F64x2Nearest => map(stack, f64::round_ties_even),
+        SIMD::F64x2Abs => map(stack, f64::abs),
+        SIMD::F64x2Neg => map(stack, |a: f64| -a),
+        SIMD::F64x2Sqrt => map(stack, f64::sqrt),
+        SIMD::F64x2Add => zip(stack, |a: f64, b| a + b),
+        SIMD::F64x2Sub => zip(stack, |a: f64, b| a - b),
+        SIMD::F64x2Mul => zip(stack, |a: f64, b| a * b),
+        SIMD::F64x2Div => zip(stack, |a: f64, b| a / b),
+        SIMD::F64x2Min => zip(stack, f64_min),
+        SIMD::F64x2Max => zip(stack, f64_max),
+        SIMD::F64x2Pmin => zip(stack, |a: f64, b| if b < a { b } else { a }),
+        SIMD::F64x2Pmax => zip(stack, |a: f64, b| if a < b { b } else { a }),
+        SIMD::I32x4TruncSatF32x4S => map(stack, |a: f32| a as i32),
+        SIMD::I32x4TruncSatF32x4U => map(stack, |a: f32| a as u32),
+        SIMD::F32x4ConvertI32x4S => map(stack, |a: i32| a as f32),
+        SIMD::F32x4ConvertI32x4U => map(stack, |a: u32| a as f32),
+        SIMD::F64x2ConvertLowI32x4S => extend(stack, false, |a: i32| f64::from(a)),
+        SIMD::F64x2ConvertLowI32x4U => extend(stack, false, |a: u32| f64::from(a)),
+        SIMD::I32x4TruncSatF64x2SZero => map(stack, |a: f64| a as i32),
+        SIMD::I32x4TruncSatF64x2UZero => map(stack, |a: f64| a as u32),
+        SIMD::F32x4DemoteF64x2Zero => map(stack, |a: f64| a as f32),
+        SIMD::F64x2PromoteLowF32x4 => extend(stack, false, |a: f32| f64::from(a)),
+        _ => return Ok(false),
+    }?;
+    Ok(true)
+}
+
+pub(super) fn eval<H: Host>(m: &mut Machine<'_, H>, op: &SIMD) -> Result<(), Trap> {
+    if memory_op(m, op)?
+        || lane_op(op, &mut m.stack)?
+        || compare_op(op, &mut m.stack)?
+        || narrow_int_op(op, &mut m.stack)?
+        || wide_int_op(op, &mut m.stack)?
+        || float_op(op, &mut m.stack)?
+    {
+        Ok(())
+    } else {
+        Err(Trap::Unsupported(Box::new(Instruction::SIMD(op.clone()))))
+    }
+}
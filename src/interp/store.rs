@@ -0,0 +1,162 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Trap, Value};
+use crate::types::{MemType, TableType};
+
+/// Linear memory instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Memory {
+    pub data: Vec<u8>,
+    /// Size of a single page in bytes.
+    pub page_size: u64,
+    /// Maximum size in pages.
+    pub max_pages: Option<u64>,
+}
+
+impl Memory {
+    /// Create a memory of the minimum size allowed by its type.
+    pub fn new(ty: &MemType) -> Self {
+        let page_size = ty.page_bytes();
+        let size = u64::from(ty.limits.min).saturating_mul(page_size);
+        Self {
+            data: vec![0; usize::try_from(size).unwrap_or(usize::MAX)],
+            page_size,
+            max_pages: ty.limits.max.map(u64::from),
+        }
+    }
+
+    /// Current size in pages.
+    pub fn pages(&self) -> u64 {
+        self.data.len() as u64 / self.page_size
+    }
+
+    /// Grow the memory by `delta` pages, returning the previous size or `None` if the new size
+    /// would exceed the limits.
+    pub fn grow(&mut self, delta: u64) -> Option<u64> {
+        let old = self.pages();
+        // Memories are indexed with 32-bit addresses, so they can't grow past 4 GiB.
+        let limit = ((1 << 32) / self.page_size).min(u64::from(u32::MAX));
+        let new = old
+            .checked_add(delta)
+            .filter(|&new| new <= self.max_pages.unwrap_or(limit).min(limit))?;
+        self.data
+            .resize(usize::try_from(new * self.page_size).ok()?, 0);
+        Some(old)
+    }
+
+    fn range(&self, addr: u64, len: usize) -> Result<std::ops::Range<usize>, Trap> {
+        usize::try_from(addr)
+            .ok()
+            .and_then(|start| Some(start..start.checked_add(len)?))
+            .filter(|range| range.end <= self.data.len())
+            .ok_or(Trap::MemoryOutOfBounds)
+    }
+
+    /// Read `len` bytes starting at `addr`.
+    pub fn read(&self, addr: u64, len: usize) -> Result<&[u8], Trap> {
+        let range = self.range(addr, len)?;
+        Ok(&self.data[range])
+    }
+
+    /// Write bytes starting at `addr`.
+    pub fn write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), Trap> {
+        let range = self.range(addr, bytes.len())?;
+        self.data[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Set `len` bytes starting at `addr` to `value`.
+    pub fn fill(&mut self, addr: u64, len: usize, value: u8) -> Result<(), Trap> {
+        let range = self.range(addr, len)?;
+        self.data[range].fill(value);
+        Ok(())
+    }
+}
+
+/// Table instance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub elements: Vec<Value>,
+    /// Maximum number of elements.
+    pub max: Option<u32>,
+}
+
+impl Table {
+    /// Create a table of the minimum size allowed by its type, filled with nulls.
+    pub fn new(ty: &TableType) -> Self {
+        Self {
+            elements: vec![Value::Null(ty.elem_type.clone()); ty.limits.min as usize],
+            max: ty.limits.max,
+        }
+    }
+
+    /// Current number of elements.
+    pub fn size(&self) -> u32 {
+        // Growth is limited to `u32` sizes.
+        u32::try_from(self.elements.len()).unwrap_or(u32::MAX)
+    }
+
+    /// Grow the table by `delta` elements set to `init`, returning the previous size or `None`
+    /// if the new size would exceed the limits.
+    pub fn grow(&mut self, delta: u32, init: Value) -> Option<u32> {
+        let old = self.size();
+        let new = old
+            .checked_add(delta)
+            .filter(|&new| self.max.is_none_or(|max| new <= max))?;
+        self.elements.resize(new as usize, init);
+        Some(old)
+    }
+
+    fn range(&self, start: u64, len: usize) -> Result<std::ops::Range<usize>, Trap> {
+        usize::try_from(start)
+            .ok()
+            .and_then(|start| Some(start..start.checked_add(len)?))
+            .filter(|range| range.end <= self.elements.len())
+            .ok_or(Trap::TableOutOfBounds)
+    }
+
+    /// Read `len` elements starting at `start`.
+    pub fn read(&self, start: u64, len: usize) -> Result<&[Value], Trap> {
+        let range = self.range(start, len)?;
+        Ok(&self.elements[range])
+    }
+
+    /// Write elements starting at `start`.
+    pub fn write(&mut self, start: u64, values: &[Value]) -> Result<(), Trap> {
+        let range = self.range(start, values.len())?;
+        self.elements[range].clone_from_slice(values);
+        Ok(())
+    }
+
+    /// Set `len` elements starting at `start` to `value`.
+    pub fn fill(&mut self, start: u64, len: usize, value: &Value) -> Result<(), Trap> {
+        let range = self.range(start, len)?;
+        self.elements[range].fill(value.clone());
+        Ok(())
+    }
+}
+
+/// Mutable state of an [`Instance`](super::Instance), indexed by [`MemId`](crate::indices::MemId),
+/// [`TableId`](crate::indices::TableId) and [`GlobalId`](crate::indices::GlobalId) respectively.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Store {
+    pub memories: Vec<Memory>,
+    pub tables: Vec<Table>,
+    pub globals: Vec<Value>,
+    /// Contents of data segments; dropped segments are empty.
+    pub(crate) data: Vec<Vec<u8>>,
+    /// Contents of element segments; dropped segments are empty.
+    pub(crate) elems: Vec<Vec<Value>>,
+}
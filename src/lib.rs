@@ -30,11 +30,21 @@
 #![doc = include_str!("../README.md")]
 
 pub mod builtins;
+#[cfg(feature = "component-model")]
+pub mod component;
+#[cfg(feature = "dwarf")]
+pub mod dwarf;
+pub mod features;
 pub mod indices;
 pub mod instructions;
+#[cfg(feature = "interp")]
+pub mod interp;
 pub mod io;
 mod module;
 pub mod sections;
+#[cfg(feature = "source-maps")]
+pub mod source_map;
+pub mod transforms;
 pub mod types;
 pub mod visit;
 
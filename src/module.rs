@@ -16,8 +16,9 @@
 
 use crate::builtins::Blob;
 use crate::io::{encode_decode_as, Decode, DecodeError, DecodeErrorKind, Encode, Wasmbin};
-use crate::sections::{Section, StdPayload};
+use crate::sections::{encode_sections, Section, StdPayload};
 use crate::visit::Visit;
+use std::borrow::Borrow;
 use std::cmp::Ordering;
 
 const MAGIC_AND_VERSION: [u8; 8] = [b'\0', b'a', b's', b'm', 0x01, 0x00, 0x00, 0x00];
@@ -63,6 +64,16 @@ impl Encode for Module {
     }
 }
 
+/// Encode a module consisting of the given sections without assembling them into a [`Module`].
+pub(crate) fn encode_module_sections<W: std::io::Write>(
+    sections: impl IntoIterator<Item = impl Borrow<Section>>,
+    mut w: W,
+) -> std::io::Result<W> {
+    MagicAndVersion.encode(&mut w)?;
+    encode_sections(sections, &mut w)?;
+    Ok(w)
+}
+
 impl Decode for Module {
     fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
         ModuleRepr::decode(r).map(|repr| unsafe { std::mem::transmute::<ModuleRepr, Module>(repr) })
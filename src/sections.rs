@@ -24,12 +24,17 @@ use crate::instructions::Expression;
 use crate::io::{Decode, DecodeError, DecodeWithDiscriminant, Encode, PathItem, Wasmbin};
 #[cfg(feature = "exception-handling")]
 use crate::types::ExceptionType;
-use crate::types::{FuncType, GlobalType, MemType, RefType, TableType, ValueType};
+use crate::types::{GlobalType, MemType, RefType, TableType, TypeDef, ValueType};
 use crate::visit::{Visit, VisitError};
 use custom_debug::Debug as CustomDebug;
+use std::borrow::Borrow;
 use std::convert::TryFrom;
 use thiserror::Error;
 
+pub mod dylink;
+pub mod linking;
+pub mod names;
+
 /// A [name association](https://webassembly.github.io/spec/core/appendix/custom.html#binary-namemap) key-value pair.
 ///
 /// Might also be used to represent an [indirect name association](https://webassembly.github.io/spec/core/appendix/custom.html#binary-indirectnamemap).
@@ -81,6 +86,12 @@ pub enum NameSubSection {
     #[cfg(feature = "extended-name-section")]
     /// Data segment names.
     Data(Blob<NameMap<DataId>>) = 9,
+    #[cfg(feature = "extended-name-section")]
+    /// [Field names](https://github.com/WebAssembly/gc/blob/main/proposals/gc/MVP.md#field-names) grouped by struct type index.
+    Field(Blob<IndirectNameMap<TypeId, u32>>) = 10,
+    #[cfg(all(feature = "extended-name-section", feature = "exception-handling"))]
+    /// [Tag names](https://github.com/WebAssembly/exception-handling/blob/main/proposals/exception-handling/Exceptions.md#tag-names).
+    Tag(Blob<NameMap<ExceptionId>>) = 11,
 }
 
 impl Encode for [NameSubSection] {
@@ -120,6 +131,76 @@ pub struct ProducerVersionedName {
     pub version: String,
 }
 
+/// Policy prefix of a [`TargetFeature`].
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum FeaturePolicy {
+    /// The feature is used by the module (`+`).
+    Used = b'+',
+    /// The feature must not be used by any module linked together with this one (`-`).
+    Disallowed = b'-',
+    /// The feature must be used by all modules linked together with this one (`=`).
+    Required = b'=',
+}
+
+/// [`target_features`](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#target-features-section) entry.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct TargetFeature {
+    pub policy: FeaturePolicy,
+    pub name: String,
+}
+
+/// Direction of a [`BranchHint`].
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum BranchHintValue {
+    Unlikely = 0,
+    Likely = 1,
+}
+
+/// A [branch hint](https://github.com/WebAssembly/branch-hinting/blob/main/proposals/branch-hinting/Overview.md#binary-format)
+/// for a `br_if` or `if` instruction.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct BranchHint {
+    /// Byte offset of the instruction relative to the start of the function body.
+    pub offset: u32,
+    pub value: BranchHintValue,
+}
+
+impl WasmbinCountable for BranchHint {}
+
+// Size of the hint value in bytes, which is always 1 for branch hints.
+const BRANCH_HINT_SIZE: u32 = 1;
+
+impl Encode for BranchHint {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.offset.encode(w)?;
+        BRANCH_HINT_SIZE.encode(w)?;
+        self.value.encode(w)
+    }
+}
+
+impl Decode for BranchHint {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let offset = u32::decode(r)?;
+        let size = u32::decode(r)?;
+        if size != BRANCH_HINT_SIZE {
+            return Err(DecodeError::unsupported_discriminant::<Self>(size));
+        }
+        Ok(BranchHint {
+            offset,
+            value: BranchHintValue::decode(r)?,
+        })
+    }
+}
+
+/// Branch hints of a single function.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct FuncBranchHints {
+    pub func: FuncId,
+    pub hints: Vec<BranchHint>,
+}
+
 /// A raw [custom section](https://webassembly.github.io/spec/core/binary/modules.html#custom-section).
 ///
 /// Used to represent custom sections with unknown semantics.
@@ -147,6 +228,9 @@ macro_rules! define_custom_sections {
                 #[doc = ") custom section."]
                 $name($ty),
             )*
+            /// [Relocation section](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#relocation-sections)
+            /// named `reloc.*`.
+            Reloc(linking::RelocSection),
             /// A custom section that is not recognized by this library.
             Other(RawCustomSection),
         }
@@ -156,6 +240,7 @@ macro_rules! define_custom_sections {
             pub fn name(&self) -> &str {
                 match self {
                     $(Self::$name(_) => $disc,)*
+                    Self::Reloc(reloc) => reloc.name.as_str(),
                     Self::Other(raw) => raw.name.as_str(),
                 }
             }
@@ -168,6 +253,7 @@ macro_rules! define_custom_sections {
                         $disc.encode(w)?;
                         data.encode(w)
                     })*
+                    CustomSection::Reloc(reloc) => reloc.encode(w),
                     CustomSection::Other(raw) => raw.encode(w)
                 }
             }
@@ -178,6 +264,10 @@ macro_rules! define_custom_sections {
                 let name = String::decode(r)?;
                 Ok(match name.as_str() {
                     $($disc => CustomSection::$name(<$ty>::decode(r)?),)*
+                    _ if name.starts_with("reloc.") => CustomSection::Reloc(linking::RelocSection {
+                        name,
+                        reloc: Lazy::decode(r)?
+                    }),
                     _ => CustomSection::Other(RawCustomSection {
                         name,
                         data: UnparsedBytes::decode(r)?
@@ -194,6 +284,7 @@ macro_rules! define_custom_sections {
                 // Custom section decoding errors must be ignored.
                 drop(match self {
                     $(CustomSection::$name(data) => Visit::visit_child(data, f),)*
+                    CustomSection::Reloc(reloc) => Visit::visit_child(reloc, f),
                     CustomSection::Other(raw) => Visit::visit_child(raw, f),
                 });
                 Ok(())
@@ -206,6 +297,7 @@ macro_rules! define_custom_sections {
                 // Custom section decoding errors must be ignored.
                 drop(match self {
                     $(CustomSection::$name(data) => Visit::visit_child_mut(data, f),)*
+                    CustomSection::Reloc(reloc) => Visit::visit_child_mut(reloc, f),
                     CustomSection::Other(raw) => Visit::visit_child_mut(raw, f),
                 });
                 Ok(())
@@ -225,6 +317,14 @@ define_custom_sections! {
     SourceMappingUrl(Lazy<String>) = "sourceMappingURL",
     /// https://github.com/WebAssembly/tool-conventions/blob/9b80cd2339c648822bb845a083d9ffa6e20fb1ee/BuildId.md
     BuildId(Vec<u8>) = "build_id",
+    /// https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#linking-metadata-section
+    Linking(Lazy<linking::Linking>) = "linking",
+    /// https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#target-features-section
+    TargetFeatures(Lazy<Vec<TargetFeature>>) = "target_features",
+    /// https://github.com/WebAssembly/branch-hinting/blob/main/proposals/branch-hinting/Overview.md
+    BranchHints(Lazy<Vec<FuncBranchHints>>) = "metadata.code.branch_hint",
+    /// https://github.com/WebAssembly/tool-conventions/blob/main/DynamicLinking.md#the-dylink0-section
+    Dylink0(Lazy<Vec<dylink::DylinkSubSection>>) = "dylink.0",
 }
 
 /// [Import descriptor](https://webassembly.github.io/spec/core/binary/modules.html#binary-importdesc).
@@ -520,7 +620,7 @@ define_sections! {
     /// [Custom section](https://webassembly.github.io/spec/core/binary/modules.html#custom-section).
     Custom(super::CustomSection) = 0,
     /// [Type section](https://webassembly.github.io/spec/core/binary/modules.html#type-section).
-    Type(Vec<super::FuncType>) = 1,
+    Type(Vec<super::TypeDef>) = 1,
     /// [Import section](https://webassembly.github.io/spec/core/binary/modules.html#import-section).
     Import(Vec<super::Import>) = 2,
     /// [Function section](https://webassembly.github.io/spec/core/binary/modules.html#function-section).
@@ -598,14 +698,23 @@ impl SectionOrderTracker {
     }
 }
 
+/// Encode a sequence of sections, checking their order.
+pub(crate) fn encode_sections(
+    sections: impl IntoIterator<Item = impl Borrow<Section>>,
+    w: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    let mut section_order_tracker = SectionOrderTracker::default();
+    for section in sections {
+        let section = section.borrow();
+        section_order_tracker.try_add(section)?;
+        section.encode(w)?;
+    }
+    Ok(())
+}
+
 impl Encode for [Section] {
     fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        let mut section_order_tracker = SectionOrderTracker::default();
-        for section in self {
-            section_order_tracker.try_add(section)?;
-            section.encode(w)?;
-        }
-        Ok(())
+        encode_sections(self, w)
     }
 }
 
@@ -0,0 +1,124 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Dynamic linking metadata](https://github.com/WebAssembly/tool-conventions/blob/main/DynamicLinking.md)
+//! custom section used by WebAssembly shared libraries.
+
+use super::linking::SymbolFlags;
+use crate::builtins::{Blob, WasmbinCountable};
+use crate::io::{Decode, DecodeError, DecodeWithDiscriminant, Encode, PathItem, Wasmbin};
+use crate::visit::Visit;
+
+/// Memory and table requirements of a shared library.
+#[derive(Wasmbin, Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct MemInfo {
+    /// Size of the data segments in bytes.
+    pub memory_size: u32,
+    /// Required alignment of the memory region as a power of two.
+    pub memory_alignment_log2: u32,
+    /// Number of table elements used by the library.
+    pub table_size: u32,
+    /// Required alignment of the table region as a power of two.
+    pub table_alignment_log2: u32,
+}
+
+/// Additional symbol information for an export.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ExportInfo {
+    pub name: String,
+    pub flags: SymbolFlags,
+}
+
+/// Additional symbol information for an import.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ImportInfo {
+    pub module: String,
+    pub name: String,
+    pub flags: SymbolFlags,
+}
+
+/// A subsection of the [`dylink.0`](https://github.com/WebAssembly/tool-conventions/blob/main/DynamicLinking.md#the-dylink0-section)
+/// custom section.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum DylinkSubSection {
+    MemInfo(Blob<MemInfo>) = 1,
+    /// Names of the shared libraries this one depends on.
+    Needed(Blob<Vec<String>>) = 2,
+    ExportInfo(Blob<Vec<ExportInfo>>) = 3,
+    ImportInfo(Blob<Vec<ImportInfo>>) = 4,
+    /// Paths to search for the [needed](DylinkSubSection::Needed) libraries in.
+    RuntimePath(Blob<Vec<String>>) = 5,
+    /// A subsection that is not recognized by this library.
+    Other(RawDylinkSubSection),
+}
+
+/// A raw subsection of the `dylink.0` custom section.
+///
+/// Used to preserve subsections with unknown semantics.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct RawDylinkSubSection {
+    pub id: u8,
+    pub data: Vec<u8>,
+}
+
+impl Encode for RawDylinkSubSection {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.id.encode(w)?;
+        self.data.encode(w)
+    }
+}
+
+impl Decode for RawDylinkSubSection {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        Self::decode_without_discriminant(r)
+    }
+}
+
+impl DecodeWithDiscriminant for RawDylinkSubSection {
+    type Discriminant = u8;
+
+    fn maybe_decode_with_discriminant(
+        id: u8,
+        r: &mut impl std::io::Read,
+    ) -> Result<Option<Self>, DecodeError> {
+        Ok(Some(RawDylinkSubSection {
+            id,
+            data: Vec::decode(r)?,
+        }))
+    }
+}
+
+impl Encode for [DylinkSubSection] {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        for sub in self {
+            sub.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode for Vec<DylinkSubSection> {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let mut sub = Vec::new();
+        while let Some(disc) = Option::decode(r)? {
+            let i = sub.len();
+            sub.push(
+                DylinkSubSection::decode_with_discriminant(disc, r)
+                    .map_err(move |err| err.in_path(PathItem::Index(i)))?,
+            );
+        }
+        Ok(sub)
+    }
+}
@@ -0,0 +1,674 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Linking metadata](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md)
+//! custom sections used by WebAssembly object files.
+
+use crate::builtins::{Blob, Lazy, WasmbinCountable};
+use crate::indices::{DataId, FuncId, GlobalId, TableId};
+use crate::io::{Decode, DecodeError, DecodeWithDiscriminant, Encode, PathItem, Wasmbin};
+use crate::visit::Visit;
+use thiserror::Error;
+
+/// Symbol flags (`WASM_SYM_*`) of a [`SymbolInfo`].
+#[derive(Wasmbin, Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+pub struct SymbolFlags(pub u32);
+
+impl SymbolFlags {
+    /// The symbol can be overridden by a strong definition.
+    pub const BINDING_WEAK: Self = Self(0x01);
+    /// The symbol is not visible outside of the object file.
+    pub const BINDING_LOCAL: Self = Self(0x02);
+    /// The symbol is not exported from the linked module.
+    pub const VISIBILITY_HIDDEN: Self = Self(0x04);
+    /// The symbol refers to an import.
+    pub const UNDEFINED: Self = Self(0x10);
+    /// The symbol is exported from the linked module.
+    pub const EXPORTED: Self = Self(0x20);
+    /// The symbol name is present even though the symbol is undefined.
+    pub const EXPLICIT_NAME: Self = Self(0x40);
+    /// The symbol must not be stripped by the linker.
+    pub const NO_STRIP: Self = Self(0x80);
+    /// The symbol is a thread-local data symbol.
+    pub const TLS: Self = Self(0x100);
+    /// The symbol has an absolute address.
+    pub const ABSOLUTE: Self = Self(0x200);
+
+    /// Check whether all the given flags are set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn has_name(self) -> bool {
+        !self.contains(Self::UNDEFINED) || self.contains(Self::EXPLICIT_NAME)
+    }
+}
+
+impl std::ops::BitOr for SymbolFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Location of a defined data symbol.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct DataSymbolDef {
+    pub segment: DataId,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Kind-specific part of a [`SymbolInfo`].
+///
+/// Names of function, global, tag and table symbols are present only for
+/// defined symbols or when [`SymbolFlags::EXPLICIT_NAME`] is set, and data
+/// symbols have a definition only when they're not [`SymbolFlags::UNDEFINED`].
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum SymbolKind {
+    Func {
+        index: FuncId,
+        name: Option<String>,
+    } = 0,
+    Data {
+        name: String,
+        def: Option<DataSymbolDef>,
+    } = 1,
+    Global {
+        index: GlobalId,
+        name: Option<String>,
+    } = 2,
+    Section {
+        section: u32,
+    } = 3,
+    Tag {
+        index: u32,
+        name: Option<String>,
+    } = 4,
+    Table {
+        index: TableId,
+        name: Option<String>,
+    } = 5,
+}
+
+/// An entry of the [symbol table](LinkingSubSection::SymbolTable).
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct SymbolInfo {
+    pub flags: SymbolFlags,
+    pub kind: SymbolKind,
+}
+
+impl WasmbinCountable for SymbolInfo {}
+
+fn mismatched_flags() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "symbol contents don't match its flags",
+    )
+}
+
+fn encode_if_present<T: Encode>(
+    value: Option<&T>,
+    expected: bool,
+    w: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    match (value, expected) {
+        (Some(value), true) => value.encode(w),
+        (None, false) => Ok(()),
+        _ => Err(mismatched_flags()),
+    }
+}
+
+fn decode_if_present<T: Decode>(
+    expected: bool,
+    r: &mut impl std::io::Read,
+) -> Result<Option<T>, DecodeError> {
+    Ok(match expected {
+        true => Some(T::decode(r)?),
+        false => None,
+    })
+}
+
+impl Encode for SymbolInfo {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        let has_name = self.flags.has_name();
+        match &self.kind {
+            SymbolKind::Func { index, name } => {
+                0_u8.encode(w)?;
+                self.flags.encode(w)?;
+                index.encode(w)?;
+                encode_if_present(name.as_ref(), has_name, w)
+            }
+            SymbolKind::Data { name, def } => {
+                1_u8.encode(w)?;
+                self.flags.encode(w)?;
+                name.encode(w)?;
+                encode_if_present(
+                    def.as_ref(),
+                    !self.flags.contains(SymbolFlags::UNDEFINED),
+                    w,
+                )
+            }
+            SymbolKind::Global { index, name } => {
+                2_u8.encode(w)?;
+                self.flags.encode(w)?;
+                index.encode(w)?;
+                encode_if_present(name.as_ref(), has_name, w)
+            }
+            SymbolKind::Section { section } => {
+                3_u8.encode(w)?;
+                self.flags.encode(w)?;
+                section.encode(w)
+            }
+            SymbolKind::Tag { index, name } => {
+                4_u8.encode(w)?;
+                self.flags.encode(w)?;
+                index.encode(w)?;
+                encode_if_present(name.as_ref(), has_name, w)
+            }
+            SymbolKind::Table { index, name } => {
+                5_u8.encode(w)?;
+                self.flags.encode(w)?;
+                index.encode(w)?;
+                encode_if_present(name.as_ref(), has_name, w)
+            }
+        }
+    }
+}
+
+impl Decode for SymbolInfo {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let discriminant = u8::decode(r)?;
+        let flags = SymbolFlags::decode(r)?;
+        let has_name = flags.has_name();
+        let kind = match discriminant {
+            0 => SymbolKind::Func {
+                index: FuncId::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            1 => SymbolKind::Data {
+                name: String::decode(r)?,
+                def: decode_if_present(!flags.contains(SymbolFlags::UNDEFINED), r)?,
+            },
+            2 => SymbolKind::Global {
+                index: GlobalId::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            3 => SymbolKind::Section {
+                section: u32::decode(r)?,
+            },
+            4 => SymbolKind::Tag {
+                index: u32::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            5 => SymbolKind::Table {
+                index: TableId::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            _ => {
+                return Err(DecodeError::unsupported_discriminant::<SymbolInfo>(
+                    discriminant,
+                ))
+            }
+        };
+        Ok(SymbolInfo { flags, kind })
+    }
+}
+
+/// Data segment metadata in the [segment info](LinkingSubSection::SegmentInfo) subsection.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct SegmentInfo {
+    pub name: String,
+    /// Alignment of the segment as a power of two.
+    pub alignment_log2: u32,
+    /// Segment flags (`WASM_SEG_FLAG_*`).
+    pub flags: u32,
+}
+
+/// An [initialization function](LinkingSubSection::InitFuncs) entry.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct InitFunc {
+    pub priority: u32,
+    /// Index of the function symbol in the [symbol table](LinkingSubSection::SymbolTable).
+    pub symbol: u32,
+}
+
+/// Kind of a [`ComdatSym`].
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum ComdatSymKind {
+    Data = 0,
+    Func = 1,
+    Global = 2,
+    Tag = 3,
+    Table = 4,
+    Section = 5,
+}
+
+/// A member of a [`Comdat`].
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct ComdatSym {
+    pub kind: ComdatSymKind,
+    /// Index of the data segment, function, global, tag, table or section
+    /// depending on the [`kind`](ComdatSym::kind).
+    pub index: u32,
+}
+
+/// A group of entities deduplicated as a whole by the linker.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct Comdat {
+    pub name: String,
+    pub flags: u32,
+    pub syms: Vec<ComdatSym>,
+}
+
+/// A subsection of the [`linking`](Linking) custom section.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum LinkingSubSection {
+    SegmentInfo(Blob<Vec<SegmentInfo>>) = 5,
+    InitFuncs(Blob<Vec<InitFunc>>) = 6,
+    ComdatInfo(Blob<Vec<Comdat>>) = 7,
+    SymbolTable(Blob<Vec<SymbolInfo>>) = 8,
+}
+
+impl Encode for [LinkingSubSection] {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        for sub in self {
+            sub.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode for Vec<LinkingSubSection> {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let mut sub = Vec::new();
+        while let Some(disc) = Option::decode(r)? {
+            let i = sub.len();
+            sub.push(
+                LinkingSubSection::decode_with_discriminant(disc, r)
+                    .map_err(move |err| err.in_path(PathItem::Index(i)))?,
+            );
+        }
+        Ok(sub)
+    }
+}
+
+/// Contents of the [`linking`](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#linking-metadata-section)
+/// custom section.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct Linking {
+    /// Version of the linking metadata, currently 2.
+    pub version: u32,
+    pub subsections: Vec<LinkingSubSection>,
+}
+
+/// [Relocation type](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#relocation-sections)
+/// (`R_WASM_*`).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[repr(u8)]
+pub enum RelocType {
+    FunctionIndexLeb = 0,
+    TableIndexSleb = 1,
+    TableIndexI32 = 2,
+    MemoryAddrLeb = 3,
+    MemoryAddrSleb = 4,
+    MemoryAddrI32 = 5,
+    TypeIndexLeb = 6,
+    GlobalIndexLeb = 7,
+    FunctionOffsetI32 = 8,
+    SectionOffsetI32 = 9,
+    TagIndexLeb = 10,
+    MemoryAddrRelSleb = 11,
+    TableIndexRelSleb = 12,
+    GlobalIndexI32 = 13,
+    MemoryAddrLeb64 = 14,
+    MemoryAddrSleb64 = 15,
+    MemoryAddrI64 = 16,
+    MemoryAddrRelSleb64 = 17,
+    TableIndexSleb64 = 18,
+    TableIndexI64 = 19,
+    TableNumberLeb = 20,
+    MemoryAddrTlsSleb = 21,
+    FunctionOffsetI64 = 22,
+    MemoryAddrLocrelI32 = 23,
+    TableIndexRelSleb64 = 24,
+    MemoryAddrTlsSleb64 = 25,
+    FunctionIndexI32 = 26,
+}
+
+/// Encoding of the value at a relocation site.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum RelocSite {
+    Leb32,
+    Sleb32,
+    I32,
+    Leb64,
+    Sleb64,
+    I64,
+}
+
+impl RelocSite {
+    const fn size(self) -> usize {
+        match self {
+            RelocSite::Leb32 | RelocSite::Sleb32 => 5,
+            RelocSite::I32 => 4,
+            RelocSite::Leb64 | RelocSite::Sleb64 => 10,
+            RelocSite::I64 => 8,
+        }
+    }
+}
+
+impl RelocType {
+    /// Whether relocation entries of this type carry an addend.
+    pub const fn has_addend(self) -> bool {
+        matches!(
+            self,
+            RelocType::MemoryAddrLeb
+                | RelocType::MemoryAddrSleb
+                | RelocType::MemoryAddrI32
+                | RelocType::FunctionOffsetI32
+                | RelocType::SectionOffsetI32
+                | RelocType::MemoryAddrRelSleb
+                | RelocType::MemoryAddrLeb64
+                | RelocType::MemoryAddrSleb64
+                | RelocType::MemoryAddrI64
+                | RelocType::MemoryAddrRelSleb64
+                | RelocType::MemoryAddrTlsSleb
+                | RelocType::FunctionOffsetI64
+                | RelocType::MemoryAddrLocrelI32
+                | RelocType::MemoryAddrTlsSleb64
+        )
+    }
+
+    const fn site(self) -> RelocSite {
+        match self {
+            RelocType::FunctionIndexLeb
+            | RelocType::MemoryAddrLeb
+            | RelocType::TypeIndexLeb
+            | RelocType::GlobalIndexLeb
+            | RelocType::TagIndexLeb
+            | RelocType::TableNumberLeb => RelocSite::Leb32,
+            RelocType::TableIndexSleb
+            | RelocType::MemoryAddrSleb
+            | RelocType::MemoryAddrRelSleb
+            | RelocType::TableIndexRelSleb
+            | RelocType::MemoryAddrTlsSleb => RelocSite::Sleb32,
+            RelocType::TableIndexI32
+            | RelocType::MemoryAddrI32
+            | RelocType::FunctionOffsetI32
+            | RelocType::SectionOffsetI32
+            | RelocType::GlobalIndexI32
+            | RelocType::MemoryAddrLocrelI32
+            | RelocType::FunctionIndexI32 => RelocSite::I32,
+            RelocType::MemoryAddrLeb64 => RelocSite::Leb64,
+            RelocType::MemoryAddrSleb64
+            | RelocType::MemoryAddrRelSleb64
+            | RelocType::TableIndexSleb64
+            | RelocType::TableIndexRelSleb64
+            | RelocType::MemoryAddrTlsSleb64 => RelocSite::Sleb64,
+            RelocType::MemoryAddrI64 | RelocType::TableIndexI64 | RelocType::FunctionOffsetI64 => {
+                RelocSite::I64
+            }
+        }
+    }
+
+    /// Size in bytes of the relocation site.
+    ///
+    /// LEB128-encoded sites are always padded to the maximum width for their type.
+    pub const fn site_size(self) -> usize {
+        self.site().size()
+    }
+}
+
+/// Error returned when reading or patching a relocation site.
+#[derive(Debug, Error)]
+pub enum RelocError {
+    /// Decoding error occured while reading a section or a relocation site.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Encoding error occured while serializing the target section.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Relocation section refers to a section index that doesn't exist.
+    #[error("Relocation section targets non-existent section {0}")]
+    InvalidSection(u32),
+
+    /// Relocation site doesn't fit into the target section payload.
+    #[error("Relocation site at offset 0x{offset:X} is out of bounds")]
+    OutOfBounds { offset: u32 },
+
+    /// LEB128 relocation site is not padded to its maximum width.
+    #[error("Relocation site at offset 0x{offset:X} is not a padded LEB128")]
+    NotPadded { offset: u32 },
+
+    /// Relocation offset doesn't point to the start of an instruction immediate.
+    #[error("Relocation site at offset 0x{offset:X} doesn't match any instruction immediate")]
+    MisplacedSite { offset: u32 },
+
+    /// Resolved value is out of range for the relocation site.
+    #[error("Value {value} does not fit into a {ty:?} relocation site")]
+    Overflow { ty: RelocType, value: i64 },
+}
+
+/// A single [relocation entry](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#relocation-sections).
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct RelocEntry {
+    pub ty: RelocType,
+    /// Offset of the relocation site relative to the start of the target
+    /// section payload (after the name for custom sections).
+    pub offset: u32,
+    /// Symbol index, or type index for [`RelocType::TypeIndexLeb`].
+    pub index: u32,
+    /// Addend to add to the symbol value, present only if [`RelocType::has_addend`].
+    pub addend: i64,
+}
+
+impl WasmbinCountable for RelocEntry {}
+
+impl Encode for RelocEntry {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.ty.encode(w)?;
+        self.offset.encode(w)?;
+        self.index.encode(w)?;
+        if self.ty.has_addend() {
+            self.addend.encode(w)?;
+        } else if self.addend != 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "relocation type doesn't support an addend",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Decode for RelocEntry {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let ty = RelocType::decode(r)?;
+        Ok(RelocEntry {
+            ty,
+            offset: u32::decode(r)?,
+            index: u32::decode(r)?,
+            addend: match ty.has_addend() {
+                true => i64::decode(r)?,
+                false => 0,
+            },
+        })
+    }
+}
+
+impl RelocEntry {
+    fn site_range(&self) -> std::ops::Range<usize> {
+        let start = self.offset as usize;
+        start..start + self.ty.site_size()
+    }
+
+    fn out_of_bounds(&self) -> RelocError {
+        RelocError::OutOfBounds {
+            offset: self.offset,
+        }
+    }
+
+    /// Read the current value at the relocation site in the target section payload.
+    ///
+    /// Unsigned 32-bit sites are zero-extended and signed ones are sign-extended to `i64`.
+    /// 64-bit sites are returned as is, so unsigned values above [`i64::MAX`] come out negative.
+    pub fn read(&self, payload: &[u8]) -> Result<i64, RelocError> {
+        let mut site = payload
+            .get(self.site_range())
+            .ok_or_else(|| self.out_of_bounds())?;
+        Ok(match self.ty.site() {
+            RelocSite::Leb32 => i64::from(u32::decode(&mut site)?),
+            RelocSite::Sleb32 => i64::from(i32::decode(&mut site)?),
+            RelocSite::Leb64 => u64::decode(&mut site)?.cast_signed(),
+            RelocSite::Sleb64 => i64::decode(&mut site)?,
+            RelocSite::I32 => i64::from(u32::from_le_bytes(<[u8; 4]>::decode(&mut site)?)),
+            RelocSite::I64 => i64::from_le_bytes(<[u8; 8]>::decode(&mut site)?),
+        })
+    }
+
+    /// Overwrite the relocation site in the target section payload with the given value.
+    ///
+    /// LEB128 sites must already be padded to their maximum width so that the
+    /// payload size doesn't change. As with [`read`](Self::read), values of 64-bit
+    /// sites are written as is.
+    pub fn write(&self, payload: &mut [u8], value: i64) -> Result<(), RelocError> {
+        let ty = self.ty;
+        let overflow = || RelocError::Overflow { ty, value };
+        let site = payload
+            .get_mut(self.site_range())
+            .ok_or_else(|| self.out_of_bounds())?;
+        let site_kind = ty.site();
+        if matches!(
+            site_kind,
+            RelocSite::Leb32 | RelocSite::Sleb32 | RelocSite::Leb64 | RelocSite::Sleb64
+        ) && !is_padded_leb(site)
+        {
+            return Err(RelocError::NotPadded {
+                offset: self.offset,
+            });
+        }
+        match site_kind {
+            RelocSite::Leb32 => {
+                let value = u32::try_from(value).map_err(|_| overflow())?;
+                write_padded_leb(site, u64::from(value));
+            }
+            RelocSite::Sleb32 => {
+                i32::try_from(value).map_err(|_| overflow())?;
+                write_padded_sleb(site, value);
+            }
+            RelocSite::Leb64 => write_padded_leb(site, value.cast_unsigned()),
+            RelocSite::Sleb64 => write_padded_sleb(site, value),
+            RelocSite::I32 => {
+                if value < i64::from(i32::MIN) || value > i64::from(u32::MAX) {
+                    return Err(overflow());
+                }
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                site.copy_from_slice(&(value as u32).to_le_bytes());
+            }
+            RelocSite::I64 => site.copy_from_slice(&value.to_le_bytes()),
+        }
+        Ok(())
+    }
+}
+
+impl RelocEntry {
+    /// Re-encode a minimal LEB128 immediate as a padded relocation site for this entry.
+    pub(crate) fn pad_site(&self, minimal: &[u8]) -> Result<Vec<u8>, RelocError> {
+        let mut r = minimal;
+        let mut site = vec![0; self.ty.site_size()];
+        match self.ty.site() {
+            RelocSite::Leb32 | RelocSite::Leb64 => {
+                write_padded_leb(&mut site, u64::decode(&mut r)?);
+            }
+            RelocSite::Sleb32 | RelocSite::Sleb64 => {
+                write_padded_sleb(&mut site, i64::decode(&mut r)?);
+            }
+            RelocSite::I32 | RelocSite::I64 => {
+                return Err(RelocError::MisplacedSite {
+                    offset: self.offset,
+                })
+            }
+        }
+        Ok(site)
+    }
+}
+
+fn is_padded_leb(site: &[u8]) -> bool {
+    match site.split_last() {
+        Some((last, rest)) => last & 0x80 == 0 && rest.iter().all(|byte| byte & 0x80 != 0),
+        None => false,
+    }
+}
+
+/// Write an unsigned LEB128 value spanning the entire site.
+fn write_padded_leb(site: &mut [u8], mut value: u64) {
+    let len = site.len();
+    for (i, byte) in site.iter_mut().enumerate() {
+        #[allow(clippy::cast_possible_truncation)]
+        let bits = value as u8 & 0x7F;
+        *byte = if i + 1 < len { bits | 0x80 } else { bits };
+        value >>= 7;
+    }
+}
+
+/// Write a signed LEB128 value spanning the entire site.
+fn write_padded_sleb(site: &mut [u8], mut value: i64) {
+    let len = site.len();
+    for (i, byte) in site.iter_mut().enumerate() {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let bits = value as u8 & 0x7F;
+        *byte = if i + 1 < len { bits | 0x80 } else { bits };
+        value >>= 7;
+    }
+}
+
+/// Contents of a [relocation section](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#relocation-sections).
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct Reloc {
+    /// Index of the target section among all the module sections, including custom ones.
+    pub section: u32,
+    pub entries: Vec<RelocEntry>,
+}
+
+/// A [relocation section](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#relocation-sections)
+/// named `reloc.*`.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct RelocSection {
+    /// Full name of the section, conventionally `reloc.` followed by the target section name.
+    pub name: String,
+    pub reloc: Lazy<Reloc>,
+}
+
+/// Whether the module is a relocatable object file, i.e. has a `linking` section.
+///
+/// Symbols and relocations of such modules point at indices and offsets, so transforms that
+/// renumber or move items have to reject them.
+pub(crate) fn is_relocatable(module: &crate::Module) -> bool {
+    module.sections.iter().any(|section| match section {
+        crate::sections::Section::Custom(custom) => {
+            matches!(
+                custom.try_contents(),
+                Ok(crate::sections::CustomSection::Linking(_))
+            )
+        }
+        _ => false,
+    })
+}
@@ -0,0 +1,413 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Editing of the [name section](https://webassembly.github.io/spec/core/appendix/custom.html#name-section)
+//! through hash maps keyed by index.
+
+use crate::builtins::{Blob, Lazy};
+#[cfg(all(feature = "extended-name-section", feature = "exception-handling"))]
+use crate::indices::ExceptionId;
+#[cfg(feature = "extended-name-section")]
+use crate::indices::{DataId, ElemId, GlobalId, LabelId, MemId, TableId, TypeId};
+use crate::indices::{FuncId, LocalId};
+use crate::io::{Decode, DecodeError};
+use crate::sections::{
+    CustomSection, IndirectNameMap, NameAssoc, NameMap, NameSubSection, Section,
+};
+use crate::Module;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Names grouped by function (or type, for fields) and then by the inner index.
+pub type IndirectNames<I1, I2> = HashMap<I1, HashMap<I2, String>>;
+
+/// Contents of the name section.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Names {
+    pub module: Option<String>,
+    pub funcs: HashMap<FuncId, String>,
+    pub locals: IndirectNames<FuncId, LocalId>,
+    #[cfg(feature = "extended-name-section")]
+    pub labels: IndirectNames<FuncId, LabelId>,
+    #[cfg(feature = "extended-name-section")]
+    pub types: HashMap<TypeId, String>,
+    #[cfg(feature = "extended-name-section")]
+    pub tables: HashMap<TableId, String>,
+    #[cfg(feature = "extended-name-section")]
+    pub memories: HashMap<MemId, String>,
+    #[cfg(feature = "extended-name-section")]
+    pub globals: HashMap<GlobalId, String>,
+    #[cfg(feature = "extended-name-section")]
+    pub elems: HashMap<ElemId, String>,
+    #[cfg(feature = "extended-name-section")]
+    pub data: HashMap<DataId, String>,
+    #[cfg(feature = "extended-name-section")]
+    pub fields: IndirectNames<TypeId, u32>,
+    #[cfg(all(feature = "extended-name-section", feature = "exception-handling"))]
+    pub tags: HashMap<ExceptionId, String>,
+}
+
+fn get<I: Copy + Eq + Hash>(map: &HashMap<I, String>, index: I) -> Option<&str> {
+    map.get(&index).map(String::as_str)
+}
+
+fn get_indirect<I1: Copy + Eq + Hash, I2: Copy + Eq + Hash>(
+    map: &IndirectNames<I1, I2>,
+    outer: I1,
+    inner: I2,
+) -> Option<&str> {
+    get(map.get(&outer)?, inner)
+}
+
+fn set_indirect<I1: Copy + Eq + Hash, I2: Copy + Eq + Hash>(
+    map: &mut IndirectNames<I1, I2>,
+    outer: I1,
+    inner: I2,
+    name: String,
+) {
+    map.entry(outer).or_default().insert(inner, name);
+}
+
+fn read_map<I: Copy + Eq + Hash + Decode>(
+    target: &mut HashMap<I, String>,
+    map: &Blob<NameMap<I>>,
+) -> Result<(), DecodeError> {
+    for assoc in &map.try_contents()?.items {
+        target.insert(assoc.index, assoc.value.clone());
+    }
+    Ok(())
+}
+
+fn read_indirect_map<I1: Copy + Eq + Hash + Decode, I2: Copy + Eq + Hash + Decode>(
+    target: &mut IndirectNames<I1, I2>,
+    map: &Blob<IndirectNameMap<I1, I2>>,
+) -> Result<(), DecodeError> {
+    for assoc in &map.try_contents()?.items {
+        let inner = target.entry(assoc.index).or_default();
+        for inner_assoc in &assoc.value.items {
+            inner.insert(inner_assoc.index, inner_assoc.value.clone());
+        }
+    }
+    Ok(())
+}
+
+fn to_map<I: Copy + Ord>(map: &HashMap<I, String>) -> NameMap<I> {
+    let mut items: Vec<_> = map
+        .iter()
+        .map(|(&index, value)| NameAssoc {
+            index,
+            value: value.clone(),
+        })
+        .collect();
+    items.sort_by_key(|assoc| assoc.index);
+    NameMap { items }
+}
+
+fn to_indirect_map<I1: Copy + Ord, I2: Copy + Ord>(
+    map: &IndirectNames<I1, I2>,
+) -> IndirectNameMap<I1, I2> {
+    let mut items: Vec<_> = map
+        .iter()
+        .filter(|(_, inner)| !inner.is_empty())
+        .map(|(&index, inner)| NameAssoc {
+            index,
+            value: to_map(inner),
+        })
+        .collect();
+    items.sort_by_key(|assoc| assoc.index);
+    NameMap { items }
+}
+
+fn push_map<I: Copy + Ord + Decode>(
+    subsections: &mut Vec<NameSubSection>,
+    map: &HashMap<I, String>,
+    variant: fn(Blob<NameMap<I>>) -> NameSubSection,
+) {
+    if !map.is_empty() {
+        subsections.push(variant(to_map(map).into()));
+    }
+}
+
+fn push_indirect_map<I1: Copy + Ord + Decode, I2: Copy + Ord + Decode>(
+    subsections: &mut Vec<NameSubSection>,
+    map: &IndirectNames<I1, I2>,
+    variant: fn(Blob<IndirectNameMap<I1, I2>>) -> NameSubSection,
+) {
+    if map.values().any(|inner| !inner.is_empty()) {
+        subsections.push(variant(to_indirect_map(map).into()));
+    }
+}
+
+fn find_name_section(module: &Module) -> Option<(usize, &Lazy<Vec<NameSubSection>>)> {
+    module
+        .sections
+        .iter()
+        .enumerate()
+        .find_map(|(index, section)| match section {
+            Section::Custom(custom) => match custom.try_contents() {
+                Ok(CustomSection::Name(subsections)) => Some((index, subsections)),
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+pub(crate) fn is_name_section(section: &Section) -> bool {
+    match section {
+        Section::Custom(custom) => matches!(custom.try_contents(), Ok(CustomSection::Name(_))),
+        _ => false,
+    }
+}
+
+/// Point names at new indices, dropping the ones that map to `None` and keeping the first name
+/// when several indices are merged into one.
+pub(crate) fn remap_name_map<I: Copy + Ord, V>(
+    names: &mut NameMap<I, V>,
+    remap: impl Fn(I) -> Option<I>,
+) {
+    names.items.retain_mut(|assoc| match remap(assoc.index) {
+        Some(index) => {
+            assoc.index = index;
+            true
+        }
+        None => false,
+    });
+    names.items.sort_by_key(|assoc| assoc.index);
+    names.items.dedup_by_key(|assoc| assoc.index);
+}
+
+impl Names {
+    /// Read names from the name section of a module, if any.
+    pub fn from_module(module: &Module) -> Result<Self, DecodeError> {
+        match find_name_section(module) {
+            Some((_, subsections)) => Self::from_subsections(subsections.try_contents()?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Read names from decoded name subsections.
+    ///
+    /// Repeated subsections are merged, with later entries taking precedence.
+    pub fn from_subsections(subsections: &[NameSubSection]) -> Result<Self, DecodeError> {
+        let mut names = Self::default();
+        for subsection in subsections {
+            match subsection {
+                NameSubSection::Module(name) => names.module = Some(name.try_contents()?.clone()),
+                NameSubSection::Func(map) => read_map(&mut names.funcs, map)?,
+                NameSubSection::Local(map) => read_indirect_map(&mut names.locals, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Label(map) => read_indirect_map(&mut names.labels, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Type(map) => read_map(&mut names.types, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Table(map) => read_map(&mut names.tables, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Memory(map) => read_map(&mut names.memories, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Global(map) => read_map(&mut names.globals, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Elem(map) => read_map(&mut names.elems, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Data(map) => read_map(&mut names.data, map)?,
+                #[cfg(feature = "extended-name-section")]
+                NameSubSection::Field(map) => read_indirect_map(&mut names.fields, map)?,
+                #[cfg(all(feature = "extended-name-section", feature = "exception-handling"))]
+                NameSubSection::Tag(map) => read_map(&mut names.tags, map)?,
+            }
+        }
+        Ok(names)
+    }
+
+    /// Name of the module.
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    /// Set the name of the module.
+    pub fn set_module(&mut self, name: impl Into<String>) {
+        self.module = Some(name.into());
+    }
+
+    /// Name of a function.
+    pub fn func(&self, func: FuncId) -> Option<&str> {
+        get(&self.funcs, func)
+    }
+
+    /// Set the name of a function.
+    pub fn set_func(&mut self, func: FuncId, name: impl Into<String>) {
+        self.funcs.insert(func, name.into());
+    }
+
+    /// Name of a local within a function.
+    pub fn local(&self, func: FuncId, local: LocalId) -> Option<&str> {
+        get_indirect(&self.locals, func, local)
+    }
+
+    /// Set the name of a local within a function.
+    pub fn set_local(&mut self, func: FuncId, local: LocalId, name: impl Into<String>) {
+        set_indirect(&mut self.locals, func, local, name.into());
+    }
+
+    /// Name of a label within a function.
+    #[cfg(feature = "extended-name-section")]
+    pub fn label(&self, func: FuncId, label: LabelId) -> Option<&str> {
+        get_indirect(&self.labels, func, label)
+    }
+
+    /// Set the name of a label within a function.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_label(&mut self, func: FuncId, label: LabelId, name: impl Into<String>) {
+        set_indirect(&mut self.labels, func, label, name.into());
+    }
+
+    /// Name of a type.
+    #[cfg(feature = "extended-name-section")]
+    pub fn ty(&self, ty: TypeId) -> Option<&str> {
+        get(&self.types, ty)
+    }
+
+    /// Set the name of a type.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_ty(&mut self, ty: TypeId, name: impl Into<String>) {
+        self.types.insert(ty, name.into());
+    }
+
+    /// Name of a table.
+    #[cfg(feature = "extended-name-section")]
+    pub fn table(&self, table: TableId) -> Option<&str> {
+        get(&self.tables, table)
+    }
+
+    /// Set the name of a table.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_table(&mut self, table: TableId, name: impl Into<String>) {
+        self.tables.insert(table, name.into());
+    }
+
+    /// Name of a memory.
+    #[cfg(feature = "extended-name-section")]
+    pub fn memory(&self, memory: MemId) -> Option<&str> {
+        get(&self.memories, memory)
+    }
+
+    /// Set the name of a memory.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_memory(&mut self, memory: MemId, name: impl Into<String>) {
+        self.memories.insert(memory, name.into());
+    }
+
+    /// Name of a global.
+    #[cfg(feature = "extended-name-section")]
+    pub fn global(&self, global: GlobalId) -> Option<&str> {
+        get(&self.globals, global)
+    }
+
+    /// Set the name of a global.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_global(&mut self, global: GlobalId, name: impl Into<String>) {
+        self.globals.insert(global, name.into());
+    }
+
+    /// Name of an element segment.
+    #[cfg(feature = "extended-name-section")]
+    pub fn elem(&self, elem: ElemId) -> Option<&str> {
+        get(&self.elems, elem)
+    }
+
+    /// Set the name of an element segment.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_elem(&mut self, elem: ElemId, name: impl Into<String>) {
+        self.elems.insert(elem, name.into());
+    }
+
+    /// Name of a data segment.
+    #[cfg(feature = "extended-name-section")]
+    pub fn data(&self, data: DataId) -> Option<&str> {
+        get(&self.data, data)
+    }
+
+    /// Set the name of a data segment.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_data(&mut self, data: DataId, name: impl Into<String>) {
+        self.data.insert(data, name.into());
+    }
+
+    /// Name of a field within a struct type.
+    #[cfg(feature = "extended-name-section")]
+    pub fn field(&self, ty: TypeId, field: u32) -> Option<&str> {
+        get_indirect(&self.fields, ty, field)
+    }
+
+    /// Set the name of a field within a struct type.
+    #[cfg(feature = "extended-name-section")]
+    pub fn set_field(&mut self, ty: TypeId, field: u32, name: impl Into<String>) {
+        set_indirect(&mut self.fields, ty, field, name.into());
+    }
+
+    /// Name of a tag.
+    #[cfg(all(feature = "extended-name-section", feature = "exception-handling"))]
+    pub fn tag(&self, tag: ExceptionId) -> Option<&str> {
+        get(&self.tags, tag)
+    }
+
+    /// Set the name of a tag.
+    #[cfg(all(feature = "extended-name-section", feature = "exception-handling"))]
+    pub fn set_tag(&mut self, tag: ExceptionId, name: impl Into<String>) {
+        self.tags.insert(tag, name.into());
+    }
+
+    /// Convert names into subsections ordered by id, with entries sorted by index
+    /// and empty subsections omitted.
+    pub fn to_subsections(&self) -> Vec<NameSubSection> {
+        let mut subsections = Vec::new();
+        if let Some(module) = &self.module {
+            subsections.push(NameSubSection::Module(module.clone().into()));
+        }
+        push_map(&mut subsections, &self.funcs, NameSubSection::Func);
+        push_indirect_map(&mut subsections, &self.locals, NameSubSection::Local);
+        #[cfg(feature = "extended-name-section")]
+        {
+            push_indirect_map(&mut subsections, &self.labels, NameSubSection::Label);
+            push_map(&mut subsections, &self.types, NameSubSection::Type);
+            push_map(&mut subsections, &self.tables, NameSubSection::Table);
+            push_map(&mut subsections, &self.memories, NameSubSection::Memory);
+            push_map(&mut subsections, &self.globals, NameSubSection::Global);
+            push_map(&mut subsections, &self.elems, NameSubSection::Elem);
+            push_map(&mut subsections, &self.data, NameSubSection::Data);
+            push_indirect_map(&mut subsections, &self.fields, NameSubSection::Field);
+        }
+        #[cfg(all(feature = "extended-name-section", feature = "exception-handling"))]
+        push_map(&mut subsections, &self.tags, NameSubSection::Tag);
+        subsections
+    }
+
+    /// Replace the name section of a module, appending one at the end if there is none.
+    ///
+    /// The section is removed if there are no names.
+    pub fn write_to_module(&self, module: &mut Module) {
+        let subsections = self.to_subsections();
+        let existing = find_name_section(module).map(|(index, _)| index);
+        if subsections.is_empty() {
+            if let Some(index) = existing {
+                module.sections.remove(index);
+            }
+            return;
+        }
+        let section = Section::Custom(Blob::from(CustomSection::Name(subsections.into())));
+        match existing {
+            Some(index) => module.sections[index] = section,
+            None => module.sections.push(section),
+        }
+    }
+}
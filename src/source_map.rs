@@ -0,0 +1,216 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Loading and updating of [Source Map v3](https://tc39.es/ecma426/) files for a module.
+//!
+//! WebAssembly source maps describe the binary as a single line, where generated columns
+//! are byte offsets within the module. [`SourceMap::remap`] keeps them accurate after
+//! function bodies are re-encoded or removed, or sections preceding the code change size.
+//!
+//! ```no_run
+//! use wasmbin::source_map::SourceMap;
+//! use wasmbin::transforms::address_map::{AddressMap, CodeLayout};
+//! # fn transform(_: &mut wasmbin::Module) {}
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = wasmbin::Module::decode_from(std::fs::File::open("input.wasm")?)?;
+//! let mut source_map = SourceMap::from_slice(&std::fs::read("input.wasm.map")?)?;
+//! let before = CodeLayout::of(&module)?;
+//! transform(&mut module);
+//! let after = CodeLayout::of(&module)?;
+//! source_map.remap(&AddressMap::new(before, after));
+//! std::fs::write("output.wasm.map", source_map.to_vec()?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::transforms::address_map::AddressMap;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+/// Error returned when loading or saving a [`SourceMap`].
+#[derive(Debug, Error)]
+pub enum SourceMapError {
+    /// Source map is not valid JSON or can't be serialized.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    /// Source map is not an object with `"version": 3`.
+    #[error("Unsupported source map version")]
+    UnsupportedVersion,
+
+    /// The `mappings` field is missing or malformed.
+    #[error("Malformed mappings at offset {0}")]
+    InvalidMappings(usize),
+}
+
+/// Location in the original sources, with indices into the `sources` and `names` fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OriginalLocation {
+    pub source: u32,
+    pub line: u32,
+    pub column: u32,
+    pub name: Option<u32>,
+}
+
+/// Single mapping from a byte offset within the module to an original location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mapping {
+    /// Byte offset within the module.
+    pub offset: u32,
+    /// Original location, or `None` if the code has no source.
+    pub original: Option<OriginalLocation>,
+}
+
+/// Source map for a WebAssembly module.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceMap {
+    /// Mappings sorted by byte offset.
+    pub mappings: Vec<Mapping>,
+    /// Remaining fields (`sources`, `names` etc.), preserved as is.
+    pub fields: Map<String, Value>,
+}
+
+/// Accumulator for a single relative VLQ field.
+fn add_delta(value: &mut i64, delta: i64) -> Option<u32> {
+    *value = value.checked_add(delta)?;
+    u32::try_from(*value).ok()
+}
+
+fn decode_mappings(mappings: &str) -> Result<Vec<Mapping>, SourceMapError> {
+    let mut result = Vec::new();
+    let (mut offset, mut source, mut line, mut column, mut name) = (0, 0, 0, 0, 0);
+    let mut next_segment_start = 0;
+    for segment in mappings.split(',') {
+        let segment_start = next_segment_start;
+        next_segment_start += segment.len() + 1;
+        let err = || SourceMapError::InvalidMappings(segment_start);
+        if segment.is_empty() {
+            continue;
+        }
+        // WebAssembly modules have a single generated line.
+        if segment.contains(';') {
+            return Err(err());
+        }
+        let mut bytes = segment.bytes().peekable();
+        let mut fields = Vec::with_capacity(5);
+        while bytes.peek().is_some() {
+            fields.push(vlq::decode(&mut bytes).map_err(|_| err())?);
+        }
+        let mapping = match *fields.as_slice() {
+            [d_offset] => Mapping {
+                offset: add_delta(&mut offset, d_offset).ok_or_else(err)?,
+                original: None,
+            },
+            [d_offset, d_source, d_line, d_column, ref d_name @ ..] if d_name.len() <= 1 => {
+                Mapping {
+                    offset: add_delta(&mut offset, d_offset).ok_or_else(err)?,
+                    original: Some(OriginalLocation {
+                        source: add_delta(&mut source, d_source).ok_or_else(err)?,
+                        line: add_delta(&mut line, d_line).ok_or_else(err)?,
+                        column: add_delta(&mut column, d_column).ok_or_else(err)?,
+                        name: match d_name {
+                            [d_name] => Some(add_delta(&mut name, *d_name).ok_or_else(err)?),
+                            _ => None,
+                        },
+                    }),
+                }
+            }
+            _ => return Err(err()),
+        };
+        result.push(mapping);
+    }
+    Ok(result)
+}
+
+fn encode_mappings(mappings: &[Mapping]) -> String {
+    let mut result = Vec::new();
+    let (mut offset, mut source, mut line, mut column, mut name) = (0, 0, 0, 0, 0);
+    let encode = |value: u32, prev: &mut i64, out: &mut Vec<u8>| {
+        let value = i64::from(value);
+        vlq::encode(value - *prev, out).unwrap_or_default();
+        *prev = value;
+    };
+    for (i, mapping) in mappings.iter().enumerate() {
+        if i > 0 {
+            result.push(b',');
+        }
+        encode(mapping.offset, &mut offset, &mut result);
+        if let Some(original) = mapping.original {
+            encode(original.source, &mut source, &mut result);
+            encode(original.line, &mut line, &mut result);
+            encode(original.column, &mut column, &mut result);
+            if let Some(original_name) = original.name {
+                encode(original_name, &mut name, &mut result);
+            }
+        }
+    }
+    // VLQ digits are always ASCII.
+    result.into_iter().map(char::from).collect()
+}
+
+impl SourceMap {
+    /// Parse a source map from its JSON representation.
+    pub fn from_slice(json: &[u8]) -> Result<Self, SourceMapError> {
+        let Value::Object(mut fields) = serde_json::from_slice(json)? else {
+            return Err(SourceMapError::UnsupportedVersion);
+        };
+        if fields.get("version") != Some(&Value::from(3)) {
+            return Err(SourceMapError::UnsupportedVersion);
+        }
+        let mappings = match fields.remove("mappings") {
+            Some(Value::String(mappings)) => decode_mappings(&mappings)?,
+            _ => return Err(SourceMapError::InvalidMappings(0)),
+        };
+        let mut source_map = SourceMap { mappings, fields };
+        source_map.sort();
+        Ok(source_map)
+    }
+
+    /// Serialize the source map to JSON.
+    pub fn to_vec(&self) -> Result<Vec<u8>, SourceMapError> {
+        let mut fields = self.fields.clone();
+        fields.insert(
+            "mappings".to_owned(),
+            Value::String(encode_mappings(&self.mappings)),
+        );
+        Ok(serde_json::to_vec(&fields)?)
+    }
+
+    /// Names of the original sources.
+    pub fn sources(&self) -> impl Iterator<Item = Option<&str>> {
+        self.fields
+            .get("sources")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(Value::as_str)
+    }
+
+    fn sort(&mut self) {
+        self.mappings.sort_by_key(|mapping| mapping.offset);
+    }
+
+    /// Translate mapped offsets to a new module layout.
+    ///
+    /// Mappings that belong to removed functions are dropped.
+    pub fn remap(&mut self, map: &AddressMap) {
+        self.mappings.retain_mut(|mapping| {
+            map.map_file_offset(mapping.offset)
+                .map(|offset| mapping.offset = offset)
+                .is_some()
+        });
+        self.sort();
+    }
+}
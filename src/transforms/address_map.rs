@@ -0,0 +1,338 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mapping of code addresses between two layouts of the code section.
+//!
+//! Debug information refers to code by byte offsets within the code section, which change
+//! whenever function bodies are re-encoded, added or removed. A [`CodeLayout`] snapshot taken
+//! before and after a transformation can be combined into an [`AddressMap`] to translate
+//! such offsets.
+
+use crate::instructions::Instruction;
+use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode};
+use crate::sections::{payload, Kind, Locals};
+use crate::Module;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Layout of a single function body within the code section payload.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct FuncLayout {
+    /// Offset of the body right after its size prefix, which is used as the function address
+    /// in debug info.
+    pub start: u32,
+    /// Offsets of the instructions, including the terminating `end`.
+    pub instrs: Vec<u32>,
+    /// Offset right after the function body.
+    pub end: u32,
+    /// Hashes of the decoded instructions and of their kinds, used to match them up between
+    /// layouts.
+    hashes: Vec<u64>,
+    kinds: Vec<u64>,
+}
+
+/// Layout of all function bodies within the code section payload.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct CodeLayout {
+    /// Offset of the code section payload within the encoded module.
+    pub file_offset: u32,
+    /// Size of the code section payload.
+    pub size: u32,
+    pub funcs: Vec<FuncLayout>,
+}
+
+/// Size of the magic and version preceding the module sections.
+const MODULE_HEADER_SIZE: usize = 8;
+
+fn offset(payload: &[u8], rest: &[u8]) -> Result<u32, DecodeError> {
+    Ok(u32::try_from(payload.len() - rest.len())?)
+}
+
+fn hash(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl CodeLayout {
+    /// Compute the layout of the code section as it will be encoded.
+    ///
+    /// Unmodified function bodies keep their original encoding, so taking a snapshot
+    /// right after decoding describes the layout of the input binary.
+    pub fn of(module: &Module) -> Result<Self, DecodeError> {
+        let Some(code) = module.find_std_section::<payload::Code>() else {
+            return Ok(Self::default());
+        };
+        let mut payload = Vec::new();
+        code.contents.encode(&mut payload)?;
+        let payload = payload.as_slice();
+        let mut file_offset = MODULE_HEADER_SIZE;
+        for section in &module.sections {
+            if section.kind() == Kind::Code {
+                break;
+            }
+            let mut encoded = Vec::new();
+            section.encode(&mut encoded)?;
+            file_offset += encoded.len();
+        }
+        let mut size_prefix = Vec::new();
+        payload.len().encode(&mut size_prefix)?;
+        // Section id followed by the payload size.
+        file_offset += 1 + size_prefix.len();
+        let mut r = payload;
+        let count = u32::decode(&mut r)?;
+        let mut funcs = Vec::new();
+        for _ in 0..count {
+            let size = u32::decode(&mut r)? as usize;
+            let start = offset(payload, r)?;
+            let mut body = r.get(..size).ok_or(DecodeErrorKind::UnrecognizedData)?;
+            r = &r[size..];
+            <Vec<Locals>>::decode(&mut body)?;
+            let mut instrs = Vec::new();
+            let mut hashes = Vec::new();
+            let mut kinds = Vec::new();
+            while !body.is_empty() {
+                instrs.push(start + u32::try_from(size - body.len())?);
+                let instr = Instruction::decode(&mut body)?;
+                hashes.push(hash(&instr));
+                kinds.push(hash(&std::mem::discriminant(&instr)));
+            }
+            funcs.push(FuncLayout {
+                start,
+                instrs,
+                end: offset(payload, r)?,
+                hashes,
+                kinds,
+            });
+        }
+        Ok(CodeLayout {
+            file_offset: u32::try_from(file_offset)?,
+            size: u32::try_from(payload.len())?,
+            funcs,
+        })
+    }
+}
+
+/// Translation of code section offsets from an old [`CodeLayout`] to a new one.
+///
+/// Instructions of corresponding functions are matched up with a diff of the old and new
+/// bodies. Addresses of instructions that were kept are mapped to the same instruction in the
+/// new layout, addresses of modified instructions to the start of their new version, and
+/// addresses of removed instructions to the next instruction that was kept.
+#[derive(Debug, Clone)]
+pub struct AddressMap {
+    old: CodeLayout,
+    new: CodeLayout,
+    funcs: Vec<Option<usize>>,
+    /// Index of the new instruction corresponding to each old one, for each old function.
+    instrs: Vec<Vec<usize>>,
+}
+
+/// Pairs of indices of the elements of a longest common subsequence of `old` and `new`, found
+/// with Myers' diff algorithm after skipping their common prefix and suffix.
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+fn common_subsequence(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    let prefix = old.iter().zip(new).take_while(|(x, y)| x == y).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let (old_len, new_len) = (old_middle.len() as isize, new_middle.len() as isize);
+    // Furthest reaching position in `old_middle` on each diagonal, identified by the
+    // difference of the positions and offset by `max`, before each step.
+    let max = old_len + new_len;
+    let mut furthest = vec![0_isize; 2 * max as usize + 2];
+    let mut trace = Vec::new();
+    let from_above = |furthest: &[isize], diagonal: isize, step: isize| {
+        let index = (diagonal + max) as usize;
+        diagonal == -step || (diagonal != step && furthest[index - 1] < furthest[index + 1])
+    };
+    'search: for step in 0..=max {
+        trace.push(furthest.clone());
+        for diagonal in (-step..=step).step_by(2) {
+            let index = (diagonal + max) as usize;
+            let mut old_pos = if from_above(&furthest, diagonal, step) {
+                furthest[index + 1]
+            } else {
+                furthest[index - 1] + 1
+            };
+            let mut new_pos = old_pos - diagonal;
+            while old_pos < old_len
+                && new_pos < new_len
+                && old_middle[old_pos as usize] == new_middle[new_pos as usize]
+            {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            furthest[index] = old_pos;
+            if old_pos >= old_len && new_pos >= new_len {
+                break 'search;
+            }
+        }
+    }
+    let mut pairs: Vec<_> = (0..suffix)
+        .map(|i| (old.len() - 1 - i, new.len() - 1 - i))
+        .collect();
+    let (mut old_pos, mut new_pos) = (old_len, new_len);
+    for (step, furthest) in trace.iter().enumerate().rev() {
+        let step = step as isize;
+        let diagonal = old_pos - new_pos;
+        let prev_diagonal = if from_above(furthest, diagonal, step) {
+            diagonal + 1
+        } else {
+            diagonal - 1
+        };
+        let prev_old_pos = furthest[(prev_diagonal + max) as usize];
+        let prev_new_pos = prev_old_pos - prev_diagonal;
+        while old_pos > prev_old_pos && new_pos > prev_new_pos {
+            old_pos -= 1;
+            new_pos -= 1;
+            pairs.push((prefix + old_pos as usize, prefix + new_pos as usize));
+        }
+        if step > 0 {
+            (old_pos, new_pos) = (prev_old_pos, prev_new_pos);
+        }
+    }
+    pairs.extend((0..prefix).rev().map(|i| (i, i)));
+    pairs.reverse();
+    pairs
+}
+
+/// Index of the new instruction corresponding to each old one.
+///
+/// Identical instructions are matched up first, then instructions of the same kind between
+/// them, which are likely modified versions of each other. Remaining old instructions were
+/// removed and are mapped to the next matched instruction.
+fn match_instrs(old: &FuncLayout, new: &FuncLayout) -> Vec<usize> {
+    let mut pairs = Vec::new();
+    let (mut next_old, mut next_new) = (0, 0);
+    let end = (old.hashes.len(), new.hashes.len());
+    for (matched_old, matched_new) in common_subsequence(&old.hashes, &new.hashes)
+        .into_iter()
+        .chain([end])
+    {
+        pairs.extend(
+            common_subsequence(
+                &old.kinds[next_old..matched_old],
+                &new.kinds[next_new..matched_new],
+            )
+            .into_iter()
+            .map(|(i, j)| (next_old + i, next_new + j)),
+        );
+        pairs.push((matched_old, matched_new));
+        (next_old, next_new) = (matched_old + 1, matched_new + 1);
+    }
+    let mut instrs = Vec::with_capacity(old.hashes.len());
+    for (matched_old, matched_new) in pairs {
+        instrs.resize(matched_old + 1, matched_new);
+    }
+    // Drop the end marker.
+    instrs.pop();
+    instrs
+}
+
+impl AddressMap {
+    /// Create a map where functions at the same index in both layouts correspond to each other.
+    pub fn new(old: CodeLayout, new: CodeLayout) -> Self {
+        Self::with_func_map(old, new, Some)
+    }
+
+    /// Create a map with an explicit correspondence from old to new function indices
+    /// (relative to the code section), where `None` means the function was removed.
+    pub fn with_func_map(
+        old: CodeLayout,
+        new: CodeLayout,
+        mut func_map: impl FnMut(usize) -> Option<usize>,
+    ) -> Self {
+        let funcs: Vec<_> = (0..old.funcs.len())
+            .map(|index| func_map(index).filter(|&index| index < new.funcs.len()))
+            .collect();
+        let instrs = old
+            .funcs
+            .iter()
+            .zip(&funcs)
+            .map(|(old_func, new_index)| match new_index {
+                Some(new_index) => match_instrs(old_func, &new.funcs[*new_index]),
+                None => Vec::new(),
+            })
+            .collect();
+        AddressMap {
+            old,
+            new,
+            funcs,
+            instrs,
+        }
+    }
+
+    /// Translate an old code section offset to the new layout.
+    ///
+    /// Returns `None` if the address belongs to a removed function. Addresses outside of
+    /// any function are returned unchanged.
+    pub fn map(&self, addr: u32) -> Option<u32> {
+        let Some(index) = self
+            .old
+            .funcs
+            .partition_point(|func| func.start <= addr)
+            .checked_sub(1)
+        else {
+            return Some(addr);
+        };
+        let old = &self.old.funcs[index];
+        if addr > old.end {
+            return Some(addr);
+        }
+        let new = &self.new.funcs[self.funcs[index]?];
+        let instrs = &self.instrs[index];
+        if addr == old.end {
+            return Some(new.end);
+        }
+        let (Some(&old_first), Some(&new_first)) = (old.instrs.first(), new.instrs.first()) else {
+            return Some(new.start);
+        };
+        if addr < old_first {
+            return Some((new.start + (addr - old.start)).min(new_first.saturating_sub(1)));
+        }
+        let instr = old.instrs.partition_point(|&offset| offset <= addr) - 1;
+        let new_instr = instrs[instr];
+        let Some(&new_offset) = new.instrs.get(new_instr) else {
+            return Some(new.end);
+        };
+        if old.hashes[instr] != new.hashes[new_instr] {
+            // Removed or modified instruction.
+            return Some(new_offset);
+        }
+        let next = new.instrs.get(new_instr + 1).copied().unwrap_or(new.end);
+        Some((new_offset + (addr - old.instrs[instr])).min(next - 1))
+    }
+
+    /// Translate an old offset within the encoded module to the new layout.
+    ///
+    /// Offsets within the code section are translated as with [`map`](Self::map), offsets
+    /// after it are shifted along with its end, and offsets before it are returned unchanged.
+    pub fn map_file_offset(&self, offset: u32) -> Option<u32> {
+        let (old, new) = (&self.old, &self.new);
+        if offset < old.file_offset {
+            return Some(offset);
+        }
+        let code_offset = offset - old.file_offset;
+        if code_offset >= old.size {
+            return Some(offset - (old.file_offset + old.size) + (new.file_offset + new.size));
+        }
+        self.map(code_offset)
+            .map(|code_offset| code_offset + new.file_offset)
+    }
+}
@@ -0,0 +1,1457 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Asyncify](https://kripken.github.io/blog/wasm/2019/07/16/asyncify.html)-style transform that
+//! lets synchronous code call asynchronous host imports by unwinding the call stack into linear
+//! memory and rewinding it later.
+//!
+//! The transform follows the runtime interface of Binaryen's Asyncify pass, so existing JavaScript
+//! runtimes for it can drive the result. It adds the `asyncify_start_unwind`,
+//! `asyncify_stop_unwind`, `asyncify_start_rewind`, `asyncify_stop_rewind` and
+//! `asyncify_get_state` exports, and the pointer passed to the `start` functions refers to a pair of
+//! `i32` fields holding the current and the end address of the buffer that frames are saved to.
+//!
+//! ```no_run
+//! use wasmbin::sections::ImportPath;
+//! use wasmbin::transforms::asyncify::{asyncify, Asyncify};
+//! use wasmbin::Module;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = Module::decode_from(std::fs::File::open("app.wasm")?)?;
+//! asyncify(
+//!     &mut module,
+//!     &Asyncify {
+//!         imports: vec![ImportPath {
+//!             module: "env".to_owned(),
+//!             name: "fetch".to_owned(),
+//!         }],
+//!         ignore_indirect_calls: false,
+//!     },
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::builtins::FloatConst;
+use crate::indices::{FuncId, GlobalId, LabelId, LocalId, MemId};
+use crate::instructions::{Expression, Instruction, MemArg, Misc, SIMD};
+use crate::io::DecodeError;
+use crate::sections::{payload, Export, ExportDesc, FuncBody, ImportDesc, ImportPath, Locals};
+use crate::transforms::instrument::{add_func, add_global};
+use crate::transforms::interface::InterfaceError;
+use crate::transforms::stack_effect::{effect, AnalysisError, Effect, Signatures};
+use crate::types::{BlockType, FuncType, GlobalType, RefType, ValueType};
+use crate::Module;
+use std::collections::HashMap;
+use std::ops::Range;
+use thiserror::Error;
+
+/// Error returned by [`asyncify`].
+#[derive(Debug, Error)]
+pub enum AsyncifyError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Runtime functions couldn't be exported.
+    #[error(transparent)]
+    Interface(#[from] InterfaceError),
+
+    /// Module doesn't have a linear memory to save the call stack to.
+    #[error("Module doesn't have a linear memory")]
+    NoMemory,
+
+    /// Function is invalid or refers to a type or function that doesn't exist.
+    #[error("Function {0:?} is invalid")]
+    Invalid(FuncId),
+
+    /// Function that can unwind contains an instruction or a value that can't be saved and
+    /// restored, such as a reference or a tail call that can unwind.
+    #[error("Function {0:?} can unwind, but contains an unsupported instruction")]
+    Unsupported(FuncId),
+}
+
+/// Configuration of [`asyncify`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Asyncify {
+    /// Imports that can unwind the call stack. Imports that the module doesn't have are ignored.
+    pub imports: Vec<ImportPath>,
+    /// Assume that indirect calls never reach a function that unwinds, instead of instrumenting
+    /// all functions that make them.
+    pub ignore_indirect_calls: bool,
+}
+
+const NORMAL: i32 = 0;
+const UNWINDING: i32 = 1;
+const REWINDING: i32 = 2;
+
+/// Reason a function couldn't be transformed.
+enum Failure {
+    Invalid,
+    Unsupported,
+}
+
+impl From<AnalysisError> for Failure {
+    fn from(err: AnalysisError) -> Self {
+        match err {
+            AnalysisError::InvalidReference => Failure::Invalid,
+            #[cfg(feature = "stack-switching")]
+            AnalysisError::Unsupported => Failure::Unsupported,
+        }
+    }
+}
+
+/// Module-level information needed to transform function bodies.
+struct Context {
+    signatures: Signatures,
+    imported_funcs: Vec<ImportPath>,
+    globals: Vec<ValueType>,
+    tables: Vec<RefType>,
+    has_memory: bool,
+}
+
+impl Context {
+    fn new(module: &Module) -> Result<Self, DecodeError> {
+        let mut context = Context {
+            signatures: Signatures::new(module)?,
+            imported_funcs: Vec::new(),
+            globals: Vec::new(),
+            tables: Vec::new(),
+            has_memory: false,
+        };
+        if let Some(imports) = module.find_std_section::<payload::Import>() {
+            for import in imports.try_contents()? {
+                match &import.desc {
+                    ImportDesc::Func(_) => context.imported_funcs.push(import.path.clone()),
+                    ImportDesc::Table(ty) => context.tables.push(ty.elem_type.clone()),
+                    ImportDesc::Mem(_) => context.has_memory = true,
+                    ImportDesc::Global(ty) => context.globals.push(ty.value_type.clone()),
+                    #[cfg(feature = "exception-handling")]
+                    ImportDesc::Exception(_) => {}
+                }
+            }
+        }
+        if let Some(globals) = module.find_std_section::<payload::Global>() {
+            context.globals.extend(
+                globals
+                    .try_contents()?
+                    .iter()
+                    .map(|global| global.ty.value_type.clone()),
+            );
+        }
+        if let Some(tables) = module.find_std_section::<payload::Table>() {
+            context.tables.extend(
+                tables
+                    .try_contents()?
+                    .iter()
+                    .map(|table| table.elem_type.clone()),
+            );
+        }
+        if let Some(memories) = module.find_std_section::<payload::Memory>() {
+            context.has_memory |= !memories.try_contents()?.is_empty();
+        }
+        Ok(context)
+    }
+}
+
+/// Direct callees of a function and whether it makes indirect calls.
+fn calls(instructions: &[Instruction], callees: &mut Vec<FuncId>, indirect: &mut bool) {
+    for instruction in instructions {
+        match instruction {
+            Instruction::Call(func) | Instruction::ReturnCall(func) => callees.push(*func),
+            Instruction::CallIndirect(_) | Instruction::ReturnCallIndirect(_) => *indirect = true,
+            #[cfg(feature = "exception-handling")]
+            Instruction::TryTable(try_table) => calls(&try_table.instructions, callees, indirect),
+            _ => {}
+        }
+    }
+}
+
+/// Function body in the form produced by [`Converter`], where values are passed between
+/// instructions in locals and blocks have no parameters or results.
+enum Item {
+    /// Instruction that can't unwind.
+    Code(Instruction),
+    /// Call that can unwind, split into instructions that make the call and instructions that
+    /// store its results.
+    Call {
+        index: u32,
+        call: Expression,
+        results: Expression,
+    },
+    /// `block` or `loop`.
+    Block {
+        start: Instruction,
+        body: Vec<Item>,
+        has_calls: bool,
+    },
+    If {
+        cond: LocalId,
+        then: Vec<Item>,
+        then_calls: Range<u32>,
+        otherwise: Option<Vec<Item>>,
+        has_calls: bool,
+    },
+}
+
+impl Item {
+    fn flatten(self, out: &mut Expression) {
+        match self {
+            Item::Code(instruction) => out.push(instruction),
+            Item::Call { call, results, .. } => {
+                out.extend(call);
+                out.extend(results);
+            }
+            Item::Block { start, body, .. } => {
+                out.push(start);
+                for item in body {
+                    item.flatten(out);
+                }
+                out.push(Instruction::End);
+            }
+            Item::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                out.push(Instruction::LocalGet(cond));
+                out.push(Instruction::IfStart(BlockType::Empty));
+                for item in then {
+                    item.flatten(out);
+                }
+                if let Some(otherwise) = otherwise {
+                    out.push(Instruction::IfElse);
+                    for item in otherwise {
+                        item.flatten(out);
+                    }
+                }
+                out.push(Instruction::End);
+            }
+        }
+    }
+}
+
+enum FrameKind {
+    Func,
+    Block(Instruction),
+    If(LocalId),
+}
+
+struct Frame {
+    kind: FrameKind,
+    /// Stack height below the block parameters.
+    base: usize,
+    params: Vec<ValueType>,
+    results: Vec<ValueType>,
+    items: Vec<Item>,
+    /// Index of the first call inside the block.
+    calls: u32,
+    /// Contents of the `then` branch once `else` is reached.
+    then: Option<(Vec<Item>, Range<u32>)>,
+}
+
+/// Converts a function body so that every value on the operand stack lives in a local, with a
+/// separate local for each stack height and type, and unreachable code is removed.
+struct Converter<'a> {
+    context: &'a Context,
+    unwinds: &'a [bool],
+    ignore_indirect_calls: bool,
+    locals: Vec<ValueType>,
+    slots: HashMap<(usize, ValueType), LocalId>,
+    stack: Vec<ValueType>,
+    frames: Vec<Frame>,
+    live: bool,
+    skipped: u32,
+    calls: u32,
+}
+
+#[allow(clippy::cast_possible_truncation)]
+impl Converter<'_> {
+    fn add_local(&mut self, ty: ValueType) -> LocalId {
+        let local = LocalId::from(self.locals.len() as u32);
+        self.locals.push(ty);
+        local
+    }
+
+    fn slot(&mut self, depth: usize, ty: ValueType) -> LocalId {
+        if let Some(&local) = self.slots.get(&(depth, ty.clone())) {
+            return local;
+        }
+        let local = self.add_local(ty.clone());
+        self.slots.insert((depth, ty), local);
+        local
+    }
+
+    fn push(&mut self, item: Item) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.items.push(item);
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.push(Item::Code(instruction));
+    }
+
+    fn base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.base)
+    }
+
+    /// Pops values from the stack and returns instructions that load them from their locals.
+    fn operands(&mut self, count: usize) -> Result<Expression, Failure> {
+        let start = self
+            .stack
+            .len()
+            .checked_sub(count)
+            .filter(|&start| start >= self.base())
+            .ok_or(Failure::Invalid)?;
+        let values = self.stack.split_off(start);
+        Ok((start..)
+            .zip(values)
+            .map(|(depth, ty)| Instruction::LocalGet(self.slot(depth, ty)))
+            .collect())
+    }
+
+    /// Pushes values to the stack and returns instructions that store them into their locals.
+    fn results(&mut self, types: &[ValueType]) -> Expression {
+        let start = self.stack.len();
+        self.stack.extend_from_slice(types);
+        (start..self.stack.len())
+            .rev()
+            .map(|depth| {
+                let ty = self.stack[depth].clone();
+                Instruction::LocalSet(self.slot(depth, ty))
+            })
+            .collect()
+    }
+
+    fn pop_i32(&mut self) -> Result<LocalId, Failure> {
+        let depth = self
+            .stack
+            .len()
+            .checked_sub(1)
+            .filter(|&depth| depth >= self.base())
+            .ok_or(Failure::Invalid)?;
+        self.stack.pop();
+        Ok(self.slot(depth, ValueType::I32))
+    }
+
+    fn plain(
+        &mut self,
+        instruction: Instruction,
+        pops: usize,
+        results: &[ValueType],
+    ) -> Result<(), Failure> {
+        for get in self.operands(pops)? {
+            self.emit(get);
+        }
+        self.emit(instruction);
+        for set in self.results(results) {
+            self.emit(set);
+        }
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        instruction: Instruction,
+        ty: Option<&FuncType>,
+        extra: usize,
+        unwinds: bool,
+    ) -> Result<(), Failure> {
+        let ty = ty.ok_or(Failure::Invalid)?.clone();
+        let pops = ty.params.len() + extra;
+        if !unwinds {
+            return self.plain(instruction, pops, &ty.results);
+        }
+        let mut call = self.operands(pops)?;
+        call.push(instruction);
+        let results = self.results(&ty.results);
+        let index = self.calls;
+        self.calls += 1;
+        self.push(Item::Call {
+            index,
+            call,
+            results,
+        });
+        Ok(())
+    }
+
+    fn tail_call(
+        &mut self,
+        instruction: Instruction,
+        ty: Option<&FuncType>,
+        extra: usize,
+        unwinds: bool,
+    ) -> Result<(), Failure> {
+        if unwinds {
+            return Err(Failure::Unsupported);
+        }
+        let pops = ty.ok_or(Failure::Invalid)?.params.len() + extra;
+        self.plain(instruction, pops, &[])?;
+        self.live = false;
+        Ok(())
+    }
+
+    /// Copies values from the top of the stack to the locals at the given height.
+    fn copy_top(&mut self, to: usize, types: &[ValueType]) -> Result<(), Failure> {
+        let from = self
+            .stack
+            .len()
+            .checked_sub(types.len())
+            .ok_or(Failure::Invalid)?;
+        if from != to {
+            for (i, ty) in types.iter().enumerate() {
+                let src = self.slot(from + i, ty.clone());
+                let dst = self.slot(to + i, ty.clone());
+                self.emit(Instruction::LocalGet(src));
+                self.emit(Instruction::LocalSet(dst));
+            }
+        }
+        Ok(())
+    }
+
+    fn label_types(&self, frame: usize) -> &[ValueType] {
+        let frame = &self.frames[frame];
+        match frame.kind {
+            FrameKind::Block(Instruction::LoopStart(_)) => &frame.params,
+            _ => &frame.results,
+        }
+    }
+
+    fn target(&self, label: LabelId) -> Result<usize, Failure> {
+        (self.frames.len() - 1)
+            .checked_sub(label.index as usize)
+            .ok_or(Failure::Invalid)
+    }
+
+    /// Whether branching to the frame needs to copy values to other locals first.
+    fn needs_copy(&self, frame: usize) -> bool {
+        let count = self.label_types(frame).len();
+        frame == 0
+            || (count != 0 && self.stack.len().checked_sub(count) != Some(self.frames[frame].base))
+    }
+
+    fn ret(&mut self) -> Result<(), Failure> {
+        let count = self.frames[0].results.len();
+        let start = self
+            .stack
+            .len()
+            .checked_sub(count)
+            .ok_or(Failure::Invalid)?;
+        for depth in start..self.stack.len() {
+            let ty = self.stack[depth].clone();
+            let local = self.slot(depth, ty);
+            self.emit(Instruction::LocalGet(local));
+        }
+        self.emit(Instruction::Return);
+        Ok(())
+    }
+
+    /// Branches to the frame from inside `extra` blocks added by the conversion.
+    fn branch(&mut self, frame: usize, extra: u32) -> Result<(), Failure> {
+        if frame == 0 {
+            return self.ret();
+        }
+        let types = self.label_types(frame).to_vec();
+        self.copy_top(self.frames[frame].base, &types)?;
+        let depth = (self.frames.len() - 1 - frame) as u32;
+        self.emit(Instruction::Br(LabelId::from(depth + extra)));
+        Ok(())
+    }
+
+    fn block_types(&self, ty: &BlockType) -> Result<(Vec<ValueType>, Vec<ValueType>), Failure> {
+        Ok(match ty {
+            BlockType::Empty => (Vec::new(), Vec::new()),
+            BlockType::Value(ty) => (Vec::new(), vec![ty.clone()]),
+            BlockType::MultiValue(ty) => {
+                let ty = self.context.signatures.ty(*ty).ok_or(Failure::Invalid)?;
+                (ty.params.clone(), ty.results.clone())
+            }
+        })
+    }
+
+    fn enter(&mut self, kind: FrameKind, ty: &BlockType) -> Result<(), Failure> {
+        let (params, results) = self.block_types(ty)?;
+        let base = self
+            .stack
+            .len()
+            .checked_sub(params.len())
+            .filter(|&base| base >= self.base())
+            .ok_or(Failure::Invalid)?;
+        self.frames.push(Frame {
+            kind,
+            base,
+            params,
+            results,
+            items: Vec::new(),
+            calls: self.calls,
+            then: None,
+        });
+        Ok(())
+    }
+
+    fn otherwise(&mut self) -> Result<(), Failure> {
+        let frame = self.frames.len() - 1;
+        if !matches!(self.frames[frame].kind, FrameKind::If(_)) || self.frames[frame].then.is_some()
+        {
+            return Err(Failure::Invalid);
+        }
+        if self.live {
+            let results = self.frames[frame].results.clone();
+            self.copy_top(self.frames[frame].base, &results)?;
+        }
+        let frame = &mut self.frames[frame];
+        frame.then = Some((std::mem::take(&mut frame.items), frame.calls..self.calls));
+        self.stack.truncate(frame.base);
+        self.stack.extend_from_slice(&frame.params);
+        self.live = true;
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), Failure> {
+        if self.frames.len() == 1 {
+            return Err(Failure::Invalid);
+        }
+        let frame = self.frames.len() - 1;
+        if self.live {
+            let results = self.frames[frame].results.clone();
+            self.copy_top(self.frames[frame].base, &results)?;
+        }
+        let Some(frame) = self.frames.pop() else {
+            return Err(Failure::Invalid);
+        };
+        self.stack.truncate(frame.base);
+        self.stack.extend(frame.results);
+        self.live = true;
+        let has_calls = self.calls > frame.calls;
+        let item = match frame.kind {
+            FrameKind::Func => return Err(Failure::Invalid),
+            FrameKind::Block(start) => Item::Block {
+                start,
+                body: frame.items,
+                has_calls,
+            },
+            FrameKind::If(cond) => {
+                let (then, then_calls, otherwise) = match frame.then {
+                    Some((then, then_calls)) => (then, then_calls, Some(frame.items)),
+                    None => (frame.items, frame.calls..self.calls, None),
+                };
+                Item::If {
+                    cond,
+                    then,
+                    then_calls,
+                    otherwise,
+                    has_calls,
+                }
+            }
+        };
+        self.push(item);
+        Ok(())
+    }
+
+    fn br_table(&mut self, branches: Vec<LabelId>, otherwise: LabelId) -> Result<(), Failure> {
+        let index = self.pop_i32()?;
+        let targets = branches
+            .iter()
+            .chain([&otherwise])
+            .map(|label| self.target(*label))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !targets.iter().any(|&frame| self.needs_copy(frame)) {
+            self.emit(Instruction::LocalGet(index));
+            self.emit(Instruction::BrTable {
+                branches,
+                otherwise,
+            });
+            return Ok(());
+        }
+        // Dispatch to a separate block for each target that copies the values before branching.
+        let mut distinct = targets.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let count = distinct.len() as u32;
+        let mut labels = targets
+            .iter()
+            .map(|frame| LabelId::from(distinct.binary_search(frame).unwrap_or_default() as u32));
+        let otherwise = labels.next_back().unwrap_or(LabelId::from(0));
+        let branches = labels.collect();
+        for _ in 0..count {
+            self.emit(Instruction::BlockStart(BlockType::Empty));
+        }
+        self.emit(Instruction::LocalGet(index));
+        self.emit(Instruction::BrTable {
+            branches,
+            otherwise,
+        });
+        for (outer, frame) in (0..count).rev().zip(distinct) {
+            self.emit(Instruction::End);
+            self.branch(frame, outer)?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn result_type(&self, instruction: &Instruction) -> Result<ValueType, Failure> {
+        Ok(match instruction {
+            Instruction::LocalGet(local) | Instruction::LocalTee(local) => self
+                .locals
+                .get(local.index as usize)
+                .ok_or(Failure::Invalid)?
+                .clone(),
+            Instruction::GlobalGet(global) => self
+                .context
+                .globals
+                .get(global.index as usize)
+                .ok_or(Failure::Invalid)?
+                .clone(),
+            Instruction::TableGet(table) => ValueType::Ref(
+                self.context
+                    .tables
+                    .get(table.index as usize)
+                    .ok_or(Failure::Invalid)?
+                    .clone(),
+            ),
+            Instruction::Select => self
+                .stack
+                .len()
+                .checked_sub(2)
+                .and_then(|depth| self.stack.get(depth))
+                .ok_or(Failure::Invalid)?
+                .clone(),
+            Instruction::SelectWithTypes(types) => types.first().ok_or(Failure::Invalid)?.clone(),
+            Instruction::RefNull(ty) => ValueType::Ref(ty.clone()),
+            Instruction::RefFunc(_) => ValueType::Ref(RefType::Func),
+            Instruction::I32Load(_)
+            | Instruction::I32Load8S(_)
+            | Instruction::I32Load8U(_)
+            | Instruction::I32Load16S(_)
+            | Instruction::I32Load16U(_)
+            | Instruction::MemorySize(_)
+            | Instruction::MemoryGrow(_)
+            | Instruction::I32Const(_)
+            | Instruction::RefIsNull
+            | Instruction::I32Eqz
+            | Instruction::I32Eq
+            | Instruction::I32Ne
+            | Instruction::I32LtS
+            | Instruction::I32LtU
+            | Instruction::I32GtS
+            | Instruction::I32GtU
+            | Instruction::I32LeS
+            | Instruction::I32LeU
+            | Instruction::I32GeS
+            | Instruction::I32GeU
+            | Instruction::I64Eqz
+            | Instruction::I64Eq
+            | Instruction::I64Ne
+            | Instruction::I64LtS
+            | Instruction::I64LtU
+            | Instruction::I64GtS
+            | Instruction::I64GtU
+            | Instruction::I64LeS
+            | Instruction::I64LeU
+            | Instruction::I64GeS
+            | Instruction::I64GeU
+            | Instruction::F32Eq
+            | Instruction::F32Ne
+            | Instruction::F32Lt
+            | Instruction::F32Gt
+            | Instruction::F32Le
+            | Instruction::F32Ge
+            | Instruction::F64Eq
+            | Instruction::F64Ne
+            | Instruction::F64Lt
+            | Instruction::F64Gt
+            | Instruction::F64Le
+            | Instruction::F64Ge
+            | Instruction::I32Clz
+            | Instruction::I32Ctz
+            | Instruction::I32PopCnt
+            | Instruction::I32Add
+            | Instruction::I32Sub
+            | Instruction::I32Mul
+            | Instruction::I32DivS
+            | Instruction::I32DivU
+            | Instruction::I32RemS
+            | Instruction::I32RemU
+            | Instruction::I32And
+            | Instruction::I32Or
+            | Instruction::I32Xor
+            | Instruction::I32Shl
+            | Instruction::I32ShrS
+            | Instruction::I32ShrU
+            | Instruction::I32RotL
+            | Instruction::I32RotR
+            | Instruction::I32WrapI64
+            | Instruction::I32TruncF32S
+            | Instruction::I32TruncF332U
+            | Instruction::I32TruncF64S
+            | Instruction::I32TruncF64U
+            | Instruction::I32ReinterpretF32
+            | Instruction::I32Extend8S
+            | Instruction::I32Extend16S
+            | Instruction::Misc(
+                Misc::I32TruncSatF32S
+                | Misc::I32TruncSatF32U
+                | Misc::I32TruncSatF64S
+                | Misc::I32TruncSatF64U
+                | Misc::TableGrow(_)
+                | Misc::TableSize(_),
+            )
+            | Instruction::SIMD(
+                SIMD::I8x16ExtractLaneS(_)
+                | SIMD::I8x16ExtractLaneU(_)
+                | SIMD::I16x8ExtractLaneS(_)
+                | SIMD::I16x8ExtractLaneU(_)
+                | SIMD::I32x4ExtractLane(_)
+                | SIMD::V128AnyTrue
+                | SIMD::I8x16AllTrue
+                | SIMD::I8x16Bitmask
+                | SIMD::I16x8AllTrue
+                | SIMD::I16x8Bitmask
+                | SIMD::I32x4AllTrue
+                | SIMD::I32x4Bitmask
+                | SIMD::I64x2AllTrue
+                | SIMD::I64x2Bitmask,
+            ) => ValueType::I32,
+            Instruction::I64Load(_)
+            | Instruction::I64Load8S(_)
+            | Instruction::I64Load8U(_)
+            | Instruction::I64Load16S(_)
+            | Instruction::I64Load16U(_)
+            | Instruction::I64Load32S(_)
+            | Instruction::I64Load32U(_)
+            | Instruction::I64Const(_)
+            | Instruction::I64Clz
+            | Instruction::I64Ctz
+            | Instruction::I64PopCnt
+            | Instruction::I64Add
+            | Instruction::I64Sub
+            | Instruction::I64Mul
+            | Instruction::I64DivS
+            | Instruction::I64DivU
+            | Instruction::I64RemS
+            | Instruction::I64RemU
+            | Instruction::I64And
+            | Instruction::I64Or
+            | Instruction::I64Xor
+            | Instruction::I64Shl
+            | Instruction::I64ShrS
+            | Instruction::I64ShrU
+            | Instruction::I64RotL
+            | Instruction::I64RotR
+            | Instruction::I64ExtendI32S
+            | Instruction::I64ExtendI32U
+            | Instruction::I64TruncF32S
+            | Instruction::I64TruncF32U
+            | Instruction::I64TruncF64S
+            | Instruction::I64TruncF64U
+            | Instruction::I64ReinterpretF64
+            | Instruction::I64Extend8S
+            | Instruction::I64Extend16S
+            | Instruction::I64Extend32S
+            | Instruction::Misc(
+                Misc::I64TruncSatF32S
+                | Misc::I64TruncSatF32U
+                | Misc::I64TruncSatF64S
+                | Misc::I64TruncSatF64U,
+            )
+            | Instruction::SIMD(SIMD::I64x2ExtractLane(_)) => ValueType::I64,
+            Instruction::F32Load(_)
+            | Instruction::F32Const(_)
+            | Instruction::F32Abs
+            | Instruction::F32Neg
+            | Instruction::F32Ceil
+            | Instruction::F32Floor
+            | Instruction::F32Trunc
+            | Instruction::F32Nearest
+            | Instruction::F32Sqrt
+            | Instruction::F32Add
+            | Instruction::F32Sub
+            | Instruction::F32Mul
+            | Instruction::F32Div
+            | Instruction::F32Min
+            | Instruction::F32Max
+            | Instruction::F32CopySign
+            | Instruction::F32ConvertI32S
+            | Instruction::F32ConvertI32U
+            | Instruction::F32ConvertI64S
+            | Instruction::F32ConvertI64U
+            | Instruction::F32DemoteF64
+            | Instruction::F32ReinterpretI32
+            | Instruction::SIMD(SIMD::F32x4ExtractLane(_)) => ValueType::F32,
+            #[cfg(feature = "fp16")]
+            Instruction::Misc(Misc::F32LoadF16(_))
+            | Instruction::SIMD(SIMD::F16x8ExtractLane(_)) => ValueType::F32,
+            Instruction::F64Load(_)
+            | Instruction::F64Const(_)
+            | Instruction::F64Abs
+            | Instruction::F64Neg
+            | Instruction::F64Ceil
+            | Instruction::F64Floor
+            | Instruction::F64Trunc
+            | Instruction::F64Nearest
+            | Instruction::F64Sqrt
+            | Instruction::F64Add
+            | Instruction::F64Sub
+            | Instruction::F64Mul
+            | Instruction::F64Div
+            | Instruction::F64Min
+            | Instruction::F64Max
+            | Instruction::F64CopySign
+            | Instruction::F64ConvertI32S
+            | Instruction::F64ConvertI32U
+            | Instruction::F64ConvertI64S
+            | Instruction::F64ConvertI64U
+            | Instruction::F64PromoteF32
+            | Instruction::F64ReinterpretI64
+            | Instruction::SIMD(SIMD::F64x2ExtractLane(_)) => ValueType::F64,
+            // All other SIMD instructions producing a value produce a vector.
+            Instruction::SIMD(_) => ValueType::V128,
+            _ => return Err(Failure::Unsupported),
+        })
+    }
+
+    /// Skips an instruction in unreachable code until the end of the current block.
+    fn skip(&mut self, instruction: &Instruction) -> Result<(), Failure> {
+        match instruction {
+            Instruction::BlockStart(_) | Instruction::LoopStart(_) | Instruction::IfStart(_) => {
+                self.skipped += 1;
+            }
+            #[cfg(feature = "legacy-exceptions")]
+            Instruction::TryStart(_) => self.skipped += 1,
+            #[cfg(feature = "legacy-exceptions")]
+            Instruction::TryDelegate(_) => self.skipped = self.skipped.saturating_sub(1),
+            Instruction::End if self.skipped > 0 => self.skipped -= 1,
+            Instruction::End => self.end()?,
+            Instruction::IfElse if self.skipped == 0 => self.otherwise()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn convert(&mut self, instruction: Instruction) -> Result<(), Failure> {
+        if !self.live {
+            return self.skip(&instruction);
+        }
+        let signatures = &self.context.signatures;
+        match instruction {
+            Instruction::Nop => {}
+            Instruction::Drop => {
+                self.operands(1)?;
+            }
+            Instruction::Unreachable => {
+                self.emit(Instruction::Unreachable);
+                self.live = false;
+            }
+            Instruction::BlockStart(ty) => {
+                let start = Instruction::BlockStart(BlockType::Empty);
+                self.enter(FrameKind::Block(start), &ty)?;
+            }
+            Instruction::LoopStart(ty) => {
+                let start = Instruction::LoopStart(BlockType::Empty);
+                self.enter(FrameKind::Block(start), &ty)?;
+            }
+            Instruction::IfStart(ty) => {
+                let cond = self.pop_i32()?;
+                self.enter(FrameKind::If(cond), &ty)?;
+            }
+            Instruction::IfElse => self.otherwise()?,
+            Instruction::End => self.end()?,
+            Instruction::Br(label) => {
+                let frame = self.target(label)?;
+                self.branch(frame, 0)?;
+                self.live = false;
+            }
+            Instruction::BrIf(label) => {
+                let frame = self.target(label)?;
+                let cond = self.pop_i32()?;
+                self.emit(Instruction::LocalGet(cond));
+                if self.needs_copy(frame) {
+                    self.emit(Instruction::IfStart(BlockType::Empty));
+                    self.branch(frame, 1)?;
+                    self.emit(Instruction::End);
+                } else {
+                    self.emit(Instruction::BrIf(label));
+                }
+            }
+            Instruction::BrTable {
+                branches,
+                otherwise,
+            } => {
+                self.br_table(branches, otherwise)?;
+                self.live = false;
+            }
+            Instruction::Return => {
+                self.ret()?;
+                self.live = false;
+            }
+            Instruction::Call(func) => {
+                let unwinds = self.unwinds.get(func.index as usize) == Some(&true);
+                self.call(instruction, signatures.func(func), 0, unwinds)?;
+            }
+            Instruction::CallIndirect(ref call) => {
+                let ty = signatures.ty(call.ty);
+                self.call(instruction, ty, 1, !self.ignore_indirect_calls)?;
+            }
+            Instruction::ReturnCall(func) => {
+                let unwinds = self.unwinds.get(func.index as usize) == Some(&true);
+                self.tail_call(instruction, signatures.func(func), 0, unwinds)?;
+            }
+            Instruction::ReturnCallIndirect(ref call) => {
+                let ty = signatures.ty(call.ty);
+                self.tail_call(instruction, ty, 1, !self.ignore_indirect_calls)?;
+            }
+            instruction => {
+                let Effect::Values(pops, pushes) = effect(signatures, &instruction)? else {
+                    return Err(Failure::Unsupported);
+                };
+                let results = match pushes {
+                    0 => Vec::new(),
+                    1 => vec![self.result_type(&instruction)?],
+                    _ => return Err(Failure::Unsupported),
+                };
+                self.plain(instruction, pops as usize, &results)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Increments labels in the instructions that refer to blocks outside of them.
+fn shift_labels(instructions: &mut [Instruction]) {
+    fn shift(label: &mut LabelId, depth: u32) {
+        if label.index >= depth {
+            label.index += 1;
+        }
+    }
+
+    let mut depth = 0;
+    for instruction in instructions {
+        match instruction {
+            Instruction::BlockStart(_) | Instruction::LoopStart(_) | Instruction::IfStart(_) => {
+                depth += 1;
+            }
+            Instruction::End => depth -= 1,
+            Instruction::Br(label) | Instruction::BrIf(label) => shift(label, depth),
+            Instruction::BrTable {
+                branches,
+                otherwise,
+            } => {
+                for label in branches.iter_mut().chain([otherwise]) {
+                    shift(label, depth);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn mem_arg(align_log2: u32, offset: u32) -> MemArg {
+    MemArg {
+        align_log2,
+        memory: MemId::from(0),
+        offset,
+    }
+}
+
+fn zero(ty: &ValueType) -> Instruction {
+    match ty {
+        ValueType::I32 => Instruction::I32Const(0),
+        ValueType::I64 => Instruction::I64Const(0),
+        ValueType::F32 => Instruction::F32Const(FloatConst { value: 0.0 }),
+        ValueType::F64 => Instruction::F64Const(FloatConst { value: 0.0 }),
+        ValueType::V128 => Instruction::SIMD(SIMD::V128Const([0; 16])),
+        ValueType::Ref(ty) => Instruction::RefNull(ty.clone()),
+    }
+}
+
+/// Generates the code that resumes calls while rewinding and saves and restores locals.
+struct Rewinder {
+    state: GlobalId,
+    data: GlobalId,
+    index: LocalId,
+}
+
+impl Rewinder {
+    fn guard(&self, mut segment: Expression, out: &mut Expression) {
+        if segment.is_empty() {
+            return;
+        }
+        shift_labels(&mut segment);
+        out.extend([
+            Instruction::GlobalGet(self.state),
+            Instruction::I32Const(REWINDING),
+            Instruction::I32Ne,
+            Instruction::IfStart(BlockType::Empty),
+        ]);
+        out.append(&mut segment);
+        out.push(Instruction::End);
+    }
+
+    /// Emits the items, skipping everything except the path to the resumed call while rewinding.
+    /// `depth` is the label of the block that unwinding branches to.
+    fn emit(&self, items: Vec<Item>, depth: u32, out: &mut Expression) {
+        let mut segment = Vec::new();
+        for item in items {
+            match item {
+                Item::Call {
+                    index,
+                    call,
+                    results,
+                } => {
+                    self.guard(std::mem::take(&mut segment), out);
+                    out.extend([
+                        Instruction::GlobalGet(self.state),
+                        Instruction::I32Const(REWINDING),
+                        Instruction::I32Ne,
+                        Instruction::LocalGet(self.index),
+                        Instruction::I32Const(index.cast_signed()),
+                        Instruction::I32Eq,
+                        Instruction::I32Or,
+                        Instruction::IfStart(BlockType::Empty),
+                    ]);
+                    out.extend(call);
+                    out.extend([
+                        Instruction::GlobalGet(self.state),
+                        Instruction::I32Const(UNWINDING),
+                        Instruction::I32Eq,
+                        Instruction::IfStart(BlockType::Empty),
+                        Instruction::I32Const(index.cast_signed()),
+                        Instruction::LocalSet(self.index),
+                        Instruction::Br(LabelId::from(depth + 2)),
+                        Instruction::End,
+                    ]);
+                    out.extend(results);
+                    out.push(Instruction::End);
+                }
+                Item::Block {
+                    start,
+                    body,
+                    has_calls: true,
+                } => {
+                    self.guard(std::mem::take(&mut segment), out);
+                    out.push(start);
+                    self.emit(body, depth + 1, out);
+                    out.push(Instruction::End);
+                }
+                Item::If {
+                    cond,
+                    then,
+                    then_calls,
+                    otherwise,
+                    has_calls: true,
+                } => {
+                    self.guard(std::mem::take(&mut segment), out);
+                    // While rewinding, take the branch that contains the resumed call.
+                    if then_calls.is_empty() {
+                        out.push(Instruction::I32Const(0));
+                    } else {
+                        out.extend([
+                            Instruction::LocalGet(self.index),
+                            Instruction::I32Const(then_calls.start.cast_signed()),
+                            Instruction::I32Sub,
+                            Instruction::I32Const(
+                                (then_calls.end - then_calls.start).cast_signed(),
+                            ),
+                            Instruction::I32LtU,
+                        ]);
+                    }
+                    out.extend([
+                        Instruction::LocalGet(cond),
+                        Instruction::GlobalGet(self.state),
+                        Instruction::I32Const(REWINDING),
+                        Instruction::I32Eq,
+                        Instruction::Select,
+                        Instruction::IfStart(BlockType::Empty),
+                    ]);
+                    self.emit(then, depth + 1, out);
+                    if let Some(otherwise) = otherwise {
+                        out.push(Instruction::IfElse);
+                        self.emit(otherwise, depth + 1, out);
+                    }
+                    out.push(Instruction::End);
+                }
+                item => item.flatten(&mut segment),
+            }
+        }
+        self.guard(segment, out);
+    }
+
+    /// Checks that the saved frames fit in the buffer, trapping otherwise.
+    fn check_bounds(&self, size: u32, out: &mut Expression) {
+        out.extend([
+            Instruction::GlobalGet(self.data),
+            Instruction::I32Load(mem_arg(2, 0)),
+            Instruction::I32Const(size.cast_signed()),
+            Instruction::I32Add,
+            Instruction::GlobalGet(self.data),
+            Instruction::I32Load(mem_arg(2, 4)),
+            Instruction::I32GtU,
+            Instruction::IfStart(BlockType::Empty),
+            Instruction::Unreachable,
+            Instruction::End,
+        ]);
+    }
+
+    /// Pushes the locals to the buffer.
+    fn save(&self, locals: &[(LocalId, ValueType, u32)], size: u32, ptr: LocalId) -> Expression {
+        let mut out = Vec::new();
+        self.check_bounds(size, &mut out);
+        out.extend([
+            Instruction::GlobalGet(self.data),
+            Instruction::I32Load(mem_arg(2, 0)),
+            Instruction::LocalSet(ptr),
+        ]);
+        for (local, ty, offset) in locals {
+            let store = match ty {
+                ValueType::I32 => Instruction::I32Store(mem_arg(0, *offset)),
+                ValueType::I64 => Instruction::I64Store(mem_arg(0, *offset)),
+                ValueType::F32 => Instruction::F32Store(mem_arg(0, *offset)),
+                ValueType::F64 => Instruction::F64Store(mem_arg(0, *offset)),
+                ValueType::V128 | ValueType::Ref(_) => {
+                    Instruction::SIMD(SIMD::V128Store(mem_arg(0, *offset)))
+                }
+            };
+            out.extend([
+                Instruction::LocalGet(ptr),
+                Instruction::LocalGet(*local),
+                store,
+            ]);
+        }
+        out.extend([
+            Instruction::GlobalGet(self.data),
+            Instruction::LocalGet(ptr),
+            Instruction::I32Const(size.cast_signed()),
+            Instruction::I32Add,
+            Instruction::I32Store(mem_arg(2, 0)),
+        ]);
+        out
+    }
+
+    /// Pops the locals from the buffer.
+    fn restore(&self, locals: &[(LocalId, ValueType, u32)], size: u32, ptr: LocalId) -> Expression {
+        let mut out = vec![
+            Instruction::GlobalGet(self.data),
+            Instruction::GlobalGet(self.data),
+            Instruction::I32Load(mem_arg(2, 0)),
+            Instruction::I32Const(size.cast_signed()),
+            Instruction::I32Sub,
+            Instruction::LocalTee(ptr),
+            Instruction::I32Store(mem_arg(2, 0)),
+        ];
+        for (local, ty, offset) in locals {
+            let load = match ty {
+                ValueType::I32 => Instruction::I32Load(mem_arg(0, *offset)),
+                ValueType::I64 => Instruction::I64Load(mem_arg(0, *offset)),
+                ValueType::F32 => Instruction::F32Load(mem_arg(0, *offset)),
+                ValueType::F64 => Instruction::F64Load(mem_arg(0, *offset)),
+                ValueType::V128 | ValueType::Ref(_) => {
+                    Instruction::SIMD(SIMD::V128Load(mem_arg(0, *offset)))
+                }
+            };
+            out.extend([
+                Instruction::LocalGet(ptr),
+                load,
+                Instruction::LocalSet(*local),
+            ]);
+        }
+        out
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn transform(
+        &self,
+        mut converter: Converter,
+        params: usize,
+        body: FuncBody,
+    ) -> Result<FuncBody, Failure> {
+        let FuncBody { mut locals, expr } = body;
+        for instruction in expr {
+            converter.convert(instruction)?;
+        }
+        if converter.frames.len() != 1 {
+            return Err(Failure::Invalid);
+        }
+        if converter.live {
+            converter.ret()?;
+        }
+        let items = std::mem::take(&mut converter.frames[0].items);
+        let results = std::mem::take(&mut converter.frames[0].results);
+        let first_added = locals
+            .iter()
+            .fold(params, |count, locals| count + locals.repeat as usize);
+
+        let mut layout = Vec::new();
+        let mut size = 0_u32;
+        for (index, ty) in converter.locals.iter().enumerate() {
+            let ty_size = match ty {
+                ValueType::I32 | ValueType::F32 => 4,
+                ValueType::I64 | ValueType::F64 => 8,
+                ValueType::V128 => 16,
+                ValueType::Ref(_) => return Err(Failure::Unsupported),
+            };
+            layout.push((LocalId::from(index as u32), ty.clone(), size));
+            size += ty_size;
+        }
+        let ptr = converter.add_local(ValueType::I32);
+
+        let mut expr = vec![
+            Instruction::GlobalGet(self.state),
+            Instruction::I32Const(REWINDING),
+            Instruction::I32Eq,
+            Instruction::IfStart(BlockType::Empty),
+        ];
+        expr.extend(self.restore(&layout, size, ptr));
+        expr.extend([Instruction::End, Instruction::BlockStart(BlockType::Empty)]);
+        self.emit(items, 0, &mut expr);
+        expr.extend([Instruction::Unreachable, Instruction::End]);
+        expr.extend(self.save(&layout, size, ptr));
+        expr.extend(results.iter().map(zero));
+
+        for ty in converter.locals.drain(first_added..) {
+            match locals.last_mut() {
+                Some(last) if last.ty == ty => last.repeat += 1,
+                _ => locals.push(Locals { repeat: 1, ty }),
+            }
+        }
+        Ok(FuncBody { locals, expr })
+    }
+}
+
+fn i32_global() -> GlobalType {
+    GlobalType {
+        value_type: ValueType::I32,
+        mutable: true,
+        #[cfg(feature = "threads")]
+        is_shared: false,
+    }
+}
+
+/// Runtime functions that switch between the states, with their export names.
+fn runtime(rewinder: &Rewinder) -> Vec<(&'static str, FuncType, FuncBody)> {
+    let mut funcs = Vec::new();
+    for (name, state, takes_data) in [
+        ("asyncify_start_unwind", UNWINDING, true),
+        ("asyncify_stop_unwind", NORMAL, false),
+        ("asyncify_start_rewind", REWINDING, true),
+        ("asyncify_stop_rewind", NORMAL, false),
+    ] {
+        let mut expr = vec![
+            Instruction::I32Const(state),
+            Instruction::GlobalSet(rewinder.state),
+        ];
+        if takes_data {
+            expr.extend([
+                Instruction::LocalGet(LocalId::from(0)),
+                Instruction::GlobalSet(rewinder.data),
+            ]);
+        }
+        rewinder.check_bounds(0, &mut expr);
+        let ty = FuncType {
+            params: if takes_data {
+                vec![ValueType::I32]
+            } else {
+                vec![]
+            },
+            results: vec![],
+        };
+        funcs.push((
+            name,
+            ty,
+            FuncBody {
+                locals: vec![],
+                expr,
+            },
+        ));
+    }
+    let ty = FuncType {
+        params: vec![],
+        results: vec![ValueType::I32],
+    };
+    let expr = vec![Instruction::GlobalGet(rewinder.state)];
+    funcs.push((
+        "asyncify_get_state",
+        ty,
+        FuncBody {
+            locals: vec![],
+            expr,
+        },
+    ));
+    funcs
+}
+
+/// Convert the body of a function that can unwind.
+fn convert(
+    context: &Context,
+    unwinds: &[bool],
+    ignore_indirect_calls: bool,
+    rewinder: &Rewinder,
+    func: FuncId,
+    body: FuncBody,
+) -> Result<FuncBody, AsyncifyError> {
+    let ty = context
+        .signatures
+        .func(func)
+        .ok_or(AsyncifyError::Invalid(func))?;
+    let mut locals = ty.params.clone();
+    for declared in &body.locals {
+        locals.extend(std::iter::repeat_n(
+            declared.ty.clone(),
+            declared.repeat as usize,
+        ));
+    }
+    let params = ty.params.len();
+    let mut converter = Converter {
+        context,
+        unwinds,
+        ignore_indirect_calls,
+        locals,
+        slots: HashMap::new(),
+        stack: Vec::new(),
+        frames: vec![Frame {
+            kind: FrameKind::Func,
+            base: 0,
+            params: Vec::new(),
+            results: ty.results.clone(),
+            items: Vec::new(),
+            calls: 0,
+            then: None,
+        }],
+        live: true,
+        skipped: 0,
+        calls: 0,
+    };
+    let rewinder = Rewinder {
+        index: converter.add_local(ValueType::I32),
+        ..*rewinder
+    };
+    rewinder
+        .transform(converter, params, body)
+        .map_err(|err| match err {
+            Failure::Invalid => AsyncifyError::Invalid(func),
+            Failure::Unsupported => AsyncifyError::Unsupported(func),
+        })
+}
+
+/// Make functions that can call the given imports, directly or through other functions, able to
+/// unwind the call stack into linear memory and to rewind it later, and add the runtime exports.
+///
+/// Instrumented functions keep all values on the operand stack in locals, save all locals to
+/// memory when unwinding, and restore them and skip to the interrupted call when rewinding.
+/// Calls from functions that aren't instrumented, such as indirect calls when
+/// [`Asyncify::ignore_indirect_calls`] is set, must not unwind.
+///
+/// Returns the instrumented functions. The module is left unchanged if an error is returned.
+pub fn asyncify(module: &mut Module, config: &Asyncify) -> Result<Vec<FuncId>, AsyncifyError> {
+    let context = Context::new(module)?;
+    let mut unwinds = context
+        .imported_funcs
+        .iter()
+        .map(|path| config.imports.contains(path))
+        .collect::<Vec<_>>();
+    let imported = unwinds.len();
+    let indirect_unwinds = !config.ignore_indirect_calls && unwinds.contains(&true);
+    let mut callees = Vec::new();
+    if let Some(code) = module.find_std_section::<payload::Code>() {
+        for body in code.try_contents()? {
+            let mut funcs = Vec::new();
+            let mut indirect = false;
+            calls(&body.try_contents()?.expr, &mut funcs, &mut indirect);
+            callees.push((funcs, indirect && indirect_unwinds));
+        }
+    }
+    unwinds.resize(imported + callees.len(), false);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (index, (funcs, indirect)) in callees.iter().enumerate() {
+            if !unwinds[imported + index]
+                && (*indirect
+                    || funcs
+                        .iter()
+                        .any(|func| unwinds.get(func.index as usize) == Some(&true)))
+            {
+                unwinds[imported + index] = true;
+                changed = true;
+            }
+        }
+    }
+    if !unwinds.contains(&true) {
+        return Ok(Vec::new());
+    }
+    if !context.has_memory {
+        return Err(AsyncifyError::NoMemory);
+    }
+
+    // The globals are added after all existing ones, but only once all bodies are converted and
+    // the export names are checked, so that the module is left untouched on failure.
+    #[allow(clippy::cast_possible_truncation)]
+    let rewinder = Rewinder {
+        state: GlobalId::from(context.globals.len() as u32),
+        data: GlobalId::from(context.globals.len() as u32 + 1),
+        index: LocalId::from(0),
+    };
+    let mut bodies = Vec::new();
+    if let Some(code) = module.find_std_section::<payload::Code>() {
+        #[allow(clippy::cast_possible_truncation)]
+        for (index, body) in (imported as u32..).zip(code.try_contents()?) {
+            if !unwinds[index as usize] {
+                continue;
+            }
+            let func = FuncId::from(index);
+            let body = body.try_contents()?;
+            let body = convert(
+                &context,
+                &unwinds,
+                !indirect_unwinds,
+                &rewinder,
+                func,
+                body.clone(),
+            )?;
+            bodies.push((func, body));
+        }
+    }
+
+    let runtime = runtime(&rewinder);
+    if let Some(exports) = module.find_std_section::<payload::Export>() {
+        let exports = exports.try_contents()?;
+        if let Some((name, ..)) = runtime
+            .iter()
+            .find(|(name, ..)| exports.iter().any(|export| export.name == *name))
+        {
+            return Err(InterfaceError::DuplicateExport((*name).to_owned()).into());
+        }
+    }
+
+    let state = add_global(module, i32_global(), vec![Instruction::I32Const(NORMAL)])?;
+    let data = add_global(module, i32_global(), vec![Instruction::I32Const(0)])?;
+    debug_assert_eq!((state, data), (rewinder.state, rewinder.data));
+    let mut instrumented = Vec::new();
+    if let Some(code) = module.find_std_section_mut::<payload::Code>() {
+        let code = code.try_contents_mut()?;
+        for (func, body) in bodies {
+            code[func.index as usize - imported] = body.into();
+            instrumented.push(func);
+        }
+    }
+    for (name, ty, body) in runtime {
+        let func = add_func(module, ty, body)?;
+        module.add_export(Export {
+            name: name.to_owned(),
+            desc: ExportDesc::Func(func),
+        })?;
+    }
+    Ok(instrumented)
+}
@@ -0,0 +1,243 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of [branch hints](https://github.com/WebAssembly/branch-hinting) between instruction
+//! byte offsets used in the binary format and instruction indices that survive re-encoding.
+
+use crate::builtins::{Blob, Lazy};
+use crate::indices::FuncId;
+#[cfg(feature = "exception-handling")]
+use crate::instructions::Catch;
+use crate::instructions::Instruction;
+use crate::io::{Decode, DecodeError, Encode};
+use crate::sections::{
+    payload, BranchHint, BranchHintValue, CustomSection, FuncBody, FuncBranchHints, ImportDesc,
+    Kind, Locals, Section,
+};
+#[cfg(feature = "exception-handling")]
+use crate::types::BlockType;
+use crate::Module;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Branch hints keyed by function and by instruction index within its [body](FuncBody::expr).
+///
+/// Instructions nested in `try_table` bodies are counted in depth-first order, right after the
+/// `try_table` itself.
+pub type IndexedBranchHints = BTreeMap<FuncId, BTreeMap<usize, BranchHintValue>>;
+
+/// Error returned by [`read_branch_hints`] and [`write_branch_hints`].
+#[derive(Debug, Error)]
+pub enum BranchHintError {
+    /// Decoding error occured while reading a section or a function body.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Encoding error occured while measuring a function body.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Function is imported or doesn't exist.
+    #[error("Function {0:?} is not defined in the code section")]
+    UnknownFunc(FuncId),
+
+    /// Hint offset doesn't point to the start of an instruction.
+    #[error("Branch hint for {func:?} at offset 0x{offset:X} doesn't point to an instruction")]
+    InvalidOffset { func: FuncId, offset: u32 },
+
+    /// Hinted instruction is neither `br_if` nor `if`.
+    #[error("Instruction {index} in {func:?} is not a `br_if` or `if`")]
+    NotABranch { func: FuncId, index: usize },
+}
+
+fn func_body(module: &Module, func: FuncId) -> Result<&Blob<FuncBody>, BranchHintError> {
+    let mut imported = 0;
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        imported = imports
+            .try_contents()?
+            .iter()
+            .filter(|import| matches!(import.desc, ImportDesc::Func(_)))
+            .count();
+    }
+    let code = module
+        .find_std_section::<payload::Code>()
+        .ok_or(BranchHintError::UnknownFunc(func))?
+        .try_contents()?;
+    (func.index as usize)
+        .checked_sub(imported)
+        .and_then(|index| code.get(index))
+        .ok_or(BranchHintError::UnknownFunc(func))
+}
+
+/// Record the offset of the instruction at the start of `r` and of the instructions nested in it,
+/// advancing `r` past it.
+#[cfg_attr(not(feature = "exception-handling"), allow(unused_variables))]
+fn push_offsets(
+    bytes: &[u8],
+    r: &mut &[u8],
+    offsets: &mut Vec<u32>,
+) -> Result<(), BranchHintError> {
+    offsets.push(u32::try_from(bytes.len() - r.len()).map_err(std::io::Error::other)?);
+    let start = *r;
+    let instr = Instruction::decode(r)?;
+    #[cfg(feature = "exception-handling")]
+    if let Instruction::TryTable(try_table) = instr {
+        // Skip the opcode and the header, which may be encoded non-minimally.
+        let mut nested = &start[1..];
+        BlockType::decode(&mut nested)?;
+        <Vec<Catch>>::decode(&mut nested)?;
+        for _ in &try_table.instructions {
+            push_offsets(bytes, &mut nested, offsets)?;
+        }
+    }
+    Ok(())
+}
+
+/// Byte offsets of the body instructions (without the terminating `end`) as they will be encoded,
+/// in the order of [`IndexedBranchHints`] indices.
+fn instruction_offsets(body: &Blob<FuncBody>) -> Result<Vec<u32>, BranchHintError> {
+    let encoded;
+    let bytes: &[u8] = match body.try_as_raw() {
+        Ok(raw) => raw,
+        Err(body) => {
+            let mut buf = Vec::new();
+            body.encode(&mut buf)?;
+            encoded = buf;
+            &encoded
+        }
+    };
+    let mut r = bytes;
+    <Vec<Locals>>::decode(&mut r)?;
+    let mut offsets = Vec::new();
+    while !r.is_empty() {
+        push_offsets(bytes, &mut r, &mut offsets)?;
+    }
+    offsets.pop();
+    Ok(offsets)
+}
+
+/// Body instructions in the order of [`IndexedBranchHints`] indices.
+fn flat_instrs<'a>(instrs: &'a [Instruction], out: &mut Vec<&'a Instruction>) {
+    for instr in instrs {
+        out.push(instr);
+        #[cfg(feature = "exception-handling")]
+        if let Instruction::TryTable(try_table) = instr {
+            flat_instrs(&try_table.instructions, out);
+        }
+    }
+}
+
+fn find_branch_hints(module: &Module) -> Option<(usize, &Lazy<Vec<FuncBranchHints>>)> {
+    module
+        .sections
+        .iter()
+        .enumerate()
+        .find_map(
+            |(index, section)| match section.try_as::<payload::Custom>()?.try_contents() {
+                Ok(CustomSection::BranchHints(hints)) => Some((index, hints)),
+                _ => None,
+            },
+        )
+}
+
+/// Read the branch hints section of the module and convert hint offsets to instruction indices.
+pub fn read_branch_hints(module: &Module) -> Result<IndexedBranchHints, BranchHintError> {
+    let mut res = IndexedBranchHints::new();
+    let Some((_, section)) = find_branch_hints(module) else {
+        return Ok(res);
+    };
+    for func_hints in section.try_contents()? {
+        let func = func_hints.func;
+        let offsets = instruction_offsets(func_body(module, func)?)?;
+        let indexed = res.entry(func).or_default();
+        for hint in &func_hints.hints {
+            let index = offsets.binary_search(&hint.offset).map_err(|_| {
+                BranchHintError::InvalidOffset {
+                    func,
+                    offset: hint.offset,
+                }
+            })?;
+            indexed.insert(index, hint.value);
+        }
+    }
+    Ok(res)
+}
+
+/// Write branch hints to the module, computing instruction offsets from the current function bodies.
+///
+/// Call this after editing function bodies to keep hints attached to the right instructions.
+/// An existing branch hints section is replaced, otherwise a new one is inserted before the
+/// code section.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use wasmbin::transforms::branch_hints::{read_branch_hints, write_branch_hints};
+/// use wasmbin::Module;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut module = Module::decode_from(File::open("module.wasm")?)?;
+/// let hints = read_branch_hints(&module)?;
+/// // ... edit function bodies, adjusting hint indices accordingly ...
+/// write_branch_hints(&mut module, &hints)?;
+/// module.encode_into(File::create("module.wasm")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_branch_hints(
+    module: &mut Module,
+    hints: &IndexedBranchHints,
+) -> Result<(), BranchHintError> {
+    let mut section = Vec::new();
+    for (&func, indexed) in hints {
+        if indexed.is_empty() {
+            continue;
+        }
+        let body = func_body(module, func)?;
+        let offsets = instruction_offsets(body)?;
+        let mut instrs = Vec::new();
+        flat_instrs(&body.try_contents()?.expr, &mut instrs);
+        let hints = indexed
+            .iter()
+            .map(|(&index, &value)| match instrs.get(index) {
+                Some(Instruction::BrIf(_) | Instruction::IfStart(_)) => Ok(BranchHint {
+                    offset: offsets[index],
+                    value,
+                }),
+                _ => Err(BranchHintError::NotABranch { func, index }),
+            })
+            .collect::<Result<_, _>>()?;
+        section.push(FuncBranchHints { func, hints });
+    }
+    let existing = find_branch_hints(module).map(|(index, _)| index);
+    if section.is_empty() {
+        if let Some(index) = existing {
+            module.sections.remove(index);
+        }
+        return Ok(());
+    }
+    let section = Section::Custom(Blob::from(CustomSection::BranchHints(section.into())));
+    if let Some(index) = existing {
+        module.sections[index] = section;
+    } else {
+        let index = module
+            .sections
+            .iter()
+            .position(|section| section.kind() == Kind::Code)
+            .unwrap_or(module.sections.len());
+        module.sections.insert(index, section);
+    }
+    Ok(())
+}
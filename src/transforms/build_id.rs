@@ -0,0 +1,103 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Computation and verification of the [build id](https://github.com/WebAssembly/tool-conventions/blob/main/BuildId.md)
+//! custom section.
+
+use crate::builtins::Blob;
+use crate::io::{DecodeError, Encode};
+use crate::sections::{CustomSection, Kind, Section};
+use crate::Module;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Size of computed build ids, matching UUID-based debug identifiers.
+pub const BUILD_ID_SIZE: usize = 16;
+
+/// Error returned by [`verify_build_id`].
+#[derive(Debug, Error)]
+pub enum BuildIdError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Module doesn't have a build id section.
+    #[error("Module doesn't have a build id")]
+    Missing,
+
+    /// Stored build id doesn't match the module contents.
+    #[error("Build id {actual:02X?} doesn't match the expected {expected:02X?}")]
+    Mismatch { expected: Vec<u8>, actual: Vec<u8> },
+}
+
+/// Compute a deterministic build id as a truncated SHA-256 hash of all the non-custom sections.
+///
+/// Custom sections, including the build id itself and debug info, don't affect the result.
+pub fn compute_build_id(module: &Module) -> Result<Vec<u8>, DecodeError> {
+    let mut hasher = Sha256::new();
+    let mut encoded = Vec::new();
+    for section in &module.sections {
+        if section.kind() == Kind::Custom {
+            continue;
+        }
+        encoded.clear();
+        section.encode(&mut encoded)?;
+        hasher.update(&encoded);
+    }
+    Ok(hasher.finalize()[..BUILD_ID_SIZE].to_vec())
+}
+
+fn find_build_id(module: &Module) -> Option<(usize, &Vec<u8>)> {
+    module
+        .sections
+        .iter()
+        .enumerate()
+        .find_map(|(index, section)| match section {
+            Section::Custom(custom) => match custom.try_contents() {
+                Ok(CustomSection::BuildId(build_id)) => Some((index, build_id)),
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+/// Stored build id of a module, if any.
+pub fn read_build_id(module: &Module) -> Option<&[u8]> {
+    find_build_id(module).map(|(_, build_id)| build_id.as_slice())
+}
+
+/// Compute the build id and store it in the module, replacing the existing one or inserting
+/// a new section at the start of the module.
+pub fn set_build_id(module: &mut Module) -> Result<Vec<u8>, DecodeError> {
+    let build_id = compute_build_id(module)?;
+    let section = Section::Custom(Blob::from(CustomSection::BuildId(build_id.clone())));
+    match find_build_id(module) {
+        Some((index, _)) => module.sections[index] = section,
+        None => module.sections.insert(0, section),
+    }
+    Ok(build_id)
+}
+
+/// Check that the stored build id matches the module contents.
+pub fn verify_build_id(module: &Module) -> Result<(), BuildIdError> {
+    let actual = read_build_id(module).ok_or(BuildIdError::Missing)?;
+    let expected = compute_build_id(module)?;
+    if actual != expected {
+        return Err(BuildIdError::Mismatch {
+            expected,
+            actual: actual.to_vec(),
+        });
+    }
+    Ok(())
+}
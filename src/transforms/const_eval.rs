@@ -0,0 +1,296 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Evaluation of [constant expressions](https://webassembly.github.io/spec/core/valid/instructions.html#constant-expressions)
+//! used by global initializers and active element and data segment offsets.
+
+use crate::indices::{DataId, ElemId, FuncId, GlobalId, MemId, TableId};
+use crate::instructions::{Instruction, SIMD};
+use crate::io::DecodeError;
+use crate::sections::{payload, DataInit, Element, ImportDesc, ImportPath};
+use crate::types::{GlobalType, RefType};
+use crate::Module;
+use thiserror::Error;
+
+/// Result of a constant expression.
+///
+/// Floats compare by their bit pattern, so that NaNs with the same payload are equal and `0.0`
+/// differs from `-0.0`.
+#[derive(Debug, Clone)]
+pub enum ConstValue {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    V128([u8; 16]),
+    /// Null reference of the given type.
+    Null(RefType),
+    /// Reference to a function.
+    Func(FuncId),
+    /// Opaque host reference, which can only come from an imported global.
+    Extern(u32),
+}
+
+impl PartialEq for ConstValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ConstValue::I32(a), ConstValue::I32(b)) => a == b,
+            (ConstValue::I64(a), ConstValue::I64(b)) => a == b,
+            (ConstValue::F32(a), ConstValue::F32(b)) => a.to_bits() == b.to_bits(),
+            (ConstValue::F64(a), ConstValue::F64(b)) => a.to_bits() == b.to_bits(),
+            (ConstValue::V128(a), ConstValue::V128(b)) => a == b,
+            (ConstValue::Null(a), ConstValue::Null(b)) => a == b,
+            (ConstValue::Func(a), ConstValue::Func(b)) => a == b,
+            (ConstValue::Extern(a), ConstValue::Extern(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ConstValue {}
+
+/// Error returned when a constant expression can't be evaluated.
+#[derive(Debug, Error)]
+pub enum ConstEvalError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Instruction is not allowed in constant expressions.
+    #[error("Instruction {index} is not allowed in a constant expression")]
+    NotConstant { index: usize },
+
+    /// Instruction operands are missing or have mismatched types.
+    #[error("Instruction {index} has invalid operands")]
+    InvalidOperands { index: usize },
+
+    /// Referenced global is not defined before the expression.
+    #[error("Global {0:?} is not available to the constant expression")]
+    UnknownGlobal(GlobalId),
+
+    /// Caller didn't supply a value for an imported global.
+    #[error("No value supplied for imported global {}.{}", .0.module, .0.name)]
+    MissingImport(ImportPath),
+
+    /// Expression didn't produce exactly one value.
+    #[error("Constant expression produced {0} values instead of one")]
+    InvalidResultCount(usize),
+
+    /// Segment offset is not an integer.
+    #[error("Segment offset {0:?} is not an integer")]
+    InvalidOffset(ConstValue),
+}
+
+/// Evaluate a constant expression, given the values of the globals preceding it.
+pub fn eval_const_expr(
+    expr: &[Instruction],
+    globals: &[ConstValue],
+) -> Result<ConstValue, ConstEvalError> {
+    let mut stack = Vec::new();
+    for (index, instr) in expr.iter().enumerate() {
+        let value = match instr {
+            Instruction::I32Const(value) => ConstValue::I32(*value),
+            Instruction::I64Const(value) => ConstValue::I64(*value),
+            Instruction::F32Const(value) => ConstValue::F32(value.value),
+            Instruction::F64Const(value) => ConstValue::F64(value.value),
+            Instruction::SIMD(SIMD::V128Const(value)) => ConstValue::V128(*value),
+            Instruction::RefNull(ty) => ConstValue::Null(ty.clone()),
+            Instruction::RefFunc(func) => ConstValue::Func(*func),
+            Instruction::GlobalGet(global) => globals
+                .get(global.index as usize)
+                .cloned()
+                .ok_or(ConstEvalError::UnknownGlobal(*global))?,
+            Instruction::I32Add
+            | Instruction::I32Sub
+            | Instruction::I32Mul
+            | Instruction::I64Add
+            | Instruction::I64Sub
+            | Instruction::I64Mul => {
+                let invalid = || ConstEvalError::InvalidOperands { index };
+                let rhs = stack.pop().ok_or_else(invalid)?;
+                let lhs = stack.pop().ok_or_else(invalid)?;
+                match (instr, lhs, rhs) {
+                    (Instruction::I32Add, ConstValue::I32(a), ConstValue::I32(b)) => {
+                        ConstValue::I32(a.wrapping_add(b))
+                    }
+                    (Instruction::I32Sub, ConstValue::I32(a), ConstValue::I32(b)) => {
+                        ConstValue::I32(a.wrapping_sub(b))
+                    }
+                    (Instruction::I32Mul, ConstValue::I32(a), ConstValue::I32(b)) => {
+                        ConstValue::I32(a.wrapping_mul(b))
+                    }
+                    (Instruction::I64Add, ConstValue::I64(a), ConstValue::I64(b)) => {
+                        ConstValue::I64(a.wrapping_add(b))
+                    }
+                    (Instruction::I64Sub, ConstValue::I64(a), ConstValue::I64(b)) => {
+                        ConstValue::I64(a.wrapping_sub(b))
+                    }
+                    (Instruction::I64Mul, ConstValue::I64(a), ConstValue::I64(b)) => {
+                        ConstValue::I64(a.wrapping_mul(b))
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(ConstEvalError::NotConstant { index }),
+        };
+        stack.push(value);
+    }
+    match stack.len() {
+        1 => Ok(stack.remove(0)),
+        len => Err(ConstEvalError::InvalidResultCount(len)),
+    }
+}
+
+/// Evaluate all globals of a module, indexed by [`GlobalId`].
+///
+/// Values of imported globals are requested from `import`. Each initializer can only refer to
+/// the globals preceding it.
+pub fn eval_globals(
+    module: &Module,
+    mut import: impl FnMut(&ImportPath, &GlobalType) -> Option<ConstValue>,
+) -> Result<Vec<ConstValue>, ConstEvalError> {
+    let mut values = Vec::new();
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        for imported in imports.try_contents()? {
+            if let ImportDesc::Global(ty) = &imported.desc {
+                values.push(
+                    import(&imported.path, ty)
+                        .ok_or_else(|| ConstEvalError::MissingImport(imported.path.clone()))?,
+                );
+            }
+        }
+    }
+    if let Some(globals) = module.find_std_section::<payload::Global>() {
+        for global in globals.try_contents()? {
+            let value = eval_const_expr(&global.init, &values)?;
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+fn eval_offset(expr: &[Instruction], globals: &[ConstValue]) -> Result<u64, ConstEvalError> {
+    match eval_const_expr(expr, globals)? {
+        // 32-bit offsets are unsigned.
+        ConstValue::I32(offset) => Ok(u64::from(offset.cast_unsigned())),
+        ConstValue::I64(offset) => Ok(offset.cast_unsigned()),
+        value => Err(ConstEvalError::InvalidOffset(value)),
+    }
+}
+
+/// Placement of an active data segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ActiveData {
+    pub data: DataId,
+    pub memory: MemId,
+    /// Start address within the memory.
+    pub offset: u64,
+    /// Size of the segment in bytes.
+    pub len: usize,
+}
+
+/// Compute the placement of all active data segments, given the [values of globals](eval_globals).
+pub fn active_data(
+    module: &Module,
+    globals: &[ConstValue],
+) -> Result<Vec<ActiveData>, ConstEvalError> {
+    let mut result = Vec::new();
+    let Some(data) = module.find_std_section::<payload::Data>() else {
+        return Ok(result);
+    };
+    for (index, segment) in (0..).zip(data.try_contents()?) {
+        let (memory, offset) = match &segment.init {
+            DataInit::Active { offset } => (MemId::from(0), offset),
+            DataInit::ActiveWithMemory { memory, offset } => (*memory, offset),
+            DataInit::Passive => continue,
+        };
+        result.push(ActiveData {
+            data: DataId::from(index),
+            memory,
+            offset: eval_offset(offset, globals)?,
+            len: segment.blob.len(),
+        });
+    }
+    Ok(result)
+}
+
+/// Contents of an active element segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveElements {
+    pub elem: ElemId,
+    pub table: TableId,
+    /// Start index within the table.
+    pub offset: u64,
+    /// Evaluated references.
+    pub items: Vec<ConstValue>,
+}
+
+/// Evaluate all active element segments, given the [values of globals](eval_globals).
+pub fn active_elements(
+    module: &Module,
+    globals: &[ConstValue],
+) -> Result<Vec<ActiveElements>, ConstEvalError> {
+    let mut result = Vec::new();
+    let Some(elements) = module.find_std_section::<payload::Element>() else {
+        return Ok(result);
+    };
+    for (index, element) in (0..).zip(elements.try_contents()?) {
+        let (table, offset, items) = match element {
+            Element::ActiveWithFuncs { offset, funcs } => (
+                TableId::from(0),
+                offset,
+                funcs.iter().copied().map(ConstValue::Func).collect(),
+            ),
+            Element::ActiveWithTableAndFuncs {
+                table,
+                offset,
+                funcs,
+                ..
+            } => (
+                *table,
+                offset,
+                funcs.iter().copied().map(ConstValue::Func).collect(),
+            ),
+            Element::ActiveWithExprs { offset, exprs } => (
+                TableId::from(0),
+                offset,
+                exprs
+                    .iter()
+                    .map(|expr| eval_const_expr(expr, globals))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Element::ActiveWithTableAndExprs {
+                table,
+                offset,
+                exprs,
+                ..
+            } => (
+                *table,
+                offset,
+                exprs
+                    .iter()
+                    .map(|expr| eval_const_expr(expr, globals))
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => continue,
+        };
+        result.push(ActiveElements {
+            elem: ElemId::from(index),
+            table,
+            offset: eval_offset(offset, globals)?,
+            items,
+        });
+    }
+    Ok(result)
+}
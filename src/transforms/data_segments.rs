@@ -0,0 +1,309 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Optimization of [data segments](https://webassembly.github.io/spec/core/syntax/modules.html#data-segments):
+//! merging, splitting around runs of zeroes and dropping segments that only initialize zeroes.
+
+use crate::indices::{DataId, MemId};
+use crate::instructions::Instruction;
+use crate::io::DecodeError;
+use crate::sections::linking::is_relocatable;
+use crate::sections::{payload, Data, DataInit, ImportDesc, Section};
+use crate::transforms::const_eval::{eval_const_expr, ConstValue};
+use crate::visit::Visit;
+use crate::Module;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use thiserror::Error;
+
+#[cfg(feature = "extended-name-section")]
+use crate::sections::{CustomSection, NameMap};
+
+/// Shortest run of zero bytes that splits memory contents into separate data segments.
+///
+/// Shorter runs are cheaper to keep than the header of another segment.
+pub const MIN_ZERO_GAP: usize = 8;
+
+/// Error returned by [`optimize_data_segments`].
+#[derive(Debug, Error)]
+pub enum DataSegmentsError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Module is a relocatable object file, whose symbols and relocations point into segments.
+    #[error("Data segments of relocatable modules can't be rearranged")]
+    Relocatable,
+}
+
+/// Ranges of `data` containing non-zero bytes, merging those separated by fewer than
+/// [`MIN_ZERO_GAP`] zeroes.
+fn non_zero_runs(data: &[u8]) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == 0 {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if i - run.end < MIN_ZERO_GAP => run.end = i + 1,
+            _ => runs.push(i..i + 1),
+        }
+    }
+    runs
+}
+
+/// Active data segment placed at a constant address.
+fn active_segment(memory: MemId, offset: u64, blob: Vec<u8>) -> Data {
+    // Offsets are produced from the 32-bit address space.
+    #[allow(clippy::cast_possible_truncation)]
+    let offset = vec![Instruction::I32Const((offset as u32).cast_signed())];
+    Data {
+        init: match memory.index {
+            0 => DataInit::Active { offset },
+            _ => DataInit::ActiveWithMemory { memory, offset },
+        },
+        blob,
+    }
+}
+
+fn segment_memory(segment: &Data) -> Option<(MemId, &[Instruction])> {
+    match &segment.init {
+        DataInit::Active { offset } => Some((MemId::from(0), offset)),
+        DataInit::ActiveWithMemory { memory, offset } => Some((*memory, offset)),
+        DataInit::Passive => None,
+    }
+}
+
+/// Initial size in bytes of each memory, or `None` for imported memories, whose initial
+/// contents are not known.
+fn initial_sizes(module: &Module) -> Result<Vec<Option<u64>>, DecodeError> {
+    let mut sizes = Vec::new();
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        for import in imports.try_contents()? {
+            if let ImportDesc::Mem(_) = import.desc {
+                sizes.push(None);
+            }
+        }
+    }
+    if let Some(memories) = module.find_std_section::<payload::Memory>() {
+        sizes.extend(
+            memories
+                .try_contents()?
+                .iter()
+                .map(|ty| Some(u64::from(ty.limits.min).saturating_mul(ty.page_bytes()))),
+        );
+    }
+    Ok(sizes)
+}
+
+/// Constant offset of each active segment, if all active segments of its memory have one
+/// and fit into the initial memory size, so that the memory contents are fully known.
+fn rebuilt_offsets(data: &[Data], mut sizes: Vec<Option<u64>>) -> Vec<Option<u64>> {
+    let mut offsets = Vec::with_capacity(data.len());
+    for segment in data {
+        let offset = segment_memory(segment).and_then(|(memory, offset)| {
+            let size = sizes.get_mut(memory.index as usize)?;
+            let Ok(ConstValue::I32(offset)) = eval_const_expr(offset, &[]) else {
+                *size = None;
+                return None;
+            };
+            let offset = u64::from(offset.cast_unsigned());
+            if offset + segment.blob.len() as u64 > (*size)? {
+                // Segment traps at instantiation; keep the memory as is to preserve that.
+                *size = None;
+                return None;
+            }
+            Some(offset)
+        });
+        offsets.push(offset);
+    }
+    // Drop offsets of segments whose memory turned out to be unknown after all.
+    for (segment, offset) in data.iter().zip(&mut offsets) {
+        if let Some((memory, _)) = segment_memory(segment) {
+            if !matches!(sizes.get(memory.index as usize), Some(Some(_))) {
+                *offset = None;
+            }
+        }
+    }
+    offsets
+}
+
+/// Apply segments to zero-initialized memories, producing disjoint spans of contents
+/// sorted by address.
+// Spans only cover the in-memory segments and gaps between them, so their sizes fit into `usize`.
+#[allow(clippy::cast_possible_truncation)]
+fn memory_images(data: &[Data], offsets: &[Option<u64>]) -> HashMap<MemId, Vec<(u64, Vec<u8>)>> {
+    let mut ranges = HashMap::<MemId, Vec<Range<u64>>>::new();
+    for (segment, offset) in data.iter().zip(offsets) {
+        if let (Some((memory, _)), Some(offset)) = (segment_memory(segment), *offset) {
+            if !segment.blob.is_empty() {
+                ranges
+                    .entry(memory)
+                    .or_default()
+                    .push(offset..offset + segment.blob.len() as u64);
+            }
+        }
+    }
+    let mut images = HashMap::new();
+    for (memory, mut ranges) in ranges {
+        ranges.sort_by_key(|range| range.start);
+        let mut spans: Vec<Range<u64>> = Vec::new();
+        for range in ranges {
+            match spans.last_mut() {
+                // Memory is zero-initialized, so short gaps can be filled in.
+                Some(span) if range.start <= span.end + MIN_ZERO_GAP as u64 => {
+                    span.end = span.end.max(range.end);
+                }
+                _ => spans.push(range),
+            }
+        }
+        let spans: Vec<_> = spans
+            .into_iter()
+            .map(|span| (span.start, vec![0; (span.end - span.start) as usize]))
+            .collect();
+        images.insert(memory, spans);
+    }
+    // Later segments overwrite earlier ones, same as during instantiation.
+    for (segment, offset) in data.iter().zip(offsets) {
+        if segment.blob.is_empty() {
+            // Empty segments aren't covered by any span.
+            continue;
+        }
+        if let (Some((memory, _)), Some(offset)) = (segment_memory(segment), *offset) {
+            let Some(spans) = images.get_mut(&memory) else {
+                continue;
+            };
+            let index = spans.partition_point(|(start, _)| *start <= offset);
+            if index == 0 {
+                continue;
+            }
+            let (start, contents) = &mut spans[index - 1];
+            let start = (offset - *start) as usize;
+            contents[start..start + segment.blob.len()].copy_from_slice(&segment.blob);
+        }
+    }
+    images
+}
+
+/// Update references to kept segments, and point references to removed ones at `placeholder`.
+fn remap_data_ids(
+    module: &mut Module,
+    ids: &HashMap<DataId, DataId>,
+    placeholder: Option<DataId>,
+) -> Result<(), DecodeError> {
+    if let Some(code) = module.find_std_section_mut::<payload::Code>() {
+        code.visit_mut(|id: &mut DataId| {
+            if let Some(new_id) = ids.get(id).copied().or(placeholder) {
+                *id = new_id;
+            }
+        })?;
+    }
+    #[cfg(feature = "extended-name-section")]
+    for section in &mut module.sections {
+        if let Section::Custom(custom) = section {
+            if let Ok(CustomSection::Name(_)) = custom.try_contents() {
+                custom.visit_mut(|names: &mut NameMap<DataId>| {
+                    names.items.retain_mut(|assoc| match ids.get(&assoc.index) {
+                        Some(&new_id) => {
+                            assoc.index = new_id;
+                            true
+                        }
+                        None => false,
+                    });
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite active data segments of memories defined by the module so that they only cover
+/// non-zero bytes of the initial memory contents.
+///
+/// Adjacent and overlapping segments at constant offsets are merged, segments are split around
+/// runs of at least [`MIN_ZERO_GAP`] zeroes, and segments that only contain zeroes are dropped,
+/// relying on memory being zero-initialized. Passive segments and segments of imported memories
+/// are kept as is. [`DataId`] references in bulk memory instructions and data segment names are
+/// updated accordingly.
+///
+/// Memories are left untouched if any of their segments uses a non-constant offset or doesn't fit
+/// into the initial memory size.
+pub fn optimize_data_segments(module: &mut Module) -> Result<(), DataSegmentsError> {
+    if is_relocatable(module) {
+        return Err(DataSegmentsError::Relocatable);
+    }
+    let sizes = initial_sizes(module)?;
+    let Some(data) = module.find_std_section::<payload::Data>() else {
+        return Ok(());
+    };
+    let data = data.try_contents()?;
+    let offsets = rebuilt_offsets(data, sizes);
+    let images = memory_images(data, &offsets);
+
+    let mut referenced = HashSet::new();
+    if let Some(code) = module.find_std_section::<payload::Code>() {
+        code.visit(|id: &DataId| {
+            referenced.insert(*id);
+        })
+        .map_err(DecodeError::from)?;
+    }
+    let mut new_data = Vec::new();
+    let mut ids = HashMap::new();
+    // Instructions can still refer to removed active segments, which are dropped after
+    // instantiation. They all behave the same as a single empty passive segment.
+    let mut placeholder = None;
+    for ((index, segment), offset) in (0..).zip(data).zip(&offsets) {
+        let id = DataId::from(index);
+        let kept = match offset {
+            None => segment.clone(),
+            Some(_) if referenced.contains(&id) && placeholder.is_none() => Data {
+                init: DataInit::Passive,
+                blob: Vec::new(),
+            },
+            Some(_) => continue,
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        let new_id = DataId::from(new_data.len() as u32);
+        new_data.push(kept);
+        match offset {
+            None => ids.insert(id, new_id),
+            Some(_) => placeholder.replace(new_id),
+        };
+    }
+    let mut images: Vec<_> = images.into_iter().collect();
+    images.sort_by_key(|(memory, _)| *memory);
+    for (memory, spans) in images {
+        for (start, contents) in spans {
+            for run in non_zero_runs(&contents) {
+                let offset = start + run.start as u64;
+                new_data.push(active_segment(memory, offset, contents[run].to_vec()));
+            }
+        }
+    }
+
+    remap_data_ids(module, &ids, placeholder)?;
+    #[allow(clippy::cast_possible_truncation)]
+    let data_len = new_data.len() as u32;
+    if new_data.is_empty() {
+        module
+            .sections
+            .retain(|section| !matches!(section, Section::Data(_)));
+    } else if let Some(data) = module.find_std_section_mut::<payload::Data>() {
+        *data.try_contents_mut()? = new_data;
+    }
+    if let Some(data_count) = module.find_std_section_mut::<payload::DataCount>() {
+        *data_count.try_contents_mut()? = data_len;
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deduplication of the type section and removal of unused types.
+
+use crate::indices::TypeId;
+use crate::io::DecodeError;
+use crate::sections::linking::is_relocatable;
+use crate::sections::names::{is_name_section, remap_name_map};
+use crate::sections::{payload, NameMap, Section};
+use crate::visit::Visit;
+use crate::Module;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[cfg(feature = "stack-switching")]
+use crate::types::{ContType, TypeDef};
+
+/// Error returned by [`dedup_types`].
+#[derive(Debug, Error)]
+pub enum DedupTypesError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Module is a relocatable object file, whose relocations point at type indices.
+    #[error("Types of relocatable modules can't be renumbered")]
+    Relocatable,
+}
+
+/// Merge identical types and remove the ones that are not referenced anywhere in the module.
+///
+/// Function types are placed first in their original order, followed by
+/// continuation types when the `stack-switching` feature is enabled. Every [`TypeId`] in the
+/// module is rewritten accordingly, including function declarations, imports, tags,
+/// `call_indirect` and block types, and type names in the name section.
+///
+/// Relocatable modules are rejected with [`DedupTypesError::Relocatable`].
+pub fn dedup_types(module: &mut Module) -> Result<(), DedupTypesError> {
+    if is_relocatable(module) {
+        return Err(DedupTypesError::Relocatable);
+    }
+    let Some(types) = module.find_std_section::<payload::Type>() else {
+        return Ok(());
+    };
+    let types = types.try_contents()?.clone();
+
+    let mut used = HashSet::new();
+    for section in &module.sections {
+        if !matches!(section, Section::Type(_)) && !is_name_section(section) {
+            section
+                .visit(|id: &TypeId| {
+                    used.insert(*id);
+                })
+                .map_err(DecodeError::from)?;
+        }
+    }
+    #[cfg(feature = "stack-switching")]
+    for (index, ty) in (0..).zip(&types) {
+        if let TypeDef::Cont(cont) = ty {
+            if used.contains(&TypeId::from(index)) {
+                used.insert(cont.func_type);
+            }
+        }
+    }
+
+    let mut new_types = Vec::new();
+    let mut ids = HashMap::new();
+    let mut funcs = HashMap::new();
+    for (index, ty) in (0..).zip(&types) {
+        let id = TypeId::from(index);
+        let Some(func) = ty.as_func() else {
+            continue;
+        };
+        if used.contains(&id) {
+            let new_id = *funcs.entry(func.clone()).or_insert_with(|| {
+                new_types.push(ty.clone());
+                #[allow(clippy::cast_possible_truncation)]
+                TypeId::from(new_types.len() as u32 - 1)
+            });
+            ids.insert(id, new_id);
+        }
+    }
+    #[cfg(feature = "stack-switching")]
+    {
+        let mut conts = HashMap::new();
+        for (index, ty) in (0..).zip(&types) {
+            let id = TypeId::from(index);
+            let TypeDef::Cont(cont) = ty else {
+                continue;
+            };
+            if used.contains(&id) {
+                let func_type = ids.get(&cont.func_type).copied().unwrap_or(cont.func_type);
+                let new_id = *conts.entry(func_type).or_insert_with(|| {
+                    new_types.push(TypeDef::Cont(ContType { func_type }));
+                    #[allow(clippy::cast_possible_truncation)]
+                    TypeId::from(new_types.len() as u32 - 1)
+                });
+                ids.insert(id, new_id);
+            }
+        }
+    }
+
+    for section in &mut module.sections {
+        if let Section::Type(types) = section {
+            *types.try_contents_mut()? = std::mem::take(&mut new_types);
+        } else if is_name_section(section) {
+            let remap = |id| ids.get(&id).copied();
+            section
+                .visit_mut(|names: &mut NameMap<TypeId>| remap_name_map(names, remap))
+                .map_err(DecodeError::from)?;
+            section
+                .visit_mut(|names: &mut NameMap<TypeId, NameMap<u32>>| {
+                    remap_name_map(names, remap);
+                })
+                .map_err(DecodeError::from)?;
+        } else {
+            section
+                .visit_mut(|id: &mut TypeId| {
+                    if let Some(&new_id) = ids.get(id) {
+                        *id = new_id;
+                    }
+                })
+                .map_err(DecodeError::from)?;
+        }
+    }
+    Ok(())
+}
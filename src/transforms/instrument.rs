@@ -0,0 +1,712 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Framework for injecting code into function bodies at function entry and exit, around calls,
+//! at loop iterations and around memory accesses.
+//!
+//! ```no_run
+//! use wasmbin::indices::FuncId;
+//! use wasmbin::instructions::{Expression, Instruction};
+//! use wasmbin::sections::ImportPath;
+//! use wasmbin::transforms::instrument::{
+//!     import_func, instrument, FuncContext, InstrumentError, Instrumentation,
+//! };
+//! use wasmbin::types::{FuncType, ValueType};
+//! use wasmbin::Module;
+//!
+//! struct CallTrace {
+//!     trace: FuncId,
+//! }
+//!
+//! impl Instrumentation for CallTrace {
+//!     fn prepare(&mut self, module: &mut Module) -> Result<(), InstrumentError> {
+//!         let path = ImportPath {
+//!             module: "trace".to_owned(),
+//!             name: "enter".to_owned(),
+//!         };
+//!         let ty = FuncType {
+//!             params: vec![ValueType::I32],
+//!             results: vec![],
+//!         };
+//!         self.trace = import_func(module, path, ty)?;
+//!         Ok(())
+//!     }
+//!
+//!     fn enter(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+//!         out.push(Instruction::I32Const(cx.func.index as i32));
+//!         out.push(Instruction::Call(self.trace));
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = Module::decode_from(std::fs::File::open("module.wasm")?)?;
+//! instrument(&mut module, &mut CallTrace { trace: FuncId::from(0) })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::indices::{FuncId, GlobalId, LocalId, MemId, TypeId};
+#[cfg(feature = "threads")]
+use crate::instructions::Atomic;
+use crate::instructions::{CallIndirect, Expression, Instruction, MemArg};
+use crate::io::DecodeError;
+use crate::sections::{payload, FuncBody, Global, Import, ImportDesc, ImportPath, Locals};
+use crate::transforms::interface::InterfaceError;
+use crate::types::{BlockType, FuncType, GlobalType, ValueType};
+use crate::Module;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Error returned by [`instrument`].
+#[derive(Debug, Error)]
+pub enum InstrumentError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Function is declared without a valid function type.
+    #[error("Function {0:?} doesn't have a valid function type")]
+    InvalidFuncType(FuncId),
+
+    /// Import couldn't be added while preparing the module.
+    #[error(transparent)]
+    Interface(#[from] InterfaceError),
+}
+
+/// Callee of an instrumented call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallTarget {
+    /// Direct `call` or `return_call`.
+    Func(FuncId),
+    /// `call_indirect` or `return_call_indirect`.
+    Indirect(CallIndirect),
+}
+
+/// Kind of a memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    /// Plain or atomic load.
+    Load,
+    /// Plain or atomic store.
+    Store,
+    /// Atomic read-modify-write or compare-exchange.
+    ReadModifyWrite,
+    /// `memory.atomic.wait32` or `memory.atomic.wait64`.
+    Wait,
+    /// `memory.atomic.notify`.
+    Notify,
+}
+
+/// Memory access performed by an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub atomic: bool,
+    pub memory: MemId,
+    /// Static offset added to the dynamic address.
+    pub offset: u32,
+    /// Number of bytes accessed.
+    pub size: u32,
+    /// Local holding the dynamic address operand of the instruction.
+    pub address: LocalId,
+}
+
+/// Function being instrumented.
+#[derive(Debug)]
+pub struct FuncContext {
+    pub func: FuncId,
+    pub ty: FuncType,
+    first_added: u32,
+    added: Vec<ValueType>,
+    scratch: HashMap<(ValueType, usize), LocalId>,
+}
+
+impl FuncContext {
+    /// Declare a new local in the function.
+    pub fn add_local(&mut self, ty: ValueType) -> LocalId {
+        self.added.push(ty);
+        #[allow(clippy::cast_possible_truncation)]
+        LocalId::from(self.first_added + self.added.len() as u32 - 1)
+    }
+
+    /// Local reused between instrumentation sites for temporary values.
+    fn scratch(&mut self, ty: ValueType, slot: usize) -> LocalId {
+        if let Some(&local) = self.scratch.get(&(ty.clone(), slot)) {
+            return local;
+        }
+        let local = self.add_local(ty.clone());
+        self.scratch.insert((ty, slot), local);
+        local
+    }
+
+    /// Drop locals declared since the `mark` length of added locals.
+    fn rollback(&mut self, mark: usize) {
+        self.added.truncate(mark);
+        #[allow(clippy::cast_possible_truncation)]
+        let end = self.first_added + mark as u32;
+        self.scratch.retain(|_, local| local.index < end);
+    }
+
+    fn finish(self, locals: &mut Vec<Locals>) {
+        for ty in self.added {
+            match locals.last_mut() {
+                Some(last) if last.ty == ty => last.repeat += 1,
+                _ => locals.push(Locals { repeat: 1, ty }),
+            }
+        }
+    }
+}
+
+/// Hooks injecting code into function bodies, see [`instrument`].
+///
+/// Each hook appends instructions to `out`. Injected code must leave the operand stack as it
+/// found it, and is not instrumented itself.
+pub trait Instrumentation {
+    /// Prepare the module before any function is instrumented, e.g. by adding imports and globals
+    /// used by the injected code. Functions appended here are not instrumented.
+    fn prepare(&mut self, module: &mut Module) -> Result<(), InstrumentError> {
+        let _ = module;
+        Ok(())
+    }
+
+    /// Code to run at function entry.
+    fn enter(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+        let _ = (cx, out);
+    }
+
+    /// Code to run before the function returns, either at the end of its body, on `return`,
+    /// on a branch to the function's label or before a tail call.
+    ///
+    /// Unwinding by exceptions is not covered, including `try_table` catch clauses that target
+    /// the function's label.
+    fn exit(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+        let _ = (cx, out);
+    }
+
+    /// Code to run before a call, with call arguments already on the stack.
+    fn before_call(&mut self, cx: &mut FuncContext, target: &CallTarget, out: &mut Expression) {
+        let _ = (cx, target, out);
+    }
+
+    /// Code to run after a call returns, with call results on the stack.
+    ///
+    /// Tail calls never return to the caller, so this is not invoked for them.
+    fn after_call(&mut self, cx: &mut FuncContext, target: &CallTarget, out: &mut Expression) {
+        let _ = (cx, target, out);
+    }
+
+    /// Code to run at the start of each loop iteration, which covers both entering the loop and
+    /// taking its back-edges.
+    fn loop_iteration(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+        let _ = (cx, out);
+    }
+
+    /// Code to run before a memory access. Operands of the instruction are moved to locals
+    /// before this code and restored after it.
+    fn before_memory_access(
+        &mut self,
+        cx: &mut FuncContext,
+        access: &MemoryAccess,
+        out: &mut Expression,
+    ) {
+        let _ = (cx, access, out);
+    }
+
+    /// Code to run after a memory access, with the loaded value on the stack, if any.
+    fn after_memory_access(
+        &mut self,
+        cx: &mut FuncContext,
+        access: &MemoryAccess,
+        out: &mut Expression,
+    ) {
+        let _ = (cx, access, out);
+    }
+}
+
+const NO_OPERANDS: &[ValueType] = &[];
+const I32: &[ValueType] = &[ValueType::I32];
+const I64: &[ValueType] = &[ValueType::I64];
+const F32: &[ValueType] = &[ValueType::F32];
+const F64: &[ValueType] = &[ValueType::F64];
+#[cfg(feature = "threads")]
+const I32_I32: &[ValueType] = &[ValueType::I32, ValueType::I32];
+#[cfg(feature = "threads")]
+const I64_I64: &[ValueType] = &[ValueType::I64, ValueType::I64];
+#[cfg(feature = "threads")]
+const I32_I64: &[ValueType] = &[ValueType::I32, ValueType::I64];
+
+/// Memory access of an instruction, along with the types of its operands following the address.
+fn memory_access(instruction: &Instruction) -> Option<(AccessKind, MemArg, u32, &[ValueType])> {
+    use AccessKind::{Load, Store};
+
+    Some(match instruction {
+        Instruction::I32Load(arg)
+        | Instruction::F32Load(arg)
+        | Instruction::I64Load32S(arg)
+        | Instruction::I64Load32U(arg) => (Load, arg.clone(), 4, NO_OPERANDS),
+        Instruction::I64Load(arg) | Instruction::F64Load(arg) => {
+            (Load, arg.clone(), 8, NO_OPERANDS)
+        }
+        Instruction::I32Load8S(arg)
+        | Instruction::I32Load8U(arg)
+        | Instruction::I64Load8S(arg)
+        | Instruction::I64Load8U(arg) => (Load, arg.clone(), 1, NO_OPERANDS),
+        Instruction::I32Load16S(arg)
+        | Instruction::I32Load16U(arg)
+        | Instruction::I64Load16S(arg)
+        | Instruction::I64Load16U(arg) => (Load, arg.clone(), 2, NO_OPERANDS),
+        Instruction::I32Store(arg) => (Store, arg.clone(), 4, I32),
+        Instruction::I64Store(arg) => (Store, arg.clone(), 8, I64),
+        Instruction::F32Store(arg) => (Store, arg.clone(), 4, F32),
+        Instruction::F64Store(arg) => (Store, arg.clone(), 8, F64),
+        Instruction::I32Store8(arg) => (Store, arg.clone(), 1, I32),
+        Instruction::I32Store16(arg) => (Store, arg.clone(), 2, I32),
+        Instruction::I64Store8(arg) => (Store, arg.clone(), 1, I64),
+        Instruction::I64Store16(arg) => (Store, arg.clone(), 2, I64),
+        Instruction::I64Store32(arg) => (Store, arg.clone(), 4, I64),
+        #[cfg(feature = "threads")]
+        Instruction::Atomic(atomic) => return atomic_access(atomic),
+        _ => return None,
+    })
+}
+
+#[cfg(feature = "threads")]
+fn atomic_access(atomic: &Atomic) -> Option<(AccessKind, MemArg, u32, &[ValueType])> {
+    use AccessKind::{Load, Notify, ReadModifyWrite as Rmw, Store, Wait};
+
+    Some(match atomic {
+        Atomic::I32Load(arg) | Atomic::I64Load32U(arg) => {
+            (Load, arg.clone().into(), 4, NO_OPERANDS)
+        }
+        Atomic::I64Load(arg) => (Load, arg.clone().into(), 8, NO_OPERANDS),
+        Atomic::I32Load8U(arg) | Atomic::I64Load8U(arg) => {
+            (Load, arg.clone().into(), 1, NO_OPERANDS)
+        }
+        Atomic::I32Load16U(arg) | Atomic::I64Load16U(arg) => {
+            (Load, arg.clone().into(), 2, NO_OPERANDS)
+        }
+        Atomic::I32Store(arg) => (Store, arg.clone().into(), 4, I32),
+        Atomic::I64Store(arg) => (Store, arg.clone().into(), 8, I64),
+        Atomic::I32Store8(arg) => (Store, arg.clone().into(), 1, I32),
+        Atomic::I32Store16(arg) => (Store, arg.clone().into(), 2, I32),
+        Atomic::I64Store8(arg) => (Store, arg.clone().into(), 1, I64),
+        Atomic::I64Store16(arg) => (Store, arg.clone().into(), 2, I64),
+        Atomic::I64Store32(arg) => (Store, arg.clone().into(), 4, I64),
+        Atomic::I32RmwAdd(arg)
+        | Atomic::I32RmwSub(arg)
+        | Atomic::I32RmwAnd(arg)
+        | Atomic::I32RmwOr(arg)
+        | Atomic::I32RmwXor(arg)
+        | Atomic::I32RmwXchg(arg) => (Rmw, arg.clone().into(), 4, I32),
+        Atomic::I64RmwAdd(arg)
+        | Atomic::I64RmwSub(arg)
+        | Atomic::I64RmwAnd(arg)
+        | Atomic::I64RmwOr(arg)
+        | Atomic::I64RmwXor(arg)
+        | Atomic::I64RmwXchg(arg) => (Rmw, arg.clone().into(), 8, I64),
+        Atomic::I32Rmw8AddU(arg)
+        | Atomic::I32Rmw8SubU(arg)
+        | Atomic::I32Rmw8AndU(arg)
+        | Atomic::I32Rmw8OrU(arg)
+        | Atomic::I32Rmw8XorU(arg)
+        | Atomic::I32Rmw8XchgU(arg) => (Rmw, arg.clone().into(), 1, I32),
+        Atomic::I64Rmw8AddU(arg)
+        | Atomic::I64Rmw8SubU(arg)
+        | Atomic::I64Rmw8AndU(arg)
+        | Atomic::I64Rmw8OrU(arg)
+        | Atomic::I64Rmw8XorU(arg)
+        | Atomic::I64Rmw8XchgU(arg) => (Rmw, arg.clone().into(), 1, I64),
+        Atomic::I32Rmw16AddU(arg)
+        | Atomic::I32Rmw16SubU(arg)
+        | Atomic::I32Rmw16AndU(arg)
+        | Atomic::I32Rmw16OrU(arg)
+        | Atomic::I32Rmw16XorU(arg)
+        | Atomic::I32Rmw16XchgU(arg) => (Rmw, arg.clone().into(), 2, I32),
+        Atomic::I64Rmw16AddU(arg)
+        | Atomic::I64Rmw16SubU(arg)
+        | Atomic::I64Rmw16AndU(arg)
+        | Atomic::I64Rmw16OrU(arg)
+        | Atomic::I64Rmw16XorU(arg)
+        | Atomic::I64Rmw16XchgU(arg) => (Rmw, arg.clone().into(), 2, I64),
+        Atomic::I64Rmw32AddU(arg)
+        | Atomic::I64Rmw32SubU(arg)
+        | Atomic::I64Rmw32AndU(arg)
+        | Atomic::I64Rmw32OrU(arg)
+        | Atomic::I64Rmw32XorU(arg)
+        | Atomic::I64Rmw32XchgU(arg) => (Rmw, arg.clone().into(), 4, I64),
+        Atomic::I32RmwCmpXchg(arg) => (Rmw, arg.clone().into(), 4, I32_I32),
+        Atomic::I64RmwCmpXchg(arg) => (Rmw, arg.clone().into(), 8, I64_I64),
+        Atomic::I32Rmw8CmpXchgU(arg) => (Rmw, arg.clone().into(), 1, I32_I32),
+        Atomic::I64Rmw8CmpXchgU(arg) => (Rmw, arg.clone().into(), 1, I64_I64),
+        Atomic::I32Rmw16CmpXchgU(arg) => (Rmw, arg.clone().into(), 2, I32_I32),
+        Atomic::I64Rmw16CmpXchgU(arg) => (Rmw, arg.clone().into(), 2, I64_I64),
+        Atomic::I64Rmw32CmpXchgU(arg) => (Rmw, arg.clone().into(), 4, I64_I64),
+        Atomic::Wake(arg) => (Notify, arg.clone().into(), 4, I32),
+        Atomic::I32Wait(arg) => (Wait, arg.clone().into(), 4, I32_I64),
+        Atomic::I64Wait(arg) => (Wait, arg.clone().into(), 8, I64_I64),
+        _ => return None,
+    })
+}
+
+/// Run `exit` hooks only if the branch condition computed by `taken` from the spilled operand is
+/// true.
+fn conditional_exit(
+    instrumentation: &mut impl Instrumentation,
+    cx: &mut FuncContext,
+    out: &mut Expression,
+    taken: impl FnOnce(LocalId, &mut Expression),
+) {
+    let mut exit = Vec::new();
+    instrumentation.exit(cx, &mut exit);
+    if exit.is_empty() {
+        return;
+    }
+    let operand = cx.scratch(ValueType::I32, 0);
+    out.push(Instruction::LocalSet(operand));
+    taken(operand, out);
+    out.push(Instruction::IfStart(BlockType::Empty));
+    out.extend(exit);
+    out.push(Instruction::End);
+    out.push(Instruction::LocalGet(operand));
+}
+
+fn instrument_memory_access(
+    instrumentation: &mut impl Instrumentation,
+    cx: &mut FuncContext,
+    out: &mut Expression,
+    instruction: Instruction,
+) {
+    let Some((kind, arg, size, operands)) = memory_access(&instruction) else {
+        out.push(instruction);
+        return;
+    };
+    let mark = cx.added.len();
+    let address = cx.scratch(ValueType::I32, 0);
+    let values: Vec<_> = (1..)
+        .zip(operands)
+        .map(|(slot, ty)| cx.scratch(ty.clone(), slot))
+        .collect();
+    let hooks_mark = cx.added.len();
+    #[cfg(feature = "threads")]
+    let atomic = matches!(instruction, Instruction::Atomic(_));
+    #[cfg(not(feature = "threads"))]
+    let atomic = false;
+    let access = MemoryAccess {
+        kind,
+        atomic,
+        memory: arg.memory,
+        offset: arg.offset,
+        size,
+        address,
+    };
+    let mut before = Vec::new();
+    instrumentation.before_memory_access(cx, &access, &mut before);
+    let mut after = Vec::new();
+    instrumentation.after_memory_access(cx, &access, &mut after);
+    if before.is_empty() && after.is_empty() {
+        if cx.added.len() == hooks_mark {
+            cx.rollback(mark);
+        }
+        out.push(instruction);
+        return;
+    }
+    out.extend(
+        values
+            .iter()
+            .rev()
+            .map(|&local| Instruction::LocalSet(local)),
+    );
+    out.push(Instruction::LocalSet(address));
+    out.extend(before);
+    out.push(Instruction::LocalGet(address));
+    out.extend(values.iter().map(|&local| Instruction::LocalGet(local)));
+    out.push(instruction);
+    out.extend(after);
+}
+
+/// Instrument a sequence of instructions nested in `depth` blocks, so that branches to label
+/// `depth` leave the function.
+fn instrument_instructions(
+    instrumentation: &mut impl Instrumentation,
+    cx: &mut FuncContext,
+    instructions: Expression,
+    mut depth: u32,
+    out: &mut Expression,
+) {
+    for instruction in instructions {
+        #[cfg(feature = "exception-handling")]
+        let instruction = match instruction {
+            Instruction::TryTable(mut try_table) => {
+                let body = std::mem::take(&mut try_table.instructions);
+                instrument_instructions(
+                    instrumentation,
+                    cx,
+                    body,
+                    depth + 1,
+                    &mut try_table.instructions,
+                );
+                out.push(Instruction::TryTable(try_table));
+                continue;
+            }
+            instruction => instruction,
+        };
+        match &instruction {
+            Instruction::BlockStart(_) | Instruction::IfStart(_) => depth += 1,
+            #[cfg(feature = "legacy-exceptions")]
+            Instruction::TryStart(_) => depth += 1,
+            Instruction::LoopStart(_) => {
+                depth += 1;
+                out.push(instruction);
+                instrumentation.loop_iteration(cx, out);
+                continue;
+            }
+            Instruction::End => depth -= 1,
+            #[cfg(feature = "legacy-exceptions")]
+            Instruction::TryDelegate(_) => depth -= 1,
+            Instruction::Return => instrumentation.exit(cx, out),
+            Instruction::Br(label) if label.index == depth => instrumentation.exit(cx, out),
+            Instruction::BrIf(label) if label.index == depth => {
+                conditional_exit(instrumentation, cx, out, |operand, out| {
+                    out.push(Instruction::LocalGet(operand));
+                });
+            }
+            Instruction::BrTable {
+                branches,
+                otherwise,
+            } => {
+                let exits: Vec<u32> = (0..)
+                    .zip(branches)
+                    .filter(|(_, label)| label.index == depth)
+                    .map(|(index, _)| index)
+                    .collect();
+                let otherwise_exits = otherwise.index == depth;
+                if otherwise_exits && exits.len() == branches.len() {
+                    instrumentation.exit(cx, out);
+                } else if otherwise_exits || !exits.is_empty() {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let count = branches.len() as u32;
+                    conditional_exit(instrumentation, cx, out, |operand, out| {
+                        out.push(Instruction::I32Const(0));
+                        for index in exits {
+                            out.push(Instruction::LocalGet(operand));
+                            out.push(Instruction::I32Const(index.cast_signed()));
+                            out.push(Instruction::I32Eq);
+                            out.push(Instruction::I32Or);
+                        }
+                        if otherwise_exits {
+                            out.push(Instruction::LocalGet(operand));
+                            out.push(Instruction::I32Const(count.cast_signed()));
+                            out.push(Instruction::I32GeU);
+                            out.push(Instruction::I32Or);
+                        }
+                    });
+                }
+            }
+            Instruction::Call(func) => {
+                let target = CallTarget::Func(*func);
+                instrumentation.before_call(cx, &target, out);
+                out.push(instruction);
+                instrumentation.after_call(cx, &target, out);
+                continue;
+            }
+            Instruction::CallIndirect(call) => {
+                let target = CallTarget::Indirect(call.clone());
+                instrumentation.before_call(cx, &target, out);
+                out.push(instruction);
+                instrumentation.after_call(cx, &target, out);
+                continue;
+            }
+            Instruction::ReturnCall(func) => {
+                instrumentation.exit(cx, out);
+                instrumentation.before_call(cx, &CallTarget::Func(*func), out);
+            }
+            Instruction::ReturnCallIndirect(call) => {
+                instrumentation.exit(cx, out);
+                instrumentation.before_call(cx, &CallTarget::Indirect(call.clone()), out);
+            }
+            _ => {
+                instrument_memory_access(instrumentation, cx, out, instruction);
+                continue;
+            }
+        }
+        out.push(instruction);
+    }
+}
+
+fn instrument_body(
+    instrumentation: &mut impl Instrumentation,
+    cx: &mut FuncContext,
+    body: Expression,
+) -> Expression {
+    let mut out = Vec::with_capacity(body.len());
+    instrumentation.enter(cx, &mut out);
+    instrument_instructions(instrumentation, cx, body, 0, &mut out);
+    // The final `end` of the function body is implicit.
+    instrumentation.exit(cx, &mut out);
+    out
+}
+
+fn imported_funcs(module: &Module) -> Result<u32, DecodeError> {
+    let mut count = 0;
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        for import in imports.try_contents()? {
+            if let ImportDesc::Func(_) = import.desc {
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Inject code produced by `instrumentation` into all functions defined by the module.
+///
+/// New locals requested by the hooks are declared in [`FuncBody::locals`], and temporary locals
+/// used to expose operands of memory accesses are allocated and reused automatically.
+///
+/// Instruction offsets change as a result, so custom sections referring to them, such as
+/// branch hints or DWARF, are not valid afterwards.
+pub fn instrument(
+    module: &mut Module,
+    instrumentation: &mut impl Instrumentation,
+) -> Result<(), InstrumentError> {
+    let defined = match module.find_std_section::<payload::Code>() {
+        Some(code) => code.try_contents()?.len(),
+        None => 0,
+    };
+    instrumentation.prepare(module)?;
+    let imported = imported_funcs(module)?;
+    let types: Vec<Option<FuncType>> = match module.find_std_section::<payload::Type>() {
+        Some(types) => types
+            .try_contents()?
+            .iter()
+            .map(|ty| ty.as_func().cloned())
+            .collect(),
+        None => Vec::new(),
+    };
+    let funcs: Vec<TypeId> = match module.find_std_section::<payload::Function>() {
+        Some(funcs) => funcs.try_contents()?.clone(),
+        None => Vec::new(),
+    };
+    let Some(code) = module.find_std_section_mut::<payload::Code>() else {
+        return Ok(());
+    };
+    for ((index, body), ty) in (imported..)
+        .zip(&mut code.try_contents_mut()?[..defined])
+        .zip(funcs.iter().map(Some).chain(std::iter::repeat(None)))
+    {
+        let func = FuncId::from(index);
+        let ty = ty
+            .and_then(|ty| types.get(ty.index as usize)?.clone())
+            .ok_or(InstrumentError::InvalidFuncType(func))?;
+        let body = body.try_contents_mut()?;
+        #[allow(clippy::cast_possible_truncation)]
+        let params = ty.params.len() as u32;
+        let mut cx = FuncContext {
+            func,
+            ty,
+            first_added: body
+                .locals
+                .iter()
+                .fold(params, |count, locals| count + locals.repeat),
+            added: Vec::new(),
+            scratch: HashMap::new(),
+        };
+        body.expr = instrument_body(instrumentation, &mut cx, std::mem::take(&mut body.expr));
+        cx.finish(&mut body.locals);
+    }
+    Ok(())
+}
+
+/// Find or add a function type in the type section.
+pub fn add_func_type(module: &mut Module, ty: FuncType) -> Result<TypeId, DecodeError> {
+    let types = module
+        .find_or_insert_std_section(payload::Type::default)
+        .try_contents_mut()?;
+    let index = if let Some(index) = types.iter().position(|other| other.as_func() == Some(&ty)) {
+        index
+    } else {
+        types.push(ty.into());
+        types.len() - 1
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    let index = index as u32;
+    Ok(TypeId::from(index))
+}
+
+/// Find or add a function import with the given path and type.
+///
+/// Adding an import shifts indices of the functions defined by the module, so this should be
+/// used from [`Instrumentation::prepare`] rather than from the hooks.
+pub fn import_func(
+    module: &mut Module,
+    path: ImportPath,
+    ty: FuncType,
+) -> Result<FuncId, InterfaceError> {
+    let ty = add_func_type(module, ty)?;
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        let mut index = 0;
+        for import in imports.try_contents()? {
+            if let ImportDesc::Func(other) = import.desc {
+                if other == ty && import.path == path {
+                    return Ok(FuncId::from(index));
+                }
+                index += 1;
+            }
+        }
+    }
+    let index = module.add_import(Import {
+        path,
+        desc: ImportDesc::Func(ty),
+    })?;
+    Ok(FuncId::from(index))
+}
+
+/// Add a function to the end of the function index space.
+pub fn add_func(module: &mut Module, ty: FuncType, body: FuncBody) -> Result<FuncId, DecodeError> {
+    let ty = add_func_type(module, ty)?;
+    let imported = imported_funcs(module)?;
+    let funcs = module
+        .find_or_insert_std_section(payload::Function::default)
+        .try_contents_mut()?;
+    funcs.push(ty);
+    #[allow(clippy::cast_possible_truncation)]
+    let index = imported + funcs.len() as u32 - 1;
+    module
+        .find_or_insert_std_section(payload::Code::default)
+        .try_contents_mut()?
+        .push(body.into());
+    Ok(FuncId::from(index))
+}
+
+/// Add a global to the end of the global index space.
+pub fn add_global(
+    module: &mut Module,
+    ty: GlobalType,
+    init: Expression,
+) -> Result<GlobalId, DecodeError> {
+    let mut imported = 0;
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        for import in imports.try_contents()? {
+            if let ImportDesc::Global(_) = import.desc {
+                imported += 1;
+            }
+        }
+    }
+    let globals = module
+        .find_or_insert_std_section(payload::Global::default)
+        .try_contents_mut()?;
+    globals.push(Global { ty, init });
+    #[allow(clippy::cast_possible_truncation)]
+    Ok(GlobalId::from(imported + globals.len() as u32 - 1))
+}
@@ -0,0 +1,447 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Editing of module [imports](Import) and [exports](Export) that keeps references to the
+//! affected index spaces up to date.
+//!
+//! ```no_run
+//! use wasmbin::sections::{ImportPath, FuncBody};
+//! use wasmbin::transforms::interface::ImportDefinition;
+//! use wasmbin::Module;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = Module::decode_from(std::fs::File::open("module.wasm")?)?;
+//! module.rename_imports(|path| {
+//!     if path.module == "env" {
+//!         path.module = "a".to_owned();
+//!     }
+//! })?;
+//! let memory = ImportPath {
+//!     module: "a".to_owned(),
+//!     name: "memory".to_owned(),
+//! };
+//! module.define_import(&memory, ImportDefinition::Memory)?;
+//! module.rename_export("_start", "main")?;
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "exception-handling")]
+use crate::indices::ExceptionId;
+#[cfg(feature = "extended-name-section")]
+use crate::indices::LabelId;
+use crate::indices::{FuncId, GlobalId, LocalId, MemId, TableId, TypeId};
+use crate::instructions::Expression;
+use crate::io::DecodeError;
+use crate::sections::linking::is_relocatable;
+use crate::sections::names::{is_name_section, remap_name_map};
+#[cfg(feature = "exception-handling")]
+use crate::sections::Exception;
+use crate::sections::{
+    payload, Export, ExportDesc, FuncBody, Global, Import, ImportDesc, ImportPath, NameMap,
+};
+use crate::types::{GlobalType, MemType, TableType};
+use crate::visit::{Visit, VisitError};
+use crate::Module;
+use std::cmp::Ordering;
+use thiserror::Error;
+
+/// Error returned by the import and export helpers of [`Module`].
+#[derive(Debug, Error)]
+pub enum InterfaceError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Module doesn't have an import with the given path.
+    #[error("No import of {}.{}", .0.module, .0.name)]
+    UnknownImport(ImportPath),
+
+    /// Import is still referenced and can't be removed.
+    #[error("Import {}.{} is still in use", .0.module, .0.name)]
+    ImportInUse(ImportPath),
+
+    /// Definition doesn't match the kind of the imported item.
+    #[error("Definition doesn't match the kind of import {}.{}", .0.module, .0.name)]
+    KindMismatch(ImportPath),
+
+    /// Module doesn't export anything under the given name.
+    #[error("No export named {0:?}")]
+    UnknownExport(String),
+
+    /// Export name is already taken.
+    #[error("Export name {0:?} is already in use")]
+    DuplicateExport(String),
+
+    /// Module is a relocatable object file, whose symbols and relocations point at indices.
+    #[error("Imports of relocatable modules can't be added or removed")]
+    Relocatable,
+}
+
+/// Local definition replacing an import, see [`Module::define_import`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportDefinition {
+    /// Function of the imported type with the given body.
+    Func(FuncBody),
+    /// Table of the imported type.
+    Table,
+    /// Memory of the imported type.
+    Memory,
+    /// Global of the imported type with the given initializer.
+    Global(Expression),
+    /// Tag of the imported type.
+    #[cfg(feature = "exception-handling")]
+    Exception,
+}
+
+/// Imported item together with its local definition.
+enum Definition {
+    Func(TypeId, FuncBody),
+    Table(TableType),
+    Memory(MemType),
+    Global(GlobalType, Expression),
+    #[cfg(feature = "exception-handling")]
+    Exception(TypeId),
+}
+
+/// Index space shared by imported and defined items of the same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Space {
+    Func,
+    Table,
+    Memory,
+    Global,
+    #[cfg(feature = "exception-handling")]
+    Exception,
+}
+
+impl Space {
+    fn of(desc: &ImportDesc) -> Self {
+        match desc {
+            ImportDesc::Func(_) => Space::Func,
+            ImportDesc::Table(_) => Space::Table,
+            ImportDesc::Mem(_) => Space::Memory,
+            ImportDesc::Global(_) => Space::Global,
+            #[cfg(feature = "exception-handling")]
+            ImportDesc::Exception(_) => Space::Exception,
+        }
+    }
+
+    fn export(self, index: u32) -> ExportDesc {
+        match self {
+            Space::Func => ExportDesc::Func(FuncId::from(index)),
+            Space::Table => ExportDesc::Table(TableId::from(index)),
+            Space::Memory => ExportDesc::Mem(MemId::from(index)),
+            Space::Global => ExportDesc::Global(GlobalId::from(index)),
+            #[cfg(feature = "exception-handling")]
+            Space::Exception => ExportDesc::Exception(ExceptionId::from(index)),
+        }
+    }
+
+    fn is_referenced(self, module: &Module, index: u32) -> Result<bool, DecodeError> {
+        match self {
+            Space::Func => is_referenced(module, FuncId::from(index)),
+            Space::Table => is_referenced(module, TableId::from(index)),
+            Space::Memory => is_referenced(module, MemId::from(index)),
+            Space::Global => is_referenced(module, GlobalId::from(index)),
+            #[cfg(feature = "exception-handling")]
+            Space::Exception => is_referenced(module, ExceptionId::from(index)),
+        }
+    }
+
+    fn remap(
+        self,
+        module: &mut Module,
+        remap: impl Fn(u32) -> Option<u32>,
+    ) -> Result<(), InterfaceError> {
+        match self {
+            Space::Func => remap_ids::<FuncId>(module, remap),
+            Space::Table => remap_ids::<TableId>(module, remap),
+            Space::Memory => remap_ids::<MemId>(module, remap),
+            Space::Global => remap_ids::<GlobalId>(module, remap),
+            #[cfg(feature = "exception-handling")]
+            Space::Exception => remap_ids::<ExceptionId>(module, remap),
+        }
+    }
+}
+
+/// Whether the item is referenced anywhere outside of the name section.
+fn is_referenced<I: Copy + PartialEq + 'static>(
+    module: &Module,
+    id: I,
+) -> Result<bool, DecodeError> {
+    for section in &module.sections {
+        if is_name_section(section) {
+            continue;
+        }
+        match section.visit(|other: &I| *other != id) {
+            Ok(()) => {}
+            Err(VisitError::Custom(())) => return Ok(true),
+            Err(VisitError::LazyDecode(err)) => return Err(err),
+        }
+    }
+    Ok(false)
+}
+
+/// Update all references to an index space, dropping names of items that map to `None`.
+///
+/// Relocatable modules are rejected before anything is changed.
+fn remap_ids<I>(
+    module: &mut Module,
+    remap: impl Fn(u32) -> Option<u32>,
+) -> Result<(), InterfaceError>
+where
+    I: Copy + Ord + From<u32> + 'static,
+    u32: From<I>,
+{
+    if is_relocatable(module) {
+        return Err(InterfaceError::Relocatable);
+    }
+    let remap = |id: I| remap(u32::from(id)).map(I::from);
+    for section in &mut module.sections {
+        if is_name_section(section) {
+            section
+                .visit_mut(|names: &mut NameMap<I>| remap_name_map(names, remap))
+                .map_err(DecodeError::from)?;
+            section
+                .visit_mut(|names: &mut NameMap<I, NameMap<LocalId>>| {
+                    remap_name_map(names, remap);
+                })
+                .map_err(DecodeError::from)?;
+            #[cfg(feature = "extended-name-section")]
+            section
+                .visit_mut(|names: &mut NameMap<I, NameMap<LabelId>>| {
+                    remap_name_map(names, remap);
+                })
+                .map_err(DecodeError::from)?;
+        } else {
+            section
+                .visit_mut(|id: &mut I| {
+                    if let Some(new_id) = remap(*id) {
+                        *id = new_id;
+                    }
+                })
+                .map_err(DecodeError::from)?;
+        }
+    }
+    Ok(())
+}
+
+impl Module {
+    fn imports(&self) -> Result<&[Import], DecodeError> {
+        Ok(match self.find_std_section::<payload::Import>() {
+            Some(imports) => imports.try_contents()?,
+            None => &[],
+        })
+    }
+
+    /// Number of imported items in the index space.
+    fn imported_count(&self, space: Space) -> Result<u32, DecodeError> {
+        let mut count = 0;
+        for import in self.imports()? {
+            if Space::of(&import.desc) == space {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Position of the import within the import section, and its index in its index space.
+    fn find_import(&self, path: &ImportPath) -> Result<(usize, u32, Space), InterfaceError> {
+        let imports = self.imports()?;
+        let position = imports
+            .iter()
+            .position(|import| import.path == *path)
+            .ok_or_else(|| InterfaceError::UnknownImport(path.clone()))?;
+        let space = Space::of(&imports[position].desc);
+        let mut index = 0;
+        for import in &imports[..position] {
+            if Space::of(&import.desc) == space {
+                index += 1;
+            }
+        }
+        Ok((position, index, space))
+    }
+
+    /// Rename modules and fields of all imports.
+    pub fn rename_imports(
+        &mut self,
+        mut rename: impl FnMut(&mut ImportPath),
+    ) -> Result<(), DecodeError> {
+        if let Some(imports) = self.find_std_section_mut::<payload::Import>() {
+            for import in imports.try_contents_mut()? {
+                rename(&mut import.path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Append an import, shifting locally defined items of the same kind.
+    ///
+    /// Returns the index of the imported item in its index space. Relocatable modules are
+    /// rejected with [`InterfaceError::Relocatable`], same as in [`Module::remove_import`] and
+    /// [`Module::define_import`].
+    pub fn add_import(&mut self, import: Import) -> Result<u32, InterfaceError> {
+        let space = Space::of(&import.desc);
+        let index = self.imported_count(space)?;
+        space.remap(self, |i| Some(if i >= index { i + 1 } else { i }))?;
+        self.find_or_insert_std_section(payload::Import::default)
+            .try_contents_mut()?
+            .push(import);
+        Ok(index)
+    }
+
+    /// Remove an import that is not referenced anywhere in the module, shifting the items
+    /// following it in its index space.
+    pub fn remove_import(&mut self, path: &ImportPath) -> Result<Import, InterfaceError> {
+        let (position, index, space) = self.find_import(path)?;
+        if space.is_referenced(self, index)? {
+            return Err(InterfaceError::ImportInUse(path.clone()));
+        }
+        space.remap(self, |i| match i.cmp(&index) {
+            Ordering::Less => Some(i),
+            Ordering::Equal => None,
+            Ordering::Greater => Some(i - 1),
+        })?;
+        let import = self
+            .find_or_insert_std_section(payload::Import::default)
+            .try_contents_mut()?
+            .remove(position);
+        Ok(import)
+    }
+
+    /// Replace an import with a local definition of the same type.
+    ///
+    /// The definition is placed before other local definitions of its kind, so only the imports
+    /// following the replaced one are shifted. Function bodies and global initializers of the
+    /// definition should use the indices after the replacement.
+    pub fn define_import(
+        &mut self,
+        path: &ImportPath,
+        definition: ImportDefinition,
+    ) -> Result<(), InterfaceError> {
+        let (position, index, space) = self.find_import(path)?;
+        let definition = match (&self.imports()?[position].desc, definition) {
+            (ImportDesc::Func(ty), ImportDefinition::Func(body)) => Definition::Func(*ty, body),
+            (ImportDesc::Table(ty), ImportDefinition::Table) => Definition::Table(ty.clone()),
+            (ImportDesc::Mem(ty), ImportDefinition::Memory) => Definition::Memory(ty.clone()),
+            (ImportDesc::Global(ty), ImportDefinition::Global(init)) => {
+                Definition::Global(ty.clone(), init)
+            }
+            #[cfg(feature = "exception-handling")]
+            (ImportDesc::Exception(ty), ImportDefinition::Exception) => {
+                Definition::Exception(ty.func_type)
+            }
+            _ => return Err(InterfaceError::KindMismatch(path.clone())),
+        };
+        let imported = self.imported_count(space)?;
+        space.remap(self, |i| {
+            Some(if i == index {
+                imported - 1
+            } else if i > index && i < imported {
+                i - 1
+            } else {
+                i
+            })
+        })?;
+        self.find_or_insert_std_section(payload::Import::default)
+            .try_contents_mut()?
+            .remove(position);
+        match definition {
+            Definition::Func(ty, body) => {
+                self.find_or_insert_std_section(payload::Function::default)
+                    .try_contents_mut()?
+                    .insert(0, ty);
+                self.find_or_insert_std_section(payload::Code::default)
+                    .try_contents_mut()?
+                    .insert(0, body.into());
+            }
+            Definition::Table(ty) => self
+                .find_or_insert_std_section(payload::Table::default)
+                .try_contents_mut()?
+                .insert(0, ty),
+            Definition::Memory(ty) => self
+                .find_or_insert_std_section(payload::Memory::default)
+                .try_contents_mut()?
+                .insert(0, ty),
+            Definition::Global(ty, init) => self
+                .find_or_insert_std_section(payload::Global::default)
+                .try_contents_mut()?
+                .insert(0, Global { ty, init }),
+            #[cfg(feature = "exception-handling")]
+            Definition::Exception(ty) => self
+                .find_or_insert_std_section(payload::Exception::default)
+                .try_contents_mut()?
+                .insert(0, Exception { ty }),
+        }
+        Ok(())
+    }
+
+    fn exports_mut(&mut self) -> Result<&mut Vec<Export>, DecodeError> {
+        self.find_or_insert_std_section(payload::Export::default)
+            .try_contents_mut()
+    }
+
+    /// Add an export with a unique name.
+    pub fn add_export(&mut self, export: Export) -> Result<(), InterfaceError> {
+        let exports = self.exports_mut()?;
+        if exports.iter().any(|existing| existing.name == export.name) {
+            return Err(InterfaceError::DuplicateExport(export.name));
+        }
+        exports.push(export);
+        Ok(())
+    }
+
+    /// Remove an export by name, returning what it used to export.
+    pub fn remove_export(&mut self, name: &str) -> Result<ExportDesc, InterfaceError> {
+        let exports = self.exports_mut()?;
+        let position = exports
+            .iter()
+            .position(|export| export.name == name)
+            .ok_or_else(|| InterfaceError::UnknownExport(name.to_owned()))?;
+        Ok(exports.remove(position).desc)
+    }
+
+    /// Change the name of an export.
+    pub fn rename_export(
+        &mut self,
+        name: &str,
+        new_name: impl Into<String>,
+    ) -> Result<(), InterfaceError> {
+        let new_name = new_name.into();
+        let exports = self.exports_mut()?;
+        if exports.iter().any(|export| export.name == new_name) {
+            return Err(InterfaceError::DuplicateExport(new_name));
+        }
+        exports
+            .iter_mut()
+            .find(|export| export.name == name)
+            .ok_or_else(|| InterfaceError::UnknownExport(name.to_owned()))?
+            .name = new_name;
+        Ok(())
+    }
+
+    /// Export an imported item under the given name.
+    pub fn reexport_import(
+        &mut self,
+        path: &ImportPath,
+        name: impl Into<String>,
+    ) -> Result<(), InterfaceError> {
+        let (_, index, space) = self.find_import(path)?;
+        self.add_export(Export {
+            name: name.into(),
+            desc: space.export(index),
+        })
+    }
+}
@@ -0,0 +1,556 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of [legacy exception handling](https://github.com/WebAssembly/exception-handling/blob/main/proposals/exception-handling/legacy/Exceptions.md)
+//! instructions into the [`TryTable`] form.
+
+use crate::indices::{ExceptionId, LabelId, LocalId, TypeId};
+use crate::instructions::{Catch, Expression, Instruction, TryTable};
+use crate::io::DecodeError;
+use crate::sections::{payload, FuncBody, ImportDesc, Locals};
+use crate::types::{BlockType, FuncType, RefType, ValueType};
+use crate::visit::{Visit, VisitError};
+use crate::Module;
+use std::convert::TryFrom;
+use thiserror::Error;
+
+/// Error returned by [`convert_legacy_exceptions`].
+#[derive(Debug, Error)]
+pub enum LegacyExceptionsError {
+    /// Decoding error occured while reading a section or a function body.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Mismatched `try` / `catch` / `delegate` / `end` structure.
+    #[error("Mismatched legacy try block structure")]
+    Malformed,
+
+    /// A type, exception tag or label index is out of range.
+    #[error("Index out of range")]
+    OutOfRange,
+
+    /// `rethrow` doesn't refer to a `catch` clause.
+    #[error("Rethrow target is not a catch clause")]
+    InvalidRethrow,
+
+    /// `delegate` skips over other exception handlers, which can't be expressed with `try_table`.
+    #[error("Delegate across intermediate exception handlers is not supported")]
+    UnsupportedDelegate,
+}
+
+fn is_legacy(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::TryStart(_)
+            | Instruction::TryCatch(_)
+            | Instruction::TryCatchAll
+            | Instruction::TryDelegate(_)
+            | Instruction::Rethrow(_)
+    )
+}
+
+fn label(depth: usize) -> Result<LabelId, LegacyExceptionsError> {
+    u32::try_from(depth)
+        .map(LabelId::from)
+        .map_err(|_| LegacyExceptionsError::OutOfRange)
+}
+
+struct TryInfo {
+    catches: Vec<Option<ExceptionId>>,
+    needs_exn: bool,
+}
+
+enum ScanFrame {
+    Block,
+    TryTable,
+    TryBody(usize),
+    TryHandler(usize),
+}
+
+/// Collects the shape of each legacy `try` block in order of appearance.
+///
+/// The number of catch clauses and whether any of them is a `rethrow` target
+/// determines the output structure, so it needs to be known upfront.
+fn scan(
+    instrs: &[Instruction],
+    frames: &mut Vec<ScanFrame>,
+    infos: &mut Vec<TryInfo>,
+) -> Result<(), LegacyExceptionsError> {
+    for instr in instrs {
+        match instr {
+            Instruction::BlockStart(_) | Instruction::LoopStart(_) | Instruction::IfStart(_) => {
+                frames.push(ScanFrame::Block);
+            }
+            Instruction::TryStart(_) => {
+                frames.push(ScanFrame::TryBody(infos.len()));
+                infos.push(TryInfo {
+                    catches: Vec::new(),
+                    needs_exn: false,
+                });
+            }
+            Instruction::TryCatch(_) | Instruction::TryCatchAll => {
+                let frame = frames.last_mut().ok_or(LegacyExceptionsError::Malformed)?;
+                let (ScanFrame::TryBody(i) | ScanFrame::TryHandler(i)) = *frame else {
+                    return Err(LegacyExceptionsError::Malformed);
+                };
+                let catches = &mut infos[i].catches;
+                // `catch_all` must be the last clause.
+                if catches.last() == Some(&None) {
+                    return Err(LegacyExceptionsError::Malformed);
+                }
+                catches.push(match instr {
+                    Instruction::TryCatch(exception) => Some(*exception),
+                    _ => None,
+                });
+                *frame = ScanFrame::TryHandler(i);
+            }
+            Instruction::Rethrow(target) => match frames.iter().rev().nth(target.index as usize) {
+                Some(ScanFrame::TryHandler(i)) => infos[*i].needs_exn = true,
+                _ => return Err(LegacyExceptionsError::InvalidRethrow),
+            },
+            Instruction::TryDelegate(target) => {
+                let Some(ScanFrame::TryBody(_)) = frames.pop() else {
+                    return Err(LegacyExceptionsError::Malformed);
+                };
+                let depth = target.index as usize;
+                if depth > frames.len() {
+                    return Err(LegacyExceptionsError::OutOfRange);
+                }
+                // Delegating is equivalent to a plain block only if there are
+                // no other handlers between this block and the target label.
+                if frames
+                    .iter()
+                    .rev()
+                    .take(depth)
+                    .any(|frame| matches!(frame, ScanFrame::TryBody(_) | ScanFrame::TryTable))
+                {
+                    return Err(LegacyExceptionsError::UnsupportedDelegate);
+                }
+            }
+            Instruction::End => {
+                frames.pop().ok_or(LegacyExceptionsError::Malformed)?;
+            }
+            Instruction::TryTable(try_table) => {
+                frames.push(ScanFrame::TryTable);
+                let depth = frames.len();
+                scan(&try_table.instructions, frames, infos)?;
+                if frames.len() != depth {
+                    return Err(LegacyExceptionsError::Malformed);
+                }
+                frames.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+enum EmitFrame {
+    Block,
+    TryBody {
+        info: usize,
+        catch_count: usize,
+        exn: Option<LocalId>,
+        block_type: BlockType,
+    },
+    TryHandler {
+        catch_count: usize,
+        clause: usize,
+        exn: Option<LocalId>,
+    },
+}
+
+impl EmitFrame {
+    /// Number of blocks this frame expands into at the current position.
+    ///
+    /// Branches to the original frame target the outermost one.
+    fn new_levels(&self) -> usize {
+        match *self {
+            EmitFrame::Block => 1,
+            // Outer block, a block per catch clause and the `try_table` itself.
+            EmitFrame::TryBody { catch_count, .. } => catch_count + 2,
+            // Outer block and blocks of the remaining catch clauses.
+            EmitFrame::TryHandler {
+                catch_count,
+                clause,
+                ..
+            } => catch_count - clause + 1,
+        }
+    }
+}
+
+struct Converter<'a> {
+    infos: &'a [TryInfo],
+    next_info: usize,
+    types: &'a mut payload::Type,
+    tag_types: &'a [TypeId],
+    next_local: u32,
+    frames: Vec<EmitFrame>,
+}
+
+impl Converter<'_> {
+    fn func_type(&self, ty: TypeId) -> Result<&FuncType, LegacyExceptionsError> {
+        self.types
+            .get(ty.index as usize)
+            .and_then(|ty| ty.as_func())
+            .ok_or(LegacyExceptionsError::OutOfRange)
+    }
+
+    fn block_params(
+        &self,
+        block_type: &BlockType,
+    ) -> Result<Vec<ValueType>, LegacyExceptionsError> {
+        Ok(match block_type {
+            BlockType::Empty | BlockType::Value(_) => Vec::new(),
+            BlockType::MultiValue(ty) => self.func_type(*ty)?.params.clone(),
+        })
+    }
+
+    fn tag_params(&self, tag: ExceptionId) -> Result<Vec<ValueType>, LegacyExceptionsError> {
+        let ty = *self
+            .tag_types
+            .get(tag.index as usize)
+            .ok_or(LegacyExceptionsError::OutOfRange)?;
+        Ok(self.func_type(ty)?.params.clone())
+    }
+
+    fn block_type(
+        &mut self,
+        params: &[ValueType],
+        mut results: Vec<ValueType>,
+    ) -> Result<BlockType, LegacyExceptionsError> {
+        if params.is_empty() && results.len() <= 1 {
+            return Ok(results.pop().map_or(BlockType::Empty, BlockType::Value));
+        }
+        let ty = FuncType {
+            params: params.to_vec(),
+            results,
+        };
+        let index = if let Some(index) = self
+            .types
+            .iter()
+            .position(|existing| existing.as_func() == Some(&ty))
+        {
+            index
+        } else {
+            self.types.push(ty.into());
+            self.types.len() - 1
+        };
+        let index = u32::try_from(index).map_err(|_| LegacyExceptionsError::OutOfRange)?;
+        Ok(BlockType::MultiValue(TypeId::from(index)))
+    }
+
+    fn remap(&self, target: LabelId) -> Result<LabelId, LegacyExceptionsError> {
+        let mut frames = self.frames.iter().rev();
+        let mut depth = 0;
+        for _ in 0..target.index {
+            depth += frames
+                .next()
+                .ok_or(LegacyExceptionsError::OutOfRange)?
+                .new_levels();
+        }
+        // If there is no frame left, this is a branch to the function body itself.
+        if let Some(frame) = frames.next() {
+            depth += frame.new_levels() - 1;
+        }
+        label(depth)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn convert(&mut self, instrs: Expression) -> Result<Expression, LegacyExceptionsError> {
+        // The innermost output is the body of the `try_table` being built, if any.
+        let mut outputs = vec![Vec::with_capacity(instrs.len())];
+        for instr in instrs {
+            let out = outputs.last_mut().expect("at least one output");
+            match instr {
+                Instruction::BlockStart(_)
+                | Instruction::LoopStart(_)
+                | Instruction::IfStart(_) => {
+                    self.frames.push(EmitFrame::Block);
+                    out.push(instr);
+                }
+                Instruction::End | Instruction::TryDelegate(_) => {
+                    match self.frames.pop() {
+                        Some(EmitFrame::Block | EmitFrame::TryHandler { .. }) => {}
+                        _ => return Err(LegacyExceptionsError::Malformed),
+                    }
+                    out.push(Instruction::End);
+                }
+                Instruction::Br(target) => {
+                    out.push(Instruction::Br(self.remap(target)?));
+                }
+                Instruction::BrIf(target) => {
+                    out.push(Instruction::BrIf(self.remap(target)?));
+                }
+                Instruction::BrTable {
+                    branches,
+                    otherwise,
+                } => {
+                    out.push(Instruction::BrTable {
+                        branches: branches
+                            .into_iter()
+                            .map(|target| self.remap(target))
+                            .collect::<Result<_, _>>()?,
+                        otherwise: self.remap(otherwise)?,
+                    });
+                }
+                Instruction::TryTable(mut try_table) => {
+                    // Catch targets are resolved outside of the `try_table` itself.
+                    for catch in &mut try_table.catches {
+                        catch.target = self.remap(catch.target)?;
+                    }
+                    self.frames.push(EmitFrame::Block);
+                    try_table.instructions = self.convert(try_table.instructions)?;
+                    self.frames.pop();
+                    out.push(Instruction::TryTable(try_table));
+                }
+                Instruction::TryStart(block_type) => {
+                    let info = self.next_info;
+                    self.next_info += 1;
+                    let catches = &self.infos[info].catches;
+                    if catches.is_empty() {
+                        // A `try` without handlers (or with `delegate`) is just a block.
+                        self.frames.push(EmitFrame::Block);
+                        out.push(Instruction::BlockStart(block_type));
+                        continue;
+                    }
+                    let exn = self.infos[info].needs_exn.then(|| {
+                        let local = LocalId::from(self.next_local);
+                        self.next_local += 1;
+                        local
+                    });
+                    let params = self.block_params(&block_type)?;
+                    out.push(Instruction::BlockStart(block_type.clone()));
+                    // Blocks for catch clauses go from the last to the first one,
+                    // so that the first clause is handled by the innermost block.
+                    for filter in catches.iter().rev() {
+                        let mut results = match *filter {
+                            Some(tag) => self.tag_params(tag)?,
+                            None => Vec::new(),
+                        };
+                        if exn.is_some() {
+                            results.push(ValueType::Ref(RefType::Exception));
+                        }
+                        let block_type = self.block_type(&params, results)?;
+                        out.push(Instruction::BlockStart(block_type));
+                    }
+                    self.frames.push(EmitFrame::TryBody {
+                        info,
+                        catch_count: catches.len(),
+                        exn,
+                        block_type,
+                    });
+                    outputs.push(Vec::new());
+                }
+                Instruction::TryCatch(_) | Instruction::TryCatchAll => {
+                    let frame = self
+                        .frames
+                        .last_mut()
+                        .ok_or(LegacyExceptionsError::Malformed)?;
+                    let (catch_count, clause, exn) = match frame {
+                        EmitFrame::TryBody {
+                            info,
+                            catch_count,
+                            exn,
+                            block_type,
+                        } => {
+                            let instructions = outputs.pop().expect("try_table body");
+                            let catches = self.infos[*info]
+                                .catches
+                                .iter()
+                                .enumerate()
+                                .map(|(i, filter)| {
+                                    Ok(Catch {
+                                        catch_ref: exn.is_some(),
+                                        exception_filter: *filter,
+                                        target: label(i)?,
+                                    })
+                                })
+                                .collect::<Result<_, LegacyExceptionsError>>()?;
+                            outputs
+                                .last_mut()
+                                .ok_or(LegacyExceptionsError::Malformed)?
+                                .push(Instruction::TryTable(TryTable {
+                                    block_type: block_type.clone(),
+                                    catches,
+                                    instructions,
+                                }));
+                            (*catch_count, 1, *exn)
+                        }
+                        EmitFrame::TryHandler {
+                            catch_count,
+                            clause,
+                            exn,
+                        } => (*catch_count, *clause + 1, *exn),
+                        EmitFrame::Block => return Err(LegacyExceptionsError::Malformed),
+                    };
+                    *frame = EmitFrame::TryHandler {
+                        catch_count,
+                        clause,
+                        exn,
+                    };
+                    let out = outputs.last_mut().expect("at least one output");
+                    // Skip the remaining catch blocks on the happy path of the
+                    // previous handler (or of the `try_table` itself).
+                    out.push(Instruction::Br(label(catch_count - clause + 1)?));
+                    out.push(Instruction::End);
+                    if let Some(exn) = exn {
+                        out.push(Instruction::LocalSet(exn));
+                    }
+                }
+                Instruction::Rethrow(target) => {
+                    match self.frames.iter().rev().nth(target.index as usize) {
+                        Some(EmitFrame::TryHandler { exn: Some(exn), .. }) => {
+                            out.push(Instruction::LocalGet(*exn));
+                            out.push(Instruction::ThrowRef);
+                        }
+                        _ => return Err(LegacyExceptionsError::InvalidRethrow),
+                    }
+                }
+                instr => out.push(instr),
+            }
+        }
+        match <[_; 1]>::try_from(outputs) {
+            Ok([out]) => Ok(out),
+            Err(_) => Err(LegacyExceptionsError::Malformed),
+        }
+    }
+}
+
+fn convert_body(
+    body: &mut FuncBody,
+    param_count: usize,
+    types: &mut payload::Type,
+    tag_types: &[TypeId],
+) -> Result<(), LegacyExceptionsError> {
+    let mut frames = Vec::new();
+    let mut infos = Vec::new();
+    scan(&body.expr, &mut frames, &mut infos)?;
+    if !frames.is_empty() {
+        return Err(LegacyExceptionsError::Malformed);
+    }
+    let first_new_local = u32::try_from(param_count)
+        .ok()
+        .and_then(|count| {
+            body.locals
+                .iter()
+                .try_fold(count, |count, locals| count.checked_add(locals.repeat))
+        })
+        .ok_or(LegacyExceptionsError::OutOfRange)?;
+    let mut converter = Converter {
+        infos: &infos,
+        next_info: 0,
+        types,
+        tag_types,
+        next_local: first_new_local,
+        frames: Vec::new(),
+    };
+    body.expr = converter.convert(std::mem::take(&mut body.expr))?;
+    let new_locals = converter.next_local - first_new_local;
+    if new_locals != 0 {
+        body.locals.push(Locals {
+            repeat: new_locals,
+            ty: ValueType::Ref(RefType::Exception),
+        });
+    }
+    Ok(())
+}
+
+/// Converts legacy `try` / `catch` / `catch_all` / `delegate` / `rethrow` instructions in
+/// all function bodies into the [`TryTable`] form.
+///
+/// Each legacy `try` block with catch clauses is rewritten as a `try_table` nested in
+/// a block per catch clause, so that each handler follows the end of its block. If any
+/// of the handlers is targeted by `rethrow`, the caught exception is stored in a new
+/// `exnref` local and rethrown via `throw_ref`.
+///
+/// A `try` block with `delegate` is rewritten as a plain block, which is only possible
+/// when there are no other exception handlers between it and the delegation target;
+/// otherwise [`LegacyExceptionsError::UnsupportedDelegate`] is returned.
+///
+/// New block types are appended to the type section as necessary. Function bodies
+/// without legacy instructions are left untouched.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use wasmbin::transforms::legacy_exceptions::convert_legacy_exceptions;
+/// use wasmbin::Module;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut module = Module::decode_from(File::open("module.wasm")?)?;
+/// convert_legacy_exceptions(&mut module)?;
+/// module.encode_into(File::create("module.wasm")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn convert_legacy_exceptions(module: &mut Module) -> Result<(), LegacyExceptionsError> {
+    let mut types = match module.find_std_section::<payload::Type>() {
+        Some(types) => types.try_contents()?.clone(),
+        None => Vec::new(),
+    };
+    let type_count = types.len();
+    let mut tag_types = Vec::new();
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        for import in imports.try_contents()? {
+            if let ImportDesc::Exception(ty) = &import.desc {
+                tag_types.push(ty.func_type);
+            }
+        }
+    }
+    if let Some(tags) = module.find_std_section::<payload::Exception>() {
+        tag_types.extend(tags.try_contents()?.iter().map(|tag| tag.ty));
+    }
+    let func_types = match module.find_std_section::<payload::Function>() {
+        Some(funcs) => funcs.try_contents()?.clone(),
+        None => Vec::new(),
+    };
+    // Convert copies of the bodies first, so that the module is left untouched on error.
+    let mut converted = Vec::new();
+    if let Some(code) = module.find_std_section::<payload::Code>() {
+        for (i, body) in code.try_contents()?.iter().enumerate() {
+            let body = body.try_contents()?;
+            match body.expr.visit(|instr| !is_legacy(instr)) {
+                Ok(()) => continue,
+                Err(VisitError::Custom(())) => {}
+                Err(VisitError::LazyDecode(err)) => return Err(err.into()),
+            }
+            let ty = *func_types.get(i).ok_or(LegacyExceptionsError::OutOfRange)?;
+            let param_count = types
+                .get(ty.index as usize)
+                .and_then(|ty| ty.as_func())
+                .ok_or(LegacyExceptionsError::OutOfRange)?
+                .params
+                .len();
+            let mut body = body.clone();
+            convert_body(&mut body, param_count, &mut types, &tag_types)?;
+            converted.push((i, body));
+        }
+    }
+    if let Some(code) = module.find_std_section_mut::<payload::Code>() {
+        if !converted.is_empty() {
+            let bodies = code.try_contents_mut()?;
+            for (i, body) in converted {
+                bodies[i] = body.into();
+            }
+        }
+    }
+    if types.len() != type_count {
+        *module
+            .find_or_insert_std_section(Vec::new)
+            .try_contents_mut()? = types;
+    }
+    Ok(())
+}
@@ -0,0 +1,291 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic fuel metering: charging the cost of each basic block against a global counter
+//! before it runs.
+//!
+//! ```no_run
+//! use wasmbin::instructions::Instruction;
+//! use wasmbin::sections::ImportPath;
+//! use wasmbin::transforms::metering::{meter, FuelCounter, Metering, OutOfFuel};
+//! use wasmbin::Module;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = Module::decode_from(std::fs::File::open("contract.wasm")?)?;
+//! meter(
+//!     &mut module,
+//!     &Metering {
+//!         costs: |instruction: &Instruction| match instruction {
+//!             Instruction::Call(_) | Instruction::CallIndirect(_) => 10,
+//!             Instruction::End | Instruction::IfElse => 0,
+//!             _ => 1,
+//!         },
+//!         counter: FuelCounter::Imported(ImportPath {
+//!             module: "env".to_owned(),
+//!             name: "fuel".to_owned(),
+//!         }),
+//!         out_of_fuel: OutOfFuel::Trap,
+//!     },
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::indices::{FuncId, GlobalId, LabelId};
+use crate::instructions::{Expression, Instruction};
+use crate::io::DecodeError;
+use crate::sections::linking::is_relocatable;
+use crate::sections::{payload, Export, ExportDesc, Import, ImportDesc, ImportPath};
+use crate::transforms::instrument::{add_global, import_func};
+use crate::transforms::interface::InterfaceError;
+use crate::types::{BlockType, FuncType, GlobalType, ValueType};
+use crate::Module;
+use thiserror::Error;
+
+/// Error returned by [`meter`].
+#[derive(Debug, Error)]
+pub enum MeteringError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Fuel counter couldn't be exported.
+    #[error(transparent)]
+    Interface(#[from] InterfaceError),
+}
+
+/// Cost of executing each instruction.
+pub trait CostTable {
+    fn cost(&self, instruction: &Instruction) -> u64;
+}
+
+impl<F: Fn(&Instruction) -> u64> CostTable for F {
+    fn cost(&self, instruction: &Instruction) -> u64 {
+        self(instruction)
+    }
+}
+
+/// Cost table charging a unit of fuel for every instruction except the `end` and `else`
+/// markers of structured control flow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UniformCost;
+
+impl CostTable for UniformCost {
+    fn cost(&self, instruction: &Instruction) -> u64 {
+        match instruction {
+            Instruction::End | Instruction::IfElse => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// Mutable `i64` global holding the remaining fuel, treated as an unsigned amount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuelCounter {
+    /// Global defined by the module and exported under `name`, so that the host can read and
+    /// refill it.
+    Exported { name: String, initial: u64 },
+    /// Global imported from the host.
+    Imported(ImportPath),
+}
+
+/// Action taken when a basic block costs more than the remaining fuel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutOfFuel {
+    /// Trap with `unreachable`.
+    Trap,
+    /// Call an imported function with the `i64` cost of the block, which should either trap or
+    /// add fuel. The function is called repeatedly until there is enough fuel to run the block.
+    Call(ImportPath),
+}
+
+/// Configuration of [`meter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metering<C> {
+    pub costs: C,
+    pub counter: FuelCounter,
+    pub out_of_fuel: OutOfFuel,
+}
+
+fn fuel_type() -> GlobalType {
+    GlobalType {
+        value_type: ValueType::I64,
+        mutable: true,
+        #[cfg(feature = "threads")]
+        is_shared: false,
+    }
+}
+
+/// Whether the instruction ends a basic block, either by transferring control or by being a
+/// point where control from elsewhere joins.
+fn ends_block(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::LoopStart(_)
+        | Instruction::IfStart(_)
+        | Instruction::IfElse
+        | Instruction::End
+        | Instruction::Br(_)
+        | Instruction::BrIf(_)
+        | Instruction::BrTable { .. }
+        | Instruction::Return
+        | Instruction::ReturnCall(_)
+        | Instruction::ReturnCallIndirect(_)
+        | Instruction::Unreachable => true,
+        #[cfg(feature = "exception-handling")]
+        Instruction::TryTable(_) | Instruction::Throw(_) | Instruction::ThrowRef => true,
+        #[cfg(feature = "legacy-exceptions")]
+        Instruction::TryCatch(_)
+        | Instruction::TryCatchAll
+        | Instruction::TryDelegate(_)
+        | Instruction::Rethrow(_) => true,
+        _ => false,
+    }
+}
+
+struct Charger {
+    counter: GlobalId,
+    out_of_fuel: Option<FuncId>,
+}
+
+impl Charger {
+    fn charge(&self, cost: u64, out: &mut Expression) {
+        if cost == 0 {
+            return;
+        }
+        let cost = Instruction::I64Const(i64::try_from(cost).unwrap_or(i64::MAX));
+        let exhausted = [
+            Instruction::GlobalGet(self.counter),
+            cost.clone(),
+            Instruction::I64LtU,
+            Instruction::IfStart(BlockType::Empty),
+        ];
+        match self.out_of_fuel {
+            None => {
+                out.extend(exhausted);
+                out.push(Instruction::Unreachable);
+                out.push(Instruction::End);
+            }
+            Some(func) => {
+                out.push(Instruction::LoopStart(BlockType::Empty));
+                out.extend(exhausted);
+                out.push(cost.clone());
+                out.push(Instruction::Call(func));
+                out.push(Instruction::Br(LabelId::from(1)));
+                out.push(Instruction::End);
+                out.push(Instruction::End);
+            }
+        }
+        out.extend([
+            Instruction::GlobalGet(self.counter),
+            cost,
+            Instruction::I64Sub,
+            Instruction::GlobalSet(self.counter),
+        ]);
+    }
+
+    fn meter(&self, costs: &impl CostTable, body: Expression) -> Expression {
+        let mut out = Vec::with_capacity(body.len());
+        let mut block = Vec::new();
+        let mut cost = 0_u64;
+        for instruction in body {
+            cost = cost.saturating_add(costs.cost(&instruction));
+            #[cfg(feature = "exception-handling")]
+            let instruction = match instruction {
+                Instruction::TryTable(mut try_table) => {
+                    try_table.instructions =
+                        self.meter(costs, std::mem::take(&mut try_table.instructions));
+                    Instruction::TryTable(try_table)
+                }
+                instruction => instruction,
+            };
+            let ends_block = ends_block(&instruction);
+            block.push(instruction);
+            if ends_block {
+                self.charge(cost, &mut out);
+                out.append(&mut block);
+                cost = 0;
+            }
+        }
+        self.charge(cost, &mut out);
+        out.append(&mut block);
+        out
+    }
+}
+
+/// Insert fuel accounting at the start of each basic block of every function defined by the
+/// module, charging the total cost of the block's instructions up front.
+///
+/// Basic blocks end at branches and at the boundaries of `loop`, `if`, `else` and `try_table`
+/// blocks, so every loop iteration is charged. Calls don't end a block, since the callee is
+/// metered separately. Returns the fuel counter global.
+pub fn meter(
+    module: &mut Module,
+    metering: &Metering<impl CostTable>,
+) -> Result<GlobalId, MeteringError> {
+    // Check everything that can fail before adding anything to the module.
+    if is_relocatable(module) {
+        return Err(InterfaceError::Relocatable.into());
+    }
+    if let FuelCounter::Exported { name, .. } = &metering.counter {
+        if let Some(exports) = module.find_std_section::<payload::Export>() {
+            if exports
+                .try_contents()?
+                .iter()
+                .any(|export| export.name == *name)
+            {
+                return Err(InterfaceError::DuplicateExport(name.clone()).into());
+            }
+        }
+    }
+    let counter = match &metering.counter {
+        FuelCounter::Exported { name, initial } => {
+            let counter = add_global(
+                module,
+                fuel_type(),
+                vec![Instruction::I64Const(initial.cast_signed())],
+            )?;
+            module.add_export(Export {
+                name: name.clone(),
+                desc: ExportDesc::Global(counter),
+            })?;
+            counter
+        }
+        FuelCounter::Imported(path) => GlobalId::from(module.add_import(Import {
+            path: path.clone(),
+            desc: ImportDesc::Global(fuel_type()),
+        })?),
+    };
+    let out_of_fuel = match &metering.out_of_fuel {
+        OutOfFuel::Trap => None,
+        OutOfFuel::Call(path) => Some(import_func(
+            module,
+            path.clone(),
+            FuncType {
+                params: vec![ValueType::I64],
+                results: vec![],
+            },
+        )?),
+    };
+    let charger = Charger {
+        counter,
+        out_of_fuel,
+    };
+    if let Some(code) = module.find_std_section_mut::<payload::Code>() {
+        for body in code.try_contents_mut()? {
+            let body = body.try_contents_mut()?;
+            body.expr = charger.meter(&metering.costs, std::mem::take(&mut body.expr));
+        }
+    }
+    Ok(counter)
+}
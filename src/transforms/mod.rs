@@ -0,0 +1,35 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Module-level transformations.
+
+pub mod address_map;
+pub mod asyncify;
+pub mod branch_hints;
+#[cfg(feature = "build-id")]
+pub mod build_id;
+pub mod const_eval;
+pub mod data_segments;
+pub mod dedup_types;
+pub mod instrument;
+pub mod interface;
+#[cfg(feature = "legacy-exceptions")]
+pub mod legacy_exceptions;
+pub mod metering;
+pub mod producers;
+pub mod relocations;
+#[cfg(feature = "interp")]
+pub mod snapshot;
+mod stack_effect;
+pub mod stack_limit;
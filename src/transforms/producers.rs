@@ -0,0 +1,162 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Editing of the [producers section](https://github.com/WebAssembly/tool-conventions/blob/main/ProducersSection.md)
+//! following the deduplication rules of the tool conventions.
+
+use crate::builtins::{Blob, Lazy};
+use crate::io::DecodeError;
+use crate::module::encode_module_sections;
+use crate::sections::{CustomSection, ProducerField, ProducerVersionedName, Section};
+use crate::Module;
+use thiserror::Error;
+
+/// Field listing the source languages.
+pub const LANGUAGE: &str = "language";
+/// Field listing the tools that produced or transformed the module.
+pub const PROCESSED_BY: &str = "processed-by";
+/// Field listing the SDKs the module was built with.
+pub const SDK: &str = "sdk";
+
+/// Error returned by [`encode_with_provenance`].
+#[derive(Debug, Error)]
+pub enum ProducersError {
+    /// Decoding error occured while reading the existing producers section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Encoding error occured while writing the module.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Add a `name` with `version` to the given field, creating the field if necessary.
+///
+/// Each field and each name within a field appear at most once, so an existing entry with
+/// the same name has its version replaced instead.
+pub fn add_producer(fields: &mut Vec<ProducerField>, field: &str, name: &str, version: &str) {
+    let index = if let Some(index) = fields.iter().position(|existing| existing.name == field) {
+        index
+    } else {
+        fields.push(ProducerField {
+            name: field.to_owned(),
+            values: Vec::new(),
+        });
+        fields.len() - 1
+    };
+    let values = &mut fields[index].values;
+    match values.iter_mut().find(|existing| existing.name == name) {
+        Some(existing) => version.clone_into(&mut existing.version),
+        None => values.push(ProducerVersionedName {
+            name: name.to_owned(),
+            version: version.to_owned(),
+        }),
+    }
+}
+
+/// Merge all entries from `other` into `fields`, e.g. when combining modules.
+pub fn merge_producers(fields: &mut Vec<ProducerField>, other: &[ProducerField]) {
+    for field in other {
+        for value in &field.values {
+            add_producer(fields, &field.name, &value.name, &value.version);
+        }
+    }
+}
+
+fn find_producers(module: &Module) -> Option<(usize, &Lazy<Vec<ProducerField>>)> {
+    module
+        .sections
+        .iter()
+        .enumerate()
+        .find_map(|(index, section)| match section {
+            Section::Custom(custom) => match custom.try_contents() {
+                Ok(CustomSection::Producers(fields)) => Some((index, fields)),
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+/// Read the producers section of a module, or an empty list if there is none.
+pub fn read_producers(module: &Module) -> Result<Vec<ProducerField>, DecodeError> {
+    match find_producers(module) {
+        Some((_, fields)) => Ok(fields.try_contents()?.clone()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Replace the producers section of a module, appending one at the end if there is none.
+pub fn write_producers(module: &mut Module, fields: Vec<ProducerField>) {
+    let section = Section::Custom(Blob::from(CustomSection::Producers(fields.into())));
+    match find_producers(module) {
+        Some((index, _)) => module.sections[index] = section,
+        None => module.sections.push(section),
+    }
+}
+
+/// Add an entry to the producers section of a module, creating the section if necessary.
+pub fn add_module_producer(
+    module: &mut Module,
+    field: &str,
+    name: &str,
+    version: &str,
+) -> Result<(), DecodeError> {
+    let mut fields = read_producers(module)?;
+    add_producer(&mut fields, field, name, version);
+    write_producers(module, fields);
+    Ok(())
+}
+
+/// Encode the module with `processed-by: wasmbin` recorded in its producers section, leaving
+/// the module itself unchanged.
+///
+/// The merged producers section is written in place of the existing one, or after all other
+/// sections if there is none.
+///
+/// ## Example
+///
+/// ```no_run
+/// use wasmbin::transforms::producers::encode_with_provenance;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let module = wasmbin::Module::decode_from(std::fs::File::open("input.wasm")?)?;
+/// encode_with_provenance(&module, std::fs::File::create("output.wasm")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn encode_with_provenance<W: std::io::Write>(
+    module: &Module,
+    w: W,
+) -> Result<W, ProducersError> {
+    let existing = find_producers(module);
+    let mut fields = match existing {
+        Some((_, fields)) => fields.try_contents()?.clone(),
+        None => Vec::new(),
+    };
+    add_producer(
+        &mut fields,
+        PROCESSED_BY,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    );
+    let section = Section::Custom(Blob::from(CustomSection::Producers(fields.into())));
+    let (before, after) = match existing {
+        Some((index, _)) => (&module.sections[..index], &module.sections[index + 1..]),
+        None => (module.sections.as_slice(), &[][..]),
+    };
+    Ok(encode_module_sections(
+        before.iter().chain(std::iter::once(&section)).chain(after),
+        w,
+    )?)
+}
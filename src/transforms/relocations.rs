@@ -0,0 +1,532 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Application of [relocations](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#relocation-sections)
+//! recorded in the `reloc.*` custom sections of WebAssembly object files.
+
+use crate::builtins::{Blob, Lazy};
+#[cfg(feature = "exception-handling")]
+use crate::instructions::TryTable;
+use crate::instructions::{Instruction, SIMD};
+use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode};
+use crate::sections::linking::{Reloc, RelocEntry, RelocError, RelocSection};
+use crate::sections::{payload, CustomSection, Kind, Locals, Section};
+use crate::Module;
+
+/// Collect the contents of all relocation sections in the module.
+pub fn relocations(module: &Module) -> Result<Vec<Reloc>, RelocError> {
+    let mut relocs = Vec::new();
+    for section in &module.sections {
+        if let Some(custom) = section.try_as::<payload::Custom>() {
+            // Unrelated custom sections are allowed to be malformed.
+            if let Ok(CustomSection::Reloc(reloc)) = custom.try_contents() {
+                relocs.push(reloc.reloc.try_contents()?.clone());
+            }
+        }
+    }
+    Ok(relocs)
+}
+
+fn code_section_index(module: &Module) -> Option<usize> {
+    module
+        .sections
+        .iter()
+        .position(|section| section.kind() == Kind::Code)
+}
+
+/// Offset of the payload within an encoded section, skipping over the name of custom sections.
+fn payload_offset(encoded: &[u8], kind: Kind) -> Result<usize, RelocError> {
+    let mut r = encoded.get(1..).unwrap_or_default();
+    u32::decode(&mut r)?;
+    if kind == Kind::Custom {
+        String::decode(&mut r)?;
+    }
+    Ok(encoded.len() - r.len())
+}
+
+/// Patch relocation sites in the module sections with values provided by the resolver.
+///
+/// The resolver is called for each entry of each `reloc.*` section and should return the final
+/// value to store at the relocation site (including the [addend](RelocEntry::addend)), or `None`
+/// to leave the site untouched.
+///
+/// Sites are overwritten in place in the raw bytes of the target section, so everything else
+/// is preserved verbatim, including the padding of other LEB128 sites. This requires the target
+/// sections to still use the padded encoding produced by the compiler; otherwise
+/// [`RelocError::NotPadded`] is returned.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use wasmbin::transforms::relocations::apply_relocations;
+/// use wasmbin::Module;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let symbol_values: Vec<i64> = Vec::new();
+/// let mut module = Module::decode_from(File::open("object.o")?)?;
+/// apply_relocations(&mut module, |entry| {
+///     let value = symbol_values.get(entry.index as usize)?;
+///     Some(value + entry.addend)
+/// })?;
+/// module.encode_into(File::create("object.o")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn apply_relocations(
+    module: &mut Module,
+    mut resolve: impl FnMut(&RelocEntry) -> Option<i64>,
+) -> Result<(), RelocError> {
+    for reloc in relocations(module)? {
+        let section = module
+            .sections
+            .get_mut(reloc.section as usize)
+            .ok_or(RelocError::InvalidSection(reloc.section))?;
+        let mut encoded = Vec::new();
+        section.encode(&mut encoded)?;
+        let start = payload_offset(&encoded, section.kind())?;
+        let payload = &mut encoded[start..];
+        let mut changed = false;
+        for entry in &reloc.entries {
+            if let Some(value) = resolve(entry) {
+                entry.write(payload, value)?;
+                changed = true;
+            }
+        }
+        if changed {
+            *section = Section::decode(&mut encoded.as_slice())?;
+        }
+    }
+    Ok(())
+}
+
+/// Offsets of the LEB128 immediates of an encoded instruction, relative to the start of
+/// `bytes`, and of the instructions nested in its body, if any.
+#[derive(Default)]
+struct Immediates {
+    offsets: Vec<usize>,
+    nested: Vec<Immediates>,
+}
+
+/// Split the bytes after an opcode byte into consecutive LEB128 values.
+///
+/// Single-byte immediates such as value types and lane indices are indistinguishable from
+/// LEB128 values, so this holds for all immediates except for the raw bytes of constants.
+fn leb_offsets(bytes: &[u8], base: usize) -> impl Iterator<Item = usize> + '_ {
+    let mut start = 1;
+    bytes
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter_map(move |(i, byte)| {
+            if byte & 0x80 != 0 {
+                return None;
+            }
+            let offset = base + start;
+            start = i + 1;
+            Some(offset)
+        })
+}
+
+/// Instructions nested in the body of `instr`.
+fn nested_instrs(instr: &Instruction) -> &[Instruction] {
+    match instr {
+        #[cfg(feature = "exception-handling")]
+        Instruction::TryTable(try_table) => &try_table.instructions,
+        _ => &[],
+    }
+}
+
+/// Length of the LEB128 value at the start of `bytes`.
+fn leb_len(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .position(|byte| byte & 0x80 == 0)
+        .map_or(bytes.len(), |last| last + 1)
+}
+
+impl Immediates {
+    /// Find the immediates of `instr`, encoded as `bytes` at `base`.
+    ///
+    /// The encoding doesn't have to be the minimal one, which is why nested instructions are
+    /// located by decoding them from `bytes` rather than by re-encoding.
+    #[cfg_attr(
+        not(feature = "exception-handling"),
+        allow(clippy::unnecessary_wraps)
+    )]
+    fn new(instr: &Instruction, bytes: &[u8], base: usize) -> Result<Self, RelocError> {
+        Ok(match instr {
+            Instruction::F32Const(_)
+            | Instruction::F64Const(_)
+            | Instruction::SIMD(SIMD::V128Const(_)) => Self::default(),
+            #[cfg(feature = "exception-handling")]
+            Instruction::TryTable(try_table) => {
+                // Count the immediates before the body in the minimal encoding of the header.
+                let mut header = Vec::new();
+                Instruction::TryTable(TryTable {
+                    instructions: Vec::new(),
+                    ..try_table.clone()
+                })
+                .encode(&mut header)?;
+                header.pop();
+                let count = leb_offsets(&header, 0).count();
+                let offsets: Vec<_> = leb_offsets(bytes, base).take(count).collect();
+                let mut r = match offsets.last() {
+                    Some(&last) => &bytes[last - base..],
+                    None => bytes,
+                };
+                r = &r[leb_len(r)..];
+                let mut nested = Vec::with_capacity(try_table.instructions.len());
+                for instr in &try_table.instructions {
+                    let start = bytes.len() - r.len();
+                    Instruction::decode(&mut r)?;
+                    let end = bytes.len() - r.len();
+                    nested.push(Self::new(instr, &bytes[start..end], base + start)?);
+                }
+                Self { offsets, nested }
+            }
+            _ => Self {
+                offsets: leb_offsets(bytes, base).collect(),
+                nested: Vec::new(),
+            },
+        })
+    }
+
+    /// Find the immediate starting at `offset`, returning the indices of the nested
+    /// instructions leading to it and the index of the immediate itself.
+    fn locate(&self, offset: usize) -> Option<(Vec<usize>, usize)> {
+        if let Some(index) = self.offsets.iter().position(|&start| start == offset) {
+            return Some((Vec::new(), index));
+        }
+        self.nested.iter().enumerate().find_map(|(i, nested)| {
+            let (mut path, index) = nested.locate(offset)?;
+            path.insert(0, i);
+            Some((path, index))
+        })
+    }
+
+    /// Offset of the immediate found by [`locate`](Self::locate) in the immediates of
+    /// `other_instr`, an edited version of `instr`.
+    ///
+    /// Nested instructions are matched up by skipping the common prefix and suffix of the
+    /// original and edited bodies, and instructions in between only if the number of
+    /// instructions didn't change. Fails if the instruction containing the immediate has a
+    /// different number of immediates, as they can't be matched up then either.
+    fn find(
+        &self,
+        instr: &Instruction,
+        other: &Self,
+        other_instr: &Instruction,
+        path: &[usize],
+        index: usize,
+    ) -> Option<usize> {
+        let Some((&i, path)) = path.split_first() else {
+            if self.offsets.len() != other.offsets.len() {
+                return None;
+            }
+            return other.offsets.get(index).copied();
+        };
+        let (instrs, other_instrs) = (nested_instrs(instr), nested_instrs(other_instr));
+        let prefix = instrs
+            .iter()
+            .zip(other_instrs)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = instrs[prefix..]
+            .iter()
+            .rev()
+            .zip(other_instrs[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let j = if i < prefix {
+            i
+        } else if i >= instrs.len() - suffix {
+            i + other_instrs.len() - instrs.len()
+        } else if instrs.len() == other_instrs.len() {
+            i
+        } else {
+            return None;
+        };
+        self.nested.get(i)?.find(
+            instrs.get(i)?,
+            other.nested.get(j)?,
+            other_instrs.get(j)?,
+            path,
+            index,
+        )
+    }
+}
+
+/// An instruction together with the relocations that apply to its immediates.
+///
+/// Instructions decoded via [`RelocatedCode::from_module`] remember their original encoding,
+/// which is reused verbatim as long as neither the instruction nor its relocations are modified,
+/// preserving the width of every LEB128 immediate. Modified and new instructions are re-encoded
+/// with minimal LEB128 immediates, except for the relocation sites which are padded to their
+/// fixed width.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct RelocatedInstruction {
+    pub instr: Instruction,
+    /// Relocations with [offsets](RelocEntry::offset) relative to the start of the instruction.
+    ///
+    /// For new instructions, offsets refer to the minimal encoding of the instruction.
+    pub relocs: Vec<RelocEntry>,
+    /// Decoded instruction, its encoding and its relocations.
+    original: Option<(Instruction, Vec<u8>, Vec<RelocEntry>)>,
+}
+
+impl From<Instruction> for RelocatedInstruction {
+    fn from(instr: Instruction) -> Self {
+        RelocatedInstruction {
+            instr,
+            relocs: Vec::new(),
+            original: None,
+        }
+    }
+}
+
+impl RelocatedInstruction {
+    fn encode_into(
+        &self,
+        out: &mut Vec<u8>,
+        relocs: &mut Vec<RelocEntry>,
+    ) -> Result<(), RelocError> {
+        let base = out.len();
+        let mut push_reloc = |entry: &RelocEntry, offset: usize| -> Result<(), RelocError> {
+            relocs.push(RelocEntry {
+                offset: u32::try_from(base + offset).map_err(std::io::Error::other)?,
+                ..entry.clone()
+            });
+            Ok(())
+        };
+        if let Some((original, raw, relocs)) = &self.original {
+            if *original == self.instr && *relocs == self.relocs {
+                out.extend_from_slice(raw);
+                for entry in &self.relocs {
+                    push_reloc(entry, entry.offset as usize)?;
+                }
+                return Ok(());
+            }
+        }
+        let mut fresh = Vec::new();
+        self.instr.encode(&mut fresh)?;
+        if self.relocs.is_empty() {
+            out.extend_from_slice(&fresh);
+            return Ok(());
+        }
+        let reference = match &self.original {
+            Some((original, raw, _)) => Immediates::new(original, raw, 0)?,
+            None => Immediates::new(&self.instr, &fresh, 0)?,
+        };
+        let immediates = Immediates::new(&self.instr, &fresh, 0)?;
+        let mut targets = Vec::with_capacity(self.relocs.len());
+        for entry in &self.relocs {
+            let misplaced = || RelocError::MisplacedSite {
+                offset: entry.offset,
+            };
+            let (path, index) = reference
+                .locate(entry.offset as usize)
+                .ok_or_else(misplaced)?;
+            let original = self
+                .original
+                .as_ref()
+                .map_or(&self.instr, |(original, ..)| original);
+            let offset = reference
+                .find(original, &immediates, &self.instr, &path, index)
+                .ok_or_else(misplaced)?;
+            targets.push((offset, entry));
+        }
+        targets.sort_by_key(|(offset, _)| *offset);
+        let mut done = 0;
+        for (offset, entry) in targets {
+            // Two entries can't share the same site.
+            if offset < done {
+                return Err(RelocError::MisplacedSite {
+                    offset: entry.offset,
+                });
+            }
+            out.extend_from_slice(&fresh[done..offset]);
+            done = offset + leb_len(&fresh[offset..]);
+            push_reloc(entry, out.len() - base)?;
+            out.extend_from_slice(&entry.pad_site(&fresh[offset..done])?);
+        }
+        out.extend_from_slice(&fresh[done..]);
+        Ok(())
+    }
+}
+
+/// A function body with [relocated instructions](RelocatedInstruction).
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct RelocatedFuncBody {
+    pub locals: Vec<Locals>,
+    /// Instructions of the function body, without the terminating `end`.
+    pub instrs: Vec<RelocatedInstruction>,
+}
+
+/// Contents of the code section together with the relocations from its `reloc.*` section.
+///
+/// This allows editing function bodies of object files while keeping the relocation
+/// offsets valid: the offsets are recomputed when writing the code back into the module.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use wasmbin::instructions::Instruction;
+/// use wasmbin::transforms::relocations::RelocatedCode;
+/// use wasmbin::Module;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut module = Module::decode_from(File::open("object.o")?)?;
+/// let mut code = RelocatedCode::from_module(&module)?;
+/// for func in &mut code.funcs {
+///     func.instrs.insert(0, Instruction::Nop.into());
+/// }
+/// code.write_to_module(&mut module)?;
+/// module.encode_into(File::create("object.o")?)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct RelocatedCode {
+    pub funcs: Vec<RelocatedFuncBody>,
+}
+
+impl RelocatedCode {
+    /// Decode the code section of the module and attach its relocations to the instructions.
+    pub fn from_module(module: &Module) -> Result<Self, RelocError> {
+        let Some(code_index) = code_section_index(module) else {
+            return Ok(Self::default());
+        };
+        let mut entries = relocations(module)?
+            .into_iter()
+            .filter(|reloc| reloc.section as usize == code_index)
+            .flat_map(|reloc| reloc.entries)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.offset);
+        let mut entries = entries.into_iter().peekable();
+
+        let mut encoded = Vec::new();
+        module.sections[code_index].encode(&mut encoded)?;
+        let payload = &encoded[payload_offset(&encoded, Kind::Code)?..];
+        let offset_of = |rest: &[u8]| payload.len() - rest.len();
+
+        let mut r = payload;
+        let count = u32::decode(&mut r)?;
+        let mut funcs = Vec::new();
+        for _ in 0..count {
+            let body = <Vec<u8>>::decode(&mut r)?;
+            let body_start = offset_of(r) - body.len();
+            let mut body_r = body.as_slice();
+            let locals = <Vec<Locals>>::decode(&mut body_r)?;
+            let mut instrs = Vec::new();
+            while !body_r.is_empty() {
+                let start = body_start + body.len() - body_r.len();
+                let instr_bytes = body_r;
+                let instr = Instruction::decode(&mut body_r)?;
+                let end = body_start + body.len() - body_r.len();
+                if let Some(entry) = entries.next_if(|entry| (entry.offset as usize) < start) {
+                    return Err(RelocError::MisplacedSite {
+                        offset: entry.offset,
+                    });
+                }
+                let mut relocs = Vec::new();
+                while let Some(mut entry) = entries.next_if(|entry| (entry.offset as usize) < end) {
+                    #[allow(clippy::cast_possible_truncation)]
+                    {
+                        entry.offset -= start as u32;
+                    }
+                    relocs.push(entry);
+                }
+                let raw = instr_bytes[..end - start].to_vec();
+                instrs.push(RelocatedInstruction {
+                    original: Some((instr.clone(), raw, relocs.clone())),
+                    instr,
+                    relocs,
+                });
+            }
+            match instrs.pop() {
+                Some(last) if last.instr == Instruction::End && last.relocs.is_empty() => {}
+                _ => return Err(DecodeError::from(DecodeErrorKind::UnrecognizedData).into()),
+            }
+            funcs.push(RelocatedFuncBody { locals, instrs });
+        }
+        if !r.is_empty() {
+            return Err(DecodeError::from(DecodeErrorKind::UnrecognizedData).into());
+        }
+        if let Some(entry) = entries.next() {
+            return Err(RelocError::MisplacedSite {
+                offset: entry.offset,
+            });
+        }
+        Ok(RelocatedCode { funcs })
+    }
+
+    /// Encode function bodies back into the code section of the module and update the
+    /// offsets in its relocation section, creating a `reloc.CODE` section if necessary.
+    pub fn write_to_module(&self, module: &mut Module) -> Result<(), RelocError> {
+        let mut payload = Vec::new();
+        let mut entries = Vec::new();
+        self.funcs.len().encode(&mut payload)?;
+        for func in &self.funcs {
+            let mut body = Vec::new();
+            let mut body_entries = Vec::new();
+            func.locals.encode(&mut body)?;
+            for instr in &func.instrs {
+                instr.encode_into(&mut body, &mut body_entries)?;
+            }
+            Instruction::End.encode(&mut body)?;
+            body.len().encode(&mut payload)?;
+            let body_start = u32::try_from(payload.len()).map_err(std::io::Error::other)?;
+            entries.extend(body_entries.into_iter().map(|entry| RelocEntry {
+                offset: entry.offset + body_start,
+                ..entry
+            }));
+            payload.extend_from_slice(&body);
+        }
+        *module.find_or_insert_std_section(payload::Code::new) = Blob {
+            contents: Lazy::from_raw(payload.into()),
+        };
+        let Some(code_index) = code_section_index(module) else {
+            unreachable!("code section was just inserted");
+        };
+        let section = u32::try_from(code_index).map_err(std::io::Error::other)?;
+        let new_reloc = Reloc { section, entries };
+        for custom in module
+            .sections
+            .iter_mut()
+            .filter_map(|section| section.try_as_mut::<payload::Custom>())
+        {
+            if let Ok(CustomSection::Reloc(reloc)) = custom.try_contents() {
+                if reloc.reloc.try_contents()?.section == section {
+                    if let CustomSection::Reloc(reloc) = custom.try_contents_mut()? {
+                        reloc.reloc = Lazy::from(new_reloc);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        if !new_reloc.entries.is_empty() {
+            module
+                .sections
+                .push(Section::Custom(Blob::from(CustomSection::Reloc(
+                    RelocSection {
+                        name: "reloc.CODE".to_owned(),
+                        reloc: Lazy::from(new_reloc),
+                    },
+                ))));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Wizer](https://github.com/bytecodealliance/wizer)-style pre-initialization: running the
+//! initialization code of a module once and baking the resulting state back into the binary.
+//!
+//! ```no_run
+//! use wasmbin::interp::{Host, Instance, Store, Trap, Value};
+//! use wasmbin::sections::ImportPath;
+//! use wasmbin::transforms::snapshot::snapshot;
+//! use wasmbin::types::FuncType;
+//! use wasmbin::Module;
+//!
+//! struct NoImports;
+//!
+//! impl Host for NoImports {
+//!     fn call(
+//!         &mut self,
+//!         import: &ImportPath,
+//!         _ty: &FuncType,
+//!         _args: &[Value],
+//!         _store: &mut Store,
+//!     ) -> Result<Vec<Value>, Trap> {
+//!         Err(Trap::Host(format!("unexpected call to {}.{}", import.module, import.name)))
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = Module::decode_from(std::fs::File::open("app.wasm")?)?;
+//! let mut instance = Instance::new(&module, NoImports)?;
+//! instance.set_fuel(Some(1_000_000_000));
+//! snapshot(&mut module, &mut instance, "wizer.initialize")?;
+//! module.encode_into(std::fs::File::create("app.initialized.wasm")?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::builtins::FloatConst;
+use crate::indices::{GlobalId, MemId};
+use crate::instructions::{Instruction, SIMD};
+use crate::interp::{Host, Instance, InterpError, Value};
+use crate::io::DecodeError;
+use crate::sections::linking::is_relocatable;
+use crate::sections::{payload, Data, DataInit, ImportDesc, Kind};
+use crate::transforms::data_segments::{optimize_data_segments, DataSegmentsError};
+use crate::Module;
+use thiserror::Error;
+
+/// Error returned by [`snapshot`].
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Initialization failed.
+    #[error(transparent)]
+    Interp(#[from] InterpError),
+
+    /// Module imports a memory, whose contents are owned by the host.
+    #[error("Modules with imported memories can't be snapshotted")]
+    ImportedMemory,
+
+    /// Initialization modified tables or element segments, which are not captured.
+    #[error("Initialization modified tables or element segments")]
+    TablesModified,
+
+    /// Mutable global holds an external reference that has no constant representation.
+    #[error("Global {0:?} holds a host reference")]
+    HostReference(GlobalId),
+
+    /// Data segments of the snapshot couldn't be optimized.
+    #[error(transparent)]
+    DataSegments(#[from] DataSegmentsError),
+}
+
+fn const_expr(value: &Value) -> Option<Instruction> {
+    Some(match value {
+        Value::I32(value) => Instruction::I32Const(*value),
+        Value::I64(value) => Instruction::I64Const(*value),
+        Value::F32(value) => Instruction::F32Const(FloatConst { value: *value }),
+        Value::F64(value) => Instruction::F64Const(FloatConst { value: *value }),
+        Value::V128(value) => Instruction::SIMD(SIMD::V128Const(*value)),
+        Value::Null(ty) => Instruction::RefNull(ty.clone()),
+        Value::Func(func) => Instruction::RefFunc(*func),
+        Value::Extern(_) => return None,
+    })
+}
+
+/// Run the start function and the `init` export of a freshly [created](Instance::new)
+/// `instance` of `module`, then store the resulting state in the module.
+///
+/// Contents of memories replace all active data segments, which are then split around runs of
+/// zeroes with [`optimize_data_segments`], and mutable globals are initialized with their final
+/// values. Passive data segments keep their contents at the time of the snapshot. The start
+/// function and the `init` export are removed, so that initialization doesn't run again.
+///
+/// Values of imported globals and any state kept by the [`Host`] are not captured. The module is
+/// left unchanged if an error is returned.
+pub fn snapshot<H: Host>(
+    module: &mut Module,
+    instance: &mut Instance<H>,
+    init: &str,
+) -> Result<(), SnapshotError> {
+    let mut imported_globals = 0;
+    if let Some(imports) = module.find_std_section::<payload::Import>() {
+        for import in imports.try_contents()? {
+            match import.desc {
+                ImportDesc::Mem(_) => return Err(SnapshotError::ImportedMemory),
+                ImportDesc::Global(_) => imported_globals += 1,
+                _ => {}
+            }
+        }
+    }
+    if is_relocatable(module) {
+        return Err(DataSegmentsError::Relocatable.into());
+    }
+    let tables = instance.store.tables.clone();
+    let elems = instance.store.elems.clone();
+    instance.run_start()?;
+    instance.invoke(init, &[])?;
+    let store = &instance.store;
+    if store.tables != tables || store.elems != elems {
+        return Err(SnapshotError::TablesModified);
+    }
+
+    // Compute the new state before changing anything.
+    let mut global_inits = Vec::new();
+    if let Some(globals) = module.find_std_section::<payload::Global>() {
+        for (global, (index, value)) in globals
+            .try_contents()?
+            .iter()
+            .zip((imported_globals..).zip(&store.globals[imported_globals as usize..]))
+        {
+            global_inits.push(if global.ty.mutable {
+                let init =
+                    const_expr(value).ok_or(SnapshotError::HostReference(GlobalId::from(index)))?;
+                Some(vec![init])
+            } else {
+                None
+            });
+        }
+    }
+    // Data section can only be referenced by index from bulk memory instructions, which
+    // require the data count section. Without one, all the old segments can be dropped.
+    let mut new_data = Vec::new();
+    if module.find_std_section::<payload::DataCount>().is_some() {
+        new_data.extend(store.data.iter().map(|contents| Data {
+            init: DataInit::Passive,
+            blob: contents.clone(),
+        }));
+    }
+    for (memory, contents) in (0..).zip(&store.memories) {
+        let offset = vec![Instruction::I32Const(0)];
+        new_data.push(Data {
+            init: match memory {
+                0 => DataInit::Active { offset },
+                _ => DataInit::ActiveWithMemory {
+                    memory: MemId::from(memory),
+                    offset,
+                },
+            },
+            blob: contents.data.clone(),
+        });
+    }
+
+    if let Some(globals) = module.find_std_section_mut::<payload::Global>() {
+        for (global, init) in globals.try_contents_mut()?.iter_mut().zip(global_inits) {
+            if let Some(init) = init {
+                global.init = init;
+            }
+        }
+    }
+    if let Some(memories) = module.find_std_section_mut::<payload::Memory>() {
+        for (ty, memory) in memories.try_contents_mut()?.iter_mut().zip(&store.memories) {
+            // Memories can't grow beyond the 32-bit address space.
+            #[allow(clippy::cast_possible_truncation)]
+            {
+                ty.limits.min = memory.pages() as u32;
+            }
+        }
+    }
+    *module
+        .find_or_insert_std_section::<payload::Data>(Vec::new)
+        .try_contents_mut()? = new_data;
+    optimize_data_segments(module)?;
+
+    module
+        .sections
+        .retain(|section| section.kind() != Kind::Start);
+    if let Some(exports) = module.find_std_section_mut::<payload::Export>() {
+        exports
+            .try_contents_mut()?
+            .retain(|export| export.name != init);
+    }
+    Ok(())
+}
@@ -0,0 +1,817 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Operand stack effects of instructions, shared by the transforms that track the operand stack.
+
+use crate::indices::{FuncId, TypeId};
+#[cfg(feature = "threads")]
+use crate::instructions::Atomic;
+use crate::instructions::{Instruction, Misc, SIMD};
+use crate::io::DecodeError;
+use crate::sections::{payload, ImportDesc};
+use crate::types::{BlockType, FuncType};
+use crate::Module;
+
+#[cfg(any(feature = "legacy-exceptions", feature = "stack-switching"))]
+use crate::indices::ExceptionId;
+
+/// Reason the stack effect of an instruction couldn't be determined.
+pub(crate) enum AnalysisError {
+    InvalidReference,
+    #[cfg(feature = "stack-switching")]
+    Unsupported,
+}
+
+/// Signatures needed to compute stack effects of instructions.
+pub(crate) struct Signatures {
+    types: Vec<Option<FuncType>>,
+    #[cfg(feature = "stack-switching")]
+    conts: Vec<Option<TypeId>>,
+    funcs: Vec<TypeId>,
+    #[cfg(feature = "exception-handling")]
+    tags: Vec<TypeId>,
+}
+
+impl Signatures {
+    pub(crate) fn new(module: &Module) -> Result<Self, DecodeError> {
+        let mut signatures = Signatures {
+            types: Vec::new(),
+            #[cfg(feature = "stack-switching")]
+            conts: Vec::new(),
+            funcs: Vec::new(),
+            #[cfg(feature = "exception-handling")]
+            tags: Vec::new(),
+        };
+        if let Some(types) = module.find_std_section::<payload::Type>() {
+            for ty in types.try_contents()? {
+                signatures.types.push(ty.as_func().cloned());
+                #[cfg(feature = "stack-switching")]
+                signatures.conts.push(match ty {
+                    crate::types::TypeDef::Cont(cont) => Some(cont.func_type),
+                    crate::types::TypeDef::Func(_) => None,
+                });
+            }
+        }
+        if let Some(imports) = module.find_std_section::<payload::Import>() {
+            for import in imports.try_contents()? {
+                match &import.desc {
+                    ImportDesc::Func(ty) => signatures.funcs.push(*ty),
+                    #[cfg(feature = "exception-handling")]
+                    ImportDesc::Exception(ty) => signatures.tags.push(ty.func_type),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(funcs) = module.find_std_section::<payload::Function>() {
+            signatures.funcs.extend(funcs.try_contents()?);
+        }
+        #[cfg(feature = "exception-handling")]
+        if let Some(tags) = module.find_std_section::<payload::Exception>() {
+            signatures
+                .tags
+                .extend(tags.try_contents()?.iter().map(|tag| tag.ty));
+        }
+        Ok(signatures)
+    }
+
+    pub(crate) fn ty(&self, ty: TypeId) -> Option<&FuncType> {
+        self.types.get(ty.index as usize)?.as_ref()
+    }
+
+    pub(crate) fn func(&self, func: FuncId) -> Option<&FuncType> {
+        self.ty(*self.funcs.get(func.index as usize)?)
+    }
+
+    /// Number of functions, both imported and defined.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn func_count(&self) -> u32 {
+        self.funcs.len() as u32
+    }
+
+    #[cfg(any(feature = "legacy-exceptions", feature = "stack-switching"))]
+    fn tag(&self, tag: ExceptionId) -> Option<&FuncType> {
+        self.ty(*self.tags.get(tag.index as usize)?)
+    }
+
+    #[cfg(feature = "stack-switching")]
+    fn cont(&self, ty: TypeId) -> Option<&FuncType> {
+        self.ty((*self.conts.get(ty.index as usize)?)?)
+    }
+
+    fn block(&self, ty: &BlockType) -> Option<(u32, u32)> {
+        match ty {
+            BlockType::Empty => Some((0, 0)),
+            BlockType::Value(_) => Some((0, 1)),
+            BlockType::MultiValue(ty) => self.ty(*ty).map(arity),
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn arity(ty: &FuncType) -> (u32, u32) {
+    (ty.params.len() as u32, ty.results.len() as u32)
+}
+
+/// Operand stack effect of an instruction.
+pub(crate) enum Effect {
+    /// Pops and pushes the given number of values.
+    Values(u32, u32),
+    /// Starts a block with the given number of parameters and results.
+    Block(u32, u32),
+    /// Starts the `else` branch of an `if` block.
+    Else,
+    /// Starts a legacy `catch` clause pushing the given number of values.
+    #[cfg(feature = "legacy-exceptions")]
+    Catch(u32),
+    /// Ends a block.
+    End,
+    /// Transfers control unconditionally, making the rest of the block unreachable.
+    Unreachable,
+}
+
+#[allow(clippy::too_many_lines)]
+fn simd_effect(simd: &SIMD) -> Effect {
+    match simd {
+        SIMD::V128Const(_) => Effect::Values(0, 1),
+        SIMD::V128Load(_)
+        | SIMD::V128Load8x8S(_)
+        | SIMD::V128Load8x8U(_)
+        | SIMD::V128Load16x4S(_)
+        | SIMD::V128Load16x4U(_)
+        | SIMD::V128Load32x2S(_)
+        | SIMD::V128Load32x2U(_)
+        | SIMD::V128Load8Splat(_)
+        | SIMD::V128Load16Splat(_)
+        | SIMD::V128Load32Splat(_)
+        | SIMD::V128Load64Splat(_)
+        | SIMD::V128Load32Zero(_)
+        | SIMD::V128Load64Zero(_)
+        | SIMD::I8x16Splat
+        | SIMD::I16x8Splat
+        | SIMD::I32x4Splat
+        | SIMD::I64x2Splat
+        | SIMD::F32x4Splat
+        | SIMD::F64x2Splat
+        | SIMD::I8x16ExtractLaneS(_)
+        | SIMD::I8x16ExtractLaneU(_)
+        | SIMD::I16x8ExtractLaneS(_)
+        | SIMD::I16x8ExtractLaneU(_)
+        | SIMD::I32x4ExtractLane(_)
+        | SIMD::I64x2ExtractLane(_)
+        | SIMD::F32x4ExtractLane(_)
+        | SIMD::F64x2ExtractLane(_)
+        | SIMD::V128Not
+        | SIMD::V128AnyTrue
+        | SIMD::I8x16Abs
+        | SIMD::I8x16Neg
+        | SIMD::I8x16Popcnt
+        | SIMD::I8x16AllTrue
+        | SIMD::I8x16Bitmask
+        | SIMD::I16x8Abs
+        | SIMD::I16x8Neg
+        | SIMD::I16x8AllTrue
+        | SIMD::I16x8Bitmask
+        | SIMD::I16x8ExtendLowI8x16S
+        | SIMD::I16x8ExtendHighI8x16S
+        | SIMD::I16x8ExtendLowI8x16U
+        | SIMD::I16x8ExtendHighI8x16U
+        | SIMD::I16x8ExtaddPairwiseI8x16S
+        | SIMD::I16x8ExtaddPairwiseI8x16U
+        | SIMD::I32x4Abs
+        | SIMD::I32x4Neg
+        | SIMD::I32x4AllTrue
+        | SIMD::I32x4Bitmask
+        | SIMD::I32x4ExtendLowI16x8S
+        | SIMD::I32x4ExtendHighI16x8S
+        | SIMD::I32x4ExtendLowI16x8U
+        | SIMD::I32x4ExtendHighI16x8U
+        | SIMD::I32x4ExtaddPairwiseI16x8S
+        | SIMD::I32x4ExtaddPairwiseI16x8U
+        | SIMD::I64x2Abs
+        | SIMD::I64x2Neg
+        | SIMD::I64x2AllTrue
+        | SIMD::I64x2Bitmask
+        | SIMD::I64x2ExtendLowI32x4S
+        | SIMD::I64x2ExtendHighI32x4S
+        | SIMD::I64x2ExtendLowI32x4U
+        | SIMD::I64x2ExtendHighI32x4U
+        | SIMD::F32x4Ceil
+        | SIMD::F32x4Floor
+        | SIMD::F32x4Trunc
+        | SIMD::F32x4Nearest
+        | SIMD::F32x4Abs
+        | SIMD::F32x4Neg
+        | SIMD::F32x4Sqrt
+        | SIMD::F64x2Ceil
+        | SIMD::F64x2Floor
+        | SIMD::F64x2Trunc
+        | SIMD::F64x2Nearest
+        | SIMD::F64x2Abs
+        | SIMD::F64x2Neg
+        | SIMD::F64x2Sqrt
+        | SIMD::I32x4TruncSatF32x4S
+        | SIMD::I32x4TruncSatF32x4U
+        | SIMD::F32x4ConvertI32x4S
+        | SIMD::F32x4ConvertI32x4U
+        | SIMD::I32x4TruncSatF64x2SZero
+        | SIMD::I32x4TruncSatF64x2UZero
+        | SIMD::F64x2ConvertLowI32x4S
+        | SIMD::F64x2ConvertLowI32x4U
+        | SIMD::F32x4DemoteF64x2Zero
+        | SIMD::F64x2PromoteLowF32x4 => Effect::Values(1, 1),
+        #[cfg(feature = "fp16")]
+        SIMD::F16x8Splat
+        | SIMD::F16x8ExtractLane(_)
+        | SIMD::F16x8Abs
+        | SIMD::F16x8Neg
+        | SIMD::F16x8Sqrt
+        | SIMD::F16x8Ceil
+        | SIMD::F16x8Floor
+        | SIMD::F16x8Trunc
+        | SIMD::F16x8Nearest
+        | SIMD::I16x8TruncSatF16x8S
+        | SIMD::I16x8TruncSatF16x8U
+        | SIMD::F16x8ConvertI16x8S
+        | SIMD::F16x8ConvertI16x8U
+        | SIMD::F16x8DemoteF32x4Zero
+        | SIMD::F32x4PromoteLowF16x8 => Effect::Values(1, 1),
+        SIMD::V128Store(_)
+        | SIMD::V128Store8Lane(..)
+        | SIMD::V128Store16Lane(..)
+        | SIMD::V128Store32Lane(..)
+        | SIMD::V128Store64Lane(..) => Effect::Values(2, 0),
+        SIMD::V128Bitselect => Effect::Values(3, 1),
+        #[cfg(feature = "fp16")]
+        SIMD::F16x8RelaxedMadd | SIMD::F16x8RelaxedNmadd => Effect::Values(3, 1),
+        SIMD::I8x16Shuffle(..)
+        | SIMD::I8x16Swizzle
+        | SIMD::I8x16ReplaceLane(..)
+        | SIMD::I16x8ReplaceLane(..)
+        | SIMD::I32x4ReplaceLane(..)
+        | SIMD::I64x2ReplaceLane(..)
+        | SIMD::F32x4ReplaceLane(..)
+        | SIMD::F64x2ReplaceLane(..)
+        | SIMD::I8x16Eq
+        | SIMD::I8x16Ne
+        | SIMD::I8x16LtS
+        | SIMD::I8x16LtU
+        | SIMD::I8x16GtS
+        | SIMD::I8x16GtU
+        | SIMD::I8x16LeS
+        | SIMD::I8x16LeU
+        | SIMD::I8x16GeS
+        | SIMD::I8x16GeU
+        | SIMD::I16x8Eq
+        | SIMD::I16x8Ne
+        | SIMD::I16x8LtS
+        | SIMD::I16x8LtU
+        | SIMD::I16x8GtS
+        | SIMD::I16x8GtU
+        | SIMD::I16x8LeS
+        | SIMD::I16x8LeU
+        | SIMD::I16x8GeS
+        | SIMD::I16x8GeU
+        | SIMD::I32x4Eq
+        | SIMD::I32x4Ne
+        | SIMD::I32x4LtS
+        | SIMD::I32x4LtU
+        | SIMD::I32x4GtS
+        | SIMD::I32x4GtU
+        | SIMD::I32x4LeS
+        | SIMD::I32x4LeU
+        | SIMD::I32x4GeS
+        | SIMD::I32x4GeU
+        | SIMD::F32x4Eq
+        | SIMD::F32x4Ne
+        | SIMD::F32x4Lt
+        | SIMD::F32x4Gt
+        | SIMD::F32x4Le
+        | SIMD::F32x4Ge
+        | SIMD::F64x2Eq
+        | SIMD::F64x2Ne
+        | SIMD::F64x2Lt
+        | SIMD::F64x2Gt
+        | SIMD::F64x2Le
+        | SIMD::F64x2Ge
+        | SIMD::V128And
+        | SIMD::V128Andnot
+        | SIMD::V128Or
+        | SIMD::V128Xor
+        | SIMD::I8x16NarrowI16x8S
+        | SIMD::I8x16NarrowI16x8U
+        | SIMD::I8x16Shl
+        | SIMD::I8x16ShrS
+        | SIMD::I8x16ShrU
+        | SIMD::I8x16Add
+        | SIMD::I8x16AddSatS
+        | SIMD::I8x16AddSatU
+        | SIMD::I8x16Sub
+        | SIMD::I8x16SubSatS
+        | SIMD::I8x16SubSatU
+        | SIMD::I8x16MinS
+        | SIMD::I8x16MinU
+        | SIMD::I8x16MaxS
+        | SIMD::I8x16MaxU
+        | SIMD::I8x16AvgrU
+        | SIMD::I16x8NarrowI32x4S
+        | SIMD::I16x8NarrowI32x4U
+        | SIMD::I16x8Shl
+        | SIMD::I16x8ShrS
+        | SIMD::I16x8ShrU
+        | SIMD::I16x8Add
+        | SIMD::I16x8AddSatS
+        | SIMD::I16x8AddSatU
+        | SIMD::I16x8Sub
+        | SIMD::I16x8SubSatS
+        | SIMD::I16x8SubSatU
+        | SIMD::I16x8Mul
+        | SIMD::I16x8MinS
+        | SIMD::I16x8MinU
+        | SIMD::I16x8MaxS
+        | SIMD::I16x8MaxU
+        | SIMD::I16x8AvgrU
+        | SIMD::I32x4Shl
+        | SIMD::I32x4ShrS
+        | SIMD::I32x4ShrU
+        | SIMD::I32x4Add
+        | SIMD::I32x4Sub
+        | SIMD::I32x4Mul
+        | SIMD::I32x4MinS
+        | SIMD::I32x4MinU
+        | SIMD::I32x4MaxS
+        | SIMD::I32x4MaxU
+        | SIMD::I32x4DotI16x8S
+        | SIMD::I64x2Shl
+        | SIMD::I64x2ShrS
+        | SIMD::I64x2ShrU
+        | SIMD::I64x2Add
+        | SIMD::I64x2Sub
+        | SIMD::I64x2Mul
+        | SIMD::F32x4Add
+        | SIMD::F32x4Sub
+        | SIMD::F32x4Mul
+        | SIMD::F32x4Div
+        | SIMD::F32x4Min
+        | SIMD::F32x4Max
+        | SIMD::F32x4Pmin
+        | SIMD::F32x4Pmax
+        | SIMD::F64x2Add
+        | SIMD::F64x2Sub
+        | SIMD::F64x2Mul
+        | SIMD::F64x2Div
+        | SIMD::F64x2Min
+        | SIMD::F64x2Max
+        | SIMD::F64x2Pmin
+        | SIMD::F64x2Pmax
+        | SIMD::I16x8ExtmulLowI8x16S
+        | SIMD::I16x8ExtmulHighI8x16S
+        | SIMD::I16x8ExtmulLowI8x16U
+        | SIMD::I16x8ExtmulHighI8x16U
+        | SIMD::I32x4ExtmulLowI16x8S
+        | SIMD::I32x4ExtmulHighI16x8S
+        | SIMD::I32x4ExtmulLowI16x8U
+        | SIMD::I32x4ExtmulHighI16x8U
+        | SIMD::I64x2ExtmulLowI32x4S
+        | SIMD::I64x2ExtmulHighI32x4S
+        | SIMD::I64x2ExtmulLowI32x4U
+        | SIMD::I64x2ExtmulHighI32x4U
+        | SIMD::I16x8Q15mulrSatS
+        | SIMD::V128Load8Lane(..)
+        | SIMD::V128Load16Lane(..)
+        | SIMD::V128Load32Lane(..)
+        | SIMD::V128Load64Lane(..)
+        | SIMD::I64x2Eq
+        | SIMD::I64x2Ne
+        | SIMD::I64x2LtS
+        | SIMD::I64x2GtS
+        | SIMD::I64x2LeS
+        | SIMD::I64x2GeS => Effect::Values(2, 1),
+        #[cfg(feature = "fp16")]
+        SIMD::F16x8ReplaceLane(..)
+        | SIMD::F16x8Eq
+        | SIMD::F16x8Ne
+        | SIMD::F16x8Lt
+        | SIMD::F16x8Gt
+        | SIMD::F16x8Le
+        | SIMD::F16x8Ge
+        | SIMD::F16x8Add
+        | SIMD::F16x8Sub
+        | SIMD::F16x8Mul
+        | SIMD::F16x8Div
+        | SIMD::F16x8Min
+        | SIMD::F16x8Max
+        | SIMD::F16x8Pmin
+        | SIMD::F16x8Pmax => Effect::Values(2, 1),
+    }
+}
+
+fn misc_effect(misc: &Misc) -> Effect {
+    match misc {
+        Misc::I32TruncSatF32S
+        | Misc::I32TruncSatF32U
+        | Misc::I32TruncSatF64S
+        | Misc::I32TruncSatF64U
+        | Misc::I64TruncSatF32S
+        | Misc::I64TruncSatF32U
+        | Misc::I64TruncSatF64S
+        | Misc::I64TruncSatF64U => Effect::Values(1, 1),
+        Misc::MemoryInit { .. }
+        | Misc::MemoryCopy { .. }
+        | Misc::MemoryFill(_)
+        | Misc::TableInit { .. }
+        | Misc::TableCopy { .. }
+        | Misc::TableFill(_) => Effect::Values(3, 0),
+        Misc::DataDrop(_) | Misc::ElemDrop(_) => Effect::Values(0, 0),
+        Misc::TableGrow(_) => Effect::Values(2, 1),
+        Misc::TableSize(_) => Effect::Values(0, 1),
+        #[cfg(feature = "wide-arithmetic")]
+        Misc::I64Add128 | Misc::I64Sub128 => Effect::Values(4, 2),
+        #[cfg(feature = "wide-arithmetic")]
+        Misc::I64MulWideS | Misc::I64MulWideU => Effect::Values(2, 2),
+        #[cfg(feature = "fp16")]
+        Misc::F32LoadF16(_) => Effect::Values(1, 1),
+        #[cfg(feature = "fp16")]
+        Misc::F32StoreF16(_) => Effect::Values(2, 0),
+    }
+}
+
+#[cfg(feature = "threads")]
+fn atomic_effect(atomic: &Atomic) -> Effect {
+    match atomic {
+        Atomic::Fence(_) => Effect::Values(0, 0),
+        Atomic::GlobalGet { .. } => Effect::Values(0, 1),
+        Atomic::GlobalSet { .. } => Effect::Values(1, 0),
+        Atomic::I32Load(_)
+        | Atomic::I64Load(_)
+        | Atomic::I32Load8U(_)
+        | Atomic::I32Load16U(_)
+        | Atomic::I64Load8U(_)
+        | Atomic::I64Load16U(_)
+        | Atomic::I64Load32U(_)
+        | Atomic::GlobalRmwAdd { .. }
+        | Atomic::GlobalRmwSub { .. }
+        | Atomic::GlobalRmwAnd { .. }
+        | Atomic::GlobalRmwOr { .. }
+        | Atomic::GlobalRmwXor { .. }
+        | Atomic::GlobalRmwXchg { .. }
+        | Atomic::TableGet { .. } => Effect::Values(1, 1),
+        Atomic::I32Store(_)
+        | Atomic::I64Store(_)
+        | Atomic::I32Store8(_)
+        | Atomic::I32Store16(_)
+        | Atomic::I64Store8(_)
+        | Atomic::I64Store16(_)
+        | Atomic::I64Store32(_)
+        | Atomic::TableSet { .. } => Effect::Values(2, 0),
+        Atomic::I32Wait(_)
+        | Atomic::I64Wait(_)
+        | Atomic::I32RmwCmpXchg(_)
+        | Atomic::I64RmwCmpXchg(_)
+        | Atomic::I32Rmw8CmpXchgU(_)
+        | Atomic::I32Rmw16CmpXchgU(_)
+        | Atomic::I64Rmw8CmpXchgU(_)
+        | Atomic::I64Rmw16CmpXchgU(_)
+        | Atomic::I64Rmw32CmpXchgU(_)
+        | Atomic::TableRmwCmpXchg { .. } => Effect::Values(3, 1),
+        Atomic::Wake(..)
+        | Atomic::I32RmwAdd(..)
+        | Atomic::I64RmwAdd(..)
+        | Atomic::I32Rmw8AddU(..)
+        | Atomic::I32Rmw16AddU(..)
+        | Atomic::I64Rmw8AddU(..)
+        | Atomic::I64Rmw16AddU(..)
+        | Atomic::I64Rmw32AddU(..)
+        | Atomic::I32RmwSub(..)
+        | Atomic::I64RmwSub(..)
+        | Atomic::I32Rmw8SubU(..)
+        | Atomic::I32Rmw16SubU(..)
+        | Atomic::I64Rmw8SubU(..)
+        | Atomic::I64Rmw16SubU(..)
+        | Atomic::I64Rmw32SubU(..)
+        | Atomic::I32RmwAnd(..)
+        | Atomic::I64RmwAnd(..)
+        | Atomic::I32Rmw8AndU(..)
+        | Atomic::I32Rmw16AndU(..)
+        | Atomic::I64Rmw8AndU(..)
+        | Atomic::I64Rmw16AndU(..)
+        | Atomic::I64Rmw32AndU(..)
+        | Atomic::I32RmwOr(..)
+        | Atomic::I64RmwOr(..)
+        | Atomic::I32Rmw8OrU(..)
+        | Atomic::I32Rmw16OrU(..)
+        | Atomic::I64Rmw8OrU(..)
+        | Atomic::I64Rmw16OrU(..)
+        | Atomic::I64Rmw32OrU(..)
+        | Atomic::I32RmwXor(..)
+        | Atomic::I64RmwXor(..)
+        | Atomic::I32Rmw8XorU(..)
+        | Atomic::I32Rmw16XorU(..)
+        | Atomic::I64Rmw8XorU(..)
+        | Atomic::I64Rmw16XorU(..)
+        | Atomic::I64Rmw32XorU(..)
+        | Atomic::I32RmwXchg(..)
+        | Atomic::I64RmwXchg(..)
+        | Atomic::I32Rmw8XchgU(..)
+        | Atomic::I32Rmw16XchgU(..)
+        | Atomic::I64Rmw8XchgU(..)
+        | Atomic::I64Rmw16XchgU(..)
+        | Atomic::I64Rmw32XchgU(..)
+        | Atomic::GlobalRmwCmpXchg { .. }
+        | Atomic::TableRmwXchg { .. } => Effect::Values(2, 1),
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+pub(crate) fn effect(
+    signatures: &Signatures,
+    instruction: &Instruction,
+) -> Result<Effect, AnalysisError> {
+    let call = |ty: Option<&FuncType>, extra| {
+        let (params, results) = arity(ty.ok_or(AnalysisError::InvalidReference)?);
+        Ok(Effect::Values(params + extra, results))
+    };
+    Ok(match instruction {
+        Instruction::Nop => Effect::Values(0, 0),
+        Instruction::Unreachable | Instruction::Br(_) | Instruction::BrTable { .. } => {
+            Effect::Unreachable
+        }
+        Instruction::Return | Instruction::ReturnCall(_) | Instruction::ReturnCallIndirect(_) => {
+            Effect::Unreachable
+        }
+        Instruction::BlockStart(ty) | Instruction::LoopStart(ty) => {
+            let (params, results) = signatures
+                .block(ty)
+                .ok_or(AnalysisError::InvalidReference)?;
+            Effect::Block(params, results)
+        }
+        Instruction::IfStart(ty) => {
+            let (params, results) = signatures
+                .block(ty)
+                .ok_or(AnalysisError::InvalidReference)?;
+            // Condition is popped before the block starts.
+            Effect::Block(params + 1, results)
+        }
+        Instruction::IfElse => Effect::Else,
+        Instruction::End => Effect::End,
+        Instruction::BrIf(_) => Effect::Values(1, 0),
+        Instruction::Call(func) => call(signatures.func(*func), 0)?,
+        Instruction::CallIndirect(call_indirect) => call(signatures.ty(call_indirect.ty), 1)?,
+        Instruction::Drop | Instruction::LocalSet(_) | Instruction::GlobalSet(_) => {
+            Effect::Values(1, 0)
+        }
+        Instruction::Select | Instruction::SelectWithTypes(_) => Effect::Values(3, 1),
+        Instruction::LocalGet(_)
+        | Instruction::GlobalGet(_)
+        | Instruction::MemorySize(_)
+        | Instruction::I32Const(_)
+        | Instruction::I64Const(_)
+        | Instruction::F32Const(_)
+        | Instruction::F64Const(_)
+        | Instruction::RefNull(_)
+        | Instruction::RefFunc(_) => Effect::Values(0, 1),
+        Instruction::LocalTee(_)
+        | Instruction::TableGet(_)
+        | Instruction::MemoryGrow(_)
+        | Instruction::RefIsNull
+        | Instruction::I32Load(_)
+        | Instruction::I64Load(_)
+        | Instruction::F32Load(_)
+        | Instruction::F64Load(_)
+        | Instruction::I32Load8S(_)
+        | Instruction::I32Load8U(_)
+        | Instruction::I32Load16S(_)
+        | Instruction::I32Load16U(_)
+        | Instruction::I64Load8S(_)
+        | Instruction::I64Load8U(_)
+        | Instruction::I64Load16S(_)
+        | Instruction::I64Load16U(_)
+        | Instruction::I64Load32S(_)
+        | Instruction::I64Load32U(_)
+        | Instruction::I32Eqz
+        | Instruction::I64Eqz
+        | Instruction::I32Clz
+        | Instruction::I32Ctz
+        | Instruction::I32PopCnt
+        | Instruction::I64Clz
+        | Instruction::I64Ctz
+        | Instruction::I64PopCnt
+        | Instruction::F32Abs
+        | Instruction::F32Neg
+        | Instruction::F32Ceil
+        | Instruction::F32Floor
+        | Instruction::F32Trunc
+        | Instruction::F32Nearest
+        | Instruction::F32Sqrt
+        | Instruction::F64Abs
+        | Instruction::F64Neg
+        | Instruction::F64Ceil
+        | Instruction::F64Floor
+        | Instruction::F64Trunc
+        | Instruction::F64Nearest
+        | Instruction::F64Sqrt
+        | Instruction::I32WrapI64
+        | Instruction::I32TruncF32S
+        | Instruction::I32TruncF332U
+        | Instruction::I32TruncF64S
+        | Instruction::I32TruncF64U
+        | Instruction::I64ExtendI32S
+        | Instruction::I64ExtendI32U
+        | Instruction::I64TruncF32S
+        | Instruction::I64TruncF32U
+        | Instruction::I64TruncF64S
+        | Instruction::I64TruncF64U
+        | Instruction::F32ConvertI32S
+        | Instruction::F32ConvertI32U
+        | Instruction::F32ConvertI64S
+        | Instruction::F32ConvertI64U
+        | Instruction::F32DemoteF64
+        | Instruction::F64ConvertI32S
+        | Instruction::F64ConvertI32U
+        | Instruction::F64ConvertI64S
+        | Instruction::F64ConvertI64U
+        | Instruction::F64PromoteF32
+        | Instruction::I32ReinterpretF32
+        | Instruction::I64ReinterpretF64
+        | Instruction::F32ReinterpretI32
+        | Instruction::F64ReinterpretI64
+        | Instruction::I32Extend8S
+        | Instruction::I32Extend16S
+        | Instruction::I64Extend8S
+        | Instruction::I64Extend16S
+        | Instruction::I64Extend32S => Effect::Values(1, 1),
+        Instruction::TableSet(_)
+        | Instruction::I32Store(_)
+        | Instruction::I64Store(_)
+        | Instruction::F32Store(_)
+        | Instruction::F64Store(_)
+        | Instruction::I32Store8(_)
+        | Instruction::I32Store16(_)
+        | Instruction::I64Store8(_)
+        | Instruction::I64Store16(_)
+        | Instruction::I64Store32(_) => Effect::Values(2, 0),
+        Instruction::Misc(misc) => misc_effect(misc),
+        Instruction::SIMD(simd) => simd_effect(simd),
+        #[cfg(feature = "threads")]
+        Instruction::Atomic(atomic) => atomic_effect(atomic),
+        #[cfg(feature = "exception-handling")]
+        Instruction::Throw(_) | Instruction::ThrowRef => Effect::Unreachable,
+        #[cfg(feature = "exception-handling")]
+        Instruction::TryTable(try_table) => {
+            let (params, results) = signatures
+                .block(&try_table.block_type)
+                .ok_or(AnalysisError::InvalidReference)?;
+            Effect::Block(params, results)
+        }
+        #[cfg(feature = "legacy-exceptions")]
+        Instruction::TryStart(ty) => {
+            let (params, results) = signatures
+                .block(ty)
+                .ok_or(AnalysisError::InvalidReference)?;
+            Effect::Block(params, results)
+        }
+        #[cfg(feature = "legacy-exceptions")]
+        Instruction::TryCatch(tag) => Effect::Catch(
+            arity(
+                signatures
+                    .tag(*tag)
+                    .ok_or(AnalysisError::InvalidReference)?,
+            )
+            .0,
+        ),
+        #[cfg(feature = "legacy-exceptions")]
+        Instruction::TryCatchAll => Effect::Catch(0),
+        #[cfg(feature = "legacy-exceptions")]
+        Instruction::TryDelegate(_) => Effect::End,
+        #[cfg(feature = "legacy-exceptions")]
+        Instruction::Rethrow(_) => Effect::Unreachable,
+        #[cfg(feature = "stack-switching")]
+        Instruction::ContNew(_) => Effect::Values(1, 1),
+        #[cfg(feature = "stack-switching")]
+        Instruction::ContBind { from, to } => {
+            let from = arity(
+                signatures
+                    .cont(*from)
+                    .ok_or(AnalysisError::InvalidReference)?,
+            )
+            .0;
+            let to = arity(
+                signatures
+                    .cont(*to)
+                    .ok_or(AnalysisError::InvalidReference)?,
+            )
+            .0;
+            Effect::Values(from.saturating_sub(to) + 1, 1)
+        }
+        #[cfg(feature = "stack-switching")]
+        Instruction::Suspend(tag) => call(signatures.tag(*tag), 0)?,
+        #[cfg(feature = "stack-switching")]
+        Instruction::Resume(resume) => call(signatures.cont(resume.cont_type), 1)?,
+        #[cfg(feature = "stack-switching")]
+        Instruction::ResumeThrow(resume) => {
+            let params = arity(
+                signatures
+                    .tag(resume.exception)
+                    .ok_or(AnalysisError::InvalidReference)?,
+            )
+            .0;
+            let results = arity(
+                signatures
+                    .cont(resume.cont_type)
+                    .ok_or(AnalysisError::InvalidReference)?,
+            )
+            .1;
+            Effect::Values(params + 1, results)
+        }
+        // Results of `switch` depend on the type of the continuation it switches to, which
+        // can't be expressed without typed references.
+        #[cfg(feature = "stack-switching")]
+        Instruction::Switch { .. } => return Err(AnalysisError::Unsupported),
+        Instruction::I32Eq
+        | Instruction::I32Ne
+        | Instruction::I32LtS
+        | Instruction::I32LtU
+        | Instruction::I32GtS
+        | Instruction::I32GtU
+        | Instruction::I32LeS
+        | Instruction::I32LeU
+        | Instruction::I32GeS
+        | Instruction::I32GeU
+        | Instruction::I64Eq
+        | Instruction::I64Ne
+        | Instruction::I64LtS
+        | Instruction::I64LtU
+        | Instruction::I64GtS
+        | Instruction::I64GtU
+        | Instruction::I64LeS
+        | Instruction::I64LeU
+        | Instruction::I64GeS
+        | Instruction::I64GeU
+        | Instruction::F32Eq
+        | Instruction::F32Ne
+        | Instruction::F32Lt
+        | Instruction::F32Gt
+        | Instruction::F32Le
+        | Instruction::F32Ge
+        | Instruction::F64Eq
+        | Instruction::F64Ne
+        | Instruction::F64Lt
+        | Instruction::F64Gt
+        | Instruction::F64Le
+        | Instruction::F64Ge
+        | Instruction::I32Add
+        | Instruction::I32Sub
+        | Instruction::I32Mul
+        | Instruction::I32DivS
+        | Instruction::I32DivU
+        | Instruction::I32RemS
+        | Instruction::I32RemU
+        | Instruction::I32And
+        | Instruction::I32Or
+        | Instruction::I32Xor
+        | Instruction::I32Shl
+        | Instruction::I32ShrS
+        | Instruction::I32ShrU
+        | Instruction::I32RotL
+        | Instruction::I32RotR
+        | Instruction::I64Add
+        | Instruction::I64Sub
+        | Instruction::I64Mul
+        | Instruction::I64DivS
+        | Instruction::I64DivU
+        | Instruction::I64RemS
+        | Instruction::I64RemU
+        | Instruction::I64And
+        | Instruction::I64Or
+        | Instruction::I64Xor
+        | Instruction::I64Shl
+        | Instruction::I64ShrS
+        | Instruction::I64ShrU
+        | Instruction::I64RotL
+        | Instruction::I64RotR
+        | Instruction::F32Add
+        | Instruction::F32Sub
+        | Instruction::F32Mul
+        | Instruction::F32Div
+        | Instruction::F32Min
+        | Instruction::F32Max
+        | Instruction::F32CopySign
+        | Instruction::F64Add
+        | Instruction::F64Sub
+        | Instruction::F64Mul
+        | Instruction::F64Div
+        | Instruction::F64Min
+        | Instruction::F64Max
+        | Instruction::F64CopySign => Effect::Values(2, 1),
+    })
+}
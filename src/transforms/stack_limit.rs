@@ -0,0 +1,268 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limiting of the call stack depth by accounting the frame size of each function in a global
+//! counter, similar to stack limiters used by smart contract platforms.
+//!
+//! ```no_run
+//! use wasmbin::sections::{Export, ExportDesc};
+//! use wasmbin::transforms::stack_limit::limit_stack;
+//! use wasmbin::Module;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut module = Module::decode_from(std::fs::File::open("contract.wasm")?)?;
+//! let height = limit_stack(&mut module, 64 * 1024)?;
+//! module.add_export(Export {
+//!     name: "stack_height".to_owned(),
+//!     desc: ExportDesc::Global(height),
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::indices::{FuncId, GlobalId};
+use crate::instructions::{Expression, Instruction};
+use crate::io::DecodeError;
+use crate::sections::{payload, FuncBody};
+use crate::transforms::instrument::{
+    add_global, instrument, FuncContext, InstrumentError, Instrumentation,
+};
+use crate::transforms::stack_effect::{arity, effect, AnalysisError, Effect, Signatures};
+use crate::types::{BlockType, GlobalType, ValueType};
+use crate::Module;
+use thiserror::Error;
+
+/// Error returned by [`frame_sizes`] and [`limit_stack`].
+#[derive(Debug, Error)]
+pub enum StackLimitError {
+    /// Decoding error occured while reading a section.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+
+    /// Instrumentation of function bodies failed.
+    #[error(transparent)]
+    Instrument(#[from] InstrumentError),
+
+    /// Function refers to a type, function or tag that doesn't exist.
+    #[error("Function {0:?} refers to an invalid type, function or tag")]
+    InvalidReference(FuncId),
+
+    /// Function contains an instruction whose stack effect can't be determined.
+    #[cfg(feature = "stack-switching")]
+    #[error("Function {0:?} contains an unsupported instruction")]
+    Unsupported(FuncId),
+}
+
+/// Control frame of the stack height analysis.
+struct Frame {
+    /// Stack height below the block parameters.
+    base: u32,
+    params: u32,
+    results: u32,
+}
+
+struct StackHeight<'a> {
+    signatures: &'a Signatures,
+    frames: Vec<Frame>,
+    height: u32,
+    max: u32,
+}
+
+impl StackHeight<'_> {
+    fn pop(&mut self, count: u32) {
+        // Code following an unconditional branch may pop values that were never pushed.
+        let base = self.frames.last().map_or(0, |frame| frame.base);
+        self.height = self.height.saturating_sub(count).max(base);
+    }
+
+    fn push(&mut self, count: u32) {
+        self.height += count;
+        self.max = self.max.max(self.height);
+    }
+
+    fn enter(&mut self, params: u32, results: u32) {
+        self.pop(params);
+        self.frames.push(Frame {
+            base: self.height,
+            params,
+            results,
+        });
+        self.push(params);
+    }
+
+    fn exit(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            self.height = frame.base;
+            self.push(frame.results);
+        }
+    }
+
+    fn reset(&mut self, count: u32) {
+        self.height = self.frames.last().map_or(0, |frame| frame.base);
+        self.push(count);
+    }
+
+    fn analyze(&mut self, instructions: &[Instruction]) -> Result<(), AnalysisError> {
+        for instruction in instructions {
+            match effect(self.signatures, instruction)? {
+                Effect::Values(pops, pushes) => {
+                    self.pop(pops);
+                    self.push(pushes);
+                }
+                Effect::Block(params, results) => {
+                    if let Instruction::IfStart(_) = instruction {
+                        self.pop(1);
+                        self.enter(params - 1, results);
+                    } else {
+                        self.enter(params, results);
+                    }
+                    #[cfg(feature = "exception-handling")]
+                    if let Instruction::TryTable(try_table) = instruction {
+                        self.analyze(&try_table.instructions)?;
+                        self.exit();
+                    }
+                }
+                Effect::Else => {
+                    let params = self.frames.last().map_or(0, |frame| frame.params);
+                    self.reset(params);
+                }
+                #[cfg(feature = "legacy-exceptions")]
+                Effect::Catch(values) => self.reset(values),
+                Effect::End => self.exit(),
+                Effect::Unreachable => self.reset(0),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of stack slots used by a call to each function defined by the module: one for the
+/// frame itself, one for each parameter and local, and the maximum height of the operand stack.
+pub fn frame_sizes(module: &Module) -> Result<Vec<u32>, StackLimitError> {
+    let signatures = Signatures::new(module)?;
+    let Some(code) = module.find_std_section::<payload::Code>() else {
+        return Ok(Vec::new());
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    let imported = signatures.func_count()
+        - module
+            .find_std_section::<payload::Function>()
+            .map_or(Ok(0), |funcs| funcs.try_contents().map(Vec::len))? as u32;
+    let mut sizes = Vec::new();
+    for (index, body) in (imported..).zip(code.try_contents()?) {
+        let func = FuncId::from(index);
+        let FuncBody { locals, expr } = body.try_contents()?;
+        let ty = signatures
+            .func(func)
+            .ok_or(StackLimitError::InvalidReference(func))?;
+        let mut height = StackHeight {
+            signatures: &signatures,
+            frames: Vec::new(),
+            height: 0,
+            max: 0,
+        };
+        height.analyze(expr).map_err(|err| match err {
+            AnalysisError::InvalidReference => StackLimitError::InvalidReference(func),
+            #[cfg(feature = "stack-switching")]
+            AnalysisError::Unsupported => StackLimitError::Unsupported(func),
+        })?;
+        let (params, _) = arity(ty);
+        let size = locals
+            .iter()
+            .fold(params.saturating_add(1), |size, locals| {
+                size.saturating_add(locals.repeat)
+            })
+            .saturating_add(height.max);
+        sizes.push(size);
+    }
+    Ok(sizes)
+}
+
+struct StackLimiter {
+    sizes: Vec<u32>,
+    first_defined: u32,
+    limit: u32,
+    height: GlobalId,
+}
+
+impl StackLimiter {
+    fn size(&self, func: FuncId) -> u32 {
+        self.sizes
+            .get((func.index - self.first_defined) as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Instrumentation for StackLimiter {
+    fn prepare(&mut self, module: &mut Module) -> Result<(), InstrumentError> {
+        let ty = GlobalType {
+            value_type: ValueType::I32,
+            mutable: true,
+            #[cfg(feature = "threads")]
+            is_shared: false,
+        };
+        self.height = add_global(module, ty, vec![Instruction::I32Const(0)])?;
+        Ok(())
+    }
+
+    fn enter(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+        let size = self.size(cx.func);
+        let Some(headroom) = self.limit.checked_sub(size) else {
+            out.push(Instruction::Unreachable);
+            return;
+        };
+        out.extend([
+            Instruction::GlobalGet(self.height),
+            Instruction::I32Const(headroom.cast_signed()),
+            Instruction::I32GtU,
+            Instruction::IfStart(BlockType::Empty),
+            Instruction::Unreachable,
+            Instruction::End,
+            Instruction::GlobalGet(self.height),
+            Instruction::I32Const(size.cast_signed()),
+            Instruction::I32Add,
+            Instruction::GlobalSet(self.height),
+        ]);
+    }
+
+    fn exit(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+        out.extend([
+            Instruction::GlobalGet(self.height),
+            Instruction::I32Const(self.size(cx.func).cast_signed()),
+            Instruction::I32Sub,
+            Instruction::GlobalSet(self.height),
+        ]);
+    }
+}
+
+/// Trap on entry to any function defined by the module whose [frame size](frame_sizes) would
+/// take the total size of active frames above `limit`.
+///
+/// The total is kept in a new mutable `i32` global, which is returned. Frames that are
+/// abandoned by a trap or by an exception unwinding through them stay accounted, so hosts that
+/// keep using an instance after that should reset the global to zero.
+pub fn limit_stack(module: &mut Module, limit: u32) -> Result<GlobalId, StackLimitError> {
+    let sizes = frame_sizes(module)?;
+    #[allow(clippy::cast_possible_truncation)]
+    let first_defined = Signatures::new(module)?.func_count() - sizes.len() as u32;
+    let mut limiter = StackLimiter {
+        sizes,
+        first_defined,
+        limit,
+        height: GlobalId::from(0),
+    };
+    instrument(module, &mut limiter)?;
+    Ok(limiter.height)
+}
@@ -121,6 +121,45 @@ impl Debug for FuncType {
     }
 }
 
+/// [Continuation type](https://github.com/WebAssembly/stack-switching/blob/main/proposals/stack-switching/Explainer.md#binary-format).
+#[cfg(feature = "stack-switching")]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[wasmbin(discriminant = 0x5D)]
+pub struct ContType {
+    pub func_type: TypeId,
+}
+
+/// A type section entry.
+///
+/// Entries other than [function types](FuncType) are only present with the
+/// corresponding proposal features enabled. Prior to 0.9 the type section held [`FuncType`]s
+/// directly; use [`TypeDef::as_func`] to access them.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum TypeDef {
+    Func(FuncType),
+    #[cfg(feature = "stack-switching")]
+    Cont(ContType),
+}
+
+impl TypeDef {
+    /// Returns the function type if this entry is one.
+    pub fn as_func(&self) -> Option<&FuncType> {
+        match self {
+            TypeDef::Func(ty) => Some(ty),
+            #[cfg(feature = "stack-switching")]
+            TypeDef::Cont(_) => None,
+        }
+    }
+}
+
+impl From<FuncType> for TypeDef {
+    fn from(ty: FuncType) -> Self {
+        TypeDef::Func(ty)
+    }
+}
+
 /// [Limits](https://webassembly.github.io/spec/core/binary/types.html#limits) type.
 #[derive(PartialEq, Eq, Hash, Clone, Visit)]
 pub struct Limits {
@@ -249,6 +288,17 @@ pub struct MemType {
     pub limits: Limits,
 }
 
+impl MemType {
+    /// Size of a memory page in bytes.
+    pub fn page_bytes(&self) -> u64 {
+        #[cfg(feature = "custom-page-sizes")]
+        if let Some(page_size) = self.page_size {
+            return page_size.size();
+        }
+        1 << 16
+    }
+}
+
 #[cfg(all(feature = "threads", not(feature = "custom-page-sizes")))]
 encode_decode_as!(MemType, {
     (MemType { is_shared: false, limits: Limits { min, max: None } }) <=> (MemTypeRepr::Unshared(LimitsRepr::Min { min })),
@@ -293,20 +343,133 @@ pub enum RefType {
     Extern = 0x6F,
     #[cfg(feature = "exception-handling")]
     Exception = 0x69,
+    #[cfg(feature = "stack-switching")]
+    Cont = 0x68,
+    /// [Shared](https://github.com/WebAssembly/shared-everything-threads/blob/main/proposals/shared-everything-threads/Overview.md#shared-annotations)
+    /// variant of an abstract reference type.
+    #[cfg(feature = "threads")]
+    Shared(SharedRefType) = 0x65,
+}
+
+/// Abstract reference types that can be marked as [shared](RefType::Shared).
+#[cfg(feature = "threads")]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[repr(u8)]
+pub enum SharedRefType {
+    Func = 0x70,
+    Extern = 0x6F,
+    #[cfg(feature = "exception-handling")]
+    Exception = 0x69,
+    #[cfg(feature = "stack-switching")]
+    Cont = 0x68,
 }
 
 /// [Table type](https://webassembly.github.io/spec/core/binary/types.html#table-types).
-#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(not(feature = "threads"), derive(Wasmbin))]
+#[derive(WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
 pub struct TableType {
     pub elem_type: RefType,
+    #[cfg(feature = "threads")]
+    pub is_shared: bool,
     pub limits: Limits,
 }
 
+#[cfg(feature = "threads")]
+#[derive(Wasmbin)]
+#[repr(u8)]
+enum TableLimitsRepr {
+    Unshared(LimitsRepr),
+    SharedMin { min: u32 } = 0x02,
+    SharedMinMax { min: u32, max: u32 } = 0x03,
+}
+
+#[cfg(feature = "threads")]
+struct TableLimits {
+    is_shared: bool,
+    limits: Limits,
+}
+
+#[cfg(feature = "threads")]
+encode_decode_as!(TableLimits, {
+    (TableLimits { is_shared: false, limits: Limits { min, max: None } }) <=> (TableLimitsRepr::Unshared(LimitsRepr::Min { min })),
+    (TableLimits { is_shared: false, limits: Limits { min, max: Some(max) } }) <=> (TableLimitsRepr::Unshared(LimitsRepr::MinMax { min, max })),
+    (TableLimits { is_shared: true, limits: Limits { min, max: None } }) <=> (TableLimitsRepr::SharedMin { min }),
+    (TableLimits { is_shared: true, limits: Limits { min, max: Some(max) } }) <=> (TableLimitsRepr::SharedMinMax { min, max }),
+});
+
+#[cfg(feature = "threads")]
+impl Encode for TableType {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.elem_type.encode(w)?;
+        TableLimits {
+            is_shared: self.is_shared,
+            limits: self.limits.clone(),
+        }
+        .encode(w)
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Decode for TableType {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let elem_type =
+            RefType::decode(r).map_err(|err| err.in_path(PathItem::Name("elem_type")))?;
+        let TableLimits { is_shared, limits } =
+            TableLimits::decode(r).map_err(|err| err.in_path(PathItem::Name("limits")))?;
+        Ok(TableType {
+            elem_type,
+            is_shared,
+            limits,
+        })
+    }
+}
+
 /// [Global type](https://webassembly.github.io/spec/core/binary/types.html#global-types).
-#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(not(feature = "threads"), derive(Wasmbin))]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
 pub struct GlobalType {
     pub value_type: ValueType,
     pub mutable: bool,
+    #[cfg(feature = "threads")]
+    pub is_shared: bool,
+}
+
+#[cfg(feature = "threads")]
+const GLOBAL_MUTABLE_FLAG: u8 = 0b01;
+
+#[cfg(feature = "threads")]
+const GLOBAL_SHARED_FLAG: u8 = 0b10;
+
+#[cfg(feature = "threads")]
+impl Encode for GlobalType {
+    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.value_type.encode(w)?;
+        let mut flags = 0;
+        if self.mutable {
+            flags |= GLOBAL_MUTABLE_FLAG;
+        }
+        if self.is_shared {
+            flags |= GLOBAL_SHARED_FLAG;
+        }
+        flags.encode(w)
+    }
+}
+
+#[cfg(feature = "threads")]
+impl Decode for GlobalType {
+    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+        let value_type =
+            ValueType::decode(r).map_err(|err| err.in_path(PathItem::Name("value_type")))?;
+        let flags = u8::decode(r)?;
+        if flags & !(GLOBAL_MUTABLE_FLAG | GLOBAL_SHARED_FLAG) != 0 {
+            return Err(DecodeError::unsupported_discriminant::<Self>(flags));
+        }
+        Ok(GlobalType {
+            value_type,
+            mutable: flags & GLOBAL_MUTABLE_FLAG != 0,
+            is_shared: flags & GLOBAL_SHARED_FLAG != 0,
+        })
+    }
 }
 
 /// [Exception tag type](https://webassembly.github.io/exception-handling/core/binary/types.html#tag-types).
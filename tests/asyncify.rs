@@ -0,0 +1,342 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "interp")]
+
+mod common;
+
+use anyhow::Result;
+use common::{validate, wat};
+use wasmbin::interp::{Host, Instance, Store, Trap, Value};
+use wasmbin::sections::{payload, ImportPath};
+use wasmbin::transforms::asyncify::{asyncify, Asyncify, AsyncifyError};
+use wasmbin::transforms::interface::InterfaceError;
+use wasmbin::types::FuncType;
+use wasmbin::Module;
+
+const MODULE: &str = r#"(module
+    (import "env" "sleep" (func $sleep (param i32) (result i32)))
+    (import "env" "other" (func $other (param i32) (result i32)))
+    (memory (export "memory") 1)
+    (type $ii (func (param i32) (result i32)))
+    (table 2 funcref)
+    (elem (i32.const 0) $twice $plain)
+    (global (mut i32) (i32.const 0))
+    (func $plain (param i32) (result i32)
+        (i32.mul (local.get 0) (i32.const 7)))
+    (func $twice (export "twice") (param i32) (result i32)
+        (i32.add
+            (call $sleep (local.get 0))
+            (call $sleep (i32.add (local.get 0) (i32.const 1)))))
+    (func $fib (export "fib") (param i32) (result i32)
+        (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+            (then (call $sleep (local.get 0)))
+            (else
+                (i32.add
+                    (call $fib (i32.sub (local.get 0) (i32.const 1)))
+                    (call $fib (i32.sub (local.get 0) (i32.const 2)))))))
+    (func $mix (export "mix") (param $n i32) (result i64)
+        (local $acc i64) (local $f f64) (local $v v128) (local $i i32)
+        (local.set $v (i32x4.splat (local.get $n)))
+        (local.set $f (f64.const 1.5))
+        (block
+            (loop
+                (br_if 1 (i32.ge_u (local.get $i) (local.get $n)))
+                local.get $acc
+                (i32x4.extract_lane 1 (local.get $v))
+                local.get $i
+                block (param i32) (result i32)
+                    call $sleep
+                end
+                i32.add
+                i64.extend_i32_u
+                i64.add
+                local.set $acc
+                (local.set $f
+                    (f64.add
+                        (local.get $f)
+                        (f64.convert_i32_s
+                            (if (result i32) (i32.and (local.get $i) (i32.const 1))
+                                (then (call $sleep (i32.const 100)))
+                                (else
+                                    (call_indirect (type $ii)
+                                        (local.get $i)
+                                        (i32.and
+                                            (i32.shr_u (local.get $i) (i32.const 1))
+                                            (i32.const 1))))))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br 0)))
+        (i64.add (local.get $acc) (i64.trunc_f64_s (local.get $f)))
+        (call $sel (local.get $n))
+        i64.extend_i32_u
+        i64.add)
+    (func $sel (export "sel") (param i32) (result i32)
+        (block (result i32)
+            (block (result i32)
+                (i32.const 5)
+                (drop
+                    (br_if 1
+                        (call $sleep (i32.const 2))
+                        (i32.eq (local.get 0) (i32.const 7))))
+                (call $sleep (i32.const 9))
+                (i32.rem_u (local.get 0) (i32.const 3))
+                (br_table 1 0 1)
+                (i32.const 1)
+                (drop))
+            (i32.const 1000)
+            i32.add))
+    (func $mv (export "mv") (param i32) (result i32) (local $x i64) (local $y i32)
+        (local.get 0)
+        (i64.const 5)
+        (block (param i32 i64) (result i64 i32)
+            local.set $x
+            call $sleep
+            local.set $y
+            (if (i32.eq (local.get $y) (i32.const 10))
+                (then (return (i32.const -1))))
+            local.get $x
+            local.get $y)
+        local.set $y
+        i32.wrap_i64
+        local.get $y
+        i32.add
+        (global.set 0 (call $other (i32.const 1)))
+        (i32.add (global.get 0)))
+    (func (export "run") (param i32) (result i64)
+        (i64.add
+            (call $mix (local.get 0))
+            (i64.extend_i32_u
+                (i32.add
+                    (call $fib (i32.const 5))
+                    (i32.add (call $mv (local.get 0)) (call $mv (i32.const 3))))))))"#;
+
+/// Address of the pair of `i32` fields describing the buffer frames are saved to.
+const DATA: i32 = 16;
+/// Start and end of the buffer.
+const BUFFER: [u8; 8] = [32, 0, 0, 0, 0, 16, 0, 0];
+
+const STATE_NORMAL: i32 = 0;
+const STATE_UNWINDING: i32 = 1;
+const STATE_REWINDING: i32 = 2;
+
+fn sleep() -> ImportPath {
+    ImportPath {
+        module: "env".to_owned(),
+        name: "sleep".to_owned(),
+    }
+}
+
+/// Host whose `env.sleep` returns `3 * x + 1`, and `env.other` returns `x + 40`.
+///
+/// When asynchronous, every call to `env.sleep` first unwinds and returns the result once the
+/// call stack is rewound.
+struct Sleeper {
+    /// State and data globals added by [`asyncify`], if it was applied.
+    globals: Option<(usize, usize)>,
+    pending: Option<i32>,
+    /// Arguments of `env.sleep` calls.
+    log: Vec<i32>,
+    unwinds: u32,
+}
+
+impl Host for Sleeper {
+    fn call(
+        &mut self,
+        import: &ImportPath,
+        _ty: &FuncType,
+        args: &[Value],
+        store: &mut Store,
+    ) -> Result<Vec<Value>, Trap> {
+        let [Value::I32(arg)] = *args else {
+            return Err(Trap::Host(format!("unexpected arguments {args:?}")));
+        };
+        if import.name == "other" {
+            return Ok(vec![Value::I32(arg + 40)]);
+        }
+        let Some((state, data)) = self.globals else {
+            self.log.push(arg);
+            return Ok(vec![Value::I32(arg * 3 + 1)]);
+        };
+        if store.globals[state] == Value::I32(STATE_REWINDING) {
+            // Equivalent of calling `asyncify_stop_rewind`.
+            store.globals[state] = Value::I32(STATE_NORMAL);
+            let result = self
+                .pending
+                .take()
+                .ok_or(Trap::Host("nothing pending".into()))?;
+            return Ok(vec![Value::I32(result)]);
+        }
+        // Equivalent of calling `asyncify_start_unwind`.
+        self.log.push(arg);
+        self.pending = Some(arg * 3 + 1);
+        self.unwinds += 1;
+        store.globals[state] = Value::I32(STATE_UNWINDING);
+        store.globals[data] = Value::I32(DATA);
+        Ok(vec![Value::I32(0)])
+    }
+}
+
+/// Result of calling an export, with the arguments `env.sleep` was called with and the number
+/// of times the call stack was unwound.
+type Outcome = (Vec<Value>, Vec<i32>, u32);
+
+fn run(module: &Module, globals: Option<(usize, usize)>, name: &str, arg: i32) -> Result<Outcome> {
+    let mut instance = Instance::instantiate(
+        module,
+        Sleeper {
+            globals,
+            pending: None,
+            log: Vec::new(),
+            unwinds: 0,
+        },
+    )?;
+    instance.store.memories[0].write(DATA as u64, &BUFFER)?;
+    loop {
+        let results = instance.invoke(name, &[Value::I32(arg)])?;
+        let Some((state, _)) = globals else {
+            return Ok((results, instance.host.log, 0));
+        };
+        if instance.store.globals[state] != Value::I32(STATE_UNWINDING) {
+            // All saved frames were popped again.
+            assert_eq!(
+                instance.store.memories[0].read(DATA as u64, 4)?,
+                &BUFFER[..4]
+            );
+            assert_eq!(
+                instance.invoke("asyncify_get_state", &[])?,
+                [Value::I32(STATE_NORMAL)]
+            );
+            return Ok((results, instance.host.log, instance.host.unwinds));
+        }
+        instance.invoke("asyncify_stop_unwind", &[])?;
+        assert_ne!(
+            instance.store.memories[0].read(DATA as u64, 4)?,
+            &BUFFER[..4]
+        );
+        instance.invoke("asyncify_start_rewind", &[Value::I32(DATA)])?;
+    }
+}
+
+/// Check that each export behaves the same when every `env.sleep` call unwinds and rewinds the
+/// call stack as when it returns directly.
+fn assert_same_results(cases: &[(&str, &[i32])]) -> Result<()> {
+    let original = wat(MODULE)?;
+    let mut module = original.clone();
+    asyncify(
+        &mut module,
+        &Asyncify {
+            imports: vec![sleep()],
+            ignore_indirect_calls: false,
+        },
+    )?;
+    validate(&module)?;
+    // State and data are the last two globals.
+    let globals = module
+        .find_std_section::<payload::Global>()
+        .unwrap()
+        .try_contents()?
+        .len();
+    for &(name, args) in cases {
+        for &arg in args {
+            let (expected, expected_log, _) = run(&original, None, name, arg)?;
+            let (results, log, unwinds) =
+                run(&module, Some((globals - 2, globals - 1)), name, arg)?;
+            assert_eq!(results, expected, "{name}({arg})");
+            assert_eq!(log, expected_log, "{name}({arg})");
+            assert_eq!(unwinds as usize, log.len(), "{name}({arg})");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn operand_stack_is_saved_in_locals() -> Result<()> {
+    assert_same_results(&[("twice", &[4]), ("mv", &[3, 2]), ("mix", &[0, 1, 4])])
+}
+
+#[test]
+fn branches_resume_the_interrupted_call() -> Result<()> {
+    // Code skipped while rewinding is wrapped in an `if`, shifting the labels of its branches.
+    assert_same_results(&[("sel", &[0, 1, 2, 7])])
+}
+
+#[test]
+fn nested_frames_are_restored_in_reverse_order() -> Result<()> {
+    assert_same_results(&[("fib", &[0, 1, 6]), ("run", &[3])])
+}
+
+#[test]
+fn only_functions_that_can_unwind_are_instrumented() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let funcs = asyncify(
+        &mut module,
+        &Asyncify {
+            imports: vec![sleep()],
+            ignore_indirect_calls: false,
+        },
+    )?;
+    // `plain` doesn't call anything, while all the others reach `env.sleep`.
+    assert_eq!(
+        funcs.iter().map(|func| func.index).collect::<Vec<_>>(),
+        [3, 4, 5, 6, 7, 8]
+    );
+    Ok(())
+}
+
+#[test]
+fn duplicate_export_leaves_module_untouched() -> Result<()> {
+    let mut module = wat(r#"(module
+        (import "env" "sleep" (func $sleep (param i32) (result i32)))
+        (memory 1)
+        (func (export "asyncify_get_state") (result i32)
+            (call $sleep (i32.const 0))))"#)?;
+    let original = module.clone();
+    let err = asyncify(
+        &mut module,
+        &Asyncify {
+            imports: vec![sleep()],
+            ignore_indirect_calls: false,
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        AsyncifyError::Interface(InterfaceError::DuplicateExport(name)) if name == "asyncify_get_state"
+    ));
+    assert_eq!(module, original);
+    Ok(())
+}
+
+#[test]
+fn unsupported_function_leaves_module_untouched() -> Result<()> {
+    let mut module = wat(r#"(module
+        (import "env" "sleep" (func $sleep (param i32) (result i32)))
+        (memory 1)
+        (func (result i32)
+            (call $sleep (i32.const 0)))
+        (func (result i32) (local funcref)
+            (call $sleep (i32.const 0))))"#)?;
+    let original = module.clone();
+    let err = asyncify(
+        &mut module,
+        &Asyncify {
+            imports: vec![sleep()],
+            ignore_indirect_calls: false,
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(err, AsyncifyError::Unsupported(func) if func.index == 2));
+    assert_eq!(module, original);
+    Ok(())
+}
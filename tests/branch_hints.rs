@@ -0,0 +1,205 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::{validate, wat};
+use std::collections::BTreeMap;
+use wasmbin::indices::FuncId;
+use wasmbin::instructions::Instruction;
+use wasmbin::sections::{payload, BranchHintValue};
+use wasmbin::transforms::branch_hints::{
+    read_branch_hints, write_branch_hints, BranchHintError, IndexedBranchHints,
+};
+use wasmbin::Module;
+
+fn hints(func: u32, indexed: &[(usize, BranchHintValue)]) -> IndexedBranchHints {
+    let mut res = IndexedBranchHints::new();
+    res.insert(
+        FuncId { index: func },
+        indexed.iter().copied().collect::<BTreeMap<_, _>>(),
+    );
+    res
+}
+
+/// Insert `nop` at `index` into the body of the last function.
+fn insert_nop(module: &mut Module, index: usize) -> Result<()> {
+    let code = module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?;
+    let body = code.last_mut().unwrap().try_contents_mut()?;
+    body.expr.insert(index, Instruction::Nop);
+    Ok(())
+}
+
+/// Re-encode the module and check that it matches the text format with the same hints.
+fn assert_encodes_as(module: &Module, expected: &str) -> Result<()> {
+    validate(module)?;
+    assert_eq!(
+        module.encode_into(Vec::new())?,
+        wat(expected)?.encode_into(Vec::new())?
+    );
+    Ok(())
+}
+
+#[test]
+fn hints_follow_edited_instructions() -> Result<()> {
+    let mut module = wat(r#"(module
+        (func (param i32))
+        (func (param i32)
+            block
+                local.get 0
+                (@metadata.code.branch_hint "\01")
+                br_if 0
+                local.get 0
+                (@metadata.code.branch_hint "\00")
+                if
+                end
+            end))"#)?;
+    let read = read_branch_hints(&module)?;
+    assert_eq!(
+        read,
+        hints(
+            1,
+            &[(2, BranchHintValue::Likely), (4, BranchHintValue::Unlikely)]
+        )
+    );
+
+    insert_nop(&mut module, 3)?;
+    write_branch_hints(
+        &mut module,
+        &hints(
+            1,
+            &[(2, BranchHintValue::Likely), (5, BranchHintValue::Unlikely)],
+        ),
+    )?;
+    assert_encodes_as(
+        &module,
+        r#"(module
+            (func (param i32))
+            (func (param i32)
+                block
+                    local.get 0
+                    (@metadata.code.branch_hint "\01")
+                    br_if 0
+                    nop
+                    local.get 0
+                    (@metadata.code.branch_hint "\00")
+                    if
+                    end
+                end))"#,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn empty_hints_remove_section() -> Result<()> {
+    let mut module = wat(r#"(module
+        (func (param i32)
+            local.get 0
+            (@metadata.code.branch_hint "\01")
+            if
+            end))"#)?;
+    write_branch_hints(&mut module, &IndexedBranchHints::new())?;
+    assert_eq!(read_branch_hints(&module)?, IndexedBranchHints::new());
+    assert_encodes_as(
+        &module,
+        r#"(module
+            (func (param i32)
+                local.get 0
+                if
+                end))"#,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn invalid_hints_are_rejected() -> Result<()> {
+    let mut module = wat(r#"(module
+        (import "env" "f" (func))
+        (func (param i32)
+            local.get 0
+            br_if 0))"#)?;
+    let err =
+        write_branch_hints(&mut module, &hints(1, &[(0, BranchHintValue::Likely)])).unwrap_err();
+    assert!(matches!(
+        err,
+        BranchHintError::NotABranch { func, index: 0 } if func.index == 1
+    ));
+    let err =
+        write_branch_hints(&mut module, &hints(0, &[(1, BranchHintValue::Likely)])).unwrap_err();
+    assert!(matches!(err, BranchHintError::UnknownFunc(func) if func.index == 0));
+    Ok(())
+}
+
+#[cfg(feature = "exception-handling")]
+#[test]
+fn nested_hints_are_indexed_depth_first() -> Result<()> {
+    let mut module = wat(r#"(module
+        (func (param i32)
+            block
+                try_table (catch_all 0)
+                    local.get 0
+                    (@metadata.code.branch_hint "\00")
+                    br_if 0
+                end
+                local.get 0
+                (@metadata.code.branch_hint "\01")
+                br_if 0
+            end))"#)?;
+    // `block`, `try_table` and its two nested instructions come before the outer `local.get`.
+    let read = read_branch_hints(&module)?;
+    assert_eq!(
+        read,
+        hints(
+            0,
+            &[(3, BranchHintValue::Unlikely), (5, BranchHintValue::Likely)]
+        )
+    );
+
+    let code = module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?;
+    let body = code[0].try_contents_mut()?;
+    let Instruction::TryTable(try_table) = &mut body.expr[1] else {
+        panic!("expected try_table, got {:?}", body.expr[1]);
+    };
+    try_table.instructions.insert(0, Instruction::Nop);
+    let edited = hints(
+        0,
+        &[(4, BranchHintValue::Unlikely), (6, BranchHintValue::Likely)],
+    );
+    write_branch_hints(&mut module, &edited)?;
+    assert_encodes_as(
+        &module,
+        r#"(module
+            (func (param i32)
+                block
+                    try_table (catch_all 0)
+                        nop
+                        local.get 0
+                        (@metadata.code.branch_hint "\00")
+                        br_if 0
+                    end
+                    local.get 0
+                    (@metadata.code.branch_hint "\01")
+                    br_if 0
+                end))"#,
+    )?;
+    assert_eq!(read_branch_hints(&module)?, edited);
+    Ok(())
+}
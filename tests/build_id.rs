@@ -0,0 +1,113 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "build-id")]
+
+mod common;
+
+use anyhow::Result;
+use common::wat;
+use wasmbin::builtins::{Blob, UnparsedBytes};
+use wasmbin::instructions::Instruction;
+use wasmbin::sections::{payload, CustomSection, RawCustomSection, Section};
+use wasmbin::transforms::build_id::{
+    compute_build_id, read_build_id, set_build_id, verify_build_id, BuildIdError, BUILD_ID_SIZE,
+};
+use wasmbin::Module;
+
+const MODULE: &str = r#"(module
+    (memory 1)
+    (func (export "f") (result i32)
+        i32.const 42))"#;
+
+fn custom(name: &str, bytes: &[u8]) -> Section {
+    Section::Custom(Blob::from(CustomSection::Other(RawCustomSection {
+        name: name.to_owned(),
+        data: UnparsedBytes {
+            bytes: bytes.to_vec(),
+        },
+    })))
+}
+
+#[test]
+fn computed_id_is_verified() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    assert!(read_build_id(&module).is_none());
+    assert!(matches!(
+        verify_build_id(&module),
+        Err(BuildIdError::Missing)
+    ));
+
+    let build_id = set_build_id(&mut module)?;
+    assert_eq!(build_id.len(), BUILD_ID_SIZE);
+    assert_eq!(read_build_id(&module), Some(build_id.as_slice()));
+    assert!(matches!(module.sections[0], Section::Custom(_)));
+    verify_build_id(&module)?;
+
+    // The id survives encoding and is the same for an identical module.
+    let decoded = Module::decode_from(module.encode_into(Vec::new())?.as_slice())?;
+    verify_build_id(&decoded)?;
+    assert_eq!(compute_build_id(&wat(MODULE)?)?, build_id);
+
+    // Setting it again replaces the existing section.
+    assert_eq!(set_build_id(&mut module)?, build_id);
+    let sections = module.sections.len();
+    set_build_id(&mut module)?;
+    assert_eq!(module.sections.len(), sections);
+    Ok(())
+}
+
+#[test]
+fn changed_code_fails_verification() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let build_id = set_build_id(&mut module)?;
+    let code = module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?;
+    code[0].try_contents_mut()?.expr[0] = Instruction::I32Const(43);
+    match verify_build_id(&module) {
+        Err(BuildIdError::Mismatch { expected, actual }) => {
+            assert_eq!(actual, build_id);
+            assert_ne!(expected, build_id);
+        }
+        res => panic!("expected a mismatch, got {res:?}"),
+    }
+    Ok(())
+}
+
+#[test]
+fn changed_build_id_section_fails_verification() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let mut build_id = set_build_id(&mut module)?;
+    build_id[0] ^= 1;
+    module.sections[0] = Section::Custom(Blob::from(CustomSection::BuildId(build_id.clone())));
+    match verify_build_id(&module) {
+        Err(BuildIdError::Mismatch { actual, .. }) => assert_eq!(actual, build_id),
+        res => panic!("expected a mismatch, got {res:?}"),
+    }
+    Ok(())
+}
+
+#[test]
+fn other_custom_sections_are_ignored() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    set_build_id(&mut module)?;
+    // Debug info can be stripped or added separately without changing the id.
+    module.sections.push(custom(".debug_info", &[1, 2, 3]));
+    verify_build_id(&module)?;
+    module.sections.pop();
+    verify_build_id(&module)?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the transform tests.
+
+// Each test binary only uses some of the helpers.
+#![allow(dead_code)]
+
+use anyhow::Result;
+use wasmbin::sections::{payload, FuncBody};
+use wasmbin::Module;
+use wasmparser::{Validator, WasmFeatures};
+use wast::parser::{parse, ParseBuffer};
+use wast::Wat;
+
+/// Encode a module from its text format.
+pub fn wat_binary(src: &str) -> Result<Vec<u8>> {
+    let buf = ParseBuffer::new(src)?;
+    Ok(parse::<Wat>(&buf)?.encode()?)
+}
+
+/// Parse a module from its text format.
+pub fn wat(src: &str) -> Result<Module> {
+    Ok(Module::decode_from(wat_binary(src)?.as_slice())?)
+}
+
+/// Encode a module and check that it passes validation with all proposals
+/// except legacy exception handling enabled.
+pub fn validate(module: &Module) -> Result<()> {
+    let binary = module.encode_into(Vec::new())?;
+    Validator::new_with_features(WasmFeatures::all() - WasmFeatures::LEGACY_EXCEPTIONS)
+        .validate_all(&binary)?;
+    Ok(())
+}
+
+/// Decoded bodies of all functions defined by a module.
+pub fn bodies(module: &Module) -> Result<Vec<FuncBody>> {
+    let Some(code) = module.find_std_section::<payload::Code>() else {
+        return Ok(Vec::new());
+    };
+    Ok(code
+        .try_contents()?
+        .iter()
+        .map(|body| body.try_contents().cloned())
+        .collect::<Result<_, _>>()?)
+}
@@ -0,0 +1,420 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "component-model")]
+
+mod common;
+
+use anyhow::Result;
+use common::wat_binary;
+use wasmbin::component::sections::{
+    Alias, AliasTarget, Canon, CanonLift, CanonLower, CanonOpt, ComponentExport, ComponentImport,
+    ComponentInstance, ComponentSection, CoreInlineExport, CoreInstance, CoreInstantiateArg,
+    CoreSort, CoreSortIdx, ExternDesc, ExternName, InstantiateArg, Sort, SortIdx, TypeBound,
+};
+use wasmbin::component::types::{
+    ComponentDecl, ComponentFuncType, ComponentType, CoreExportDecl, CoreModuleDecl, CoreType,
+    DefinedType, ExportDecl, InstanceDecl, LabelValType, PrimitiveValType, ResultList, ValType,
+    VariantCase,
+};
+use wasmbin::component::Component;
+use wasmbin::indices::{
+    ComponentFuncId, ComponentId, ComponentInstanceId, ComponentTypeId, CoreInstanceId,
+    CoreModuleId, FuncId, MemId, TypeId,
+};
+use wasmbin::sections::{payload, ExportDesc, Import, ImportDesc, ImportPath};
+use wasmbin::types::{FuncType, ValueType};
+use wasmbin::visit::{Visit, VisitError};
+
+/// Decode a component from its text format, including all nested and lazily decoded parts, and
+/// check that it encodes back to the same bytes.
+fn round_trip(src: &str) -> Result<Component> {
+    let binary = wat_binary(src)?;
+    let mut component = Component::decode_from(binary.as_slice())?;
+    component.visit_mut(|()| {}).map_err(|err| match err {
+        VisitError::LazyDecode(err) => err,
+        VisitError::Custom(err) => match err {},
+    })?;
+    assert_eq!(component.encode_into(Vec::new())?, binary);
+    Ok(component)
+}
+
+/// Contents of all sections of the given kind, concatenated in order.
+macro_rules! contents {
+    ($component:expr, $kind:ident) => {
+        $component
+            .sections
+            .iter()
+            .filter_map(|section| match section {
+                ComponentSection::$kind(blob) => Some(blob.try_contents().unwrap().clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>()
+    };
+}
+
+fn name(name: &str) -> ExternName {
+    ExternName::Name(name.to_owned())
+}
+
+fn primitive(ty: PrimitiveValType) -> ValType {
+    ValType::Primitive(ty)
+}
+
+#[test]
+fn core_modules_and_instances() -> Result<()> {
+    let component = round_trip(
+        r#"(component
+            (core module $libc
+                (memory (export "memory") 1)
+                (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                    i32.const 0))
+            (core module $main
+                (import "libc" "memory" (memory 1))
+                (func (export "run") (result i32)
+                    i32.const 7))
+            (core instance $libc (instantiate $libc))
+            (core instance $shim (export "memory" (memory $libc "memory")))
+            (core instance $main (instantiate $main (with "libc" (instance $shim)))))"#,
+    )?;
+    let modules: Vec<_> = component
+        .sections
+        .iter()
+        .filter_map(|section| match section {
+            ComponentSection::CoreModule(module) => Some(module.try_contents().unwrap()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(modules.len(), 2);
+    let exports = modules[0]
+        .find_std_section::<payload::Export>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(exports[0].name, "memory");
+    assert_eq!(exports[0].desc, ExportDesc::Mem(MemId::from(0)));
+    let imports = modules[1]
+        .find_std_section::<payload::Import>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(imports[0].path.module, "libc");
+
+    assert_eq!(
+        contents!(component, CoreInstance),
+        [
+            CoreInstance::Instantiate {
+                module: CoreModuleId::from(0),
+                args: vec![],
+            },
+            CoreInstance::FromExports(vec![CoreInlineExport {
+                name: "memory".to_owned(),
+                item: CoreSortIdx::Memory(MemId::from(0)),
+            }]),
+            CoreInstance::Instantiate {
+                module: CoreModuleId::from(1),
+                args: vec![CoreInstantiateArg {
+                    name: "libc".to_owned(),
+                    item: CoreSortIdx::Instance(CoreInstanceId::from(1)),
+                }],
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn core_and_component_types() -> Result<()> {
+    let component = round_trip(
+        r#"(component
+            (core type (func (param i32) (result i64)))
+            (core type (module
+                (import "env" "f" (func (param i32)))
+                (export "g" (func))))
+            (type $point (record (field "x" s32) (field "y" s32)))
+            (type (variant (case "circle" u32) (case "none")))
+            (type (list $point))
+            (type (tuple u8 string))
+            (type (flags "read" "write"))
+            (type (enum "a" "b"))
+            (type (option char))
+            (type (result u32 (error string)))
+            (type $res (resource (rep i32)))
+            (type (own $res))
+            (type (borrow $res))
+            (type (func (param "p" $point) (result bool)))
+            (type (instance
+                (type $t (func))
+                (export "run" (func (type $t)))))
+            (type (component
+                (import "in" (type (sub resource)))
+                (export "out" (type (eq 0))))))"#,
+    )?;
+    assert_eq!(
+        contents!(component, CoreType),
+        [
+            CoreType::Func(FuncType {
+                params: vec![ValueType::I32],
+                results: vec![ValueType::I64],
+            }),
+            CoreType::Module(vec![
+                CoreModuleDecl::Type(FuncType {
+                    params: vec![ValueType::I32],
+                    results: vec![],
+                }),
+                CoreModuleDecl::Import(Import {
+                    path: ImportPath {
+                        module: "env".to_owned(),
+                        name: "f".to_owned(),
+                    },
+                    desc: ImportDesc::Func(TypeId::from(0)),
+                }),
+                CoreModuleDecl::Type(FuncType {
+                    params: vec![],
+                    results: vec![],
+                }),
+                CoreModuleDecl::Export(CoreExportDecl {
+                    name: "g".to_owned(),
+                    desc: ImportDesc::Func(TypeId::from(1)),
+                }),
+            ]),
+        ]
+    );
+
+    let point = ValType::Type(ComponentTypeId::from(0));
+    let res = ComponentTypeId::from(8);
+    assert_eq!(
+        contents!(component, Type),
+        [
+            ComponentType::Defined(DefinedType::Record(vec![
+                LabelValType {
+                    name: "x".to_owned(),
+                    ty: primitive(PrimitiveValType::S32),
+                },
+                LabelValType {
+                    name: "y".to_owned(),
+                    ty: primitive(PrimitiveValType::S32),
+                },
+            ])),
+            ComponentType::Defined(DefinedType::Variant(vec![
+                VariantCase {
+                    name: "circle".to_owned(),
+                    ty: Some(primitive(PrimitiveValType::U32)),
+                    refines: None,
+                },
+                VariantCase {
+                    name: "none".to_owned(),
+                    ty: None,
+                    refines: None,
+                },
+            ])),
+            ComponentType::Defined(DefinedType::List(point)),
+            ComponentType::Defined(DefinedType::Tuple(vec![
+                primitive(PrimitiveValType::U8),
+                primitive(PrimitiveValType::String),
+            ])),
+            ComponentType::Defined(DefinedType::Flags(vec![
+                "read".to_owned(),
+                "write".to_owned(),
+            ])),
+            ComponentType::Defined(DefinedType::Enum(vec!["a".to_owned(), "b".to_owned()])),
+            ComponentType::Defined(DefinedType::Option(primitive(PrimitiveValType::Char))),
+            ComponentType::Defined(DefinedType::Result {
+                ok: Some(primitive(PrimitiveValType::U32)),
+                err: Some(primitive(PrimitiveValType::String)),
+            }),
+            ComponentType::Resource {
+                rep: ValueType::I32,
+                dtor: None,
+            },
+            ComponentType::Defined(DefinedType::Own(res)),
+            ComponentType::Defined(DefinedType::Borrow(res)),
+            ComponentType::Func(ComponentFuncType {
+                params: vec![LabelValType {
+                    name: "p".to_owned(),
+                    ty: point,
+                }],
+                results: ResultList::Unnamed(primitive(PrimitiveValType::Bool)),
+            }),
+            ComponentType::Instance(vec![
+                InstanceDecl::Type(ComponentType::Func(ComponentFuncType {
+                    params: vec![],
+                    results: ResultList::Named(vec![]),
+                })),
+                InstanceDecl::Export(ExportDecl {
+                    name: name("run"),
+                    desc: ExternDesc::Func(ComponentTypeId::from(0)),
+                }),
+            ]),
+            ComponentType::Component(vec![
+                ComponentDecl::Import(ComponentImport {
+                    name: name("in"),
+                    desc: ExternDesc::Type(TypeBound::SubResource),
+                }),
+                ComponentDecl::Instance(InstanceDecl::Export(ExportDecl {
+                    name: name("out"),
+                    desc: ExternDesc::Type(TypeBound::Eq(ComponentTypeId::from(0))),
+                })),
+            ]),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn canon_lift_lower_and_aliases() -> Result<()> {
+    let component = round_trip(
+        r#"(component
+            (import "log" (func $log (param "msg" string)))
+            (core module $m
+                (memory (export "memory") 1)
+                (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                    i32.const 0)
+                (func (export "run") (result i32)
+                    i32.const 7))
+            (core instance $i (instantiate $m))
+            (core func (canon lower (func $log)
+                (memory $i "memory")
+                (realloc (func $i "realloc"))
+                string-encoding=utf16))
+            (func (result u32) (canon lift (core func $i "run")))
+            (component $inner
+                (alias outer 1 0 (type $outer_log)))
+            (instance $inst (export "log" (func $log)))
+            (alias export $inst "log" (func)))"#,
+    )?;
+    assert_eq!(
+        contents!(component, Canon),
+        [
+            Canon::Lower(CanonLower {
+                func: ComponentFuncId::from(0),
+                opts: vec![
+                    CanonOpt::Memory(MemId::from(0)),
+                    CanonOpt::Realloc(FuncId::from(0)),
+                    CanonOpt::Utf16,
+                ],
+            }),
+            Canon::Lift(CanonLift {
+                func: FuncId::from(2),
+                opts: vec![],
+                ty: ComponentTypeId::from(1),
+            }),
+        ]
+    );
+    assert_eq!(
+        contents!(component, Alias),
+        [
+            Alias {
+                sort: Sort::Core(CoreSort::Memory),
+                target: AliasTarget::CoreExport {
+                    instance: CoreInstanceId::from(0),
+                    name: "memory".to_owned(),
+                },
+            },
+            Alias {
+                sort: Sort::Core(CoreSort::Func),
+                target: AliasTarget::CoreExport {
+                    instance: CoreInstanceId::from(0),
+                    name: "realloc".to_owned(),
+                },
+            },
+            Alias {
+                sort: Sort::Core(CoreSort::Func),
+                target: AliasTarget::CoreExport {
+                    instance: CoreInstanceId::from(0),
+                    name: "run".to_owned(),
+                },
+            },
+            Alias {
+                sort: Sort::Func,
+                target: AliasTarget::Export {
+                    instance: ComponentInstanceId::from(0),
+                    name: "log".to_owned(),
+                },
+            },
+        ]
+    );
+    let inner: Vec<_> = component
+        .sections
+        .iter()
+        .filter_map(|section| match section {
+            ComponentSection::Component(inner) => Some(inner.try_contents().unwrap()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        contents!(inner[0], Alias),
+        [Alias {
+            sort: Sort::Type,
+            target: AliasTarget::Outer { count: 1, index: 0 },
+        }]
+    );
+    Ok(())
+}
+
+#[test]
+fn imports_and_exports() -> Result<()> {
+    let component = round_trip(
+        r#"(component
+            (import "wasi:cli/env@0.2.0" (instance $env
+                (export "get" (func (result u32)))))
+            (import "res" (type (sub resource)))
+            (import "m" (core module $m))
+            (alias export $env "get" (func $get))
+            (component $inner
+                (import "x" (func $x (result u32)))
+                (export "y" (func $x)))
+            (instance $i (instantiate $inner (with "x" (func $get))))
+            (export "get" (func $get))
+            (export "typed" (func $get) (func (result u32)))
+            (export "inner" (instance $i)))"#,
+    )?;
+    let imports = contents!(component, Import);
+    assert_eq!(
+        imports
+            .iter()
+            .map(|import| import.name.as_str())
+            .collect::<Vec<_>>(),
+        ["wasi:cli/env@0.2.0", "res", "m"]
+    );
+    assert!(matches!(imports[0].desc, ExternDesc::Instance(_)));
+    assert_eq!(imports[1].desc, ExternDesc::Type(TypeBound::SubResource));
+    assert!(matches!(imports[2].desc, ExternDesc::CoreModule(_)));
+
+    assert_eq!(
+        contents!(component, Instance),
+        [ComponentInstance::Instantiate {
+            component: ComponentId::from(0),
+            args: vec![InstantiateArg {
+                name: "x".to_owned(),
+                item: SortIdx::Func(ComponentFuncId::from(0)),
+            }],
+        }]
+    );
+    let exports = contents!(component, Export);
+    assert_eq!(
+        exports[0],
+        ComponentExport {
+            name: name("get"),
+            item: SortIdx::Func(ComponentFuncId::from(0)),
+            desc: None,
+        }
+    );
+    assert!(matches!(exports[1].desc, Some(ExternDesc::Func(_))));
+    // Instance 0 is the imported one.
+    assert_eq!(
+        exports[2].item,
+        SortIdx::Instance(ComponentInstanceId::from(1))
+    );
+    Ok(())
+}
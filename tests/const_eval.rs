@@ -0,0 +1,195 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::wat;
+use wasmbin::builtins::FloatConst;
+use wasmbin::indices::{DataId, ElemId, FuncId, GlobalId, MemId, TableId};
+use wasmbin::instructions::Instruction;
+use wasmbin::transforms::const_eval::{
+    active_data, active_elements, eval_const_expr, eval_globals, ActiveData, ActiveElements,
+    ConstEvalError, ConstValue,
+};
+use wasmbin::types::RefType;
+use wasmbin::Module;
+
+const MODULE: &str = r#"(module
+    (import "env" "base" (global i32))
+    (import "env" "scale" (global i64))
+    (table 8 funcref)
+    (memory 2)
+    (memory 1)
+    (global i32 (i32.add (global.get 0) (i32.const 16)))
+    (global i64 (i64.mul (global.get 1) (i64.sub (i64.const 5) (i64.const 2))))
+    (global f32 (f32.const -nan:0x200000))
+    (global funcref (ref.func $f))
+    (global externref (ref.null extern))
+    (func $f)
+    (data (i32.const 8) "ab")
+    (data "passive")
+    (data (global.get 2) "cde")
+    (data (memory 1) (i32.sub (i32.const 0) (i32.const 1)) "x")
+    (elem (i32.const 1) func $f $f)
+    (elem declare func $f)
+    (elem (table 0) (offset (global.get 0)) funcref (ref.func $f) (ref.null func)))"#;
+
+fn globals(module: &Module) -> Result<Vec<ConstValue>, ConstEvalError> {
+    eval_globals(module, |path, _| match path.name.as_str() {
+        "base" => Some(ConstValue::I32(4)),
+        "scale" => Some(ConstValue::I64(-7)),
+        _ => None,
+    })
+}
+
+#[test]
+fn globals_are_evaluated_in_order() -> Result<()> {
+    let module = wat(MODULE)?;
+    assert_eq!(
+        globals(&module)?,
+        [
+            ConstValue::I32(4),
+            ConstValue::I64(-7),
+            ConstValue::I32(20),
+            ConstValue::I64(-21),
+            ConstValue::F32(f32::from_bits(0xFFA0_0000)),
+            ConstValue::Func(FuncId { index: 0 }),
+            ConstValue::Null(RefType::Extern),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn floats_compare_by_bits() {
+    assert_eq!(ConstValue::F32(f32::NAN), ConstValue::F32(f32::NAN));
+    assert_ne!(ConstValue::F32(f32::NAN), ConstValue::F32(-f32::NAN));
+    assert_ne!(ConstValue::F64(0.0), ConstValue::F64(-0.0));
+    assert_ne!(ConstValue::I32(0), ConstValue::I64(0));
+}
+
+#[test]
+fn missing_imports_are_reported() -> Result<()> {
+    let module = wat(MODULE)?;
+    let err = eval_globals(&module, |_, _| None).unwrap_err();
+    assert!(matches!(err, ConstEvalError::MissingImport(path) if path.name == "base"));
+    Ok(())
+}
+
+#[test]
+fn invalid_expressions_are_rejected() {
+    let globals = [ConstValue::I32(1)];
+    let eval = |expr: &[Instruction]| eval_const_expr(expr, &globals);
+    assert!(matches!(
+        eval(&[Instruction::LocalGet(0.into())]),
+        Err(ConstEvalError::NotConstant { index: 0 })
+    ));
+    assert!(matches!(
+        eval(&[
+            Instruction::I64Const(1),
+            Instruction::I32Const(2),
+            Instruction::I32Add
+        ]),
+        Err(ConstEvalError::InvalidOperands { index: 2 })
+    ));
+    assert!(matches!(
+        eval(&[Instruction::I32Const(2), Instruction::I64Add]),
+        Err(ConstEvalError::InvalidOperands { index: 1 })
+    ));
+    assert!(matches!(
+        eval(&[Instruction::I32Const(1), Instruction::I32Const(2)]),
+        Err(ConstEvalError::InvalidResultCount(2))
+    ));
+    assert!(matches!(
+        eval(&[]),
+        Err(ConstEvalError::InvalidResultCount(0))
+    ));
+    assert!(matches!(
+        eval(&[Instruction::GlobalGet(GlobalId { index: 1 })]),
+        Err(ConstEvalError::UnknownGlobal(GlobalId { index: 1 }))
+    ));
+    assert!(matches!(
+        eval(&[Instruction::F32Const(FloatConst { value: 1.0 })]),
+        Ok(ConstValue::F32(value)) if value == 1.0
+    ));
+}
+
+#[test]
+fn data_segments_are_placed() -> Result<()> {
+    let module = wat(MODULE)?;
+    let globals = globals(&module)?;
+    assert_eq!(
+        active_data(&module, &globals)?,
+        [
+            ActiveData {
+                data: DataId::from(0),
+                memory: MemId::from(0),
+                offset: 8,
+                len: 2,
+            },
+            ActiveData {
+                data: DataId::from(2),
+                memory: MemId::from(0),
+                offset: 20,
+                len: 3,
+            },
+            // 32-bit offsets are unsigned.
+            ActiveData {
+                data: DataId::from(3),
+                memory: MemId::from(1),
+                offset: 0xFFFF_FFFF,
+                len: 1,
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn element_segments_are_evaluated() -> Result<()> {
+    let module = wat(MODULE)?;
+    let globals = globals(&module)?;
+    let f = ConstValue::Func(FuncId { index: 0 });
+    assert_eq!(
+        active_elements(&module, &globals)?,
+        [
+            ActiveElements {
+                elem: ElemId::from(0),
+                table: TableId::from(0),
+                offset: 1,
+                items: vec![f.clone(), f.clone()],
+            },
+            ActiveElements {
+                elem: ElemId::from(2),
+                table: TableId::from(0),
+                offset: 4,
+                items: vec![f, ConstValue::Null(RefType::Func)],
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn non_integer_offsets_are_rejected() -> Result<()> {
+    let module = wat(r#"(module
+        (import "env" "offset" (global f32))
+        (memory 1)
+        (data (global.get 0) "x"))"#)?;
+    let globals = [ConstValue::F32(1.0)];
+    let err = active_data(&module, &globals).unwrap_err();
+    assert!(matches!(err, ConstEvalError::InvalidOffset(ConstValue::F32(value)) if value == 1.0));
+    Ok(())
+}
@@ -0,0 +1,206 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::{bodies, validate, wat};
+use wasmbin::indices::DataId;
+use wasmbin::instructions::{Instruction, Misc};
+use wasmbin::sections::{payload, DataInit};
+use wasmbin::transforms::data_segments::{optimize_data_segments, DataSegmentsError, MIN_ZERO_GAP};
+use wasmbin::Module;
+
+/// Offset of each active segment, or `None` for passive ones, along with its contents.
+fn segments(module: &Module) -> Result<Vec<(Option<u32>, Vec<u8>)>> {
+    let Some(data) = module.find_std_section::<payload::Data>() else {
+        return Ok(Vec::new());
+    };
+    Ok(data
+        .try_contents()?
+        .iter()
+        .map(|segment| {
+            let offset = match &segment.init {
+                DataInit::Active { offset } => match offset[..] {
+                    [Instruction::I32Const(offset)] => Some(offset.cast_unsigned()),
+                    _ => panic!("non-constant offset {offset:?}"),
+                },
+                DataInit::Passive => None,
+                DataInit::ActiveWithMemory { .. } => panic!("unexpected memory index"),
+            };
+            (offset, segment.blob.clone())
+        })
+        .collect())
+}
+
+/// Initial contents of the first memory, which is one page long.
+fn image(module: &Module) -> Result<Vec<u8>> {
+    let mut memory = vec![0; 0x1_0000];
+    for (offset, blob) in segments(module)? {
+        if let Some(offset) = offset {
+            let offset = offset as usize;
+            memory[offset..offset + blob.len()].copy_from_slice(&blob);
+        }
+    }
+    Ok(memory)
+}
+
+/// Optimize the module, check that it has the same initial memory contents and return its segments.
+fn optimize(src: &str) -> Result<Vec<(Option<u32>, Vec<u8>)>> {
+    let mut module = wat(src)?;
+    let before = image(&module)?;
+    optimize_data_segments(&mut module)?;
+    validate(&module)?;
+    assert_eq!(image(&module)?, before);
+    segments(&module)
+}
+
+#[test]
+fn overlapping_segments_are_merged() -> Result<()> {
+    assert_eq!(
+        optimize(
+            r#"(module
+                (memory 1)
+                (data (i32.const 0) "abcdef")
+                (data (i32.const 2) "XY")
+                (data (i32.const 5) "Z!"))"#
+        )?,
+        [(Some(0), b"abXYeZ!".to_vec())]
+    );
+    Ok(())
+}
+
+#[test]
+fn gaps_shorter_than_min_zero_gap_are_filled() -> Result<()> {
+    let short = "\\00".repeat(MIN_ZERO_GAP - 1);
+    let long = "\\00".repeat(MIN_ZERO_GAP);
+    assert_eq!(
+        optimize(&format!(
+            r#"(module
+                (memory 1)
+                (data (i32.const 0) "a{short}b{long}c")
+                (data (i32.const 100) "d")
+                (data (i32.const {}) "e")
+                (data (i32.const 200) "f")
+                (data (i32.const {}) "g"))"#,
+            101 + MIN_ZERO_GAP - 1,
+            201 + MIN_ZERO_GAP,
+        ))?,
+        [
+            (
+                Some(0),
+                format!("a{}b", "\0".repeat(MIN_ZERO_GAP - 1)).into_bytes()
+            ),
+            (Some(2 * MIN_ZERO_GAP as u32 + 1), b"c".to_vec()),
+            (
+                Some(100),
+                format!("d{}e", "\0".repeat(MIN_ZERO_GAP - 1)).into_bytes()
+            ),
+            (Some(200), b"f".to_vec()),
+            (Some(201 + MIN_ZERO_GAP as u32), b"g".to_vec()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn zero_and_empty_segments_are_dropped() -> Result<()> {
+    assert_eq!(
+        optimize(
+            r#"(module
+                (memory 1)
+                (data (i32.const 0) "abcd")
+                (data (i32.const 100) "")
+                (data (i32.const 200) "\00\00\00"))"#
+        )?,
+        [(Some(0), b"abcd".to_vec())]
+    );
+    let mut module = wat(r#"(module
+            (memory 1)
+            (data (i32.const 8) "")
+            (data (i32.const 16) "\00"))"#)?;
+    optimize_data_segments(&mut module)?;
+    validate(&module)?;
+    assert!(module.find_std_section::<payload::Data>().is_none());
+    Ok(())
+}
+
+#[test]
+fn out_of_order_segments_are_sorted() -> Result<()> {
+    assert_eq!(
+        optimize(
+            r#"(module
+                (memory 1)
+                (data (i32.const 300) "c")
+                (data (i32.const 0) "a")
+                (data (i32.const 1) "b")
+                (data (i32.const 1) "B"))"#
+        )?,
+        [(Some(0), b"aB".to_vec()), (Some(300), b"c".to_vec())]
+    );
+    Ok(())
+}
+
+#[test]
+fn passive_segments_are_kept_and_renumbered() -> Result<()> {
+    let src = r#"(module
+        (memory 1)
+        (data (i32.const 0) "a")
+        (data $passive "passive")
+        (data (i32.const 1) "b")
+        (func
+            i32.const 0
+            i32.const 0
+            i32.const 1
+            memory.init $passive
+            data.drop 0
+            data.drop 2))"#;
+    assert_eq!(
+        optimize(src)?,
+        [
+            (None, Vec::new()),
+            (None, b"passive".to_vec()),
+            (Some(0), b"ab".to_vec()),
+        ]
+    );
+    let mut module = wat(src)?;
+    optimize_data_segments(&mut module)?;
+    // References to removed active segments point at an empty passive placeholder.
+    let data_ids: Vec<_> = bodies(&module)?[0]
+        .expr
+        .iter()
+        .filter_map(|instr| match instr {
+            Instruction::Misc(Misc::MemoryInit { data, .. } | Misc::DataDrop(data)) => Some(*data),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        data_ids,
+        [DataId::from(1), DataId::from(0), DataId::from(0)]
+    );
+    Ok(())
+}
+
+#[test]
+fn relocatable_modules_are_rejected() -> Result<()> {
+    let mut module =
+        wat(r#"(module (memory 1) (data (i32.const 0) "a") (@custom "linking" "\02"))"#)?;
+    let original = module.clone();
+    assert!(matches!(
+        optimize_data_segments(&mut module),
+        Err(DataSegmentsError::Relocatable)
+    ));
+    assert_eq!(module, original);
+    Ok(())
+}
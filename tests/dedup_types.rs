@@ -0,0 +1,74 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::{validate, wat};
+use wasmbin::indices::TypeId;
+use wasmbin::sections::{payload, ImportDesc};
+use wasmbin::transforms::dedup_types::{dedup_types, DedupTypesError};
+
+#[test]
+fn identical_and_unused_types_are_removed() -> Result<()> {
+    let mut module = wat(r#"(module
+            (type $unused (func (param f64)))
+            (type $a (func (param i32) (result i32)))
+            (type $b (func (param i32) (result i32)))
+            (type $c (func))
+            (import "env" "f" (func (type $b)))
+            (table 1 funcref)
+            (func (type $a)
+                local.get 0
+                i32.const 0
+                call_indirect (type $b)
+                drop
+                i32.const 0
+                call_indirect (type $c)
+                local.get 0))"#)?;
+    dedup_types(&mut module)?;
+    validate(&module)?;
+    let types = module
+        .find_std_section::<payload::Type>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(types.len(), 2);
+    let imports = module
+        .find_std_section::<payload::Import>()
+        .unwrap()
+        .try_contents()?;
+    assert!(matches!(imports[0].desc, ImportDesc::Func(ty) if ty == TypeId::from(0)));
+    let funcs = module
+        .find_std_section::<payload::Function>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(funcs, &[TypeId::from(0)]);
+    Ok(())
+}
+
+#[test]
+fn relocatable_modules_are_rejected() -> Result<()> {
+    let mut module = wat(r#"(module
+            (type (func))
+            (type (func))
+            (func (type 1))
+            (@custom "linking" "\02"))"#)?;
+    let original = module.clone();
+    assert!(matches!(
+        dedup_types(&mut module),
+        Err(DedupTypesError::Relocatable)
+    ));
+    assert_eq!(module, original);
+    Ok(())
+}
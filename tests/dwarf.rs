@@ -0,0 +1,364 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "dwarf")]
+
+mod common;
+
+use anyhow::Result;
+use common::wat;
+use gimli::write::{
+    Address, AttributeValue, EndianVec, Expression, LineProgram, LineString, Location,
+    LocationList, Range, RangeList, Sections, Unit,
+};
+use gimli::{constants, Encoding, Format, LineEncoding, LittleEndian};
+use std::convert::Infallible;
+use wasmbin::builtins::{Blob, UnparsedBytes};
+use wasmbin::dwarf::{rewrite_addresses, DwarfError, DwarfSections};
+use wasmbin::instructions::Instruction;
+use wasmbin::sections::{payload, CustomSection, RawCustomSection, Section};
+use wasmbin::transforms::address_map::{AddressMap, CodeLayout};
+use wasmbin::Module;
+
+const MODULE: &str = r#"(module
+    (func $a (result i32)
+        i32.const 1
+        i32.const 2
+        i32.add)
+    (func $b
+        nop
+        nop))"#;
+
+const TOMBSTONE: u64 = 0xFFFF_FFFF;
+
+fn custom_section(name: &str, bytes: Vec<u8>) -> Section {
+    Section::Custom(Blob::from(CustomSection::Other(RawCustomSection {
+        name: name.to_owned(),
+        data: UnparsedBytes { bytes },
+    })))
+}
+
+/// Debug info for the functions in `layout`, named by `names`.
+///
+/// Each function gets a subprogram with a variable whose location is only valid
+/// for its second instruction, and a line sequence with a row for each instruction.
+fn debug_info(version: u16, layout: &CodeLayout, names: &[&str]) -> Result<Vec<Section>> {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version,
+        address_size: 4,
+    };
+    let file_name = LineString::String(b"test.c".to_vec());
+    let program = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::String(b"/src".to_vec()),
+        None,
+        file_name.clone(),
+        None,
+    );
+    let mut dwarf = gimli::write::Dwarf::new();
+    let unit = dwarf.units.add(Unit::new(encoding, program));
+    let unit = dwarf.units.get_mut(unit);
+    let dir = unit.line_program.default_directory();
+    let file = unit.line_program.add_file(file_name, dir, None);
+    let root = unit.root();
+    let mut ranges = Vec::new();
+    for (func, name) in layout.funcs.iter().zip(names) {
+        let (start, end) = (u64::from(func.start), u64::from(func.end));
+        unit.line_program
+            .begin_sequence(Some(Address::Constant(start)));
+        for (line, &instr) in (1..).zip(&func.instrs) {
+            let row = unit.line_program.row();
+            row.address_offset = u64::from(instr) - start;
+            row.file = file;
+            row.line = line;
+            unit.line_program.generate_row();
+        }
+        unit.line_program.end_sequence(end - start);
+        ranges.push(Range::StartEnd {
+            begin: Address::Constant(start),
+            end: Address::Constant(end),
+        });
+
+        let mut data = Expression::new();
+        data.op(constants::DW_OP_lit0);
+        data.op(constants::DW_OP_stack_value);
+        let locations = unit.locations.add(LocationList(vec![Location::StartEnd {
+            begin: Address::Constant(func.instrs[1].into()),
+            end: Address::Constant(func.instrs[2].into()),
+            data,
+        }]));
+        let subprogram = unit.add(root, constants::DW_TAG_subprogram);
+        let entry = unit.get_mut(subprogram);
+        entry.set(
+            constants::DW_AT_name,
+            AttributeValue::String(name.as_bytes().to_vec()),
+        );
+        entry.set(
+            constants::DW_AT_low_pc,
+            AttributeValue::Address(Address::Constant(start)),
+        );
+        entry.set(constants::DW_AT_high_pc, AttributeValue::Udata(end - start));
+        let variable = unit.add(subprogram, constants::DW_TAG_variable);
+        unit.get_mut(variable).set(
+            constants::DW_AT_location,
+            AttributeValue::LocationListRef(locations),
+        );
+    }
+    let ranges = unit.ranges.add(RangeList(ranges));
+    let entry = unit.get_mut(root);
+    entry.set(
+        constants::DW_AT_low_pc,
+        AttributeValue::Address(Address::Constant(0)),
+    );
+    entry.set(
+        constants::DW_AT_ranges,
+        AttributeValue::RangeListRef(ranges),
+    );
+
+    let mut sections = Sections::new(EndianVec::new(LittleEndian));
+    dwarf.write(&mut sections)?;
+    let mut result = Vec::new();
+    sections.for_each(|id, data| {
+        if !data.slice().is_empty() {
+            result.push(custom_section(id.name(), data.slice().to_vec()));
+        }
+        Ok::<_, Infallible>(())
+    })?;
+    Ok(result)
+}
+
+/// Addresses found in the debug info of a module.
+#[derive(Debug, Default, PartialEq)]
+struct Summary {
+    /// Ranges of the compilation unit.
+    ranges: Vec<(u64, u64)>,
+    /// Name, low PC and length of each subprogram.
+    subprograms: Vec<(String, u64, u64)>,
+    /// Ranges of all location list entries.
+    locations: Vec<(u64, u64)>,
+    /// Address and line of each row, with line 0 for the end of a sequence.
+    rows: Vec<(u64, u64)>,
+}
+
+fn summary(module: &Module) -> Result<Summary> {
+    let sections = DwarfSections::from_module(module)?;
+    let dwarf = sections.to_gimli();
+    let mut summary = Summary::default();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let unit = unit.unit_ref(&dwarf);
+        let mut entries = unit.entries();
+        while let Some(entry) = entries.next_dfs()? {
+            if entry.tag() == constants::DW_TAG_compile_unit {
+                let mut ranges = unit.die_ranges(entry)?;
+                while let Some(range) = ranges.next()? {
+                    summary.ranges.push((range.begin, range.end));
+                }
+            }
+            if entry.tag() == constants::DW_TAG_subprogram {
+                let name = unit.attr_string(entry.attr_value(constants::DW_AT_name).unwrap())?;
+                let low_pc =
+                    unit.attr_address(entry.attr_value(constants::DW_AT_low_pc).unwrap())?;
+                let length = entry.attr(constants::DW_AT_high_pc).unwrap().udata_value();
+                summary.subprograms.push((
+                    name.to_string()?.to_owned(),
+                    low_pc.unwrap(),
+                    length.unwrap(),
+                ));
+            }
+            if let Some(offset) = unit.attr_locations_offset(
+                entry
+                    .attr_value(constants::DW_AT_location)
+                    .unwrap_or(gimli::AttributeValue::Udata(0)),
+            )? {
+                let mut locations = unit.locations(offset)?;
+                while let Some(location) = locations.next()? {
+                    summary
+                        .locations
+                        .push((location.range.begin, location.range.end));
+                }
+            }
+        }
+        if let Some(program) = unit.line_program.clone() {
+            let mut rows = program.rows();
+            while let Some((_, row)) = rows.next_row()? {
+                let line = if row.end_sequence() {
+                    0
+                } else {
+                    row.line().unwrap().get()
+                };
+                summary.rows.push((row.address(), line));
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Re-encode the first function with a wider immediate, moving all the code after it.
+fn widen_immediate(module: &mut Module) -> Result<()> {
+    let code = module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?;
+    code[0].try_contents_mut()?.expr[0] = Instruction::I32Const(100_000);
+    Ok(())
+}
+
+/// Insert a `nop` at the start and in the middle of the first function.
+fn insert_nops(module: &mut Module) -> Result<()> {
+    let code = module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?;
+    let expr = &mut code[0].try_contents_mut()?.expr;
+    expr.insert(2, Instruction::Nop);
+    expr.insert(0, Instruction::Nop);
+    Ok(())
+}
+
+/// Remove the first function, which isn't referenced by the other one.
+fn remove_first_func(module: &mut Module) -> Result<()> {
+    module
+        .find_std_section_mut::<payload::Function>()
+        .unwrap()
+        .try_contents_mut()?
+        .remove(0);
+    module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?
+        .remove(0);
+    Ok(())
+}
+
+fn assert_addresses_follow_code(version: u16) -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let before = CodeLayout::of(&module)?;
+    module
+        .sections
+        .extend(debug_info(version, &before, &["a", "b"])?);
+    widen_immediate(&mut module)?;
+    let after = CodeLayout::of(&module)?;
+    assert_ne!(before.funcs[1], after.funcs[1]);
+    rewrite_addresses(&mut module, &AddressMap::new(before, after.clone()))?;
+
+    let mut expected = wat(MODULE)?;
+    expected
+        .sections
+        .extend(debug_info(version, &after, &["a", "b"])?);
+    assert_eq!(summary(&module)?, summary(&expected)?);
+    Ok(())
+}
+
+#[test]
+fn dwarf4_addresses_follow_code() -> Result<()> {
+    assert_addresses_follow_code(4)
+}
+
+#[test]
+fn dwarf5_addresses_follow_code() -> Result<()> {
+    assert_addresses_follow_code(5)
+}
+
+#[test]
+fn addresses_follow_instructions_around_inserted_ones() -> Result<()> {
+    for version in [4, 5] {
+        let mut module = wat(MODULE)?;
+        let before = CodeLayout::of(&module)?;
+        module
+            .sections
+            .extend(debug_info(version, &before, &["a", "b"])?);
+        insert_nops(&mut module)?;
+        let after = CodeLayout::of(&module)?;
+        rewrite_addresses(&mut module, &AddressMap::new(before, after.clone()))?;
+
+        // Debug info of the original instructions at their new addresses.
+        let mut original_instrs = after.clone();
+        let instrs = &mut original_instrs.funcs[0].instrs;
+        instrs.remove(3);
+        instrs.remove(0);
+        let mut expected = wat(MODULE)?;
+        insert_nops(&mut expected)?;
+        expected
+            .sections
+            .extend(debug_info(version, &original_instrs, &["a", "b"])?);
+        assert_eq!(summary(&module)?, summary(&expected)?, "DWARF {version}");
+    }
+    Ok(())
+}
+
+#[test]
+fn removed_functions_are_dropped() -> Result<()> {
+    for version in [4, 5] {
+        let mut module = wat(MODULE)?;
+        let before = CodeLayout::of(&module)?;
+        let length = u64::from(before.funcs[0].end - before.funcs[0].start);
+        module
+            .sections
+            .extend(debug_info(version, &before, &["a", "b"])?);
+        remove_first_func(&mut module)?;
+        let after = CodeLayout::of(&module)?;
+        let map = AddressMap::with_func_map(before, after.clone(), |index| index.checked_sub(1));
+        rewrite_addresses(&mut module, &map)?;
+
+        let mut expected = wat(MODULE)?;
+        remove_first_func(&mut expected)?;
+        expected
+            .sections
+            .extend(debug_info(version, &after, &["b"])?);
+        let mut expected = summary(&expected)?;
+        expected
+            .subprograms
+            .insert(0, ("a".to_owned(), TOMBSTONE, length));
+        assert_eq!(summary(&module)?, expected, "DWARF {version}");
+    }
+    Ok(())
+}
+
+#[test]
+fn type_units_are_rejected() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let layout = CodeLayout::of(&module)?;
+    module.sections.extend(debug_info(4, &layout, &["a", "b"])?);
+    module
+        .sections
+        .push(custom_section(".debug_types", vec![0]));
+    let original = module.clone();
+    let err = rewrite_addresses(&mut module, &AddressMap::new(layout.clone(), layout)).unwrap_err();
+    assert!(matches!(
+        err,
+        DwarfError::UnsupportedSection(".debug_types")
+    ));
+    assert_eq!(module, original);
+    Ok(())
+}
+
+#[test]
+fn modules_without_debug_info_are_untouched() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    module
+        .sections
+        .push(custom_section(".debug_str", b"unused\0".to_vec()));
+    let original = module.clone();
+    widen_immediate(&mut module)?;
+    let layout = CodeLayout::of(&original)?;
+    let map = AddressMap::new(layout, CodeLayout::of(&module)?);
+    let expected = module.clone();
+    rewrite_addresses(&mut module, &map)?;
+    assert_eq!(module, expected);
+    Ok(())
+}
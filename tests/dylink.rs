@@ -0,0 +1,138 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+use wasmbin::builtins::Blob;
+use wasmbin::sections::dylink::{
+    DylinkSubSection, ExportInfo, ImportInfo, MemInfo, RawDylinkSubSection,
+};
+use wasmbin::sections::linking::SymbolFlags;
+use wasmbin::sections::{CustomSection, Section};
+use wasmbin::Module;
+
+/// Length-prefixed bytes, for lengths that fit in a single LEB128 byte.
+fn prefixed(bytes: &[u8]) -> Vec<u8> {
+    let mut res = vec![u8::try_from(bytes.len()).unwrap()];
+    assert!(res[0] < 0x80);
+    res.extend_from_slice(bytes);
+    res
+}
+
+fn subsection(id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut res = vec![id];
+    res.extend(prefixed(payload));
+    res
+}
+
+/// Binary module consisting of a single `dylink.0` section with the given subsections.
+fn module_binary(subsections: &[Vec<u8>]) -> Vec<u8> {
+    let mut section = prefixed(b"dylink.0");
+    section.extend(subsections.concat());
+    let mut binary = b"\0asm\x01\0\0\0\0".to_vec();
+    binary.extend(prefixed(&section));
+    binary
+}
+
+fn dylink(module: &Module) -> Result<&[DylinkSubSection]> {
+    match &module.sections[..] {
+        [Section::Custom(custom)] => match custom.try_contents()? {
+            CustomSection::Dylink0(sub) => Ok(sub.try_contents()?),
+            other => panic!("expected dylink.0 section, got {other:?}"),
+        },
+        other => panic!("expected a single custom section, got {other:?}"),
+    }
+}
+
+#[test]
+fn dylink_round_trip() -> Result<()> {
+    let binary = module_binary(&[
+        subsection(1, &[0x90, 0x01, 4, 3, 0]),
+        subsection(
+            2,
+            &[[2].as_slice(), &prefixed(b"libc.so"), &prefixed(b"libm.so")].concat(),
+        ),
+        subsection(3, &[[1].as_slice(), &prefixed(b"f"), &[0x21]].concat()),
+        subsection(
+            4,
+            &[[1].as_slice(), &prefixed(b"env"), &prefixed(b"g"), &[0x01]].concat(),
+        ),
+        subsection(5, &[[1].as_slice(), &prefixed(b"$ORIGIN")].concat()),
+        // Unknown subsections are kept as is.
+        subsection(0x2A, &[0xDE, 0xAD]),
+    ]);
+    let module = Module::decode_from(binary.as_slice())?;
+    let expected = [
+        DylinkSubSection::MemInfo(Blob::from(MemInfo {
+            memory_size: 0x90,
+            memory_alignment_log2: 4,
+            table_size: 3,
+            table_alignment_log2: 0,
+        })),
+        DylinkSubSection::Needed(Blob::from(vec!["libc.so".to_owned(), "libm.so".to_owned()])),
+        DylinkSubSection::ExportInfo(Blob::from(vec![ExportInfo {
+            name: "f".to_owned(),
+            flags: SymbolFlags::BINDING_WEAK | SymbolFlags::EXPORTED,
+        }])),
+        DylinkSubSection::ImportInfo(Blob::from(vec![ImportInfo {
+            module: "env".to_owned(),
+            name: "g".to_owned(),
+            flags: SymbolFlags::BINDING_WEAK,
+        }])),
+        DylinkSubSection::RuntimePath(Blob::from(vec!["$ORIGIN".to_owned()])),
+        DylinkSubSection::Other(RawDylinkSubSection {
+            id: 0x2A,
+            data: vec![0xDE, 0xAD],
+        }),
+    ];
+    assert_eq!(dylink(&module)?, expected);
+    // Untouched sections are copied verbatim.
+    assert_eq!(module.encode_into(Vec::new())?, binary);
+
+    // Re-encode everything from the decoded values.
+    let rebuilt = Module {
+        sections: vec![Section::Custom(Blob::from(CustomSection::Dylink0(
+            expected.to_vec().into(),
+        )))],
+    };
+    assert_eq!(rebuilt.encode_into(Vec::new())?, binary);
+    Ok(())
+}
+
+#[test]
+fn needed_libraries_can_be_rewritten() -> Result<()> {
+    let binary = module_binary(&[
+        subsection(2, &[[1].as_slice(), &prefixed(b"libc.so")].concat()),
+        subsection(0x2A, &[0xDE, 0xAD]),
+    ]);
+    let mut module = Module::decode_from(binary.as_slice())?;
+    let Section::Custom(custom) = &mut module.sections[0] else {
+        unreachable!();
+    };
+    let CustomSection::Dylink0(sub) = custom.try_contents_mut()? else {
+        unreachable!();
+    };
+    let DylinkSubSection::Needed(needed) = &mut sub.try_contents_mut()?[0] else {
+        unreachable!();
+    };
+    needed.try_contents_mut()?[0] = "libc.so.6".to_owned();
+
+    assert_eq!(
+        module.encode_into(Vec::new())?,
+        module_binary(&[
+            subsection(2, &[[1].as_slice(), &prefixed(b"libc.so.6")].concat()),
+            subsection(0x2A, &[0xDE, 0xAD]),
+        ])
+    );
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::wat;
+use wasmbin::features::{used_features, Feature};
+
+fn features(src: &str) -> Result<Vec<Feature>> {
+    Ok(used_features(&wat(src)?)?.into_iter().collect())
+}
+
+#[test]
+fn mvp_module_uses_no_features() -> Result<()> {
+    assert_eq!(
+        features(
+            r#"(module
+                (memory 1)
+                (table 1 funcref)
+                (global i32 (i32.const 0))
+                (func (param i32) (result i32)
+                    block (result i32)
+                        local.get 0
+                    end))"#
+        )?,
+        []
+    );
+    Ok(())
+}
+
+#[test]
+fn instructions_and_types() -> Result<()> {
+    assert_eq!(
+        features(
+            r#"(module
+                (func (result i32 i32)
+                    i32.const 0
+                    i32.extend8_s
+                    i32.const 1)
+                (func (param f32) (result i32)
+                    local.get 0
+                    i32.trunc_sat_f32_s)
+                (func (local v128)))"#
+        )?,
+        [
+            Feature::MultiValue,
+            Feature::NontrappingFptoint,
+            Feature::SignExt,
+            Feature::Simd128,
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn segments_and_multiple_definitions() -> Result<()> {
+    assert_eq!(
+        features(
+            r#"(module
+                (import "env" "memory" (memory 1))
+                (memory 1)
+                (table 1 funcref)
+                (table 1 externref)
+                (data "passive"))"#
+        )?,
+        [
+            Feature::BulkMemory,
+            Feature::MultiMemory,
+            Feature::ReferenceTypes,
+        ]
+    );
+    Ok(())
+}
+
+#[cfg(feature = "exception-handling")]
+#[test]
+fn try_table_bodies_are_inspected() -> Result<()> {
+    assert_eq!(
+        features(
+            r#"(module
+                (func
+                    block
+                        try_table (catch_all 0)
+                            ref.func 0
+                            drop
+                            return_call 0
+                        end
+                    end))"#
+        )?,
+        [
+            Feature::ExceptionHandling,
+            Feature::ReferenceTypes,
+            Feature::TailCall,
+        ]
+    );
+    Ok(())
+}
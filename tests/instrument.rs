@@ -0,0 +1,349 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "interp")]
+
+mod common;
+
+use anyhow::Result;
+use common::{bodies, validate, wat};
+use wasmbin::indices::FuncId;
+use wasmbin::instructions::{Expression, Instruction};
+use wasmbin::interp::{Host, Instance, Store, Trap, Value};
+use wasmbin::sections::{ImportPath, Locals};
+use wasmbin::transforms::instrument::{
+    import_func, instrument, AccessKind, FuncContext, InstrumentError, Instrumentation,
+    MemoryAccess,
+};
+use wasmbin::types::{FuncType, ValueType};
+use wasmbin::Module;
+
+/// Calls `trace.enter` and `trace.exit` with the function index, and `trace.access` with the
+/// address and kind of memory accesses.
+struct Trace {
+    /// Log loads as well as stores.
+    loads: bool,
+    enter: FuncId,
+    exit: FuncId,
+    access: FuncId,
+}
+
+impl Trace {
+    fn new(loads: bool) -> Self {
+        Self {
+            loads,
+            enter: FuncId::from(0),
+            exit: FuncId::from(0),
+            access: FuncId::from(0),
+        }
+    }
+}
+
+fn import(
+    module: &mut Module,
+    name: &str,
+    params: Vec<ValueType>,
+) -> Result<FuncId, InstrumentError> {
+    let path = ImportPath {
+        module: "trace".to_owned(),
+        name: name.to_owned(),
+    };
+    let ty = FuncType {
+        params,
+        results: vec![],
+    };
+    Ok(import_func(module, path, ty)?)
+}
+
+impl Instrumentation for Trace {
+    fn prepare(&mut self, module: &mut Module) -> Result<(), InstrumentError> {
+        self.enter = import(module, "enter", vec![ValueType::I32])?;
+        self.exit = import(module, "exit", vec![ValueType::I32])?;
+        self.access = import(module, "access", vec![ValueType::I32, ValueType::I32])?;
+        Ok(())
+    }
+
+    fn enter(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+        out.push(Instruction::I32Const(cx.func.index.cast_signed()));
+        out.push(Instruction::Call(self.enter));
+    }
+
+    fn exit(&mut self, cx: &mut FuncContext, out: &mut Expression) {
+        out.push(Instruction::I32Const(cx.func.index.cast_signed()));
+        out.push(Instruction::Call(self.exit));
+    }
+
+    fn before_memory_access(
+        &mut self,
+        _cx: &mut FuncContext,
+        access: &MemoryAccess,
+        out: &mut Expression,
+    ) {
+        let kind = match access.kind {
+            AccessKind::Load if self.loads => 0,
+            AccessKind::Store => 1,
+            _ => return,
+        };
+        out.push(Instruction::LocalGet(access.address));
+        out.push(Instruction::I32Const(kind));
+        out.push(Instruction::Call(self.access));
+    }
+}
+
+/// Name of a `trace` import and its arguments.
+type Event = (String, Vec<i32>);
+
+/// Host recording calls to the `trace` imports.
+#[derive(Default)]
+struct Recorder {
+    events: Vec<Event>,
+}
+
+impl Host for Recorder {
+    fn call(
+        &mut self,
+        import: &ImportPath,
+        _ty: &FuncType,
+        args: &[Value],
+        _store: &mut Store,
+    ) -> Result<Vec<Value>, Trap> {
+        let args = args
+            .iter()
+            .map(|arg| match arg {
+                Value::I32(value) => *value,
+                _ => panic!("unexpected argument {arg:?}"),
+            })
+            .collect();
+        self.events.push((import.name.clone(), args));
+        Ok(Vec::new())
+    }
+}
+
+/// Run an export of the original and the instrumented module, check that they return the same
+/// results and return the events recorded by the latter.
+fn run(
+    original: &Module,
+    instrumented: &Module,
+    name: &str,
+    args: &[Value],
+) -> Result<(Vec<Value>, Vec<Event>)> {
+    let expected = Instance::instantiate(original, Recorder::default())?.invoke(name, args)?;
+    let mut instance = Instance::instantiate(instrumented, Recorder::default())?;
+    let results = instance.invoke(name, args)?;
+    assert_eq!(results, expected, "{name}({args:?})");
+    Ok((results, std::mem::take(&mut instance.host.events)))
+}
+
+fn event(name: &str, args: &[i32]) -> Event {
+    (name.to_owned(), args.to_vec())
+}
+
+fn instrumented(src: &str, trace: &mut Trace) -> Result<(Module, Module)> {
+    let original = wat(src)?;
+    let mut module = original.clone();
+    instrument(&mut module, trace)?;
+    validate(&module)?;
+    Ok((original, module))
+}
+
+/// Types of the locals declared by each function.
+fn locals(module: &Module) -> Result<Vec<Vec<ValueType>>> {
+    Ok(bodies(module)?
+        .iter()
+        .map(|body| {
+            body.locals
+                .iter()
+                .flat_map(|Locals { repeat, ty }| std::iter::repeat_n(ty.clone(), *repeat as usize))
+                .collect()
+        })
+        .collect())
+}
+
+const BRANCHES: &str = r#"(module
+    (func (export "br_if") (param i32) (result i32)
+        i32.const 100
+        local.get 0
+        br_if 0
+        drop
+        i32.const 200)
+    (func (export "br_table") (param i32) (result i32)
+        block (result i32)
+            i32.const 7
+            local.get 0
+            br_table 0 1 0
+        end
+        i32.const 1
+        i32.add)
+    (func (export "br_table_all") (param i32) (result i32)
+        i32.const 9
+        local.get 0
+        br_table 0 0)
+    (func (export "nested") (param i32) (result i32)
+        block
+            loop
+                local.get 0
+                i32.eqz
+                br_if 1
+                local.get 0
+                i32.const 3
+                i32.eq
+                if
+                    i32.const 33
+                    br 3
+                end
+                local.get 0
+                i32.const 1
+                i32.sub
+                local.set 0
+                br 0
+            end
+        end
+        i32.const 0))"#;
+
+#[test]
+fn every_exit_is_traced_once() -> Result<()> {
+    let (original, module) = instrumented(BRANCHES, &mut Trace::new(true))?;
+    // Three imports come before the instrumented functions.
+    for (name, func, args, result) in [
+        ("br_if", 3, 0, 200),
+        ("br_if", 3, 1, 100),
+        ("br_table", 4, 0, 8),
+        ("br_table", 4, 1, 7),
+        ("br_table", 4, 2, 8),
+        ("br_table", 4, 100, 8),
+        ("br_table_all", 5, 0, 9),
+        ("br_table_all", 5, 1, 9),
+        ("nested", 6, 2, 0),
+        ("nested", 6, 5, 33),
+    ] {
+        let (results, events) = run(&original, &module, name, &[Value::I32(args)])?;
+        assert_eq!(results, [Value::I32(result)], "{name}({args})");
+        assert_eq!(
+            events,
+            [event("enter", &[func]), event("exit", &[func])],
+            "{name}({args})"
+        );
+    }
+    Ok(())
+}
+
+const MEMORY: &str = r#"(module
+    (memory 1)
+    (func (export "memory") (param i32) (result i64)
+        local.get 0
+        i32.const 42
+        i32.store offset=4
+        i64.const 5
+        local.get 0
+        i64.load32_u offset=4
+        i64.add
+        local.get 0
+        f64.const 1.5
+        f64.store offset=8
+        local.get 0
+        f64.load offset=8
+        i64.trunc_f64_s
+        i64.add)
+    (func (export "loads") (param i32) (result i32)
+        local.get 0
+        i32.load
+        local.get 0
+        i32.load offset=4
+        i32.add))"#;
+
+#[test]
+fn memory_accesses_keep_the_stack_intact() -> Result<()> {
+    let (original, module) = instrumented(MEMORY, &mut Trace::new(true))?;
+    let (results, events) = run(&original, &module, "memory", &[Value::I32(16)])?;
+    assert_eq!(results, [Value::I64(48)]);
+    assert_eq!(
+        events,
+        [
+            event("enter", &[3]),
+            event("access", &[16, 1]),
+            event("access", &[16, 0]),
+            event("access", &[16, 1]),
+            event("access", &[16, 0]),
+            event("exit", &[3]),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn scratch_locals_are_reused() -> Result<()> {
+    // The address and each operand type get a single local shared by all accesses.
+    let (_, module) = instrumented(MEMORY, &mut Trace::new(true))?;
+    assert_eq!(
+        locals(&module)?,
+        [
+            vec![ValueType::I32, ValueType::I32, ValueType::F64],
+            vec![ValueType::I32],
+        ]
+    );
+    // Locals spilled for accesses without hooks are rolled back.
+    let (original, module) = instrumented(MEMORY, &mut Trace::new(false))?;
+    assert_eq!(
+        locals(&module)?,
+        [vec![ValueType::I32, ValueType::I32, ValueType::F64], vec![]]
+    );
+    let (_, events) = run(&original, &module, "loads", &[Value::I32(0)])?;
+    assert_eq!(events, [event("enter", &[4]), event("exit", &[4])]);
+    // Only branches that may or may not leave the function need to save their condition.
+    let (_, module) = instrumented(BRANCHES, &mut Trace::new(true))?;
+    assert_eq!(
+        locals(&module)?,
+        [vec![ValueType::I32], vec![ValueType::I32], vec![], vec![]]
+    );
+    Ok(())
+}
+
+#[cfg(feature = "exception-handling")]
+#[test]
+fn try_table_bodies_are_instrumented() -> Result<()> {
+    let mut trace = Trace::new(true);
+    let (_, module) = instrumented(
+        r#"(module
+            (memory 1)
+            (func (param i32)
+                try_table (catch_all 0)
+                    local.get 0
+                    br_if 1
+                    local.get 0
+                    i32.load
+                    drop
+                end))"#,
+        &mut trace,
+    )?;
+    let body = &bodies(&module)?[0];
+    let try_table = body
+        .expr
+        .iter()
+        .find_map(|instr| match instr {
+            Instruction::TryTable(try_table) => Some(try_table),
+            _ => None,
+        })
+        .unwrap();
+    let calls: Vec<_> = try_table
+        .instructions
+        .iter()
+        .filter_map(|instr| match instr {
+            Instruction::Call(func) => Some(*func),
+            _ => None,
+        })
+        .collect();
+    // Branch to the function label from within `try_table` exits the function.
+    assert_eq!(calls, [trace.exit, trace.access]);
+    Ok(())
+}
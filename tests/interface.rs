@@ -0,0 +1,211 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::{bodies, validate, wat};
+use wasmbin::indices::{FuncId, GlobalId, LocalId};
+use wasmbin::instructions::Instruction;
+use wasmbin::sections::names::Names;
+use wasmbin::sections::{payload, ExportDesc, FuncBody, Import, ImportDesc, ImportPath};
+use wasmbin::transforms::interface::{ImportDefinition, InterfaceError};
+use wasmbin::types::{GlobalType, ValueType};
+use wasmbin::Module;
+
+const MODULE: &str = r#"(module
+    (import "env" "a" (func $a (param i32) (result i32)))
+    (import "env" "b" (func $b (param i32) (result i32)))
+    (import "env" "g" (global i32))
+    (import "env" "c" (func $c (param i32) (result i32)))
+    (func $d (export "d") (param $x i32) (result i32)
+        local.get $x
+        call $a
+        call $b
+        call $c
+        global.get 0
+        i32.add)
+    (func $e (export "e") (result i32)
+        i32.const 0
+        call $d))"#;
+
+fn path(name: &str) -> ImportPath {
+    ImportPath {
+        module: "env".to_owned(),
+        name: name.to_owned(),
+    }
+}
+
+/// Targets of all calls in each function body.
+fn calls(module: &Module) -> Result<Vec<Vec<u32>>> {
+    Ok(bodies(module)?
+        .iter()
+        .map(|body| {
+            body.expr
+                .iter()
+                .filter_map(|instr| match instr {
+                    Instruction::Call(func) => Some(func.index),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect())
+}
+
+fn func_names(names: &Names, count: u32) -> Vec<Option<&str>> {
+    (0..count).map(|i| names.func(FuncId::from(i))).collect()
+}
+
+fn exported_func(module: &Module, name: &str) -> Option<u32> {
+    module
+        .find_std_section::<payload::Export>()?
+        .try_contents()
+        .ok()?
+        .iter()
+        .find(|export| export.name == name)
+        .and_then(|export| match export.desc {
+            ExportDesc::Func(func) => Some(func.index),
+            _ => None,
+        })
+}
+
+#[test]
+fn define_import_moves_it_before_local_definitions() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    // Imported `b` is replaced by a function adding one to its argument.
+    let body = FuncBody {
+        locals: Vec::new(),
+        expr: vec![
+            Instruction::LocalGet(LocalId::from(0)),
+            Instruction::I32Const(1),
+            Instruction::I32Add,
+        ],
+    };
+    module.define_import(&path("b"), ImportDefinition::Func(body))?;
+    validate(&module)?;
+
+    // Remaining imports `a` and `c` come first, followed by `b`, `d` and `e`.
+    assert_eq!(calls(&module)?, [vec![], vec![0, 2, 1], vec![3]]);
+    assert_eq!(exported_func(&module, "d"), Some(3));
+    assert_eq!(exported_func(&module, "e"), Some(4));
+    let names = Names::from_module(&module)?;
+    assert_eq!(
+        func_names(&names, 5),
+        [Some("a"), Some("c"), Some("b"), Some("d"), Some("e")]
+    );
+    assert_eq!(names.local(FuncId::from(3), LocalId::from(0)), Some("x"));
+    Ok(())
+}
+
+#[test]
+fn define_last_import_keeps_indices() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let body = FuncBody {
+        locals: Vec::new(),
+        expr: vec![Instruction::LocalGet(LocalId::from(0))],
+    };
+    module.define_import(&path("c"), ImportDefinition::Func(body))?;
+    validate(&module)?;
+    assert_eq!(calls(&module)?, [vec![], vec![0, 1, 2], vec![3]]);
+    let names = Names::from_module(&module)?;
+    assert_eq!(
+        func_names(&names, 5),
+        [Some("a"), Some("b"), Some("c"), Some("d"), Some("e")]
+    );
+    Ok(())
+}
+
+#[test]
+fn define_global_import() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    module.define_import(
+        &path("g"),
+        ImportDefinition::Global(vec![Instruction::I32Const(5)]),
+    )?;
+    validate(&module)?;
+    assert!(bodies(&module)?[0]
+        .expr
+        .contains(&Instruction::GlobalGet(GlobalId::from(0))));
+    assert_eq!(calls(&module)?, [vec![0, 1, 2], vec![3]]);
+    assert!(matches!(
+        module.define_import(&path("a"), ImportDefinition::Memory),
+        Err(InterfaceError::KindMismatch(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn add_and_remove_imports_shift_definitions() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let imports = module
+        .find_std_section::<payload::Import>()
+        .unwrap()
+        .try_contents()?;
+    let ImportDesc::Func(ty) = imports[0].desc else {
+        panic!("unexpected import {:?}", imports[0]);
+    };
+    let index = module.add_import(Import {
+        path: path("f"),
+        desc: ImportDesc::Func(ty),
+    })?;
+    assert_eq!(index, 3);
+    validate(&module)?;
+    assert_eq!(calls(&module)?, [vec![0, 1, 2], vec![4]]);
+    let names = Names::from_module(&module)?;
+    assert_eq!(
+        func_names(&names, 6),
+        [Some("a"), Some("b"), Some("c"), None, Some("d"), Some("e")]
+    );
+
+    assert!(matches!(
+        module.remove_import(&path("b")),
+        Err(InterfaceError::ImportInUse(_))
+    ));
+    module.remove_import(&path("f"))?;
+    validate(&module)?;
+    assert_eq!(calls(&module)?, [vec![0, 1, 2], vec![3]]);
+    assert_eq!(exported_func(&module, "e"), Some(4));
+    Ok(())
+}
+
+#[test]
+fn relocatable_modules_are_rejected() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    module
+        .sections
+        .extend(wat(r#"(module (@custom "linking" "\02"))"#)?.sections);
+    let original = module.clone();
+    let global = Import {
+        path: path("h"),
+        desc: ImportDesc::Global(GlobalType {
+            value_type: ValueType::I32,
+            mutable: false,
+            #[cfg(feature = "threads")]
+            is_shared: false,
+        }),
+    };
+    assert!(matches!(
+        module.add_import(global),
+        Err(InterfaceError::Relocatable)
+    ));
+    assert!(matches!(
+        module.define_import(
+            &path("g"),
+            ImportDefinition::Global(vec![Instruction::I32Const(5)])
+        ),
+        Err(InterfaceError::Relocatable)
+    ));
+    assert_eq!(module, original);
+    Ok(())
+}
@@ -0,0 +1,440 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "interp")]
+
+mod common;
+
+use anyhow::Result;
+use common::wat;
+use wasmbin::interp::{Host, Instance, InterpError, Store, Trap, Value};
+use wasmbin::sections::ImportPath;
+use wasmbin::types::FuncType;
+
+struct NoImports;
+
+impl Host for NoImports {
+    fn call(
+        &mut self,
+        import: &ImportPath,
+        _ty: &FuncType,
+        _args: &[Value],
+        _store: &mut Store,
+    ) -> Result<Vec<Value>, Trap> {
+        Err(Trap::Host(format!(
+            "unexpected call to {}.{}",
+            import.module, import.name
+        )))
+    }
+}
+
+fn instance(src: &str) -> Result<Instance<NoImports>> {
+    Ok(Instance::instantiate(&wat(src)?, NoImports)?)
+}
+
+/// Invoke `name` and expect it to trap.
+fn trap(instance: &mut Instance<NoImports>, name: &str, args: &[Value]) -> Trap {
+    match instance.invoke(name, args) {
+        Err(InterpError::Trap(trap)) => trap,
+        result => panic!("{name} didn't trap: {result:?}"),
+    }
+}
+
+fn i32x4(lanes: [i32; 4]) -> Value {
+    let mut bytes = [0; 16];
+    for (chunk, lane) in bytes.chunks_exact_mut(4).zip(lanes) {
+        chunk.copy_from_slice(&lane.to_le_bytes());
+    }
+    Value::V128(bytes)
+}
+
+#[test]
+fn values_compare_floats_by_bits() {
+    assert_eq!(Value::F32(f32::NAN), Value::F32(f32::NAN));
+    assert_ne!(Value::F32(0.0), Value::F32(-0.0));
+    assert_ne!(Value::F64(f64::NAN), Value::F64(-f64::NAN));
+    assert_ne!(Value::I32(0), Value::I64(0));
+}
+
+#[test]
+fn integer_arithmetic() -> Result<()> {
+    let mut instance = instance(
+        r#"(module
+            (func (export "add") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.add)
+            (func (export "rem_s") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.rem_s)
+            (func (export "shl") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.shl)
+            (func (export "rotr") (param i64 i64) (result i64)
+                local.get 0
+                local.get 1
+                i64.rotr)
+            (func (export "bits") (param i32) (result i32 i32 i32)
+                local.get 0
+                i32.clz
+                local.get 0
+                i32.ctz
+                local.get 0
+                i32.popcnt)
+            (func (export "extend") (param i32) (result i32 i64)
+                local.get 0
+                i32.extend8_s
+                local.get 0
+                i64.extend_i32_u))"#,
+    )?;
+    assert_eq!(
+        instance.invoke("add", &[Value::I32(i32::MAX), Value::I32(1)])?,
+        [Value::I32(i32::MIN)]
+    );
+    assert_eq!(
+        instance.invoke("rem_s", &[Value::I32(i32::MIN), Value::I32(-1)])?,
+        [Value::I32(0)]
+    );
+    assert_eq!(
+        instance.invoke("rem_s", &[Value::I32(-7), Value::I32(2)])?,
+        [Value::I32(-1)]
+    );
+    assert_eq!(
+        instance.invoke("shl", &[Value::I32(1), Value::I32(33)])?,
+        [Value::I32(2)]
+    );
+    assert_eq!(
+        instance.invoke("rotr", &[Value::I64(1), Value::I64(1)])?,
+        [Value::I64(i64::MIN)]
+    );
+    assert_eq!(
+        instance.invoke("bits", &[Value::I32(0x0F00)])?,
+        [Value::I32(20), Value::I32(8), Value::I32(4)]
+    );
+    assert_eq!(
+        instance.invoke("extend", &[Value::I32(0x80)])?,
+        [Value::I32(-128), Value::I64(0x80)]
+    );
+    Ok(())
+}
+
+#[test]
+fn float_arithmetic() -> Result<()> {
+    let mut instance = instance(
+        r#"(module
+            (func (export "min") (param f32 f32) (result f32)
+                local.get 0
+                local.get 1
+                f32.min)
+            (func (export "nearest") (param f64) (result f64)
+                local.get 0
+                f64.nearest)
+            (func (export "copysign") (param f64 f64) (result f64)
+                local.get 0
+                local.get 1
+                f64.copysign)
+            (func (export "trunc_sat") (param f32) (result i32)
+                local.get 0
+                i32.trunc_sat_f32_s)
+            (func (export "reinterpret") (param f32) (result i32)
+                local.get 0
+                i32.reinterpret_f32))"#,
+    )?;
+    assert_eq!(
+        instance.invoke("min", &[Value::F32(0.0), Value::F32(-0.0)])?,
+        [Value::F32(-0.0)]
+    );
+    let [Value::F32(nan)] = instance.invoke("min", &[Value::F32(f32::NAN), Value::F32(1.0)])?[..]
+    else {
+        panic!("f32.min didn't return an f32");
+    };
+    assert!(nan.is_nan());
+    assert_eq!(
+        instance.invoke("nearest", &[Value::F64(2.5)])?,
+        [Value::F64(2.0)]
+    );
+    assert_eq!(
+        instance.invoke("nearest", &[Value::F64(-0.5)])?,
+        [Value::F64(-0.0)]
+    );
+    assert_eq!(
+        instance.invoke("copysign", &[Value::F64(1.5), Value::F64(-0.0)])?,
+        [Value::F64(-1.5)]
+    );
+    assert_eq!(
+        instance.invoke("trunc_sat", &[Value::F32(1e10)])?,
+        [Value::I32(i32::MAX)]
+    );
+    assert_eq!(
+        instance.invoke("trunc_sat", &[Value::F32(f32::NAN)])?,
+        [Value::I32(0)]
+    );
+    assert_eq!(
+        instance.invoke("reinterpret", &[Value::F32(-0.0)])?,
+        [Value::I32(i32::MIN)]
+    );
+    Ok(())
+}
+
+#[test]
+fn traps() -> Result<()> {
+    let mut instance = instance(
+        r#"(module
+            (type $unary (func (param i32) (result i32)))
+            (memory 1)
+            (table 3 funcref)
+            (elem (i32.const 0) $id $nullary)
+            (func $id (type $unary)
+                local.get 0)
+            (func $nullary)
+            (func (export "unreachable")
+                unreachable)
+            (func (export "div_s") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.div_s)
+            (func (export "trunc") (param f32) (result i32)
+                local.get 0
+                i32.trunc_f32_s)
+            (func (export "load") (param i32) (result i32)
+                local.get 0
+                i32.load)
+            (func (export "call_indirect") (param i32) (result i32)
+                i32.const 1
+                local.get 0
+                call_indirect (type $unary))
+            (func $recurse (export "recurse")
+                call $recurse)
+            (func (export "spin")
+                loop
+                    br 0
+                end))"#,
+    )?;
+    assert_eq!(trap(&mut instance, "unreachable", &[]), Trap::Unreachable);
+    assert_eq!(
+        trap(&mut instance, "div_s", &[Value::I32(1), Value::I32(0)]),
+        Trap::DivisionByZero
+    );
+    assert_eq!(
+        trap(
+            &mut instance,
+            "div_s",
+            &[Value::I32(i32::MIN), Value::I32(-1)]
+        ),
+        Trap::IntegerOverflow
+    );
+    assert_eq!(
+        trap(&mut instance, "trunc", &[Value::F32(f32::NAN)]),
+        Trap::InvalidConversion
+    );
+    assert_eq!(
+        trap(&mut instance, "trunc", &[Value::F32(3e9)]),
+        Trap::IntegerOverflow
+    );
+    assert_eq!(
+        instance.invoke("load", &[Value::I32(65532)])?,
+        [Value::I32(0)]
+    );
+    assert_eq!(
+        trap(&mut instance, "load", &[Value::I32(65533)]),
+        Trap::MemoryOutOfBounds
+    );
+    assert_eq!(
+        instance.invoke("call_indirect", &[Value::I32(0)])?,
+        [Value::I32(1)]
+    );
+    assert_eq!(
+        trap(&mut instance, "call_indirect", &[Value::I32(1)]),
+        Trap::IndirectCallTypeMismatch
+    );
+    assert_eq!(
+        trap(&mut instance, "call_indirect", &[Value::I32(2)]),
+        Trap::UninitializedElement
+    );
+    assert_eq!(
+        trap(&mut instance, "call_indirect", &[Value::I32(3)]),
+        Trap::UndefinedElement
+    );
+    instance.set_max_call_depth(100);
+    assert_eq!(trap(&mut instance, "recurse", &[]), Trap::StackOverflow);
+    instance.set_fuel(Some(1000));
+    assert_eq!(trap(&mut instance, "spin", &[]), Trap::OutOfFuel);
+    assert_eq!(instance.fuel(), Some(0));
+    Ok(())
+}
+
+#[test]
+fn simd() -> Result<()> {
+    let mut instance = instance(
+        r#"(module
+            (func (export "add") (param v128 v128) (result v128)
+                local.get 0
+                local.get 1
+                i32x4.add)
+            (func (export "add_sat") (param v128 v128) (result v128)
+                local.get 0
+                local.get 1
+                i8x16.add_sat_s)
+            (func (export "shuffle") (param v128 v128) (result v128)
+                local.get 0
+                local.get 1
+                i8x16.shuffle 16 17 18 19 0 1 2 3 20 21 22 23 4 5 6 7)
+            (func (export "extract") (param v128) (result i32 i32)
+                local.get 0
+                i16x8.extract_lane_s 1
+                local.get 0
+                i16x8.extract_lane_u 1)
+            (func (export "bitmask") (param v128) (result i32)
+                local.get 0
+                i32x4.bitmask)
+            (func (export "dot") (param v128 v128) (result v128)
+                local.get 0
+                local.get 1
+                i32x4.dot_i16x8_s))"#,
+    )?;
+    assert_eq!(
+        instance.invoke("add", &[i32x4([1, 2, 3, i32::MAX]), i32x4([10, 20, 30, 1])])?,
+        [i32x4([11, 22, 33, i32::MIN])]
+    );
+    assert_eq!(
+        instance.invoke(
+            "add_sat",
+            &[Value::V128([0x7F; 16]), Value::V128([0x01; 16])]
+        )?,
+        [Value::V128([0x7F; 16])]
+    );
+    assert_eq!(
+        instance.invoke("shuffle", &[i32x4([1, 2, 3, 4]), i32x4([5, 6, 7, 8])])?,
+        [i32x4([5, 1, 6, 2])]
+    );
+    assert_eq!(
+        instance.invoke("extract", &[i32x4([-1, 0, 0, 0])])?,
+        [Value::I32(-1), Value::I32(0xFFFF)]
+    );
+    assert_eq!(
+        instance.invoke("bitmask", &[i32x4([-1, 0, -5, 7])])?,
+        [Value::I32(0b0101)]
+    );
+    // Lanes of 16-bit values (1, 2), (3, 4), ... multiplied pairwise and summed.
+    assert_eq!(
+        instance.invoke(
+            "dot",
+            &[
+                i32x4([0x2_0001, 0x4_0003, 0, -1]),
+                i32x4([0x1_0001, 0x1_0001, 0, -1])
+            ]
+        )?,
+        [i32x4([3, 7, 0, 2])]
+    );
+    Ok(())
+}
+
+#[test]
+fn control_flow() -> Result<()> {
+    let mut instance = instance(
+        r#"(module
+            (func $factorial (export "factorial") (param i32) (result i64)
+                (local i64)
+                i64.const 1
+                local.set 1
+                block
+                    loop
+                        local.get 0
+                        i32.eqz
+                        br_if 1
+                        local.get 1
+                        local.get 0
+                        i64.extend_i32_u
+                        i64.mul
+                        local.set 1
+                        local.get 0
+                        i32.const 1
+                        i32.sub
+                        local.set 0
+                        br 0
+                    end
+                end
+                local.get 1)
+            (func (export "switch") (param i32) (result i32)
+                block
+                    block
+                        block
+                            local.get 0
+                            br_table 0 1 2
+                        end
+                        i32.const 10
+                        return
+                    end
+                    i32.const 20
+                    return
+                end
+                i32.const 30)
+            (func (export "branch_values") (param i32) (result i32 i32)
+                i32.const 1
+                block (param i32) (result i32 i32)
+                    i32.const 2
+                    local.get 0
+                    br_if 0
+                    drop
+                    i32.const 3
+                end)
+            (func (export "if_else") (param i32) (result i32)
+                local.get 0
+                if (result i32)
+                    i32.const 1
+                else
+                    i32.const 2
+                end
+                i32.const 10
+                i32.mul)
+            (func (export "select") (param i32) (result i64)
+                i64.const 1
+                i64.const 2
+                local.get 0
+                select))"#,
+    )?;
+    assert_eq!(
+        instance.invoke("factorial", &[Value::I32(20)])?,
+        [Value::I64(2_432_902_008_176_640_000)]
+    );
+    for (index, result) in [(0, 10), (1, 20), (2, 30), (100, 30)] {
+        assert_eq!(
+            instance.invoke("switch", &[Value::I32(index)])?,
+            [Value::I32(result)]
+        );
+    }
+    assert_eq!(
+        instance.invoke("branch_values", &[Value::I32(1)])?,
+        [Value::I32(1), Value::I32(2)]
+    );
+    assert_eq!(
+        instance.invoke("branch_values", &[Value::I32(0)])?,
+        [Value::I32(1), Value::I32(3)]
+    );
+    assert_eq!(
+        instance.invoke("if_else", &[Value::I32(7)])?,
+        [Value::I32(10)]
+    );
+    assert_eq!(
+        instance.invoke("if_else", &[Value::I32(0)])?,
+        [Value::I32(20)]
+    );
+    assert_eq!(
+        instance.invoke("select", &[Value::I32(0)])?,
+        [Value::I64(2)]
+    );
+    Ok(())
+}
@@ -0,0 +1,245 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "legacy-exceptions")]
+
+mod common;
+
+use anyhow::Result;
+use common::{bodies, validate, wat};
+use wasmbin::sections::payload;
+use wasmbin::transforms::legacy_exceptions::{convert_legacy_exceptions, LegacyExceptionsError};
+
+/// Convert `legacy` and check that the result validates and matches `expected`.
+fn assert_converts(legacy: &str, expected: &str) -> Result<()> {
+    let mut module = wat(legacy)?;
+    convert_legacy_exceptions(&mut module)?;
+    validate(&module)?;
+    let expected = wat(expected)?;
+    assert_eq!(bodies(&module)?, bodies(&expected)?);
+    assert_eq!(
+        module.find_std_section::<payload::Type>(),
+        expected.find_std_section::<payload::Type>(),
+    );
+    Ok(())
+}
+
+fn convert_err(legacy: &str) -> Result<LegacyExceptionsError> {
+    let mut module = wat(legacy)?;
+    let original = module.clone();
+    let err = convert_legacy_exceptions(&mut module).expect_err("conversion should fail");
+    assert_eq!(module, original, "module must be left untouched on error");
+    Ok(err)
+}
+
+#[test]
+fn catch_and_catch_all() -> Result<()> {
+    assert_converts(
+        r#"(module
+            (tag $e (param i32))
+            (func (result i32)
+                try (result i32)
+                    i32.const 1
+                    throw $e
+                catch $e
+                catch_all
+                    i32.const 0
+                end))"#,
+        r#"(module
+            (tag $e (param i32))
+            (func (result i32)
+                block (result i32)
+                    block
+                        block (result i32)
+                            try_table (result i32) (catch $e 0) (catch_all 1)
+                                i32.const 1
+                                throw $e
+                            end
+                            br 2
+                        end
+                        br 1
+                    end
+                    i32.const 0
+                end))"#,
+    )
+}
+
+#[test]
+fn rethrow_uses_exnref_local() -> Result<()> {
+    assert_converts(
+        r#"(module
+            (type (func (param i32)))
+            (tag $e (type 0))
+            (func (type 0)
+                try
+                    local.get 0
+                    throw $e
+                catch $e
+                    drop
+                    rethrow 0
+                end))"#,
+        r#"(module
+            (type (func (param i32)))
+            (type (func (result i32 exnref)))
+            (tag $e (type 0))
+            (func (type 0) (local exnref)
+                block
+                    block (type 1)
+                        try_table (catch_ref $e 0)
+                            local.get 0
+                            throw $e
+                        end
+                        br 1
+                    end
+                    local.set 1
+                    drop
+                    local.get 1
+                    throw_ref
+                end))"#,
+    )
+}
+
+#[test]
+fn delegate_becomes_block() -> Result<()> {
+    assert_converts(
+        r#"(module
+            (func $thrower)
+            (func
+                try
+                    try
+                        call $thrower
+                    delegate 0
+                catch_all
+                end))"#,
+        r#"(module
+            (func $thrower)
+            (func
+                block
+                    block
+                        try_table (catch_all 0)
+                            block
+                                call $thrower
+                            end
+                        end
+                        br 1
+                    end
+                end))"#,
+    )
+}
+
+#[test]
+fn branches_are_remapped() -> Result<()> {
+    assert_converts(
+        r#"(module
+            (func $thrower)
+            (func (result i32)
+                block (result i32)
+                    try
+                        call $thrower
+                        i32.const 5
+                        br 1
+                    catch_all
+                        i32.const 7
+                        br 1
+                    end
+                    i32.const 0
+                end))"#,
+        r#"(module
+            (func $thrower)
+            (func (result i32)
+                block (result i32)
+                    block
+                        block
+                            try_table (catch_all 0)
+                                call $thrower
+                                i32.const 5
+                                br 3
+                            end
+                            br 1
+                        end
+                        i32.const 7
+                        br 1
+                    end
+                    i32.const 0
+                end))"#,
+    )
+}
+
+#[test]
+fn nested_in_try_table() -> Result<()> {
+    assert_converts(
+        r#"(module
+            (func $thrower)
+            (func
+                block
+                    try_table (catch_all 0)
+                        try
+                            call $thrower
+                            br 2
+                        catch_all
+                        end
+                    end
+                end))"#,
+        r#"(module
+            (func $thrower)
+            (func
+                block
+                    try_table (catch_all 0)
+                        block
+                            block
+                                try_table (catch_all 0)
+                                    call $thrower
+                                    br 4
+                                end
+                                br 1
+                            end
+                        end
+                    end
+                end))"#,
+    )
+}
+
+#[test]
+fn delegate_across_handlers_is_rejected() -> Result<()> {
+    let err = convert_err(
+        r#"(module
+            (func $thrower)
+            (func
+                try
+                    try
+                        try
+                            call $thrower
+                        delegate 1
+                    catch_all
+                    end
+                catch_all
+                end))"#,
+    )?;
+    assert!(matches!(err, LegacyExceptionsError::UnsupportedDelegate));
+    Ok(())
+}
+
+#[test]
+fn rethrow_outside_of_catch_is_rejected() -> Result<()> {
+    let err = convert_err(
+        r#"(module
+            (func
+                try
+                    rethrow 0
+                catch_all
+                end))"#,
+    )?;
+    assert!(matches!(err, LegacyExceptionsError::InvalidRethrow));
+    Ok(())
+}
@@ -0,0 +1,142 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use wasmbin::sections::linking::{RelocEntry, RelocError, RelocType};
+
+fn entry(ty: RelocType) -> RelocEntry {
+    RelocEntry {
+        ty,
+        offset: 1,
+        index: 0,
+        addend: 0,
+    }
+}
+
+/// Payload with a zero-initialized relocation site at offset 1, surrounded by
+/// bytes that must not be touched.
+fn payload(ty: RelocType) -> Vec<u8> {
+    let mut payload = vec![0xAA];
+    let size = ty.site_size();
+    match ty {
+        // Padded LEB128 zero.
+        RelocType::FunctionIndexLeb
+        | RelocType::TableIndexSleb
+        | RelocType::MemoryAddrLeb64
+        | RelocType::MemoryAddrSleb64 => {
+            payload.extend(std::iter::repeat_n(0x80, size - 1));
+            payload.push(0);
+        }
+        _ => payload.extend(std::iter::repeat_n(0, size)),
+    }
+    payload.push(0xBB);
+    payload
+}
+
+/// Write each of `values` and check that it reads back unchanged.
+fn assert_round_trip(ty: RelocType, values: &[i64]) {
+    let entry = entry(ty);
+    let mut payload = payload(ty);
+    for &value in values {
+        entry.write(&mut payload, value).unwrap();
+        assert_eq!(entry.read(&payload).unwrap(), value, "{ty:?} {value}");
+        assert_eq!(payload.len(), ty.site_size() + 2);
+        assert_eq!(payload[0], 0xAA);
+        assert_eq!(payload[ty.site_size() + 1], 0xBB);
+    }
+}
+
+fn assert_overflow(ty: RelocType, value: i64) {
+    let err = entry(ty).write(&mut payload(ty), value).unwrap_err();
+    assert!(matches!(err, RelocError::Overflow { .. }), "{ty:?} {value}");
+}
+
+#[test]
+fn leb32() {
+    let ty = RelocType::FunctionIndexLeb;
+    assert_round_trip(ty, &[0, 1, 0x7F, 0x80, i64::from(u32::MAX)]);
+    assert_overflow(ty, -1);
+    assert_overflow(ty, i64::from(u32::MAX) + 1);
+}
+
+#[test]
+fn sleb32() {
+    let ty = RelocType::TableIndexSleb;
+    assert_round_trip(
+        ty,
+        &[0, -1, 0x40, -0x41, i64::from(i32::MIN), i64::from(i32::MAX)],
+    );
+    assert_overflow(ty, i64::from(i32::MAX) + 1);
+    assert_overflow(ty, i64::from(i32::MIN) - 1);
+}
+
+#[test]
+fn i32() {
+    let ty = RelocType::MemoryAddrI32;
+    assert_round_trip(ty, &[0, 1, i64::from(u32::MAX)]);
+    // Negative values are stored as their two's complement and read back as unsigned.
+    let mut payload = payload(ty);
+    entry(ty).write(&mut payload, -1).unwrap();
+    assert_eq!(entry(ty).read(&payload).unwrap(), i64::from(u32::MAX));
+    assert_overflow(ty, i64::from(u32::MAX) + 1);
+    assert_overflow(ty, i64::from(i32::MIN) - 1);
+}
+
+#[test]
+fn leb64() {
+    let ty = RelocType::MemoryAddrLeb64;
+    assert_round_trip(ty, &[0, 1, i64::from(u32::MAX) + 1, i64::MAX, i64::MIN, -1]);
+    // Unsigned values above `i64::MAX` are encoded as such rather than sign-extended.
+    let mut payload = payload(ty);
+    entry(ty).write(&mut payload, -1).unwrap();
+    assert_eq!(
+        payload[1..11],
+        [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]
+    );
+}
+
+#[test]
+fn sleb64() {
+    let ty = RelocType::MemoryAddrSleb64;
+    assert_round_trip(ty, &[0, -1, 0x40, -0x41, i64::MIN, i64::MAX]);
+    let mut payload = payload(ty);
+    entry(ty).write(&mut payload, -1).unwrap();
+    assert_eq!(
+        payload[1..11],
+        [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]
+    );
+}
+
+#[test]
+fn i64() {
+    assert_round_trip(RelocType::MemoryAddrI64, &[0, -1, i64::MIN, i64::MAX]);
+}
+
+#[test]
+fn unpadded_leb_is_rejected() {
+    let ty = RelocType::FunctionIndexLeb;
+    let mut payload = vec![0xAA, 0x00, 0, 0, 0, 0];
+    let err = entry(ty).write(&mut payload, 1).unwrap_err();
+    assert!(matches!(err, RelocError::NotPadded { offset: 1 }));
+}
+
+#[test]
+fn out_of_bounds_site_is_rejected() {
+    let ty = RelocType::MemoryAddrI64;
+    let mut payload = payload(ty);
+    payload.truncate(ty.site_size());
+    assert!(matches!(
+        entry(ty).read(&payload).unwrap_err(),
+        RelocError::OutOfBounds { offset: 1 }
+    ));
+}
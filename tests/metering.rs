@@ -0,0 +1,227 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::{bodies, validate, wat};
+use wasmbin::indices::GlobalId;
+use wasmbin::instructions::Instruction;
+use wasmbin::sections::ImportPath;
+use wasmbin::transforms::interface::InterfaceError;
+use wasmbin::transforms::metering::{
+    meter, FuelCounter, Metering, MeteringError, OutOfFuel, UniformCost,
+};
+use wasmbin::types::BlockType;
+use wasmbin::Module;
+
+/// Sums the numbers from its argument down to 1, which must be positive.
+///
+/// Charges 1 for the `loop`, 9 for each iteration and 1 after the loop.
+const SUM: &str = r#"(module
+    (func (export "sum") (param i32) (result i32) (local i32)
+        loop
+            local.get 1
+            local.get 0
+            i32.add
+            local.set 1
+            local.get 0
+            i32.const 1
+            i32.sub
+            local.tee 0
+            br_if 0
+        end
+        local.get 1))"#;
+
+fn exported(initial: u64, out_of_fuel: OutOfFuel) -> Metering<UniformCost> {
+    Metering {
+        costs: UniformCost,
+        counter: FuelCounter::Exported {
+            name: "fuel".to_owned(),
+            initial,
+        },
+        out_of_fuel,
+    }
+}
+
+fn refill() -> OutOfFuel {
+    OutOfFuel::Call(ImportPath {
+        module: "env".to_owned(),
+        name: "refill".to_owned(),
+    })
+}
+
+fn metered(src: &str, metering: &Metering<UniformCost>) -> Result<(Module, GlobalId)> {
+    let mut module = wat(src)?;
+    let counter = meter(&mut module, metering)?;
+    validate(&module)?;
+    Ok((module, counter))
+}
+
+/// Instructions charging `cost` with [`OutOfFuel::Trap`].
+fn charge(counter: GlobalId, cost: i64) -> Vec<Instruction> {
+    vec![
+        Instruction::GlobalGet(counter),
+        Instruction::I64Const(cost),
+        Instruction::I64LtU,
+        Instruction::IfStart(BlockType::Empty),
+        Instruction::Unreachable,
+        Instruction::End,
+        Instruction::GlobalGet(counter),
+        Instruction::I64Const(cost),
+        Instruction::I64Sub,
+        Instruction::GlobalSet(counter),
+    ]
+}
+
+#[test]
+fn charges_precede_basic_blocks() -> Result<()> {
+    let (module, counter) = metered(SUM, &exported(0, OutOfFuel::Trap))?;
+    let original = bodies(&wat(SUM)?)?.remove(0).expr;
+    let (head, rest) = original.split_at(1);
+    let (iteration, rest) = rest.split_at(9);
+    let mut expected = charge(counter, 1);
+    expected.extend_from_slice(head);
+    expected.extend(charge(counter, 9));
+    expected.extend_from_slice(iteration);
+    // `end` of the loop costs nothing, so it doesn't get its own charge.
+    expected.push(rest[0].clone());
+    expected.extend(charge(counter, 1));
+    expected.extend_from_slice(&rest[1..]);
+    assert_eq!(bodies(&module)?[0].expr, expected);
+    Ok(())
+}
+
+#[test]
+fn duplicate_export_leaves_module_untouched() -> Result<()> {
+    let mut module = wat(r#"(module
+        (global (export "fuel") i32 (i32.const 0))
+        (func (export "f")))"#)?;
+    let original = module.clone();
+    let err = meter(&mut module, &exported(10, refill())).unwrap_err();
+    assert!(matches!(
+        err,
+        MeteringError::Interface(InterfaceError::DuplicateExport(name)) if name == "fuel"
+    ));
+    assert_eq!(module, original);
+    Ok(())
+}
+
+#[cfg(feature = "exception-handling")]
+#[test]
+fn try_table_bodies_are_metered() -> Result<()> {
+    let (module, counter) = metered(
+        r#"(module
+            (func
+                nop
+                try_table
+                    nop
+                    nop
+                end))"#,
+        &exported(0, OutOfFuel::Trap),
+    )?;
+    let body = bodies(&module)?.remove(0).expr;
+    // `nop` and `try_table` are charged before the block...
+    let mut expected = charge(counter, 2);
+    expected.push(Instruction::Nop);
+    assert_eq!(body[..expected.len()], expected);
+    let Some(Instruction::TryTable(try_table)) = body.get(expected.len()) else {
+        panic!("expected try_table, got {body:?}");
+    };
+    // ...and the instructions inside it are charged on entry.
+    let mut expected = charge(counter, 2);
+    expected.extend([Instruction::Nop, Instruction::Nop]);
+    assert_eq!(try_table.instructions, expected);
+    Ok(())
+}
+
+#[cfg(feature = "interp")]
+mod interp {
+    use super::*;
+    use wasmbin::interp::{Host, Instance, InterpError, Store, Trap, Value};
+    use wasmbin::types::FuncType;
+
+    /// Host refilling a fixed amount of fuel on every call to `env.refill`.
+    struct Refill {
+        amount: i64,
+        counter: GlobalId,
+        /// Costs passed to `env.refill`.
+        calls: Vec<i64>,
+    }
+
+    impl Host for Refill {
+        fn call(
+            &mut self,
+            _import: &ImportPath,
+            _ty: &FuncType,
+            args: &[Value],
+            store: &mut Store,
+        ) -> Result<Vec<Value>, Trap> {
+            let [Value::I64(cost)] = *args else {
+                return Err(Trap::Host(format!("unexpected arguments {args:?}")));
+            };
+            self.calls.push(cost);
+            let Value::I64(fuel) = &mut store.globals[self.counter.index as usize] else {
+                return Err(Trap::Host("fuel counter isn't an i64".to_owned()));
+            };
+            *fuel += self.amount;
+            Ok(Vec::new())
+        }
+    }
+
+    fn instance(module: &Module, counter: GlobalId, amount: i64) -> Result<Instance<Refill>> {
+        Ok(Instance::instantiate(
+            module,
+            Refill {
+                amount,
+                counter,
+                calls: Vec::new(),
+            },
+        )?)
+    }
+
+    fn fuel(instance: &Instance<Refill>) -> &Value {
+        &instance.store.globals[instance.host.counter.index as usize]
+    }
+
+    #[test]
+    fn fuel_is_consumed_per_block() -> Result<()> {
+        let (module, counter) = metered(SUM, &exported(100, OutOfFuel::Trap))?;
+        let mut instance = instance(&module, counter, 0)?;
+        assert_eq!(instance.invoke("sum", &[Value::I32(3)])?, [Value::I32(6)]);
+        assert_eq!(*fuel(&instance), Value::I64(100 - (1 + 3 * 9 + 1)));
+        Ok(())
+    }
+
+    #[test]
+    fn running_out_of_fuel_traps() -> Result<()> {
+        let (module, counter) = metered(SUM, &exported(1 + 3 * 9, OutOfFuel::Trap))?;
+        let mut instance = instance(&module, counter, 0)?;
+        let err = instance.invoke("sum", &[Value::I32(3)]).unwrap_err();
+        assert!(matches!(err, InterpError::Trap(Trap::Unreachable)));
+        // The block that ran out isn't charged.
+        assert_eq!(*fuel(&instance), Value::I64(0));
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_fuel_handler_is_called_until_block_is_affordable() -> Result<()> {
+        let (module, counter) = metered(SUM, &exported(0, refill()))?;
+        let mut instance = instance(&module, counter, 4)?;
+        assert_eq!(instance.invoke("sum", &[Value::I32(3)])?, [Value::I32(6)]);
+        assert_eq!(instance.host.calls, [1, 9, 9, 9, 9, 9, 9, 1]);
+        assert_eq!(*fuel(&instance), Value::I64(3));
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::wat;
+use wasmbin::builtins::{Blob, UnparsedBytes};
+use wasmbin::sections::{CustomSection, ProducerField, RawCustomSection, Section};
+use wasmbin::transforms::producers::{
+    add_module_producer, encode_with_provenance, read_producers, ProducersError, LANGUAGE,
+    PROCESSED_BY,
+};
+use wasmbin::Module;
+
+fn producers_sections(module: &Module) -> usize {
+    module
+        .sections
+        .iter()
+        .filter(|section| match section {
+            Section::Custom(custom) => custom
+                .try_contents()
+                .is_ok_and(|custom| custom.name() == "producers"),
+            _ => false,
+        })
+        .count()
+}
+
+fn processed_by(fields: &[ProducerField]) -> Vec<(&str, &str)> {
+    fields
+        .iter()
+        .filter(|field| field.name == PROCESSED_BY)
+        .flat_map(|field| &field.values)
+        .map(|value| (value.name.as_str(), value.version.as_str()))
+        .collect()
+}
+
+#[test]
+fn provenance_is_merged_into_existing_section() -> Result<()> {
+    let mut module = wat("(module (func))")?;
+    add_module_producer(&mut module, LANGUAGE, "Rust", "")?;
+    add_module_producer(&mut module, PROCESSED_BY, "rustc", "1.0")?;
+    let original = module.clone();
+
+    let encoded = Module::decode_from(encode_with_provenance(&module, Vec::new())?.as_slice())?;
+    assert_eq!(module, original);
+    assert_eq!(producers_sections(&encoded), 1);
+    let fields = read_producers(&encoded)?;
+    assert_eq!(fields[0].name, LANGUAGE);
+    assert_eq!(
+        processed_by(&fields),
+        [
+            ("rustc", "1.0"),
+            (env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn malformed_section_is_reported() -> Result<()> {
+    let mut module = wat("(module (func))")?;
+    module
+        .sections
+        .push(Section::Custom(Blob::from(CustomSection::Other(
+            RawCustomSection {
+                name: "producers".to_owned(),
+                data: UnparsedBytes {
+                    bytes: vec![0xFF, 0xFF],
+                },
+            },
+        ))));
+    // Decode again so that the section is recognised as a (malformed) producers section.
+    let module = Module::decode_from(module.encode_into(Vec::new())?.as_slice())?;
+    assert!(read_producers(&module).is_err());
+
+    let err = encode_with_provenance(&module, Vec::new()).unwrap_err();
+    assert!(matches!(err, ProducersError::Decode(_)));
+    Ok(())
+}
+
+#[test]
+fn provenance_is_written_in_place() -> Result<()> {
+    let mut module = wat("(module (func))")?;
+    add_module_producer(&mut module, LANGUAGE, "Rust", "")?;
+    module
+        .sections
+        .push(Section::Custom(Blob::from(CustomSection::Other(
+            RawCustomSection {
+                name: "trailer".to_owned(),
+                data: UnparsedBytes {
+                    bytes: vec![1, 2, 3],
+                },
+            },
+        ))));
+
+    let mut expected = module.clone();
+    add_module_producer(
+        &mut expected,
+        PROCESSED_BY,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    )?;
+    assert_eq!(
+        encode_with_provenance(&module, Vec::new())?,
+        expected.encode_into(Vec::new())?
+    );
+    Ok(())
+}
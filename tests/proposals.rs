@@ -0,0 +1,318 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Tests of each proposal only use some of the helpers.
+#![allow(dead_code)]
+
+mod common;
+
+use anyhow::Result;
+use common::{bodies, wat_binary};
+use wasmbin::instructions::Instruction;
+use wasmbin::io::{Decode, DecodeError, Encode};
+use wasmbin::visit::{Visit, VisitError};
+use wasmbin::Module;
+
+/// Decode a module from its text format, including all lazily decoded parts, and check that it
+/// encodes back to the same bytes.
+fn round_trip(src: &str) -> Result<Module> {
+    let binary = wat_binary(src)?;
+    let mut module = Module::decode_from(binary.as_slice())?;
+    module.visit_mut(|()| {}).map_err(|err| match err {
+        VisitError::LazyDecode(err) => err,
+        VisitError::Custom(err) => match err {},
+    })?;
+    assert_eq!(module.encode_into(Vec::new())?, binary);
+    Ok(module)
+}
+
+/// Instructions of all function bodies in order.
+fn instrs(module: &Module) -> Result<Vec<Instruction>> {
+    Ok(bodies(module)?
+        .into_iter()
+        .flat_map(|body| body.expr)
+        .collect())
+}
+
+/// Check that `instr` is encoded as `bytes` and decoded back.
+fn assert_encoding(instr: &Instruction, bytes: &[u8]) -> Result<(), DecodeError> {
+    let mut encoded = Vec::new();
+    instr.encode(&mut encoded)?;
+    assert_eq!(encoded, bytes, "encoding of {instr:?}");
+    assert_eq!(&Instruction::decode(&mut &*encoded)?, instr);
+    Ok(())
+}
+
+#[cfg(feature = "wide-arithmetic")]
+#[test]
+fn wide_arithmetic() -> Result<()> {
+    use wasmbin::instructions::Misc;
+
+    let module = round_trip(
+        r#"(module
+            (func (param i64 i64 i64 i64) (result i64 i64)
+                local.get 0
+                local.get 1
+                local.get 2
+                local.get 3
+                i64.add128
+                local.get 2
+                local.get 3
+                i64.sub128
+                drop
+                drop)
+            (func (param i64 i64) (result i64 i64 i64 i64)
+                local.get 0
+                local.get 1
+                i64.mul_wide_s
+                local.get 0
+                local.get 1
+                i64.mul_wide_u))"#,
+    )?;
+    let wide: Vec<_> = instrs(&module)?
+        .into_iter()
+        .filter(|instr| matches!(instr, Instruction::Misc(_)))
+        .collect();
+    assert_eq!(
+        wide,
+        [
+            Instruction::Misc(Misc::I64Add128),
+            Instruction::Misc(Misc::I64Sub128),
+            Instruction::Misc(Misc::I64MulWideS),
+            Instruction::Misc(Misc::I64MulWideU),
+        ]
+    );
+    assert_encoding(&Instruction::Misc(Misc::I64Add128), &[0xFC, 0x13])?;
+    assert_encoding(&Instruction::Misc(Misc::I64MulWideU), &[0xFC, 0x16])?;
+    Ok(())
+}
+
+// The text format parser doesn't support half-precision instructions yet, so they are checked
+// against the opcodes from the proposal directly.
+#[cfg(feature = "fp16")]
+#[test]
+fn fp16() -> Result<()> {
+    use wasmbin::indices::MemId;
+    use wasmbin::instructions::simd::{LaneId8, SIMD};
+    use wasmbin::instructions::{MemArg, Misc};
+
+    let mem_arg = MemArg {
+        align_log2: 1,
+        memory: MemId::from(0),
+        offset: 8,
+    };
+    assert_encoding(
+        &Instruction::Misc(Misc::F32LoadF16(mem_arg.clone())),
+        &[0xFC, 0x30, 0x01, 0x08],
+    )?;
+    assert_encoding(
+        &Instruction::Misc(Misc::F32StoreF16(mem_arg)),
+        &[0xFC, 0x31, 0x01, 0x08],
+    )?;
+    for (simd, bytes) in [
+        (SIMD::F16x8Splat, &[0xA0, 0x02][..]),
+        (
+            SIMD::F16x8ExtractLane(LaneId8::try_from(7).unwrap()),
+            &[0xA1, 0x02, 0x07],
+        ),
+        (
+            SIMD::F16x8ReplaceLane(LaneId8::try_from(0).unwrap()),
+            &[0xA2, 0x02, 0x00],
+        ),
+        (SIMD::F16x8Abs, &[0xB0, 0x02]),
+        (SIMD::F16x8Nearest, &[0xB6, 0x02]),
+        (SIMD::F16x8Ge, &[0xBC, 0x02]),
+        (SIMD::F16x8Add, &[0xBD, 0x02]),
+        (SIMD::F16x8Pmax, &[0xC4, 0x02]),
+        (SIMD::I16x8TruncSatF16x8S, &[0xC5, 0x02]),
+        (SIMD::F16x8ConvertI16x8U, &[0xC8, 0x02]),
+        (SIMD::F16x8DemoteF32x4Zero, &[0xC9, 0x02]),
+        (SIMD::F32x4PromoteLowF16x8, &[0xCA, 0x02]),
+        (SIMD::F16x8RelaxedMadd, &[0xCE, 0x02]),
+        (SIMD::F16x8RelaxedNmadd, &[0xCF, 0x02]),
+    ] {
+        let mut expected = vec![0xFD];
+        expected.extend_from_slice(bytes);
+        assert_encoding(&Instruction::SIMD(simd), &expected)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "stack-switching")]
+#[test]
+fn stack_switching() -> Result<()> {
+    use wasmbin::indices::{ExceptionId, LabelId, TypeId};
+    use wasmbin::instructions::stack_switching::{Handler, Resume};
+    use wasmbin::sections::payload;
+    use wasmbin::types::{ContType, FuncType, RefType, TypeDef, ValueType};
+
+    let module = round_trip(
+        r#"(module
+            (type $f (func))
+            (type $c (cont $f))
+            (tag $t)
+            (func $a)
+            (elem declare func $a)
+            (func (param contref)
+                ref.func $a
+                cont.new $c
+                cont.bind $c $c
+                resume $c (on $t 0) (on $t switch)
+                suspend $t
+                ref.null cont
+                switch $c $t
+                unreachable))"#,
+    )?;
+    let types = module
+        .find_std_section::<payload::Type>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(
+        types,
+        &[
+            TypeDef::Func(FuncType {
+                params: vec![],
+                results: vec![],
+            }),
+            TypeDef::Cont(ContType {
+                func_type: TypeId::from(0),
+            }),
+            TypeDef::Func(FuncType {
+                params: vec![ValueType::Ref(RefType::Cont)],
+                results: vec![],
+            }),
+        ]
+    );
+    assert_eq!(types[1].as_func(), None);
+    let c = TypeId::from(1);
+    let t = ExceptionId::from(0);
+    assert_eq!(
+        instrs(&module)?,
+        [
+            Instruction::RefFunc(0.into()),
+            Instruction::ContNew(c),
+            Instruction::ContBind { from: c, to: c },
+            Instruction::Resume(Resume {
+                cont_type: c,
+                handlers: vec![
+                    Handler {
+                        tag: t,
+                        target: Some(LabelId::from(0)),
+                    },
+                    Handler {
+                        tag: t,
+                        target: None
+                    },
+                ],
+            }),
+            Instruction::Suspend(t),
+            Instruction::RefNull(RefType::Cont),
+            Instruction::Switch {
+                cont_type: c,
+                tag: t,
+            },
+            Instruction::Unreachable,
+        ]
+    );
+    let mut encoded = Vec::new();
+    types[1].encode(&mut encoded)?;
+    assert_eq!(encoded, [0x5D, 0x00]);
+    Ok(())
+}
+
+#[cfg(feature = "threads")]
+#[test]
+fn shared_everything_threads() -> Result<()> {
+    use wasmbin::sections::payload;
+    use wasmbin::types::{GlobalType, Limits, RefType, SharedRefType, TableType, ValueType};
+
+    let module = round_trip(
+        r#"(module
+            (table 1 funcref)
+            (table shared 1 (shared funcref))
+            (table shared 1 2 (shared externref))
+            (global (mut i32) (i32.const 0))
+            (global (shared i64) (i64.const 0))
+            (global (shared mut i32) (i32.const 0))
+            (func (param (shared externref))
+                ref.null (shared func)
+                drop
+                atomic.fence))"#,
+    )?;
+    let tables = module
+        .find_std_section::<payload::Table>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(
+        tables,
+        &[
+            TableType {
+                elem_type: RefType::Func,
+                is_shared: false,
+                limits: Limits { min: 1, max: None },
+            },
+            TableType {
+                elem_type: RefType::Shared(SharedRefType::Func),
+                is_shared: true,
+                limits: Limits { min: 1, max: None },
+            },
+            TableType {
+                elem_type: RefType::Shared(SharedRefType::Extern),
+                is_shared: true,
+                limits: Limits {
+                    min: 1,
+                    max: Some(2),
+                },
+            },
+        ]
+    );
+    let globals: Vec<_> = module
+        .find_std_section::<payload::Global>()
+        .unwrap()
+        .try_contents()?
+        .iter()
+        .map(|global| global.ty.clone())
+        .collect();
+    assert_eq!(
+        globals,
+        [
+            GlobalType {
+                value_type: ValueType::I32,
+                mutable: true,
+                is_shared: false,
+            },
+            GlobalType {
+                value_type: ValueType::I64,
+                mutable: false,
+                is_shared: true,
+            },
+            GlobalType {
+                value_type: ValueType::I32,
+                mutable: true,
+                is_shared: true,
+            },
+        ]
+    );
+    let mut encoded = Vec::new();
+    globals[2].encode(&mut encoded)?;
+    assert_eq!(encoded, [0x7F, 0x03]);
+    let mut encoded = Vec::new();
+    tables[2].encode(&mut encoded)?;
+    assert_eq!(encoded, [0x65, 0x6F, 0x03, 0x01, 0x02]);
+    assert_eq!(
+        instrs(&module)?[0],
+        Instruction::RefNull(RefType::Shared(SharedRefType::Func))
+    );
+    Ok(())
+}
@@ -0,0 +1,157 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "exception-handling")]
+
+mod common;
+
+use anyhow::Result;
+use common::{bodies, validate, wat};
+use wasmbin::builtins::FloatConst;
+use wasmbin::instructions::{CallIndirect, Instruction};
+use wasmbin::sections::linking::{RelocEntry, RelocError, RelocType};
+use wasmbin::transforms::relocations::{apply_relocations, RelocatedCode};
+use wasmbin::Module;
+
+// The `f32.const` immediate contains a byte with the high bit set, which must
+// not be mistaken for a part of a LEB128 value.
+const MODULE: &str = r#"(module
+    (memory 1)
+    (global (mut i32) (i32.const 0))
+    (func $a)
+    (func $b)
+    (func
+        f32.const 1.5
+        drop
+        call $a
+        block
+            try_table (catch_all 0)
+                f32.const 1.5
+                drop
+                call $b
+                i32.const 0
+                global.set 0
+            end
+        end))"#;
+
+fn entry(ty: RelocType, offset: u32, index: u32) -> RelocEntry {
+    RelocEntry {
+        ty,
+        offset,
+        index,
+        addend: if ty.has_addend() { 4 } else { 0 },
+    }
+}
+
+/// Module with relocations for all the symbol references in the last function, encoded and
+/// decoded again so that the relocations refer to the padded immediates.
+fn relocatable() -> Result<Module> {
+    let mut module = wat(MODULE)?;
+    let mut code = RelocatedCode::from_module(&module)?;
+    let instrs = &mut code.funcs[2].instrs;
+    assert_eq!(instrs[2].instr, Instruction::Call(0.into()));
+    instrs[2].relocs = vec![entry(RelocType::FunctionIndexLeb, 1, 0)];
+    // `try_table`, block type, catch count, catch kind and label, then 5 bytes of
+    // `f32.const` and `drop` precede the `call` of the body.
+    instrs[4].relocs = vec![
+        entry(RelocType::FunctionIndexLeb, 12, 1),
+        entry(RelocType::MemoryAddrSleb, 14, 2),
+        entry(RelocType::GlobalIndexLeb, 16, 3),
+    ];
+    code.write_to_module(&mut module)?;
+    Ok(Module::decode_from(
+        module.encode_into(Vec::new())?.as_slice(),
+    )?)
+}
+
+/// Swap the functions and point the memory address at 100 plus addend.
+fn resolve(entry: &RelocEntry) -> Option<i64> {
+    Some(match entry.index {
+        0 => 1,
+        1 => 0,
+        2 => 100 + entry.addend,
+        _ => 0,
+    })
+}
+
+#[test]
+fn relocations_follow_edits() -> Result<()> {
+    let mut module = relocatable()?;
+    let mut code = RelocatedCode::from_module(&module)?;
+    let instrs = &mut code.funcs[2].instrs;
+    assert_eq!(instrs[2].relocs.len(), 1);
+    assert_eq!(instrs[4].relocs.len(), 3);
+    instrs.insert(0, Instruction::Nop.into());
+    let Instruction::TryTable(try_table) = &mut instrs[5].instr else {
+        panic!("expected try_table, got {:?}", instrs[5].instr);
+    };
+    try_table.instructions.splice(
+        0..0,
+        [
+            Instruction::F64Const(FloatConst { value: -2.0 }),
+            Instruction::Drop,
+        ],
+    );
+    code.write_to_module(&mut module)?;
+    apply_relocations(&mut module, resolve)?;
+    validate(&module)?;
+    let expected = wat(r#"(module
+        (memory 1)
+        (global (mut i32) (i32.const 0))
+        (func $a)
+        (func $b)
+        (func
+            nop
+            f32.const 1.5
+            drop
+            call $b
+            block
+                try_table (catch_all 0)
+                    f64.const -2.0
+                    drop
+                    f32.const 1.5
+                    drop
+                    call $a
+                    i32.const 104
+                    global.set 0
+                end
+            end))"#)?;
+    assert_eq!(bodies(&module)?, bodies(&expected)?);
+    Ok(())
+}
+
+#[test]
+fn unchanged_code_is_preserved() -> Result<()> {
+    let module = relocatable()?;
+    let mut rewritten = module.clone();
+    RelocatedCode::from_module(&module)?.write_to_module(&mut rewritten)?;
+    assert_eq!(
+        rewritten.encode_into(Vec::new())?,
+        module.encode_into(Vec::new())?
+    );
+    Ok(())
+}
+
+#[test]
+fn mismatched_immediates_are_rejected() -> Result<()> {
+    let mut module = relocatable()?;
+    let mut code = RelocatedCode::from_module(&module)?;
+    code.funcs[2].instrs[2].instr = Instruction::CallIndirect(CallIndirect {
+        ty: 0.into(),
+        table: 0.into(),
+    });
+    let err = code.write_to_module(&mut module).unwrap_err();
+    assert!(matches!(err, RelocError::MisplacedSite { offset: 1 }));
+    Ok(())
+}
@@ -0,0 +1,246 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "interp")]
+
+mod common;
+
+use anyhow::Result;
+use common::{validate, wat};
+use wasmbin::instructions::Instruction;
+use wasmbin::interp::{Host, Instance, Store, Trap, Value};
+use wasmbin::sections::{payload, DataInit, ImportPath, Kind};
+use wasmbin::transforms::snapshot::{snapshot, SnapshotError};
+use wasmbin::types::FuncType;
+use wasmbin::Module;
+
+/// Host providing `env.get`, which returns an external reference.
+struct Env;
+
+impl Host for Env {
+    fn call(
+        &mut self,
+        import: &ImportPath,
+        _ty: &FuncType,
+        _args: &[Value],
+        _store: &mut Store,
+    ) -> Result<Vec<Value>, Trap> {
+        match import.name.as_str() {
+            "get" => Ok(vec![Value::Extern(1)]),
+            _ => Err(Trap::Host(format!(
+                "unexpected call to {}.{}",
+                import.module, import.name
+            ))),
+        }
+    }
+}
+
+fn snapshotted(src: &str) -> Result<Module> {
+    let mut module = wat(src)?;
+    let mut instance = Instance::new(&module, Env)?;
+    snapshot(&mut module, &mut instance, "init")?;
+    validate(&module)?;
+    Ok(module)
+}
+
+/// Active data segments as memory offsets and contents.
+fn active_data(module: &Module) -> Result<Vec<(i32, Vec<u8>)>> {
+    let Some(data) = module.find_std_section::<payload::Data>() else {
+        return Ok(Vec::new());
+    };
+    Ok(data
+        .try_contents()?
+        .iter()
+        .filter_map(|segment| match &segment.init {
+            DataInit::Active { offset } => match offset.as_slice() {
+                [Instruction::I32Const(offset)] => Some((*offset, segment.blob.clone())),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect())
+}
+
+#[test]
+fn state_is_baked_in() -> Result<()> {
+    let module = snapshotted(
+        r#"(module
+            (memory 1 4)
+            (global (mut i32) (i32.const 0))
+            (global (mut i64) (i64.const 0))
+            (global i32 (i32.const 5))
+            (start $start)
+            (func $start
+                (global.set 0 (i32.const 1)))
+            (func (export "init")
+                (drop (memory.grow (i32.const 1)))
+                (i32.store (i32.const 100) (i32.const 0x04030201))
+                (i32.store8 (i32.const 70000) (i32.const 7))
+                (global.set 0 (i32.add (global.get 0) (i32.const 10)))
+                (global.set 1 (i64.const 42)))
+            (func (export "get") (result i32)
+                (global.get 0))
+            (data (i32.const 0) "abc"))"#,
+    )?;
+    // Initialization doesn't run again.
+    assert!(module.find_std_section::<payload::Start>().is_none());
+    let exports = module
+        .find_std_section::<payload::Export>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(
+        exports
+            .iter()
+            .map(|export| export.name.as_str())
+            .collect::<Vec<_>>(),
+        ["get"]
+    );
+    let memories = module
+        .find_std_section::<payload::Memory>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(memories[0].limits.min, 2);
+    let inits: Vec<_> = module
+        .find_std_section::<payload::Global>()
+        .unwrap()
+        .try_contents()?
+        .iter()
+        .map(|global| global.init.clone())
+        .collect();
+    assert_eq!(
+        inits,
+        [
+            vec![Instruction::I32Const(11)],
+            vec![Instruction::I64Const(42)],
+            vec![Instruction::I32Const(5)],
+        ]
+    );
+    // Memory is split around runs of zeroes.
+    assert_eq!(
+        active_data(&module)?,
+        [
+            (0, b"abc".to_vec()),
+            (100, vec![1, 2, 3, 4]),
+            (70000, vec![7])
+        ]
+    );
+
+    let mut instance = Instance::instantiate(&module, Env)?;
+    assert_eq!(instance.invoke("get", &[])?, [Value::I32(11)]);
+    let memory = &instance.store.memories[0];
+    assert_eq!(memory.pages(), 2);
+    assert_eq!(memory.read(0, 4)?, b"abc\0");
+    assert_eq!(memory.read(100, 4)?, [1, 2, 3, 4]);
+    assert_eq!(memory.read(70000, 1)?, [7]);
+    assert_eq!(
+        instance.store.globals,
+        [Value::I32(11), Value::I64(42), Value::I32(5)]
+    );
+    Ok(())
+}
+
+#[test]
+fn passive_segments_keep_their_contents() -> Result<()> {
+    let module = snapshotted(
+        r#"(module
+            (memory 1)
+            (data "xyz")
+            (data "keep")
+            (data (i32.const 10) "hi")
+            (func (export "init")
+                (memory.init 0 (i32.const 50) (i32.const 0) (i32.const 3))
+                (data.drop 0))
+            (func (export "copy")
+                (memory.init 1 (i32.const 200) (i32.const 0) (i32.const 4))))"#,
+    )?;
+    let blobs: Vec<_> = module
+        .find_std_section::<payload::Data>()
+        .unwrap()
+        .try_contents()?
+        .iter()
+        .map(|segment| (segment.init == DataInit::Passive, segment.blob.clone()))
+        .collect();
+    // Dropped and active segments become empty passive ones, so that indices stay the same.
+    assert_eq!(
+        blobs,
+        [
+            (true, Vec::new()),
+            (true, b"keep".to_vec()),
+            (true, Vec::new()),
+            (false, b"hi".to_vec()),
+            (false, b"xyz".to_vec()),
+        ]
+    );
+    let data_count = module
+        .find_std_section::<payload::DataCount>()
+        .unwrap()
+        .try_contents()?;
+    assert_eq!(*data_count, 5);
+
+    let mut instance = Instance::instantiate(&module, Env)?;
+    instance.invoke("copy", &[])?;
+    let memory = &instance.store.memories[0];
+    assert_eq!(memory.read(10, 2)?, b"hi");
+    assert_eq!(memory.read(50, 3)?, b"xyz");
+    assert_eq!(memory.read(200, 4)?, b"keep");
+    Ok(())
+}
+
+#[test]
+fn segments_are_dropped_without_data_count() -> Result<()> {
+    let module = snapshotted(
+        r#"(module
+            (memory 1)
+            (data (i32.const 10) "hi")
+            (func (export "init")
+                (i32.store8 (i32.const 10) (i32.const 0))
+                (i32.store8 (i32.const 11) (i32.const 0))))"#,
+    )?;
+    assert!(module.find_std_section::<payload::Data>().is_none());
+    assert!(!module
+        .sections
+        .iter()
+        .any(|section| section.kind() == Kind::DataCount));
+    Ok(())
+}
+
+#[test]
+fn host_reference_leaves_module_untouched() -> Result<()> {
+    let mut module = wat(r#"(module
+        (import "env" "get" (func (result externref)))
+        (global (mut i32) (i32.const 0))
+        (global (mut externref) (ref.null extern))
+        (func (export "init")
+            (global.set 0 (i32.const 1))
+            (global.set 1 (call 0))))"#)?;
+    let original = module.clone();
+    let mut instance = Instance::new(&module, Env)?;
+    let err = snapshot(&mut module, &mut instance, "init").unwrap_err();
+    assert!(matches!(err, SnapshotError::HostReference(global) if global.index == 1));
+    assert_eq!(module, original);
+    Ok(())
+}
+
+#[test]
+fn imported_memories_are_rejected() -> Result<()> {
+    let mut module = wat(r#"(module
+        (import "env" "memory" (memory 1))
+        (func (export "init")))"#)?;
+    let original = module.clone();
+    let mut instance = Instance::new(&module, Env)?;
+    let err = snapshot(&mut module, &mut instance, "init").unwrap_err();
+    assert!(matches!(err, SnapshotError::ImportedMemory));
+    assert_eq!(module, original);
+    Ok(())
+}
@@ -0,0 +1,208 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "source-maps")]
+
+mod common;
+
+use anyhow::Result;
+use common::wat;
+use wasmbin::instructions::Instruction;
+use wasmbin::sections::payload;
+use wasmbin::source_map::{Mapping, OriginalLocation, SourceMap, SourceMapError};
+use wasmbin::transforms::address_map::{AddressMap, CodeLayout};
+use wasmbin::Module;
+
+const MODULE: &str = r#"(module
+    (func $a (result i32)
+        i32.const 1
+        i32.const 2
+        i32.add)
+    (func $b
+        nop
+        nop))"#;
+
+fn location(line: u32, name: Option<u32>) -> Option<OriginalLocation> {
+    Some(OriginalLocation {
+        source: 0,
+        line,
+        column: 2,
+        name,
+    })
+}
+
+#[test]
+fn mappings_round_trip() -> Result<()> {
+    let json =
+        br#"{"version":3,"sources":["a.c"],"names":["f","g"],"mappings":"A,CAAE,EAGAC,G,CCDAD"}"#;
+    let source_map = SourceMap::from_slice(json)?;
+    assert_eq!(
+        source_map.mappings,
+        [
+            Mapping {
+                offset: 0,
+                original: None,
+            },
+            Mapping {
+                offset: 1,
+                original: location(0, None),
+            },
+            Mapping {
+                offset: 3,
+                original: location(3, Some(1)),
+            },
+            Mapping {
+                offset: 6,
+                original: None,
+            },
+            Mapping {
+                offset: 7,
+                original: Some(OriginalLocation {
+                    source: 1,
+                    line: 2,
+                    column: 2,
+                    name: Some(0),
+                }),
+            },
+        ]
+    );
+    assert_eq!(source_map.sources().collect::<Vec<_>>(), [Some("a.c")]);
+    let encoded = source_map.to_vec()?;
+    let value: serde_json::Value = serde_json::from_slice(&encoded)?;
+    assert_eq!(value["mappings"], "A,CAAE,EAGAC,G,CCDAD");
+    assert_eq!(SourceMap::from_slice(&encoded)?, source_map);
+    Ok(())
+}
+
+#[test]
+fn malformed_mappings_are_rejected() {
+    for (json, offset) in [
+        (r#"{"version":3,"mappings":"A;C"}"#, 0),
+        (r#"{"version":3,"mappings":"A,CA"}"#, 2),
+        (r#"{"version":3,"mappings":"A,CAAAAA"}"#, 2),
+        (r#"{"version":3,"mappings":"A,D"}"#, 2),
+        (r#"{"version":3,"mappings":"A,C!"}"#, 2),
+        (r#"{"version":3}"#, 0),
+    ] {
+        let err = SourceMap::from_slice(json.as_bytes()).unwrap_err();
+        assert!(
+            matches!(err, SourceMapError::InvalidMappings(actual) if actual == offset),
+            "{json}: {err:?}"
+        );
+    }
+    let err = SourceMap::from_slice(br#"{"version":2,"mappings":""}"#).unwrap_err();
+    assert!(matches!(err, SourceMapError::UnsupportedVersion));
+}
+
+/// Source map with a mapping for every instruction, on consecutive lines.
+fn source_map(layout: &CodeLayout) -> SourceMap {
+    let mut mappings = vec![Mapping {
+        offset: 0,
+        original: None,
+    }];
+    let instrs = layout.funcs.iter().flat_map(|func| &func.instrs);
+    for (line, &instr) in (0..).zip(instrs) {
+        mappings.push(Mapping {
+            offset: layout.file_offset + instr,
+            original: location(line, None),
+        });
+    }
+    SourceMap {
+        mappings,
+        fields: serde_json::from_str(r#"{"version":3,"sources":["a.c"]}"#).unwrap(),
+    }
+}
+
+/// Insert a `nop` before the first instruction of `a`, widen its immediate and remove the first
+/// `nop` of `b`.
+fn edit(module: &mut Module) -> Result<()> {
+    let code = module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?;
+    let a = &mut code[0].try_contents_mut()?.expr;
+    a[0] = Instruction::I32Const(100_000);
+    a.insert(0, Instruction::Nop);
+    code[1].try_contents_mut()?.expr.remove(0);
+    Ok(())
+}
+
+#[test]
+fn mappings_follow_edited_instructions() -> Result<()> {
+    let mut module = wat(MODULE)?;
+    let before = CodeLayout::of(&module)?;
+    let mut source_map = source_map(&before);
+    edit(&mut module)?;
+    let after = CodeLayout::of(&module)?;
+    source_map.remap(&AddressMap::new(before, after.clone()));
+
+    let (a, b) = (&after.funcs[0].instrs, &after.funcs[1].instrs);
+    // Modified, kept, kept and `end` of `a`, then kept, removed and `end` of `b`, where the
+    // identical `nop`s are matched up in order.
+    let expected =
+        [a[1], a[2], a[3], a[4], b[0], b[1], b[1]].map(|offset| after.file_offset + offset);
+    let offsets: Vec<_> = source_map
+        .mappings
+        .iter()
+        .map(|mapping| mapping.offset)
+        .collect();
+    assert_eq!(offsets[0], 0);
+    assert_eq!(offsets[1..], expected);
+    // Lines are kept in order.
+    let lines: Vec<_> = source_map
+        .mappings
+        .iter()
+        .filter_map(|mapping| Some(mapping.original?.line))
+        .collect();
+    assert_eq!(lines, [0, 1, 2, 3, 4, 5, 6]);
+    Ok(())
+}
+
+#[test]
+fn mappings_of_removed_functions_are_dropped() -> Result<()> {
+    let module = wat(MODULE)?;
+    let before = CodeLayout::of(&module)?;
+    let mut source_map = source_map(&before);
+    let mut edited = module.clone();
+    edited
+        .find_std_section_mut::<payload::Function>()
+        .unwrap()
+        .try_contents_mut()?
+        .remove(0);
+    edited
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()?
+        .remove(0);
+    let after = CodeLayout::of(&edited)?;
+    source_map.remap(&AddressMap::with_func_map(before, after.clone(), |index| {
+        index.checked_sub(1)
+    }));
+    let offsets: Vec<_> = source_map
+        .mappings
+        .iter()
+        .map(|mapping| mapping.offset)
+        .collect();
+    let b = &after.funcs[0].instrs;
+    assert_eq!(
+        offsets,
+        [
+            0,
+            after.file_offset + b[0],
+            after.file_offset + b[1],
+            after.file_offset + b[2]
+        ]
+    );
+    Ok(())
+}
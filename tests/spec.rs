@@ -177,11 +177,19 @@ impl Tests {
         }
 
         read_proposal_tests!(? "exception-handling");
+        if cfg!(feature = "legacy-exceptions") {
+            add_test_files_in_dir(&path.join("legacy").join("exceptions"))?;
+        }
         read_proposal_tests!("extended-const");
         read_proposal_tests!("multi-memory");
         read_proposal_tests!("tail-call");
         read_proposal_tests!(? "threads");
         read_proposal_tests!(? "custom-page-sizes");
+        read_proposal_tests!(? "wide-arithmetic");
+        read_proposal_tests!(? "stack-switching");
+        if cfg!(feature = "threads") {
+            read_proposal_tests!("shared-everything-threads");
+        }
 
         ensure!(
             !test_files.is_empty(),
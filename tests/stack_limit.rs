@@ -0,0 +1,173 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use anyhow::Result;
+use common::{validate, wat};
+use wasmbin::transforms::stack_limit::{frame_sizes, limit_stack};
+
+/// Counts down from its argument, returning it after recursing that many times.
+///
+/// Frame size is 4: the frame, the parameter, and two operands.
+const RECURSIVE: &str = r#"(module
+    (func $rec (export "rec") (param i32) (result i32)
+        local.get 0
+        i32.eqz
+        if (result i32)
+            i32.const 0
+        else
+            local.get 0
+            i32.const 1
+            i32.sub
+            call $rec
+            i32.const 1
+            i32.add
+        end))"#;
+
+#[test]
+fn frame_sizes_count_params_locals_and_operands() -> Result<()> {
+    let module = wat(r#"(module
+        (import "env" "f" (func (param i32 i32 i32)))
+        (memory 1)
+        ;; Binary operator.
+        (func (param f64 f64) (result f64)
+            local.get 0
+            local.get 1
+            f64.copysign)
+        ;; Locals without operands.
+        (func (local i64 i64) (local i32))
+        ;; Operands of a call.
+        (func
+            i32.const 1
+            i32.const 2
+            i32.const 3
+            call 0)
+        ;; Nested binary operators.
+        (func (result i64)
+            i64.const 1
+            i64.const 2
+            i64.const 3
+            i64.rotl
+            i64.rotl)
+        ;; Block parameters and results.
+        (func (result i32)
+            i32.const 1
+            block (param i32) (result i32 i32)
+                i32.const 2
+            end
+            i32.add)
+        ;; Only the tallest branch counts.
+        (func (param i32) (result i32)
+            local.get 0
+            if (result i32)
+                i32.const 1
+                i32.const 2
+                i32.const 3
+                select
+            else
+                i32.const 4
+            end)
+        ;; Values popped by unreachable code were never pushed.
+        (func (result i32)
+            unreachable
+            i32.add)
+        ;; Lane loads and shuffles pop two operands.
+        (func (result v128)
+            i32.const 0
+            v128.const i64x2 0 0
+            v128.load8_lane 0
+            v128.const i64x2 0 0
+            i8x16.shuffle 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15))"#)?;
+    assert_eq!(frame_sizes(&module)?, [5, 4, 4, 4, 3, 5, 2, 3]);
+    Ok(())
+}
+
+#[cfg(feature = "threads")]
+#[test]
+fn frame_sizes_of_atomics() -> Result<()> {
+    let module = wat(r#"(module
+        (memory 1 1 shared)
+        (func (param i32) (result i32)
+            local.get 0
+            i32.const 1
+            atomic.fence
+            memory.atomic.notify)
+        (func (param i32) (result i64)
+            local.get 0
+            i64.const 1
+            i64.atomic.rmw16.xchg_u))"#)?;
+    assert_eq!(frame_sizes(&module)?, [4, 4]);
+    Ok(())
+}
+
+#[test]
+fn limit_stack_validates() -> Result<()> {
+    let mut module = wat(RECURSIVE)?;
+    assert_eq!(frame_sizes(&module)?, [4]);
+    limit_stack(&mut module, 20)?;
+    validate(&module)?;
+    Ok(())
+}
+
+#[cfg(feature = "interp")]
+mod interp {
+    use super::*;
+    use wasmbin::interp::{Host, Instance, InterpError, Store, Trap, Value};
+    use wasmbin::sections::ImportPath;
+    use wasmbin::types::FuncType;
+
+    struct NoImports;
+
+    impl Host for NoImports {
+        fn call(
+            &mut self,
+            import: &ImportPath,
+            _ty: &FuncType,
+            _args: &[Value],
+            _store: &mut Store,
+        ) -> Result<Vec<Value>, Trap> {
+            Err(Trap::Host(format!(
+                "unexpected call to {}.{}",
+                import.module, import.name
+            )))
+        }
+    }
+
+    #[test]
+    fn deep_recursion_traps() -> Result<()> {
+        let mut module = wat(RECURSIVE)?;
+        let height = limit_stack(&mut module, 20)?;
+        let mut instance = Instance::instantiate(&module, NoImports)?;
+        // Five frames of size 4 fit...
+        assert_eq!(instance.invoke("rec", &[Value::I32(4)])?, [Value::I32(4)]);
+        // ...and are all released on return.
+        assert_eq!(instance.store.globals[height.index as usize], Value::I32(0));
+        // A sixth one doesn't.
+        let err = instance.invoke("rec", &[Value::I32(5)]).unwrap_err();
+        assert!(matches!(err, InterpError::Trap(Trap::Unreachable)));
+        Ok(())
+    }
+
+    #[test]
+    fn frames_larger_than_limit_trap_on_entry() -> Result<()> {
+        let mut module = wat(RECURSIVE)?;
+        limit_stack(&mut module, 3)?;
+        validate(&module)?;
+        let mut instance = Instance::instantiate(&module, NoImports)?;
+        let err = instance.invoke("rec", &[Value::I32(0)]).unwrap_err();
+        assert!(matches!(err, InterpError::Trap(Trap::Unreachable)));
+        Ok(())
+    }
+}